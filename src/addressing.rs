@@ -0,0 +1,209 @@
+use futures::Future;
+use rusoto_core::request::{DispatchSignedRequest, HttpDispatchError, HttpResponse};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::Region;
+use rusoto_credential::{Anonymous, ProvideAwsCredentials};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::tls::HttpClient;
+
+const ACCELERATE_ENDPOINT: &str = "s3-accelerate.amazonaws.com";
+
+/// Query parameters of the object transfers, requests with other
+/// subresources (tagging, acl, ...) stay on the regular endpoint
+const TRANSFER_PARAMS: &[&str] = &["partNumber", "uploadId", "uploads", "versionId"];
+
+/// Sends the requests of access point ARNs to the access point endpoint and,
+/// with --accelerate, the object transfers (get, put, copy) to the transfer
+/// acceleration endpoint of the bucket. rusoto addresses the bucket in the
+/// path, these endpoints only accept it in the host name, so the request is
+/// re-signed after it is moved
+pub struct AddressingDispatcher<P> {
+    inner: Arc<HttpClient>,
+    provider: P,
+    accelerate: bool,
+    /// --request-timeout, the requests of rusoto have none
+    timeout: Option<Duration>,
+}
+
+impl<P> AddressingDispatcher<P> {
+    pub fn new(
+        inner: HttpClient,
+        provider: P,
+        accelerate: bool,
+        timeout: Option<Duration>,
+    ) -> Self {
+        AddressingDispatcher {
+            inner: Arc::new(inner),
+            provider,
+            accelerate,
+            timeout,
+        }
+    }
+}
+
+impl<P> DispatchSignedRequest for AddressingDispatcher<P>
+where
+    P: ProvideAwsCredentials,
+    P::Future: Send + 'static,
+{
+    type Future = Box<dyn Future<Item = HttpResponse, Error = HttpDispatchError> + Send>;
+
+    fn dispatch(&self, mut request: SignedRequest, timeout: Option<Duration>) -> Self::Future {
+        let timeout = timeout.or(self.timeout);
+        let moved = access_point(&mut request) || (self.accelerate && accelerate(&mut request));
+        if !moved {
+            return Box::new(self.inner.dispatch(request, timeout));
+        }
+
+        let inner = self.inner.clone();
+        let future = self
+            .provider
+            .credentials()
+            .map_err(|e| HttpDispatchError::new(e.message))
+            .and_then(move |credentials| {
+                if !credentials.is_anonymous() {
+                    request.sign_with_plus(&credentials, true);
+                }
+                inner.dispatch(request, timeout)
+            });
+        Box::new(future)
+    }
+}
+
+/// Moves an object transfer to the acceleration endpoint, `false` when the
+/// request is not accelerated
+pub fn accelerate(request: &mut SignedRequest) -> bool {
+    if request.service != "s3" || !["GET", "HEAD", "PUT", "POST"].contains(&request.method()) {
+        return false;
+    }
+    if !request
+        .params
+        .keys()
+        .all(|x| TRANSFER_PARAMS.contains(&x.as_str()) || x.starts_with("response-"))
+    {
+        return false;
+    }
+
+    let (bucket, key) = match request.path.trim_start_matches('/').split_once('/') {
+        Some((bucket, key)) if !key.is_empty() => (bucket.to_owned(), key.to_owned()),
+        _ => return false,
+    };
+    // the acceleration endpoint does not accept bucket names with dots
+    if bucket.contains('.') {
+        return false;
+    }
+
+    request.path = format!("/{}", key);
+    request.scheme = Some("https".to_owned());
+    request.hostname = Some(format!("{}.{}", bucket, ACCELERATE_ENDPOINT));
+    true
+}
+
+/// Moves a request of an access point ARN (s3 or s3-object-lambda) to the
+/// endpoint of the access point, signed for its region and service.
+/// `false` when the bucket is not an ARN
+pub fn access_point(request: &mut SignedRequest) -> bool {
+    let path = request.path.trim_start_matches('/');
+    if !path.starts_with("arn:") {
+        return false;
+    }
+    let fields: Vec<&str> = path.splitn(6, ':').collect();
+    let (partition, service, region, account, resource) = match fields[..] {
+        [_, partition, service, region, account, resource] => {
+            (partition, service, region, account, resource)
+        }
+        _ => return false,
+    };
+    let (name, key) = match resource.strip_prefix("accesspoint/") {
+        Some(resource) => resource.split_once('/').unwrap_or((resource, "")),
+        None => return false,
+    };
+    let host = match service {
+        "s3" => "s3-accesspoint",
+        "s3-object-lambda" => "s3-object-lambda",
+        _ => return false,
+    };
+    let domain = match partition {
+        "aws-cn" => "amazonaws.com.cn",
+        _ => "amazonaws.com",
+    };
+
+    let hostname = format!("{}-{}.{}.{}.{}", name, account, host, region, domain);
+    request.region = Region::Custom {
+        name: region.to_owned(),
+        endpoint: format!("https://{}", hostname),
+    };
+    request.service = service.to_owned();
+    request.path = format!("/{}", key);
+    request.scheme = Some("https".to_owned());
+    request.hostname = Some(hostname);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accelerate_object_requests() {
+        let mut get = SignedRequest::new("GET", "s3", &Region::EuWest1, "/bucket/dir/file.gz");
+        assert!(accelerate(&mut get));
+        assert_eq!(get.hostname(), "bucket.s3-accelerate.amazonaws.com");
+        assert_eq!(get.path(), "/dir/file.gz");
+
+        let mut part = SignedRequest::new("PUT", "s3", &Region::EuWest1, "/bucket/file");
+        part.add_param("partNumber", "1");
+        part.add_param("uploadId", "id");
+        assert!(accelerate(&mut part));
+
+        let mut list = SignedRequest::new("GET", "s3", &Region::EuWest1, "/bucket");
+        list.add_param("list-type", "2");
+        assert!(!accelerate(&mut list));
+        assert_eq!(list.path(), "/bucket");
+
+        let mut tagging = SignedRequest::new("PUT", "s3", &Region::EuWest1, "/bucket/file");
+        tagging.add_param("tagging", "");
+        assert!(!accelerate(&mut tagging));
+
+        let mut delete = SignedRequest::new("DELETE", "s3", &Region::EuWest1, "/bucket/file");
+        assert!(!accelerate(&mut delete));
+
+        let mut dotted = SignedRequest::new("GET", "s3", &Region::EuWest1, "/my.bucket/file");
+        assert!(!accelerate(&mut dotted));
+    }
+
+    #[test]
+    fn access_point_requests() {
+        let arn = "arn:aws:s3:us-west-2:123456789012:accesspoint/my-ap";
+        let mut get =
+            SignedRequest::new("GET", "s3", &Region::UsEast1, &format!("/{}/dir/file", arn));
+        assert!(access_point(&mut get));
+        assert_eq!(
+            get.hostname(),
+            "my-ap-123456789012.s3-accesspoint.us-west-2.amazonaws.com"
+        );
+        assert_eq!(get.path(), "/dir/file");
+        assert_eq!(get.region.name(), "us-west-2");
+
+        let mut list = SignedRequest::new("GET", "s3", &Region::UsEast1, &format!("/{}", arn));
+        list.add_param("list-type", "2");
+        assert!(access_point(&mut list));
+        assert_eq!(list.path(), "/");
+
+        let arn = "arn:aws:s3-object-lambda:eu-west-1:123456789012:accesspoint/my-olap";
+        let mut lambda =
+            SignedRequest::new("GET", "s3", &Region::UsEast1, &format!("/{}/file", arn));
+        assert!(access_point(&mut lambda));
+        assert_eq!(
+            lambda.hostname(),
+            "my-olap-123456789012.s3-object-lambda.eu-west-1.amazonaws.com"
+        );
+        assert_eq!(lambda.service, "s3-object-lambda");
+
+        let mut bucket = SignedRequest::new("GET", "s3", &Region::UsEast1, "/bucket/file");
+        assert!(!access_point(&mut bucket));
+        assert_eq!(bucket.path(), "/bucket/file");
+    }
+}
@@ -2,7 +2,12 @@ use failure::*;
 use glob::Pattern;
 use regex::Regex;
 use rusoto_core::Region;
+use std::cell::Cell;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
@@ -11,6 +16,7 @@ use structopt::StructOpt;
 #[structopt(
     name = "s3find",
 	global_settings(&[AppSettings::ColoredHelp, AppSettings::NeedsLongHelp, AppSettings::NeedsSubcommandHelp]),
+    setting = AppSettings::SubcommandsNegateReqs,
     after_help = r#"
 The authorization flow is the following chain:
   * use credentials from arguments provided by users
@@ -18,14 +24,102 @@ The authorization flow is the following chain:
   * use credentials via aws file profile.
     Profile can be set via environment variable AWS_PROFILE
     Profile file can be set via environment variable AWS_SHARED_CREDENTIALS_FILE
-  * use AWS instance IAM profile
+    IAM Identity Center (SSO) profiles use the token cached by aws sso login
   * use AWS container IAM profile
+  * use AWS instance IAM profile (IMDSv2, IMDSv1 if the token is refused)
+
+With --mfa-serial the credentials of the chain are exchanged for
+temporary session credentials (STS GetSessionToken) with an MFA code,
+or for the credentials of the role of --role-arn (STS AssumeRole)
+
+Failed operations on single keys are reported to stderr and do not stop the run.
+The exit code is 1 on a fatal error and 2 when some operations have failed.
+It is 3 on a credentials error, 4 when access was denied and 5 when requests
+were still throttled after the retries, whether the error was fatal or not.
+On SIGINT or SIGTERM the run stops after the running batch of keys, the summary
+is printed and the exit code is 128 plus the signal (130 on Ctrl-C).
 "#
 )]
 pub struct FindOpt {
     /// S3 path to walk through. It should be s3://bucket/path
-    #[structopt(name = "path")]
-    pub path: S3path,
+    #[structopt(name = "path", required_unless = "all-buckets")]
+    pub path: Option<S3path>,
+
+    /// More S3 paths walked after the first one with the same filters and command
+    #[structopt(
+        name = "paths",
+        conflicts_with_all = &["cache", "save-resume", "resume"],
+        long_help = r#"More S3 paths walked one after another with the same
+filters and command, e.g.:
+    s3find s3://bucket-a/logs/ s3://bucket-b/logs/ --name '*.gz' delete
+
+The summary covers all the paths and --limit is the number of
+keys over all the paths"#
+    )]
+    pub paths: Vec<S3path>,
+
+    /// Walk every bucket of the account, or the buckets matching the glob pattern
+    #[structopt(
+        name = "all-buckets",
+        long = "all-buckets",
+        value_name = "pattern",
+        require_equals = true,
+        conflicts_with_all = &["path", "paths"],
+        long_help = r#"Walk every bucket of the account, or the buckets matching
+the glob pattern, instead of a path, e.g.:
+    s3find --all-buckets --prefix logs/ --name '*.gz' ls
+    s3find --all-buckets='logs-*' --summarize ls
+
+The buckets are listed with ListBuckets. With --summarize the
+summary of each bucket is followed by the overall summary"#
+    )]
+    pub all_buckets: Option<Option<String>>,
+
+    /// Prefix of the keys searched in every bucket of --all-buckets
+    #[structopt(name = "prefix", long = "prefix", requires = "all-buckets")]
+    pub prefix: Option<String>,
+
+    /// Read the default options from the file instead of ~/.config/s3find/config.toml
+    #[structopt(
+        name = "config",
+        long = "config",
+        parse(from_os_str),
+        long_help = r#"Read the default options from the file instead of
+~/.config/s3find/config.toml ($XDG_CONFIG_HOME/s3find/config.toml).
+
+The keys are the long options, the options of a subcommand
+are set in its section, e.g.:
+    aws-region = "eu-west-1"
+    page-size = 500
+    summarize = true
+    name = ["*.log", "*.gz"]
+
+    [download]
+    concurrency = 8
+
+The options set on the command line override the file"#
+    )]
+    pub config: Option<PathBuf>,
+
+    /// Use the endpoint, region and credentials of a [target.<name>] section of the config file
+    #[structopt(
+        name = "target",
+        long = "target",
+        long_help = r#"Use the options of a [target.<name>] section of the config
+file, e.g. for --target staging:
+    [target.staging]
+    endpoint = "http://minio.local:9000"
+    region = "eu-west-1"
+    profile = "staging"
+
+The keys are the long options, with the aliases endpoint
+(--endpoint-url), region (--aws-region) and profile
+(--aws-profile). The requests are always path-style.
+
+The target options override the top-level options of the
+file, the command line overrides both"#
+    )]
+    pub target: Option<String>,
 
     /// AWS access key. Unrequired.
     #[structopt(
@@ -43,26 +137,157 @@ pub struct FindOpt {
     )]
     pub aws_secret_key: Option<String>,
 
+    /// Profile of the AWS shared credentials file
+    #[structopt(
+        name = "aws-profile",
+        long = "aws-profile",
+        conflicts_with = "aws-access-key"
+    )]
+    pub aws_profile: Option<String>,
+
+    /// Timeout of the instance metadata (IMDSv2) and container credential requests, in milliseconds
+    #[structopt(name = "imds-timeout", long = "imds-timeout", default_value = "1000")]
+    pub imds_timeout: u64,
+
+    /// Send unsigned requests, for public buckets without any credentials
+    #[structopt(
+        name = "no-sign-request",
+        long = "no-sign-request",
+        conflicts_with_all = &["aws-access-key", "aws-profile", "mfa-serial"]
+    )]
+    pub no_sign_request: bool,
+
+    /// Serial number (ARN) of the MFA device, the code is asked for unless --mfa-token is set
+    #[structopt(
+        name = "mfa-serial",
+        long = "mfa-serial",
+        long_help = r#"Serial number or ARN of the MFA device, e.g.:
+    arn:aws:iam::123456789012:mfa/user
+
+Temporary credentials are requested from STS with the
+configured credentials and the current code of the device,
+which is read from the terminal unless --mfa-token is set.
+The role of --role-arn is assumed (AssumeRole), otherwise
+session credentials are requested (GetSessionToken).
+
+A code is used once: the run fails when the credentials
+expire, after 12 hours or the session duration of the role"#
+    )]
+    pub mfa_serial: Option<String>,
+
+    /// Current code of the MFA device
+    #[structopt(name = "mfa-token", long = "mfa-token", requires = "mfa-serial")]
+    pub mfa_token: Option<String>,
+
+    /// Role assumed with the MFA code, e.g. arn:aws:iam::123456789012:role/admin
+    #[structopt(name = "role-arn", long = "role-arn", requires = "mfa-serial")]
+    pub role_arn: Option<String>,
+
     /// The region to use. Default value is us-east-1
     #[structopt(name = "aws-region", long = "aws-region", default_value = "us-east-1")]
     pub aws_region: Region,
 
+    /// Endpoint of an S3-compatible service, e.g. http://minio.local:9000
+    #[structopt(
+        name = "endpoint-url",
+        long = "endpoint-url",
+        long_help = r#"Endpoint of an S3-compatible service (MinIO, Ceph RGW,
+LocalStack), e.g.:
+    http://minio.local:9000
+
+The requests are signed for the --aws-region and the buckets
+are addressed in the path of the URL"#
+    )]
+    pub endpoint_url: Option<EndpointUrl>,
+
+    /// Transfer the objects through the transfer acceleration endpoint of the bucket
+    #[structopt(
+        name = "accelerate",
+        long = "accelerate",
+        conflicts_with = "endpoint-url",
+        long_help = r#"Transfer the objects through the transfer acceleration
+endpoint of the bucket (BUCKET.s3-accelerate.amazonaws.com).
+
+Only the object transfers (download, copy, move) are
+accelerated, the listing uses the regular endpoint. The bucket
+must have transfer acceleration enabled"#
+    )]
+    pub accelerate: bool,
+
+    /// PEM file of the CA certificates trusted in addition to the built-in ones
+    #[structopt(name = "ca-bundle", long = "ca-bundle", parse(from_os_str))]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Do not verify TLS certificates, only for test endpoints with self-signed certificates
+    #[structopt(
+        name = "insecure",
+        long = "insecure",
+        conflicts_with = "ca-bundle",
+        long_help = r#"Do not verify TLS certificates.
+
+Any server can impersonate the endpoint and read the credentials
+and the data, use it only for throwaway test endpoints (LocalStack,
+MinIO) with self-signed certificates"#
+    )]
+    pub insecure: bool,
+
+    /// Timeout of the TCP connections to the endpoints, in milliseconds
+    #[structopt(name = "connect-timeout", long = "connect-timeout")]
+    pub connect_timeout: Option<u64>,
+
+    /// Timeout of the S3 requests until the response headers, in milliseconds, retried like the other errors
+    #[structopt(name = "request-timeout", long = "request-timeout")]
+    pub request_timeout: Option<u64>,
+
+    /// Idle connections kept open per endpoint for the next requests, unlimited by default
+    #[structopt(name = "max-connections", long = "max-connections")]
+    pub max_connections: Option<usize>,
+
+    /// Time an idle connection is kept open for the next requests, in milliseconds, 0 closes it after every request
+    #[structopt(name = "keep-alive", long = "keep-alive", default_value = "90000")]
+    pub keep_alive: u64,
+
     /// Glob pattern for match, can be multiple
-    #[structopt(name = "npatern", long = "name", number_of_values = 1)]
+    #[structopt(
+        name = "name",
+        long = "name",
+        value_name = "npatern",
+        number_of_values = 1
+    )]
     pub name: Vec<NameGlob>,
 
     /// Case-insensitive glob pattern for match, can be multiple
-    #[structopt(name = "ipatern", long = "iname", number_of_values = 1)]
+    #[structopt(
+        name = "iname",
+        long = "iname",
+        value_name = "ipatern",
+        number_of_values = 1
+    )]
     pub iname: Vec<InameGlob>,
 
     /// Regex pattern for match, can be multiple
-    #[structopt(name = "rpatern", long = "regex", number_of_values = 1)]
+    #[structopt(
+        name = "regex",
+        long = "regex",
+        value_name = "rpatern",
+        number_of_values = 1
+    )]
     pub regex: Vec<Regex>,
 
+    /// Extensions for match, comma-separated, e.g. log,gz,parquet: a key matches any of them
+    #[structopt(
+        name = "ext",
+        long = "ext",
+        value_name = "extensions",
+        use_delimiter = true
+    )]
+    pub ext: Vec<String>,
+
     /// Modification time for match
     #[structopt(
-        name = "time",
+        name = "mtime",
         long = "mtime",
+        value_name = "time",
         number_of_values = 1,
         allow_hyphen_values = true,
         long_help = r#"Modification time for match, a time period:
@@ -82,8 +307,9 @@ Can be multiple, but should be overlaping"#
 
     /// File size for match
     #[structopt(
-        name = "bytes-size",
+        name = "size",
         long = "size",
+        value_name = "bytes-size",
         number_of_values = 1,
         allow_hyphen_values = true,
         long_help = r#"File size for match:
@@ -100,26 +326,657 @@ Possible file size units are as follows:
     )]
     pub size: Vec<FindSize>,
 
+    /// Match the keys modified before the date, e.g. 2023-01-01 or 2023-06-01T12:00:00Z
+    #[structopt(
+        name = "mtime-before",
+        long = "mtime-before",
+        value_name = "date",
+        long_help = r#"Match the keys modified before the date, a date or an
+RFC 3339 time, in UTC without an offset, e.g.:
+    2023-01-01
+    2023-06-01T12:00:00Z
+    2023-06-01T14:00:00+02:00
+
+With --mtime-after the keys of a calendar range are matched,
+the keys modified at the time of --mtime-after included:
+    --mtime-after 2023-01-01 --mtime-before 2023-02-01"#
+    )]
+    pub mtime_before: Option<FindDate>,
+
+    /// Match the keys modified at or after the date, in the format of --mtime-before
+    #[structopt(name = "mtime-after", long = "mtime-after", value_name = "date")]
+    pub mtime_after: Option<FindDate>,
+
+    /// Match the keys modified after the reference, an S3 object (s3://bucket/key) or a local file
+    #[structopt(
+        name = "newer",
+        long = "newer",
+        value_name = "reference",
+        long_help = r#"Match the keys modified after the reference, as find -newer.
+The reference is an S3 object, e.g. s3://bucket/markers/last-run,
+with the time of HeadObject, or a local file with its
+modification time. The times are compared to the second"#
+    )]
+    pub newer: Option<NewerRef>,
+
+    /// ETag for match, with or without the quotes, can be multiple: a key matches any of them
+    #[structopt(
+        name = "etag",
+        long = "etag",
+        value_name = "etag",
+        number_of_values = 1
+    )]
+    pub etag: Vec<FindETag>,
+
+    /// Display name of the owner for match, can be multiple: a key matches any of them
+    #[structopt(
+        name = "owner",
+        long = "owner",
+        value_name = "name",
+        number_of_values = 1,
+        conflicts_with = "keys-from",
+        long_help = r#"Display name of the owner for match, can be multiple: a key
+matches any of them. Sets --fetch-owner, the owner of the
+keys is asked in the list requests.
+
+S3 returns the display name in some regions only, the keys
+without one never match"#
+    )]
+    pub owner: Vec<String>,
+
+    /// Glob pattern the keys must not match, can be multiple
+    #[structopt(
+        name = "not-name",
+        long = "not-name",
+        value_name = "npatern",
+        number_of_values = 1
+    )]
+    pub not_name: Vec<NameGlob>,
+
+    /// Case-insensitive glob pattern the keys must not match, can be multiple
+    #[structopt(
+        name = "not-iname",
+        long = "not-iname",
+        value_name = "ipatern",
+        number_of_values = 1
+    )]
+    pub not_iname: Vec<InameGlob>,
+
+    /// Regex pattern the keys must not match, can be multiple
+    #[structopt(
+        name = "not-regex",
+        long = "not-regex",
+        value_name = "rpatern",
+        number_of_values = 1
+    )]
+    pub not_regex: Vec<Regex>,
+
+    /// Extensions the keys must not have, comma-separated, e.g. tmp,part
+    #[structopt(
+        name = "not-ext",
+        long = "not-ext",
+        value_name = "extensions",
+        use_delimiter = true
+    )]
+    pub not_ext: Vec<String>,
+
+    /// Modification time the keys must not match, in the format of --mtime, can be multiple
+    #[structopt(
+        name = "not-mtime",
+        long = "not-mtime",
+        value_name = "time",
+        number_of_values = 1,
+        allow_hyphen_values = true
+    )]
+    pub not_mtime: Vec<FindTime>,
+
+    /// File size the keys must not match, in the format of --size, can be multiple
+    #[structopt(
+        name = "not-size",
+        long = "not-size",
+        value_name = "bytes-size",
+        number_of_values = 1,
+        allow_hyphen_values = true
+    )]
+    pub not_size: Vec<FindSize>,
+
+    /// ETag the keys must not have, with or without the quotes, can be multiple
+    #[structopt(
+        name = "not-etag",
+        long = "not-etag",
+        value_name = "etag",
+        number_of_values = 1
+    )]
+    pub not_etag: Vec<FindETag>,
+
+    /// Display name of the owner the keys must not have, can be multiple. Sets --fetch-owner
+    #[structopt(
+        name = "not-owner",
+        long = "not-owner",
+        value_name = "name",
+        number_of_values = 1,
+        conflicts_with = "keys-from"
+    )]
+    pub not_owner: Vec<String>,
+
+    /// Group of filters of which any must match, e.g. 'name=*.log,name=*.txt', can be multiple
+    #[structopt(
+        name = "any-of",
+        long = "any-of",
+        value_name = "filters",
+        number_of_values = 1,
+        allow_hyphen_values = true,
+        long_help = r#"Group of filters of which any must match, can be multiple.
+The filters are comma-separated <filter>=<value> items, the
+filters are name, iname, regex, mtime, size, etag and owner
+with the values of their options, e.g.:
+    s3find s3://bucket/logs/ --any-of 'name=*.log,name=*.txt' --size +1M ls
+    s3find s3://bucket/ --any-of 'size=+1G,mtime=+30d' --any-of 'regex=^tmp/' ls
+
+Every group must match, as the other filters. A comma of a
+value is escaped with a backslash"#
+    )]
+    pub any_of: Vec<AnyOf>,
+
+    /// Filter expression in the syntax of find, e.g. "( -name '*.gz' -o -size +1G ) ! -mtime +30"
+    #[structopt(
+        name = "expr",
+        long = "expr",
+        value_name = "expression",
+        allow_hyphen_values = true,
+        long_help = r#"Filter expression in the syntax of the find tests, e.g.:
+    s3find s3://bucket/ --expr "( -name '*.gz' -o -size +1G ) ! -mtime +30" ls
+
+The tests are -name, -iname, -regex, -mtime, -size, -etag and
+-owner with the values of their options, except that a -mtime
+without a unit is in days as with find. The operators are
+( ), ! or -not, -a or -and (the default between two tests)
+and -o or -or, from the tightest to the loosest. The values
+are quoted as in a shell.
+
+The expression must match, as the other filters"#
+    )]
+    pub expr: Option<FilterExpr>,
+
+    /// Encoding type of the keys in the list responses
+    #[structopt(
+        name = "encoding-type",
+        long = "encoding-type",
+        possible_values = &["url"],
+        long_help = r#"Ask S3 to url-encode keys in the list responses. Keys are decoded
+before matching, this allows to list keys with characters
+that are not allowed in XML 1.0 (e.g. control characters)"#
+    )]
+    pub encoding_type: Option<String>,
+
+    /// Ask ListObjectsV2 for the owner of the keys, for the owner of print and the templates
+    #[structopt(name = "fetch-owner", long = "fetch-owner")]
+    pub fetch_owner: bool,
+
+    /// List one level of the path, the common prefixes are listed with the keys
+    #[structopt(
+        name = "delimiter",
+        long = "delimiter",
+        conflicts_with_all = &["list-concurrency", "cache", "source", "keys-from"],
+        long_help = r#"List one level of the path instead of recursing, e.g.
+--delimiter /. The keys under the path up to the next delimiter
+are grouped into common prefixes ("directories"), printed by
+ls, print and printf along the keys. The other commands only
+get the keys, and the summary counts only the keys"#
+    )]
+    pub delimiter: Option<String>,
+
+    /// Descend at most N levels below the path, the deeper prefixes are not listed
+    #[structopt(
+        name = "max-depth",
+        long = "max-depth",
+        alias = "maxdepth",
+        value_name = "N",
+        conflicts_with_all = &[
+            "delimiter",
+            "list-concurrency",
+            "cache",
+            "resume",
+            "save-resume",
+        ],
+        long_help = r#"Descend at most N levels below the path, the keys directly
+under the path are at level 1. The common prefixes are listed
+level by level with the "/" delimiter and the prefixes deeper
+than N are not listed at all, so a shallow search of a deep
+bucket does not pay for the whole tree. The keys of --keys-from,
+--source and --listen-sqs are filtered by the number of "/"
+below the path. Also --maxdepth, as with find"#
+    )]
+    pub max_depth: Option<usize>,
+
+    /// Skip the keys less than N levels below the path
+    #[structopt(
+        name = "min-depth",
+        long = "min-depth",
+        alias = "mindepth",
+        value_name = "N",
+        conflicts_with_all = &[
+            "delimiter",
+            "list-concurrency",
+            "cache",
+            "resume",
+            "save-resume",
+        ],
+        long_help = r#"Skip the keys less than N levels below the path, the keys
+directly under the path are at level 1. The levels above N are
+listed with the "/" delimiter only to find the prefixes at
+level N, which are then listed as a whole (or level by level
+up to --max-depth). The keys of --keys-from, --source and
+--listen-sqs are filtered by the number of "/" below the path.
+Also --mindepth, as with find"#
+    )]
+    pub min_depth: Option<usize>,
+
     /// Limit result
     #[structopt(name = "limit", long = "limit")]
     pub limit: Option<usize>,
 
+    /// Skip the first matched keys, before --limit applies
+    #[structopt(name = "skip", long = "skip")]
+    pub skip: Option<usize>,
+
+    /// Order of the keys passed to the command: key, size or mtime
+    #[structopt(
+        name = "sort",
+        long = "sort",
+        conflicts_with_all = &["save-resume", "resume"],
+        long_help = r#"Order of the keys passed to the command: key, size or mtime.
+
+By default the keys come in listing order: the key order of a
+single listing, but the pages of --list-concurrency, inventory
+reports and --keys-from files come in no particular order.
+With --sort all the listed keys of a path are ordered before
+the filters, --skip and --limit apply, the keys with the same
+size or mtime by key. The keys not fitting into memory
+(--max-memory, 500000 keys by default) are spilled to temporary
+files and merged"#
+    )]
+    pub sort: Option<SortField>,
+
+    /// Reverse the order of --sort
+    #[structopt(name = "reverse", long = "reverse")]
+    pub reverse: bool,
+
+    /// Reproducible output for the diffs between runs, like --sort key unless --sort is set
+    #[structopt(
+        name = "stable-output",
+        long = "stable-output",
+        conflicts_with_all = &["save-resume", "resume"]
+    )]
+    pub stable_output: bool,
+
     /// The number of results to return in each response to a list operation.
     #[structopt(
-        name = "number",
+        name = "page-size",
         long = "page-size",
+        value_name = "number",
         default_value = "1000",
         long_help = r#"The number of results to return in each response to a
 list operation. The default value is 1000 (the maximum
 allowed). Using a lower value may help if an operation
-times out."#
+times out.
+
+With "auto" the page size is adjusted between requests:
+lowered when responses are slow, or when the matched keys of
+a page exceed --buffer-size or the rest of --limit, and raised
+back while responses are fast"#
     )]
-    pub page_size: i64,
+    pub page_size: PageSize,
+
+    /// List API of the endpoint: auto, v1 or v2
+    #[structopt(
+        name = "list-api",
+        long = "list-api",
+        default_value = "auto",
+        possible_values = &["auto", "v1", "v2"],
+        long_help = r#"List API of the endpoint. With "auto" the keys are listed with
+ListObjectsV2 and the legacy ListObjects API with markers is
+used for the rest of the run once the endpoint answers
+NotImplemented, as older S3-compatible appliances do. "v1"
+always uses ListObjects, "v2" never falls back"#
+    )]
+    pub list_api: ListApi,
 
     /// Print summary statistic
     #[structopt(name = "summarize", long = "summarize")]
     pub summarize: bool,
 
+    /// Write summary statistic as a JSON document to the file, "-" for stdout
+    #[structopt(name = "summarize-json", long = "summarize-json", parse(from_os_str))]
+    pub summarize_json: Option<PathBuf>,
+
+    /// Override the storage price (USD per GB-month) used for cost estimation, can be multiple
+    #[structopt(
+        name = "storage-price",
+        long = "storage-price",
+        value_name = "class=price",
+        number_of_values = 1,
+        long_help = r#"Override the built-in storage price used for the estimated
+cost in the summary, a storage class and a price in USD per GB-month:
+    STANDARD=0.021
+    GLACIER=0.0036
+
+Can be multiple"#
+    )]
+    pub storage_price: Vec<StoragePrice>,
+
+    /// Save the listing (keys and metadata) to the file
+    #[structopt(
+        name = "cache",
+        long = "cache",
+        parse(from_os_str),
+        long_help = r#"Save the listing (keys and metadata) to the file.
+
+The cache is marked complete only when the whole path has
+been listed. A listing stopped early by --limit or an
+interruption leaves an incomplete cache, which --from-cache
+refuses"#
+    )]
+    pub cache: Option<PathBuf>,
+
+    /// Read the listing from the --cache file instead of listing the bucket
+    #[structopt(name = "from-cache", long = "from-cache", requires = "cache")]
+    pub from_cache: bool,
+
+    /// Read the keys from the file, or stdin with -, instead of listing the bucket
+    #[structopt(
+        name = "keys-from",
+        long = "keys-from",
+        parse(from_os_str),
+        conflicts_with_all = &["source", "cache", "save-resume", "resume", "paths", "progress"],
+        long_help = r#"Read the keys from the file, or stdin with -, instead of
+listing the bucket. A line is a key or an s3:// URL of the
+bucket, e.g. the output of s3find ls:
+    s3find s3://bucket/logs/ --name '*.gz' ls | s3find s3://bucket/ --keys-from - delete
+
+Keys outside of the searched path and URLs of other buckets
+are skipped. The objects have no size, modification time or
+ETag unless --keys-head is set or --size, --mtime or --etag
+(or their --not-* options) is used"#
+    )]
+    pub keys_from: Option<PathBuf>,
+
+    /// Fetch the size, modification time and storage class of the --keys-from keys with HeadObject
+    #[structopt(name = "keys-head", long = "keys-head", requires = "keys-from")]
+    pub keys_head: bool,
+
+    /// Read the objects from an S3 Inventory report instead of listing the bucket
+    #[structopt(
+        name = "source",
+        long = "source",
+        number_of_values = 2,
+        value_names = &["type", "manifest"],
+        long_help = r#"Read the objects from an S3 Inventory report instead of
+listing the bucket, e.g.:
+    --source inventory s3://inventory-bucket/source-bucket/config/2020-01-01T00-00Z/manifest.json
+
+The CSV and Parquet inventory formats are supported, ORC is not.
+Keys outside of the searched path, old versions and delete
+markers are skipped"#
+    )]
+    pub source: Vec<String>,
+
+    /// Save the listing position to the file, to continue an interrupted run with --resume
+    #[structopt(
+        name = "save-resume",
+        long = "save-resume",
+        parse(from_os_str),
+        conflicts_with_all = &["cache", "source"]
+    )]
+    pub save_resume: Option<PathBuf>,
+
+    /// Continue the listing from the position saved with --save-resume
+    #[structopt(
+        name = "resume",
+        long = "resume",
+        parse(from_os_str),
+        conflicts_with_all = &["cache", "source"],
+        long_help = r#"Continue the listing from the position saved with --save-resume.
+The position is saved only after the listed objects have been
+passed to the command, so no key is skipped but the last keys
+before an interruption may be processed twice.
+
+The position keeps being saved to the same file unless
+--save-resume is set"#
+    )]
+    pub resume: Option<PathBuf>,
+
+    /// Record the keys processed by the --job in the file and skip them on the next run
+    #[structopt(
+        name = "state",
+        long = "state",
+        parse(from_os_str),
+        requires = "job",
+        long_help = r#"Record the keys processed by the --job in the file and skip
+them on the next run of the same job, e.g.:
+    --state s3find.db --job nightly-tagging
+
+The keys are recorded after the command has run on them, a key
+is processed again when its ETag has changed. Several jobs can
+share the same file"#
+    )]
+    pub state: Option<PathBuf>,
+
+    /// Name of the job whose processed keys are recorded in the --state file
+    #[structopt(name = "job", long = "job", requires = "state")]
+    pub job: Option<String>,
+
+    /// List the path again every --interval until interrupted, the command runs only on new or changed keys
+    #[structopt(
+        name = "watch",
+        long = "watch",
+        conflicts_with_all = &["cache", "source", "keys-from", "resume", "save-resume"],
+        long_help = r#"List the path again every --interval until interrupted with
+Ctrl-C. The first pass runs the command on every matched key,
+the next passes only on the keys which have appeared or changed
+(another ETag or modification time) since the previous pass.
+The summary is printed after every pass.
+
+The matched keys of the last pass are kept in memory"#
+    )]
+    pub watch: bool,
+
+    /// Time between the starts of the --watch passes, e.g. 30s, 5m or 1h
+    #[structopt(name = "interval", long = "interval", default_value = "60s")]
+    pub interval: Interval,
+
+    /// Run the command on the objects of the S3 event notifications received from the SQS queue
+    #[structopt(
+        name = "listen-sqs",
+        long = "listen-sqs",
+        value_name = "queue-url",
+        conflicts_with_all = &[
+            "watch",
+            "cache",
+            "source",
+            "keys-from",
+            "resume",
+            "save-resume",
+            "delimiter",
+            "list-concurrency",
+        ],
+        long_help = r#"Receive the S3 event notifications (ObjectCreated and
+ObjectRemoved) sent to the SQS queue, directly or through SNS,
+and run the command on the objects of the events matching the
+path and the filters, until interrupted with Ctrl-C or --limit
+keys are matched. The path is not listed.
+
+A message is deleted from the queue once the command has run on
+its objects. The objects of removed keys have no size, and an
+action on them fails as the key does not exist anymore"#
+    )]
+    pub listen_sqs: Option<QueueUrl>,
+
+    /// Keep running and walk the path at the times of the --schedule
+    #[structopt(
+        name = "daemon",
+        long = "daemon",
+        requires = "schedule",
+        conflicts_with_all = &[
+            "watch",
+            "listen-sqs",
+            "cache",
+            "resume",
+            "save-resume",
+        ],
+        long_help = r#"Keep running and walk the path at the times of the --schedule,
+until interrupted with Ctrl-C or SIGTERM, e.g. as a systemd
+service:
+    --daemon --schedule "0 3 * * *"
+
+Every run lists the path again and runs the command on every
+matched key. The start and the outcome of every run are logged
+at the info level, see --log-level and --log-file"#
+    )]
+    pub daemon: bool,
+
+    /// Times of the --daemon runs in cron syntax, in local time, e.g. "0 3 * * *"
+    #[structopt(
+        name = "schedule",
+        long = "schedule",
+        requires = "daemon",
+        long_help = r#"Times of the --daemon runs in cron syntax, in local time:
+minute hour day-of-month month day-of-week, e.g.:
+    "0 3 * * *"       every day at 03:00
+    "*/15 * * * *"    every 15 minutes
+    "30 6 * * 1-5"    at 06:30 from Monday to Friday
+
+A field is *, a value, a range a-b or a list a,b, with an
+optional step /n. Sunday is 0 or 7. A run due while the previous
+one is still running is skipped"#
+    )]
+    pub schedule: Option<Schedule>,
+
+    /// Number of matched objects buffered before they are passed to the command
+    #[structopt(name = "buffer-size", long = "buffer-size", default_value = "1000")]
+    pub buffer_size: usize,
+
+    /// Number of parallel listings, the keyspace is split by the common prefixes under the path.
+    /// With --keys-from, the number of parallel HeadObject requests
+    #[structopt(
+        name = "list-concurrency",
+        long = "list-concurrency",
+        default_value = "1"
+    )]
+    pub list_concurrency: usize,
+
+    /// Memory limit for the listed objects held at once, e.g. 512M
+    #[structopt(
+        name = "max-memory",
+        long = "max-memory",
+        long_help = r#"Memory limit for the listed objects held at once, e.g. 512M.
+Possible units are k, M, G and T (powers of 1024).
+
+The command buffer (--buffer-size) and the page size are lowered
+so that the buffered objects and the pages in flight (--pipeline,
+--list-concurrency) fit into the limit, estimating 2k per object.
+With --sort the keys not fitting into the limit are spilled to
+temporary files.
+
+Not covered by the limit: the row groups of the Parquet
+inventory files, and the state growing with the matched keys:
+the reports (duplicates, reconcile, replicas, cost,
+compliance, ...), snapshot save and the keys seen by --watch"#
+    )]
+    pub max_memory: Option<MemorySize>,
+
+    /// Number of pages listed ahead while the command runs, 0 lists only between command runs
+    #[structopt(name = "pipeline", long = "pipeline", default_value = "0")]
+    pub pipeline: usize,
+
+    /// Number of retries of S3 calls failed with throttling, server or network errors
+    #[structopt(name = "retries", long = "retries", default_value = "3")]
+    pub retries: usize,
+
+    /// Base delay of the exponential backoff between retries, in milliseconds
+    #[structopt(
+        name = "retry-base-delay",
+        long = "retry-base-delay",
+        default_value = "100"
+    )]
+    pub retry_base_delay: u64,
+
+    /// Maximum number of S3 requests per second, for listing and per-object calls
+    #[structopt(name = "rps", long = "rps")]
+    pub rps: Option<RequestRate>,
+
+    /// Format of the errors printed to stderr: text or json (one document per line)
+    #[structopt(name = "errors", long = "errors", default_value = "text")]
+    pub errors: ErrorFormat,
+
+    /// Color of the errors printed to stderr: auto (on a terminal, unless NO_COLOR is set), always or never
+    #[structopt(name = "color", long = "color", default_value = "auto")]
+    pub color: ColorChoice,
+
+    /// Keys under the prefix are never deleted or changed, e.g. backups/ (can be repeated)
+    #[structopt(
+        name = "protected-prefix",
+        long = "protected-prefix",
+        number_of_values = 1,
+        long_help = r#"Keys under the prefix are never deleted or changed, in every
+bucket of the run, e.g. --protected-prefix backups/. The
+matched keys under a protected prefix are skipped with a
+warning by delete, move, tags, public and transform without
+--destination. Can be repeated, usually set in the config file"#
+    )]
+    pub protected_prefix: Vec<String>,
+
+    /// Print listing throughput, request latency percentiles and download bandwidth to stderr
+    #[structopt(name = "benchmark", long = "benchmark")]
+    pub benchmark: bool,
+
+    /// Show a live progress line while listing, --progress=full for a progress bar with ETA
+    #[structopt(
+        name = "progress",
+        long = "progress",
+        min_values = 0,
+        require_equals = true,
+        long_help = r#"Show a live progress line while listing.
+
+With --progress=full the objects are counted first with a
+listing without filters (or by reading the cache or the
+inventory report), then an overall progress bar with ETA
+is shown"#
+    )]
+    pub progress: Option<Option<ProgressMode>>,
+
+    /// Log API calls and command executions: error, warn, info, debug or trace
+    #[structopt(name = "log-level", long = "log-level")]
+    pub log_level: Option<LogLevel>,
+
+    /// Publish run metrics: statsd://host:port or textfile:///path/to/file.prom
+    #[structopt(name = "metrics", long = "metrics")]
+    pub metrics: Option<MetricsSink>,
+
+    /// POST a JSON document with the outcome of every run to the URL, e.g. a chat webhook
+    #[structopt(
+        name = "notify-url",
+        long = "notify-url",
+        value_name = "url",
+        long_help = r#"POST a JSON document with the outcome of the run to the URL,
+after every pass with --watch and every run with --daemon:
+    {"paths": ["s3://bucket/prefix"], "status": "completed",
+     "matched": 12, "errors": [], "summary": {...}}
+
+The status is completed, failed or interrupted. The summary is
+added with --summarize and the matched keys with --notify-keys.
+A failed notification is reported as an error"#
+    )]
+    pub notify_url: Option<NotifyUrl>,
+
+    /// Add the matched keys to the --notify-url document, up to 1000
+    #[structopt(name = "notify-keys", long = "notify-keys", requires = "notify-url")]
+    pub notify_keys: bool,
+
+    /// Write log records to the file instead of stderr
+    #[structopt(name = "log-file", long = "log-file", parse(from_os_str))]
+    pub log_file: Option<PathBuf>,
+
     //  /// Action to be ran with matched list of paths
     #[structopt(subcommand)]
     pub cmd: Option<Cmd>,
@@ -155,6 +1012,14 @@ pub enum Cmd {
     #[structopt(name = "ls")]
     Ls(FastPrint),
 
+    /// Print matched keys using a custom format template
+    #[structopt(name = "printf")]
+    Printf(TemplatePrint),
+
+    /// Print the common prefixes at the level of the path, like a non-recursive ls
+    #[structopt(name = "ls-dirs")]
+    LsDirs(ListDirs),
+
     /// Print the list of matched keys with tags
     #[structopt(name = "lstags")]
     LsTags(ListTags),
@@ -167,110 +1032,891 @@ pub enum Cmd {
     #[structopt(name = "public")]
     Public(SetPublic),
 
+    /// Report the matched keys readable by anyone, through their ACL or the bucket policy
+    #[structopt(name = "audit-public")]
+    AuditPublic(AuditPublic),
+
+    /// Report the matched keys stored without server-side encryption, by prefix
+    #[structopt(name = "audit-unencrypted")]
+    AuditUnencrypted(AuditUnencrypted),
+
+    /// Write the matched keys with the requested fields to CSV, JSON or Parquet files of a directory, with a manifest
+    #[structopt(name = "export-inventory")]
+    ExportInventory(ExportInventory),
+
+    /// Write a sha256sum file of the matched keys, streaming their content
+    #[structopt(name = "manifest-checksums")]
+    ManifestChecksums(ManifestChecksums),
+
+    /// Download the matched keys, pipe them through a command or (un)compress them with gzip and upload the result
+    #[structopt(name = "transform")]
+    Transform(Transform),
+
+    /// Publish a message for every matched key, or digests of the keys, to an SNS topic
+    #[structopt(name = "publish-sns")]
+    PublishSns(PublishSns),
+
     /// Do not do anything with keys, do not print them as well
     #[structopt(name = "nothing")]
     Nothing(DoNothing),
+
+    /// Print the buckets of the account instead of keys, the path is not needed
+    #[structopt(name = "buckets")]
+    Buckets(ListBuckets),
+
+    /// Save the key, size, ETag and modification time of the matched keys, or compare two snapshots
+    #[structopt(name = "snapshot")]
+    Snapshot(Snapshot),
+
+    /// Print the transitions and expirations the lifecycle rules of the bucket would apply to the matched keys
+    #[structopt(name = "simulate-lifecycle")]
+    SimulateLifecycle(SimulateLifecycle),
+
+    /// Print a report of all the matched keys once they are listed
+    #[structopt(name = "report")]
+    Report(Report),
+
+    /// Print the man page in roff, e.g. s3find man > s3find.1, the path is not needed
+    #[structopt(name = "man")]
+    Man(ManPage),
 }
 
 impl Default for Cmd {
     fn default() -> Self {
-        Cmd::Ls(FastPrint {})
+        Cmd::Ls(FastPrint::default())
     }
 }
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct FastPrint {}
-
-#[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct AdvancedPrint {}
-
-#[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct MultipleDelete {}
+pub enum Report {
+    /// Evaluate the rules of a file on the matched keys, with the pass and fail counts per rule
+    #[structopt(name = "compliance")]
+    Compliance(ComplianceReport),
+
+    /// Group the matched keys by ETag and size, with the bytes reclaimable by deduplication
+    #[structopt(name = "duplicates")]
+    Duplicates(DuplicatesReport),
+
+    /// Recommend cheaper storage classes for the matched keys by age, with the monthly savings
+    #[structopt(name = "tiering")]
+    Tiering(TieringReport),
+
+    /// Monthly storage cost of the matched keys by prefix and storage class, with the cost of planned operations
+    #[structopt(name = "cost")]
+    Cost(CostReport),
+
+    /// Flag the matched keys with unsafe characters, trailing spaces, near the length limit or colliding by case
+    #[structopt(name = "key-hygiene")]
+    KeyHygiene(KeyHygieneReport),
+
+    /// Compare the matched keys with the keys of a destination path by relative key, size and ETag
+    #[structopt(name = "reconcile")]
+    Reconcile(ReconcileReport),
+
+    /// Merge the matched keys with their copies on replicated paths, with the replicas missing or stale
+    #[structopt(name = "replicas")]
+    Replicas(ReplicasReport),
+}
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct ListTags {}
+pub struct ComplianceReport {
+    #[structopt(
+        name = "rules",
+        long = "rules",
+        parse(from_os_str),
+        long_help = r#"Rules file in a subset of YAML, every rule has a name and a check:
+    rules:
+      - name: encrypted
+        check: encryption      # kms-key: <key ARN or id> to require a KMS key
+      - name: owner-tag
+        check: tags
+        keys: [owner, team]
+      - name: retention
+        check: max-age
+        age: 365d
+      - name: private
+        check: no-public-acl
+      - name: naming
+        check: naming
+        regex: '^[a-z0-9/._-]+$'"#
+    )]
+    pub rules: PathBuf,
 
-// region ?
-#[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct SetPublic {}
+    /// Format of the report
+    #[structopt(
+        name = "format",
+        long = "format",
+        default_value = "json",
+        possible_values = &["json", "html"]
+    )]
+    pub format: ReportFormat,
 
-#[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct Exec {
-    /// Utility(program) to run
-    #[structopt(name = "utility")]
-    pub utility: String,
-}
+    /// Write the report to the file instead of stdout
+    #[structopt(name = "output", long = "output", parse(from_os_str))]
+    pub output: Option<PathBuf>,
 
-#[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct Download {
-    /// Force download files(overwrite) even if the target files are already present
-    #[structopt(long = "force", short = "f")]
-    pub force: bool,
+    /// Failed keys listed in the report for every rule
+    #[structopt(
+        name = "max-failed-keys",
+        long = "max-failed-keys",
+        default_value = "100"
+    )]
+    pub max_failed_keys: usize,
 
-    /// Directory destination to download files to
-    #[structopt(name = "destination")]
-    pub destination: String,
+    /// Number of objects processed in parallel, for the rules on tags, encryption and ACL
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
 }
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct S3Copy {
-    /// S3 path destination to copy files to
+pub struct ReconcileReport {
+    /// S3 path the matched keys are compared with, its keys are filtered as the
+    /// matched keys are, e.g. s3://dest-bucket/prefix/
     #[structopt(name = "destination")]
     pub destination: S3path,
 
-    /// Copy keys like files
-    #[structopt(long = "flat", short = "f")]
-    pub flat: bool,
+    /// Do not report the destination keys without a matched key
+    #[structopt(long = "ignore-extra")]
+    pub ignore_extra: bool,
+
+    /// Print the aws s3 commands fixing the differences
+    #[structopt(long = "remediation")]
+    pub remediation: bool,
+
+    /// Print a JSON document per difference and for the totals instead of text
+    #[structopt(long = "json")]
+    pub json: bool,
 }
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct S3Move {
-    /// S3 path destination to copy files to
-    #[structopt(name = "destination")]
-    pub destination: S3path,
-
-    /// Copy keys like files
-    #[structopt(long = "flat", short = "f")]
-    pub flat: bool,
+pub struct ReplicasReport {
+    /// Replicated S3 path of the dataset, in any region, e.g. s3://bucket-eu/prefix/ (repeatable)
+    #[structopt(name = "also", long = "also", number_of_values = 1, required = true)]
+    pub also: Vec<S3path>,
+
+    /// Do not report the keys of the replicas without a matched key
+    #[structopt(long = "ignore-extra")]
+    pub ignore_extra: bool,
+
+    /// Print a JSON document per key and for the totals instead of text
+    #[structopt(long = "json")]
+    pub json: bool,
 }
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
-pub struct SetTags {
-    /// List of the tags to set
-    #[structopt(name = "key:value", min_values = 1)]
-    pub tags: Vec<FindTag>,
+pub struct DuplicatesReport {
+    /// Print the report as a JSON document instead of text
+    #[structopt(long = "json")]
+    pub json: bool,
+
+    /// Ignore the keys smaller than this size, e.g. 1M (the empty keys are always ignored)
+    #[structopt(name = "min-size", long = "min-size")]
+    pub min_size: Option<MemorySize>,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct TieringReport {
+    /// Age from which the keys are recommended for STANDARD_IA
+    #[structopt(name = "ia-after", long = "ia-after", default_value = "30d")]
+    pub ia_after: Interval,
+
+    /// Age from which the keys are recommended for GLACIER_IR
+    #[structopt(
+        name = "glacier-ir-after",
+        long = "glacier-ir-after",
+        default_value = "90d"
+    )]
+    pub glacier_ir_after: Interval,
+
+    /// Age from which the keys are recommended for DEEP_ARCHIVE
+    #[structopt(
+        name = "deep-archive-after",
+        long = "deep-archive-after",
+        default_value = "180d"
+    )]
+    pub deep_archive_after: Interval,
+
+    /// Print JSON documents instead of text
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct CostReport {
+    /// Levels of the prefixes grouping the keys below the path, 0 for a single group
+    #[structopt(long = "depth", default_value = "1")]
+    pub depth: usize,
+
+    #[structopt(
+        name = "operation",
+        long = "operation",
+        number_of_values = 1,
+        possible_values = &["download", "copy", "restore-expedited", "restore-standard", "restore-bulk"],
+        long_help = r#"Estimate the cost of an operation on the matched keys, can be repeated:
+    download          - GET requests, retrieval and transfer out to the internet
+    copy              - GET and COPY requests and retrieval, in the region
+    restore-expedited - restore of the GLACIER keys with the Expedited tier
+    restore-standard  - restore of the GLACIER and DEEP_ARCHIVE keys with the Standard tier
+    restore-bulk      - restore of the GLACIER and DEEP_ARCHIVE keys with the Bulk tier
+
+The request, retrieval and transfer prices are the us-east-1 ones"#
+    )]
+    pub operations: Vec<PlannedOperation>,
+
+    /// Print JSON documents instead of a table
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct KeyHygieneReport {
+    /// Flag the keys of at least this many UTF-8 bytes, S3 keys are limited to 1024
+    #[structopt(name = "max-length", long = "max-length", default_value = "900")]
+    pub max_length: usize,
+
+    /// Do not flag the keys differing only by case, the keys are not kept in memory
+    #[structopt(name = "no-case-collisions", long = "no-case-collisions")]
+    pub no_case_collisions: bool,
+
+    /// Print JSON documents instead of text
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub enum Snapshot {
+    /// Save the key, size, ETag and modification time of the matched keys to the file
+    #[structopt(name = "save")]
+    Save(SnapshotSave),
+
+    /// Print the keys added, removed and changed between two snapshots, the path is not needed
+    #[structopt(name = "diff")]
+    Diff(SnapshotDiff),
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct SnapshotSave {
+    /// Snapshot file, replaced when it exists
+    #[structopt(name = "file", parse(from_os_str))]
+    pub file: PathBuf,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct SnapshotDiff {
+    /// Snapshot of the earlier run
+    #[structopt(name = "old", parse(from_os_str))]
+    pub old: PathBuf,
+
+    /// Snapshot of the later run
+    #[structopt(name = "new", parse(from_os_str))]
+    pub new: PathBuf,
+
+    /// Print JSON lines instead of tab-separated columns
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone, Default)]
+pub struct ListBuckets {
+    /// Glob pattern for the bucket name, can be multiple
+    #[structopt(long = "name", number_of_values = 1)]
+    pub name: Vec<NameGlob>,
+
+    /// Regex pattern for the bucket name, can be multiple
+    #[structopt(long = "regex", number_of_values = 1)]
+    pub regex: Vec<BucketRegex>,
+
+    /// Creation time for match, e.g. +30d for buckets created more than 30 days ago
+    #[structopt(long = "created", number_of_values = 1)]
+    pub created: Vec<FindTime>,
+
+    /// Region of the bucket for match, can be multiple
+    #[structopt(long = "region", number_of_values = 1)]
+    pub region: Vec<String>,
+
+    /// Print the default encryption of the bucket
+    #[structopt(long = "encryption")]
+    pub encryption: bool,
+
+    /// Print the versioning status of the bucket
+    #[structopt(long = "versioning")]
+    pub versioning: bool,
+
+    /// Print the public access block of the bucket: blocked, partial or none
+    #[structopt(long = "public-access-block")]
+    pub public_access_block: bool,
+
+    /// Print JSON lines instead of tab-separated columns
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+/// Regex compared by its pattern, the options of the subcommands are comparable
+#[derive(Debug, Clone)]
+pub struct BucketRegex(pub Regex);
+
+impl PartialEq for BucketRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl FromStr for BucketRegex {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s).map(BucketRegex)
+    }
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone, Default)]
+pub struct KeyFormat {
+    /// Print keys relative to the search prefix, without the s3://bucket/ part
+    #[structopt(long = "relative")]
+    pub relative: bool,
+
+    /// Percent-encode printed keys, safe for line-oriented processing
+    #[structopt(long = "encode-keys")]
+    pub encode_keys: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone, Default)]
+pub struct FastPrint {
+    #[structopt(flatten)]
+    pub key_format: KeyFormat,
+}
+
+/// Delimiter is "/" unless set with --delimiter
+#[derive(StructOpt, Debug, PartialEq, Clone, Default)]
+pub struct ListDirs {
+    #[structopt(flatten)]
+    pub key_format: KeyFormat,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct AdvancedPrint {
+    /// Print sizes in human readable format (e.g. 1.4 GB)
+    #[structopt(long = "human-readable")]
+    pub human_readable: bool,
+
+    /// Modification time format: local, iso, full or relative
+    #[structopt(
+        name = "time-style",
+        long = "time-style",
+        long_help = r#"Modification time format:
+    local    - local time, e.g. 2019-11-03 14:05
+    iso      - UTC time in RFC 3339, e.g. 2019-11-03T12:05:11Z
+    full     - local time with seconds and offset, e.g. 2019-11-03 14:05:11.000 +0200
+    relative - time passed since the modification, e.g. 3 days ago
+
+By default the time is printed as returned by the S3 API"#
+    )]
+    pub time_style: Option<TimeStyle>,
+
+    /// Output format: text, table (column-aligned), json (one document per line) or csv
+    #[structopt(name = "format", long = "format", default_value = "text")]
+    pub format: PrintFormat,
+
+    /// Fetch additional data for every key: tags, head, acl. Only fetched and printed by the json and csv formats
+    #[structopt(name = "enrich", long = "enrich", use_delimiter = true)]
+    pub enrich: Vec<EnrichField>,
+
+    /// Print the version id of every key (fetched with HeadObject, "null" for unversioned keys)
+    #[structopt(long = "show-version-id")]
+    pub show_version_id: bool,
+
+    /// Number of objects enriched in parallel, the output keeps the listing order
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+
+    #[structopt(flatten)]
+    pub key_format: KeyFormat,
+
+    #[structopt(skip)]
+    pub header_printed: Cell<bool>,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct TemplatePrint {
+    /// Format template
+    #[structopt(
+        name = "format",
+        long_help = r#"Format template, printed for every matched key:
+    %p - full path, s3://bucket/key
+    %P - key relative to the search prefix
+    %k - key
+    %b - bucket
+    %s - size in bytes
+    %h - size in human readable format
+    %T - modification time
+    %e - etag
+    %S - storage class
+    %o - owner display name or ID, listed with --fetch-owner
+    %% - literal %
+
+Escapes \t, \n and \\ are supported, no newline is added automatically"#
+    )]
+    pub format: FormatTemplate,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct MultipleDelete {
+    /// Do not print per-key output, only the summary and errors
+    #[structopt(long = "quiet", short = "q")]
+    pub quiet: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct ListTags {
+    /// Number of objects processed in parallel, the output keeps the listing order
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+}
+
+// region ?
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct SetPublic {
+    /// Number of objects processed in parallel, the output keeps the listing order
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+
+    /// Print the URLs under this base instead of the S3 endpoint, e.g. https://cdn.example.com/
+    #[structopt(name = "url-base", long = "url-base")]
+    pub url_base: Option<String>,
+
+    /// Write the URLs to the file as well, one per line, replaced when it exists
+    #[structopt(name = "urls-to", long = "urls-to", parse(from_os_str))]
+    pub urls_to: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct AuditPublic {
+    /// Number of objects processed in parallel, the output keeps the listing order
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+
+    #[structopt(
+        name = "min-severity",
+        long = "min-severity",
+        default_value = "low",
+        possible_values = &["low", "medium", "high", "critical"],
+        long_help = r#"Lowest severity of the reported keys:
+    critical - the ACL lets everyone read the key
+    high     - the bucket policy is public, or the ACL lets everyone read
+               or change the ACL of the key
+    medium   - the ACL grants the key to every authenticated AWS account
+    low      - a public grant or policy is neutralized by the public
+               access block of the bucket"#
+    )]
+    pub min_severity: Severity,
+
+    /// Print a JSON document per key instead of text
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct AuditUnencrypted {
+    /// Number of objects processed in parallel with HeadObject
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+
+    /// Flag the keys not encrypted with this KMS key (key ARN or key id, not an alias)
+    #[structopt(name = "kms-key", long = "kms-key")]
+    pub kms_key: Option<String>,
+
+    /// Levels of the prefixes grouping the keys below the path, 0 for a single group
+    #[structopt(long = "depth", default_value = "1")]
+    pub depth: usize,
+
+    /// Print a JSON document per prefix instead of text
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct SimulateLifecycle {
+    /// Number of objects processed in parallel, for the tags of the rules filtering on tags
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+
+    /// Print the totals per rule only, not every key
+    #[structopt(long = "totals")]
+    pub totals: bool,
+
+    /// Print JSON documents instead of text
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct ExportInventory {
+    /// Directory of the files and of manifest.json, created when missing
+    #[structopt(name = "dir", parse(from_os_str))]
+    pub directory: PathBuf,
+
+    /// Format of the files: csv (with a header), json (one document per line) or parquet
+    #[structopt(
+        name = "format",
+        long = "format",
+        default_value = "csv",
+        possible_values = &["csv", "json", "parquet"],
+        long_help = r#"Format of the files: csv (with a header), json (one document
+per line) or parquet. The Parquet files are compressed with
+Snappy, the size is an int64 column and the other fields are
+string columns, the tags, metadata and acl as JSON documents"#
+    )]
+    pub format: ExportFormat,
+
+    /// Fields of the keys: bucket, key, size, etag, storage_class, last_modified, tags, content_type, encryption, metadata, acl
+    #[structopt(
+        name = "fields",
+        long = "fields",
+        use_delimiter = true,
+        default_value = "key,size,etag,storage_class,last_modified"
+    )]
+    pub fields: Vec<InventoryField>,
+
+    /// Number of keys per file
+    #[structopt(
+        name = "rows-per-file",
+        long = "rows-per-file",
+        default_value = "1000000"
+    )]
+    pub rows_per_file: usize,
+
+    /// Number of objects processed in parallel, for the tags, HeadObject and ACL fields
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct ManifestChecksums {
+    /// Checksum file, e.g. out.sha256, replaced when it exists
+    #[structopt(name = "file", parse(from_os_str))]
+    pub file: PathBuf,
+
+    /// Number of objects hashed in parallel, the file keeps the listing order
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct Transform {
+    /// Shell command the content of every key is piped through, e.g. 'sed s/foo/bar/'
+    #[structopt(
+        name = "exec-filter",
+        long = "exec-filter",
+        required_unless_one = &["gunzip", "gzip", "recompress"],
+        conflicts_with_all = &["gunzip", "gzip", "recompress"]
+    )]
+    pub exec_filter: Option<String>,
+
+    /// Decompress the gzip content of the keys, the gzip Content-Encoding is removed
+    #[structopt(name = "gunzip", long = "gunzip", conflicts_with_all = &["gzip", "recompress"])]
+    pub gunzip: bool,
+
+    /// Compress the content of the keys with gzip, the Content-Encoding is set to gzip
+    #[structopt(name = "gzip", long = "gzip", conflicts_with = "recompress")]
+    pub gzip: bool,
+
+    /// Decompress the gzip content of the keys and compress it again at --level
+    #[structopt(name = "recompress", long = "recompress")]
+    pub recompress: bool,
+
+    /// Compression level of --gzip and --recompress, from 1 (fastest) to 9 (smallest)
+    #[structopt(
+        name = "level",
+        long = "level",
+        default_value = "9",
+        possible_values = &["1", "2", "3", "4", "5", "6", "7", "8", "9"]
+    )]
+    pub level: u32,
+
+    /// S3 path the results are uploaded to, the keys are replaced by default
+    #[structopt(
+        name = "destination",
+        long = "destination",
+        long_help = r#"S3 path the results are uploaded to, the keys are replaced by
+default. The uploads keep the headers, the user metadata and
+the tags of the keys. The ACL is not carried over: the results
+get the default ACL of the bucket"#
+    )]
+    pub destination: Option<S3path>,
+
+    /// Upload the results under --destination like files
+    #[structopt(long = "flat", short = "f", requires = "destination")]
+    pub flat: bool,
+
+    /// Transform the keys and print the size changes, but do not upload anything
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Number of objects processed in parallel, the output keeps the listing order
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+
+    /// Do not print per-key output, only the summary and errors
+    #[structopt(long = "quiet", short = "q")]
+    pub quiet: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct PublishSns {
+    /// ARN of the topic, e.g. arn:aws:sns:us-east-1:123456789012:new-objects
+    #[structopt(name = "topic-arn")]
+    pub topic: TopicArn,
+
+    /// Publish a message with up to 100 keys instead of a message per key
+    #[structopt(long = "digest")]
+    pub digest: bool,
+
+    /// Do not print per-key output, only the summary and errors
+    #[structopt(long = "quiet", short = "q")]
+    pub quiet: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct Exec {
+    /// Utility(program) to run
+    #[structopt(name = "utility")]
+    pub utility: String,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct Download {
+    /// Force download files(overwrite) even if the target files are already present
+    #[structopt(long = "force", short = "f")]
+    pub force: bool,
+
+    /// Directory destination to download files to
+    #[structopt(name = "destination")]
+    pub destination: String,
+
+    /// Number of objects downloaded in parallel
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+
+    /// Do not print per-key output, only the summary and errors
+    #[structopt(long = "quiet", short = "q")]
+    pub quiet: bool,
+
+    /// Append the key and ETag of every downloaded object to the file
+    #[structopt(long = "manifest", parse(from_os_str))]
+    pub manifest: Option<PathBuf>,
+
+    /// Skip the objects recorded with --manifest, unless their ETag has changed
+    #[structopt(long = "resume-from", parse(from_os_str))]
+    pub resume_from: Option<PathBuf>,
+
+    /// Print the number of objects, bytes, transfer time and egress cost of the
+    /// download and ask to proceed before downloading
+    #[structopt(long = "estimate")]
+    pub estimate: bool,
+
+    /// Bandwidth of the transfer time of --estimate, like 100Mbit or 20MB (per second)
+    #[structopt(long = "bandwidth", default_value = "100Mbit")]
+    pub bandwidth: Bandwidth,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct S3Copy {
+    /// S3 path destination to copy files to
+    #[structopt(name = "destination")]
+    pub destination: S3path,
+
+    /// Copy keys like files
+    #[structopt(long = "flat", short = "f")]
+    pub flat: bool,
+
+    /// Do not print per-key output, only the summary and errors
+    #[structopt(long = "quiet", short = "q")]
+    pub quiet: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct S3Move {
+    /// S3 path destination to copy files to
+    #[structopt(name = "destination")]
+    pub destination: S3path,
+
+    /// Copy keys like files
+    #[structopt(long = "flat", short = "f")]
+    pub flat: bool,
+
+    /// Do not print per-key output, only the summary and errors
+    #[structopt(long = "quiet", short = "q")]
+    pub quiet: bool,
+}
+
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct SetTags {
+    /// List of the tags to set
+    #[structopt(name = "key:value", min_values = 1)]
+    pub tags: Vec<FindTag>,
+
+    /// Number of objects processed in parallel, the output keeps the listing order
+    #[structopt(long = "concurrency", default_value = "1")]
+    pub concurrency: usize,
+
+    /// Do not print per-key output, only the summary and errors
+    #[structopt(long = "quiet", short = "q")]
+    pub quiet: bool,
 }
 
 #[derive(StructOpt, Debug, PartialEq, Clone)]
 pub struct DoNothing {}
 
-#[derive(Fail, Debug)]
+#[derive(StructOpt, Debug, PartialEq, Clone)]
+pub struct ManPage {}
+
+#[derive(thiserror::Error, Debug)]
 pub enum FindError {
-    #[fail(display = "Invalid s3 path")]
+    #[error("Invalid s3 path")]
     S3Parse,
-    #[fail(display = "Invalid size parameter")]
+    #[error("Invalid size parameter")]
     SizeParse,
-    #[fail(display = "Invalid mtime parameter")]
+    #[error("Invalid mtime parameter")]
     TimeParse,
-    #[fail(display = "Cannot parse tag")]
+    #[error("Cannot parse tag")]
     TagParseError,
-    #[fail(display = "Cannot parse tag key")]
+    #[error("Cannot parse tag key")]
     TagKeyParseError,
-    #[fail(display = "Cannot parse tag value")]
+    #[error("Cannot parse tag value")]
     TagValueParseError,
+    #[error("Invalid storage price parameter")]
+    StoragePriceParse,
+    #[error("Invalid time style parameter")]
+    TimeStyleParse,
+    #[error("Invalid severity parameter")]
+    SeverityParse,
+    #[error("Invalid report format parameter")]
+    ReportFormatParse,
+    #[error("Invalid operation parameter")]
+    OperationParse,
+    #[error("Invalid output format parameter")]
+    FormatParse,
+    #[error("Invalid format template")]
+    TemplateParse,
+    #[error("Invalid enrich field, possible values: tags, head, acl")]
+    EnrichParse,
+    #[error(
+        "Invalid inventory field, possible values: bucket, key, size, etag, storage_class, last_modified, tags, content_type, encryption, metadata, acl"
+    )]
+    InventoryFieldParse,
+    #[error("Invalid export format parameter")]
+    ExportFormatParse,
+    #[error("Invalid sort field, possible values: key, size, mtime")]
+    SortFieldParse,
+    #[error("Invalid error format parameter")]
+    ErrorFormatParse,
+    #[error("Invalid color parameter, possible values: auto, always, never")]
+    ColorParse,
+    #[error("Invalid list API, possible values: auto, v1, v2")]
+    ListApiParse,
+    #[error("Invalid log level, possible values: error, warn, info, debug, trace")]
+    LogLevelParse,
+    #[error("Invalid metrics sink, expected statsd://host:port or textfile:///path")]
+    MetricsParse,
+    #[error("Invalid request rate, expected a positive number")]
+    RequestRateParse,
+    #[error("Invalid bandwidth, expected a rate like 100Mbit or 20MB")]
+    BandwidthParse,
+    #[error("Invalid object source, expected: inventory s3://bucket/path/manifest.json")]
+    SourceParse,
+    #[error("Invalid page size, expected a positive number or auto")]
+    PageSizeParse,
+    #[error("Invalid size, expected a size like 512M")]
+    MemorySizeParse,
+    #[error("Invalid progress mode, possible values: line, full")]
+    ProgressModeParse,
+    #[error("Invalid endpoint URL, expected: http(s)://host[:port]")]
+    EndpointUrlParse,
+    #[error("Invalid interval, expected a duration like 30s, 5m or 1h")]
+    IntervalParse,
+    #[error("Invalid queue URL, expected: https://sqs.region.amazonaws.com/account/queue")]
+    QueueUrlParse,
+    #[error("Invalid notification URL, expected: http(s)://host/path")]
+    NotifyUrlParse,
+    #[error(
+        "Invalid filter, expected <filter>=<value> with a filter among name, iname, regex, mtime, size, etag, owner"
+    )]
+    FilterParse,
+    #[error("Invalid filter expression: {0}")]
+    ExprParse(String),
+    #[error("Invalid date, expected a date like 2023-06-01 or 2023-06-01T12:00:00Z")]
+    DateParse,
+    #[error("Invalid reference, expected s3://bucket/key or a local file")]
+    NewerParse,
+    #[error("Invalid topic ARN, expected: arn:aws:sns:region:account:topic")]
+    TopicArnParse,
+    #[error("Invalid schedule, expected a cron expression like \"0 3 * * *\"")]
+    ScheduleParse,
 }
 
+/// Bucket and key prefix of s3://bucket/prefix
 #[derive(Debug, Clone, PartialEq)]
 pub struct S3path {
     pub bucket: String,
     pub prefix: Option<String>,
 }
 
+impl S3path {
+    /// The bucket is a glob pattern, e.g. s3://logs-*-prod/, expanded with ListBuckets
+    pub fn has_bucket_pattern(&self) -> bool {
+        self.bucket.contains(&['*', '?', '['][..])
+    }
+
+    /// A path for each bucket matching the pattern of the bucket
+    pub fn expand(&self, buckets: &[String]) -> Vec<S3path> {
+        let pattern = match Pattern::new(&self.bucket) {
+            Ok(pattern) => pattern,
+            Err(_) => return Vec::new(),
+        };
+        buckets
+            .iter()
+            .filter(|x| pattern.matches(x))
+            .map(|bucket| S3path {
+                bucket: bucket.clone(),
+                prefix: self.prefix.clone(),
+            })
+            .collect()
+    }
+
+    /// Value of x-amz-copy-source for a key of the path
+    pub fn copy_source(&self, key: &str) -> String {
+        if self.bucket.starts_with("arn:") {
+            format!("{}/object/{}", self.bucket, key)
+        } else {
+            format!("{}/{}", self.bucket, key)
+        }
+    }
+
+    /// Key relative to the prefix of the path, keys outside of the prefix are kept as is
+    pub fn relative_key<'a>(&self, key: &'a str) -> &'a str {
+        let prefix = self.prefix.as_deref().unwrap_or("");
+        match key.strip_prefix(prefix) {
+            Some(relative) => relative.trim_start_matches('/'),
+            None => key,
+        }
+    }
+}
+
 impl FromStr for S3path {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Error> {
-        let regex = Regex::new(r#"s3://([\d\w _-]+)(/([\d\w/ _-]*))?"#)?;
+        // an access point ARN (s3 or s3-object-lambda) in place of the bucket
+        let regex = Regex::new(
+            r#"^s3://(arn:[\w-]+:s3(-object-lambda)?:[\w-]+:\d{12}:accesspoint)[/:]([\w-]+)(/([\d\w/ _-]*))?"#,
+        )?;
+        if let Some(captures) = regex.captures(s) {
+            let bucket = format!("{}/{}", &captures[1], &captures[3]);
+            let prefix = captures.get(5).map(|x| x.as_str().to_owned());
+            return Ok(S3path { bucket, prefix });
+        }
+
+        let regex = Regex::new(r#"s3://([\d\w _*?\[\]!-]+)(/([\d\w/ _-]*))?"#)?;
         let captures = regex.captures(s).ok_or(FindError::S3Parse)?;
 
         let bucket = captures
@@ -279,7 +1925,11 @@ impl FromStr for S3path {
             .ok_or(FindError::S3Parse)?;
         let prefix = captures.get(3).map(|x| x.as_str().to_owned());
 
-        Ok(S3path { bucket, prefix })
+        let path = S3path { bucket, prefix };
+        if path.has_bucket_pattern() {
+            Pattern::new(&path.bucket).map_err(|_| FindError::S3Parse)?;
+        }
+        Ok(path)
     }
 }
 
@@ -290,126 +1940,1341 @@ pub enum FindSize {
     Lower(i64),
 }
 
-impl FromStr for FindSize {
+impl FromStr for FindSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let re = Regex::new(r"([+-]?)(\d*)([kMGTP]?)$")?;
+        let m = re.captures(s).ok_or(FindError::SizeParse)?;
+
+        let sign = m
+            .get(1)
+            .ok_or(FindError::SizeParse)?
+            .as_str()
+            .chars()
+            .next();
+        let number: i64 = m.get(2).ok_or(FindError::SizeParse)?.as_str().parse()?;
+        let metric = m
+            .get(3)
+            .ok_or(FindError::SizeParse)?
+            .as_str()
+            .chars()
+            .next();
+
+        let bytes = match metric {
+            None => number,
+            Some('k') => number * 1024,
+            Some('M') => number * 1024_i64.pow(2),
+            Some('G') => number * 1024_i64.pow(3),
+            Some('T') => number * 1024_i64.pow(4),
+            Some('P') => number * 1024_i64.pow(5),
+            Some(_) => return Err(FindError::SizeParse.into()),
+        };
+
+        match sign {
+            Some('+') => Ok(FindSize::Bigger(bytes)),
+            Some('-') => Ok(FindSize::Lower(bytes)),
+            None => Ok(FindSize::Equal(bytes)),
+            Some(_) => Err(FindError::SizeParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FindTime {
+    Upper(i64),
+    Lower(i64),
+    /// Modified before the time of --mtime-before, in seconds since the epoch
+    Before(i64),
+    /// Modified at or after the time of --mtime-after, in seconds since the epoch
+    After(i64),
+}
+
+impl FromStr for FindTime {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let re = Regex::new(r"([+-]?)(\d*)([smhdw]?)$")?;
+        let m = re.captures(s).ok_or(FindError::TimeParse)?;
+
+        let sign = m
+            .get(1)
+            .ok_or(FindError::TimeParse)?
+            .as_str()
+            .chars()
+            .next();
+        let number: i64 = m.get(2).ok_or(FindError::TimeParse)?.as_str().parse()?;
+        let metric = m
+            .get(3)
+            .ok_or(FindError::TimeParse)?
+            .as_str()
+            .chars()
+            .next();
+
+        let seconds = match metric {
+            None => number,
+            Some('s') => number,
+            Some('m') => number * 60,
+            Some('h') => number * 3600,
+            Some('d') => number * 3600 * 24,
+            Some('w') => number * 3600 * 24 * 7,
+            Some(_) => return Err(FindError::TimeParse.into()),
+        };
+
+        match sign {
+            Some('+') => Ok(FindTime::Upper(seconds)),
+            Some('-') => Ok(FindTime::Lower(seconds)),
+            None => Ok(FindTime::Upper(seconds)),
+            Some(_) => Err(FindError::TimeParse.into()),
+        }
+    }
+}
+
+/// Time of --mtime-before and --mtime-after in seconds since the epoch, from
+/// a date or an RFC 3339 time, UTC without an offset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FindDate(pub i64);
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+impl FromStr for FindDate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let re = Regex::new(
+            r"^(\d{4})-(\d{2})-(\d{2})(?:[Tt ](\d{2}):(\d{2})(?::(\d{2})(?:\.\d+)?)?)?(?:([Zz])|([+-])(\d{2}):?(\d{2}))?$",
+        )?;
+        let m = re.captures(s.trim()).ok_or(FindError::DateParse)?;
+        let number = |i: usize| -> Result<i64, Error> {
+            Ok(match m.get(i) {
+                Some(x) => x.as_str().parse()?,
+                None => 0,
+            })
+        };
+
+        let (year, month, day) = (number(1)?, number(2)?, number(3)?);
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let month_days = match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+        let (hour, minute, second) = (number(4)?, number(5)?, number(6)?);
+        if !(1..=12).contains(&month)
+            || !(1..=month_days).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 59
+        {
+            return Err(FindError::DateParse.into());
+        }
+
+        let offset = (number(9)? * 60 + number(10)?) * 60;
+        let offset = match m.get(8).map(|x| x.as_str()) {
+            Some("-") => -offset,
+            _ => offset,
+        };
+        Ok(FindDate(
+            days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+                - offset,
+        ))
+    }
+}
+
+/// Reference of --newer, an S3 object or a local file
+#[derive(Debug, Clone, PartialEq)]
+pub enum NewerRef {
+    Object(S3path),
+    File(PathBuf),
+}
+
+impl FromStr for NewerRef {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        if !s.starts_with("s3://") {
+            return Ok(NewerRef::File(PathBuf::from(s)));
+        }
+        let path: S3path = s.parse().map_err(|_| FindError::NewerParse)?;
+        // the key is taken verbatim, the path parsing stops at the characters
+        // of the key patterns; an access point ARN has a / before its name
+        let rest = &s["s3://".len()..];
+        let parts = if rest.contains(":accesspoint/") { 3 } else { 2 };
+        let key = rest.splitn(parts, '/').nth(parts - 1).unwrap_or_default();
+        if key.is_empty() || key.ends_with('/') || path.has_bucket_pattern() {
+            return Err(FindError::NewerParse.into());
+        }
+        Ok(NewerRef::Object(S3path {
+            bucket: path.bucket,
+            prefix: Some(key.to_owned()),
+        }))
+    }
+}
+
+pub type NameGlob = Pattern;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InameGlob(pub Pattern);
+
+impl FromStr for InameGlob {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let pattern = Pattern::from_str(s)?;
+        Ok(InameGlob(pattern))
+    }
+}
+
+/// ETag of --etag, without the quotes of the ETags of the listings
+#[derive(Debug, Clone, PartialEq)]
+pub struct FindETag(pub String);
+
+impl FindETag {
+    pub fn matches(&self, e_tag: &str) -> bool {
+        e_tag.trim_matches('"').eq_ignore_ascii_case(&self.0)
+    }
+}
+
+impl FromStr for FindETag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(FindETag(s.trim().trim_matches('"').to_owned()))
+    }
+}
+
+/// Filters of --any-of and tests of --expr
+const FILTER_NAMES: [&str; 7] = ["name", "iname", "regex", "mtime", "size", "etag", "owner"];
+
+/// A filter of the listed data with its value, an item of --any-of
+#[derive(Debug, Clone)]
+pub enum FilterAtom {
+    Name(NameGlob),
+    Iname(InameGlob),
+    Regex(Regex),
+    Mtime(FindTime),
+    Size(FindSize),
+    ETag(FindETag),
+    Owner(String),
+}
+
+impl FilterAtom {
+    /// The filter of an option name without the dashes, e.g. name and *.log
+    pub fn new(filter: &str, value: &str) -> Result<Self, Error> {
+        Ok(match filter {
+            "name" => FilterAtom::Name(value.parse()?),
+            "iname" => FilterAtom::Iname(value.parse()?),
+            "regex" => FilterAtom::Regex(value.parse()?),
+            "mtime" => FilterAtom::Mtime(value.parse()?),
+            "size" => FilterAtom::Size(value.parse()?),
+            "etag" => FilterAtom::ETag(value.parse()?),
+            "owner" => FilterAtom::Owner(value.to_owned()),
+            _ => return Err(FindError::FilterParse.into()),
+        })
+    }
+
+    /// The filter needs the size, modification time or ETag, missing from
+    /// the keys of --keys-from without HeadObject
+    pub fn uses_head(&self) -> bool {
+        matches!(
+            self,
+            FilterAtom::Mtime(_) | FilterAtom::Size(_) | FilterAtom::ETag(_)
+        )
+    }
+
+    /// The filter needs the owner of the keys in the listings
+    pub fn uses_owner(&self) -> bool {
+        matches!(self, FilterAtom::Owner(_))
+    }
+}
+
+/// Filters of --any-of, a key matches when any of them matches
+#[derive(Debug, Clone)]
+pub struct AnyOf(pub Vec<FilterAtom>);
+
+impl FromStr for AnyOf {
+    type Err = Error;
+
+    /// Comma-separated <filter>=<value> items, a comma of a value is escaped
+    /// with a backslash
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut items = vec![String::new()];
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            let item = items.last_mut().ok_or(FindError::FilterParse)?;
+            match (c, chars.clone().next()) {
+                ('\\', Some(',')) => {
+                    item.push(',');
+                    chars.next();
+                }
+                (',', _) => items.push(String::new()),
+                (c, _) => item.push(c),
+            }
+        }
+
+        items
+            .iter()
+            .map(|item| {
+                let mut parts = item.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(filter), Some(value)) => FilterAtom::new(filter.trim(), value),
+                    _ => Err(FindError::FilterParse.into()),
+                }
+            })
+            .collect::<Result<_, Error>>()
+            .map(AnyOf)
+    }
+}
+
+/// Filter expression of --expr, in the syntax of the GNU find tests
+#[derive(Debug, Clone)]
+pub enum FilterExpr {
+    Atom(FilterAtom),
+    Not(Box<FilterExpr>),
+    And(Vec<FilterExpr>),
+    Or(Vec<FilterExpr>),
+}
+
+impl FilterExpr {
+    /// The filters of the expression
+    pub fn atoms(&self) -> Vec<&FilterAtom> {
+        match self {
+            FilterExpr::Atom(atom) => vec![atom],
+            FilterExpr::Not(expr) => expr.atoms(),
+            FilterExpr::And(exprs) | FilterExpr::Or(exprs) => {
+                exprs.iter().flat_map(FilterExpr::atoms).collect()
+            }
+        }
+    }
+}
+
+/// Words of an expression split like a shell does: on whitespace, with
+/// quotes and backslash escapes
+fn expr_words(s: &str) -> Result<Vec<String>, Error> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' | '"' => {
+                let value = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(x) if x == c => break,
+                        Some('\\') if c == '"' => value.extend(chars.next()),
+                        Some(x) => value.push(x),
+                        None => {
+                            return Err(FindError::ExprParse("unclosed quote".to_owned()).into())
+                        }
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Recursive descent parser of the expressions: -o binds looser than -a,
+/// which may be left out, and ! binds tightest
+struct ExprParser {
+    words: Vec<String>,
+    position: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<&str> {
+        self.words.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let word = self.words.get(self.position).cloned();
+        self.position += 1;
+        word
+    }
+
+    fn or(&mut self) -> Result<FilterExpr, Error> {
+        let mut exprs = vec![self.and()?];
+        while let Some("-o") | Some("-or") = self.peek() {
+            self.next();
+            exprs.push(self.and()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            FilterExpr::Or(exprs)
+        })
+    }
+
+    fn and(&mut self) -> Result<FilterExpr, Error> {
+        let mut exprs = vec![self.not()?];
+        loop {
+            match self.peek() {
+                Some("-a") | Some("-and") => {
+                    self.next();
+                }
+                None | Some("-o") | Some("-or") | Some(")") => break,
+                _ => (),
+            }
+            exprs.push(self.not()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            FilterExpr::And(exprs)
+        })
+    }
+
+    fn not(&mut self) -> Result<FilterExpr, Error> {
+        match self.peek() {
+            Some("!") | Some("-not") => {
+                self.next();
+                Ok(FilterExpr::Not(Box::new(self.not()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<FilterExpr, Error> {
+        let word = self
+            .next()
+            .ok_or_else(|| FindError::ExprParse("expected a test".to_owned()))?;
+        if word == "(" {
+            let expr = self.or()?;
+            return match self.next().as_deref() {
+                Some(")") => Ok(expr),
+                _ => Err(FindError::ExprParse("expected )".to_owned()).into()),
+            };
+        }
+
+        let test = match word.strip_prefix('-') {
+            Some(test) if FILTER_NAMES.contains(&test) => test,
+            Some(_) => return Err(FindError::ExprParse(format!("unknown test {}", word)).into()),
+            None => return Err(FindError::ExprParse(format!("unexpected {}", word)).into()),
+        };
+        let value = self
+            .next()
+            .ok_or_else(|| FindError::ExprParse(format!("missing the value of {}", word)))?;
+        // as find, a modification time without a unit is in days
+        let value = match test {
+            "mtime" if value.ends_with(|c: char| c.is_ascii_digit()) => format!("{}d", value),
+            _ => value,
+        };
+        FilterAtom::new(test, &value)
+            .map(FilterExpr::Atom)
+            .map_err(|e| FindError::ExprParse(format!("{} {}: {}", word, value, e)).into())
+    }
+}
+
+impl FromStr for FilterExpr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut parser = ExprParser {
+            words: expr_words(s)?,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        match parser.next() {
+            None => Ok(expr),
+            Some(word) => Err(FindError::ExprParse(format!("unexpected {}", word)).into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FindTag {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for FindTag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let re = Regex::new(r"(\w+):(\w+)$")?;
+        let m = re.captures(s).ok_or(FindError::TagParseError)?;
+
+        let key = m.get(1).ok_or(FindError::TagKeyParseError)?.as_str();
+        let value = m.get(2).ok_or(FindError::TagValueParseError)?.as_str();
+
+        Ok(FindTag {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct StoragePrice {
+    pub class: String,
+    pub price: f64,
+}
+
+impl FromStr for StoragePrice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let re = Regex::new(r"^([A-Z_]+)=(\d+(\.\d+)?)$")?;
+        let m = re.captures(s).ok_or(FindError::StoragePriceParse)?;
+
+        let class = m.get(1).ok_or(FindError::StoragePriceParse)?.as_str();
+        let price = m.get(2).ok_or(FindError::StoragePriceParse)?.as_str();
+
+        Ok(StoragePrice {
+            class: class.to_owned(),
+            price: price.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TimeStyle {
+    Local,
+    Iso,
+    Full,
+    Relative,
+}
+
+impl FromStr for TimeStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "local" => Ok(TimeStyle::Local),
+            "iso" => Ok(TimeStyle::Iso),
+            "full" => Ok(TimeStyle::Full),
+            "relative" => Ok(TimeStyle::Relative),
+            _ => Err(FindError::TimeStyleParse.into()),
+        }
+    }
+}
+
+/// Severity of a public exposure of audit-public, from the lowest
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl FromStr for Severity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "low" => Ok(Severity::Low),
+            "medium" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(FindError::SeverityParse.into()),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ReportFormat {
+    Json,
+    Html,
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "json" => Ok(ReportFormat::Json),
+            "html" => Ok(ReportFormat::Html),
+            _ => Err(FindError::ReportFormatParse.into()),
+        }
+    }
+}
+
+/// Operation of the cost report
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PlannedOperation {
+    Download,
+    Copy,
+    RestoreExpedited,
+    RestoreStandard,
+    RestoreBulk,
+}
+
+impl PlannedOperation {
+    pub fn name(self) -> &'static str {
+        match self {
+            PlannedOperation::Download => "download",
+            PlannedOperation::Copy => "copy",
+            PlannedOperation::RestoreExpedited => "restore-expedited",
+            PlannedOperation::RestoreStandard => "restore-standard",
+            PlannedOperation::RestoreBulk => "restore-bulk",
+        }
+    }
+}
+
+impl FromStr for PlannedOperation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "download" => Ok(PlannedOperation::Download),
+            "copy" => Ok(PlannedOperation::Copy),
+            "restore-expedited" => Ok(PlannedOperation::RestoreExpedited),
+            "restore-standard" => Ok(PlannedOperation::RestoreStandard),
+            "restore-bulk" => Ok(PlannedOperation::RestoreBulk),
+            _ => Err(FindError::OperationParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ProgressMode {
+    Line,
+    Full,
+}
+
+impl FromStr for ProgressMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "line" => Ok(ProgressMode::Line),
+            "full" => Ok(ProgressMode::Full),
+            _ => Err(FindError::ProgressModeParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PrintFormat {
+    Text,
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for PrintFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "text" => Ok(PrintFormat::Text),
+            "table" => Ok(PrintFormat::Table),
+            "json" => Ok(PrintFormat::Json),
+            "csv" => Ok(PrintFormat::Csv),
+            _ => Err(FindError::FormatParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(FindError::ErrorFormatParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color the output of a stream, `terminal` when it is one
+    pub fn enabled(self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => terminal && env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(FindError::ColorParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ListApi {
+    Auto,
+    V1,
+    V2,
+}
+
+impl FromStr for ListApi {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "auto" => Ok(ListApi::Auto),
+            "v1" => Ok(ListApi::V1),
+            "v2" => Ok(ListApi::V2),
+            _ => Err(FindError::ListApiParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for LogLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(FindError::LogLevelParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum MetricsSink {
+    Statsd(String),
+    Textfile(PathBuf),
+}
+
+impl FromStr for MetricsSink {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        if let Some(address) = s.strip_prefix("statsd://") {
+            if address.contains(':') {
+                return Ok(MetricsSink::Statsd(address.to_owned()));
+            }
+        } else if let Some(path) = s.strip_prefix("textfile://") {
+            if !path.is_empty() {
+                return Ok(MetricsSink::Textfile(PathBuf::from(path)));
+            }
+        }
+
+        Err(FindError::MetricsParse.into())
+    }
+}
+
+/// ARN of the SNS topic of publish-sns
+#[derive(Debug, PartialEq, Clone)]
+pub struct TopicArn(pub String);
+
+impl FromStr for TopicArn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let re = Regex::new(r"^arn:aws[\w-]*:sns:[\w-]+:\d{12}:[\w-]+(\.fifo)?$")?;
+        if re.is_match(s) {
+            Ok(TopicArn(s.to_owned()))
+        } else {
+            Err(FindError::TopicArnParse.into())
+        }
+    }
+}
+
+impl TopicArn {
+    /// Region of the topic, `region` when it is not a known one
+    pub fn region(&self, region: &Region) -> Region {
+        self.0
+            .split(':')
+            .nth(3)
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_else(|| region.clone())
+    }
+}
+
+/// URL of the --notify-url webhook
+#[derive(Debug, PartialEq, Clone)]
+pub struct NotifyUrl {
+    /// Scheme and host of the webhook
+    pub endpoint: String,
+    pub path: String,
+    pub query: Option<String>,
+}
+
+impl FromStr for NotifyUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let re = Regex::new(r"^(https?://[^/?#]+)([^?#]*)(?:\?([^#]*))?$")?;
+        let m = re.captures(s).ok_or(FindError::NotifyUrlParse)?;
+        let path = match &m[2] {
+            "" => "/",
+            path => path,
+        };
+        Ok(NotifyUrl {
+            endpoint: m[1].to_owned(),
+            path: path.to_owned(),
+            query: m.get(3).map(|x| x.as_str().to_owned()),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RequestRate(pub f64);
+
+impl FromStr for RequestRate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.parse::<f64>() {
+            Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(RequestRate(rate)),
+            _ => Err(FindError::RequestRateParse.into()),
+        }
+    }
+}
+
+/// Transfer rate in bytes per second, given in bits (100Mbit) or bytes
+/// (20MB) per second with decimal units
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bandwidth(pub f64);
+
+impl FromStr for Bandwidth {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let re = Regex::new(r"^(\d+(?:\.\d+)?)([kKMGT]?)(bit|B)(?:/s)?$")?;
+        let m = re.captures(s).ok_or(FindError::BandwidthParse)?;
+        let number: f64 = m[1].parse().map_err(|_| FindError::BandwidthParse)?;
+
+        let multiplier = match &m[2] {
+            "k" | "K" => 1e3,
+            "M" => 1e6,
+            "G" => 1e9,
+            "T" => 1e12,
+            _ => 1.0,
+        };
+        let bytes = match &m[3] {
+            "bit" => number * multiplier / 8.0,
+            _ => number * multiplier,
+        };
+        if bytes <= 0.0 {
+            return Err(FindError::BandwidthParse.into());
+        }
+        Ok(Bandwidth(bytes))
+    }
+}
+
+/// A duration like 30s, 5m or 90d: the time between the passes of --watch,
+/// the ages of the reports
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Interval(pub Duration);
+
+impl FromStr for Interval {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let re = Regex::new(r"^(\d+)([smhd]?)$")?;
+        let m = re.captures(s).ok_or(FindError::IntervalParse)?;
+        let number: u64 = m[1].parse().map_err(|_| FindError::IntervalParse)?;
+
+        let seconds = match &m[2] {
+            "m" => number * 60,
+            "h" => number * 3600,
+            "d" => number * 3600 * 24,
+            _ => number,
+        };
+        if seconds == 0 {
+            return Err(FindError::IntervalParse.into());
+        }
+        Ok(Interval(Duration::from_secs(seconds)))
+    }
+}
+
+/// Times of the --daemon runs: the minutes, hours, days of the month,
+/// months and days of the week of a cron expression, as bit sets
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Schedule {
+    pub minutes: u64,
+    pub hours: u32,
+    pub days: u32,
+    pub months: u16,
+    pub weekdays: u8,
+    /// The days of the month or of the week are restricted, a day matches
+    /// either of them when both are
+    pub any_day: bool,
+    pub any_weekday: bool,
+}
+
+impl Schedule {
+    /// Bit set of a cron field with the values from `min` to `max`
+    fn field(field: &str, min: u32, max: u32) -> Result<u64, Error> {
+        let re = Regex::new(r"^(\*|(\d+)(?:-(\d+))?)(?:/(\d+))?$")?;
+        let mut set = 0;
+        for item in field.split(',') {
+            let m = re.captures(item).ok_or(FindError::ScheduleParse)?;
+            let number = |i| -> Result<Option<u32>, Error> {
+                m.get(i)
+                    .map(|x| {
+                        x.as_str()
+                            .parse()
+                            .map_err(|_| FindError::ScheduleParse.into())
+                    })
+                    .transpose()
+            };
+
+            let step = number(4)?;
+            let (start, end) = match (number(2)?, number(3)?, step) {
+                (None, _, _) => (min, max),
+                (Some(start), Some(end), _) => (start, end),
+                // a value with a step runs to the maximum, e.g. 5/15
+                (Some(start), None, Some(_)) => (start, max),
+                (Some(start), None, None) => (start, start),
+            };
+            let step = step.unwrap_or(1);
+            if start < min || end > max || start > end || step == 0 {
+                return Err(FindError::ScheduleParse.into());
+            }
+
+            for value in (start..=end).step_by(step as usize) {
+                set |= 1 << value;
+            }
+        }
+        Ok(set)
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(FindError::ScheduleParse.into());
+        }
+
+        let weekdays = Schedule::field(fields[4], 0, 7)?;
+        Ok(Schedule {
+            minutes: Schedule::field(fields[0], 0, 59)?,
+            hours: Schedule::field(fields[1], 0, 23)? as u32,
+            days: Schedule::field(fields[2], 1, 31)? as u32,
+            months: Schedule::field(fields[3], 1, 12)? as u16,
+            // Sunday is 0 and 7
+            weekdays: ((weekdays | weekdays >> 7) & 0x7f) as u8,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+}
+
+/// URL of an S3-compatible endpoint, without a trailing slash
+#[derive(Debug, PartialEq, Clone)]
+pub struct EndpointUrl(pub String);
+
+impl FromStr for EndpointUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let host = s
+            .strip_prefix("http://")
+            .or_else(|| s.strip_prefix("https://"))
+            .ok_or(FindError::EndpointUrlParse)?;
+        if host.trim_end_matches('/').is_empty() {
+            return Err(FindError::EndpointUrlParse.into());
+        }
+        Ok(EndpointUrl(s.trim_end_matches('/').to_owned()))
+    }
+}
+
+impl EndpointUrl {
+    /// Region named as `region` with requests sent to the endpoint
+    pub fn region(&self, region: &Region) -> Region {
+        Region::Custom {
+            name: region.name().to_owned(),
+            endpoint: self.0.clone(),
+        }
+    }
+}
+
+/// URL of an SQS queue, e.g. https://sqs.us-east-1.amazonaws.com/123456789012/queue
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueueUrl {
+    /// Scheme and host of the queue
+    pub endpoint: String,
+    /// Account and name of the queue, e.g. /123456789012/queue
+    pub path: String,
+}
+
+impl FromStr for QueueUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let scheme_length = if s.starts_with("https://") {
+            "https://".len()
+        } else if s.starts_with("http://") {
+            "http://".len()
+        } else {
+            return Err(FindError::QueueUrlParse.into());
+        };
+
+        let (host, path) = s[scheme_length..]
+            .split_once('/')
+            .ok_or(FindError::QueueUrlParse)?;
+        let path = path.trim_end_matches('/');
+        if host.is_empty() || path.is_empty() {
+            return Err(FindError::QueueUrlParse.into());
+        }
+        Ok(QueueUrl {
+            endpoint: s[..scheme_length + host.len()].to_owned(),
+            path: format!("/{}", path),
+        })
+    }
+}
+
+impl QueueUrl {
+    /// Region of an AWS queue host, `region` for the other hosts (e.g. LocalStack)
+    pub fn region(&self, region: &Region) -> Region {
+        let host = self.endpoint.split("://").nth(1).unwrap_or_default();
+        let labels: Vec<&str> = host.split('.').collect();
+        let name = match labels.as_slice() {
+            ["sqs", name, "amazonaws", ..] => name,
+            [name, "queue", "amazonaws", ..] => name,
+            _ => region.name(),
+        };
+        Region::Custom {
+            name: name.to_owned(),
+            endpoint: self.endpoint.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PageSize {
+    Fixed(i64),
+    Auto,
+}
+
+impl FromStr for PageSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "auto" => Ok(PageSize::Auto),
+            _ => match s.parse::<i64>() {
+                Ok(size) if size > 0 => Ok(PageSize::Fixed(size)),
+                _ => Err(FindError::PageSizeParse.into()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MemorySize(pub u64);
+
+impl FromStr for MemorySize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.parse::<FindSize>() {
+            Ok(FindSize::Equal(bytes)) if bytes > 0 => Ok(MemorySize(bytes as u64)),
+            _ => Err(FindError::MemorySizeParse.into()),
+        }
+    }
+}
+
+/// Where the objects come from
+#[derive(Debug, PartialEq, Clone, Default)]
+pub enum ObjectSource {
+    #[default]
+    List,
+    Inventory {
+        bucket: String,
+        manifest: String,
+    },
+    Keys {
+        file: PathBuf,
+        head: bool,
+    },
+}
+
+impl ObjectSource {
+    /// The values of --source: a source type and its location
+    pub fn from_values(values: &[String]) -> Result<Self, Error> {
+        match values {
+            [] => Ok(ObjectSource::List),
+            [kind, location] if kind == "inventory" => {
+                let (bucket, manifest) = location
+                    .strip_prefix("s3://")
+                    .and_then(|x| x.split_once('/'))
+                    .ok_or(FindError::SourceParse)?;
+                if bucket.is_empty() || manifest.is_empty() {
+                    return Err(FindError::SourceParse.into());
+                }
+
+                Ok(ObjectSource::Inventory {
+                    bucket: bucket.to_owned(),
+                    manifest: manifest.to_owned(),
+                })
+            }
+            _ => Err(FindError::SourceParse.into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EnrichField {
+    Tags,
+    Head,
+    Acl,
+}
+
+impl FromStr for EnrichField {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Error> {
-        let re = Regex::new(r"([+-]?)(\d*)([kMGTP]?)$")?;
-        let m = re.captures(s).ok_or(FindError::SizeParse)?;
+        match s {
+            "tags" => Ok(EnrichField::Tags),
+            "head" => Ok(EnrichField::Head),
+            "acl" => Ok(EnrichField::Acl),
+            _ => Err(FindError::EnrichParse.into()),
+        }
+    }
+}
 
-        let sign = m
-            .get(1)
-            .ok_or(FindError::SizeParse)?
-            .as_str()
-            .chars()
-            .next();
-        let number: i64 = m.get(2).ok_or(FindError::SizeParse)?.as_str().parse()?;
-        let metric = m
-            .get(3)
-            .ok_or(FindError::SizeParse)?
-            .as_str()
-            .chars()
-            .next();
+/// Field of --sort
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SortField {
+    Key,
+    Size,
+    Mtime,
+}
 
-        let bytes = match metric {
-            None => number,
-            Some('k') => number * 1024,
-            Some('M') => number * 1024_i64.pow(2),
-            Some('G') => number * 1024_i64.pow(3),
-            Some('T') => number * 1024_i64.pow(4),
-            Some('P') => number * 1024_i64.pow(5),
-            Some(_) => return Err(FindError::SizeParse.into()),
-        };
+impl FromStr for SortField {
+    type Err = Error;
 
-        match sign {
-            Some('+') => Ok(FindSize::Bigger(bytes)),
-            Some('-') => Ok(FindSize::Lower(bytes)),
-            None => Ok(FindSize::Equal(bytes)),
-            Some(_) => Err(FindError::SizeParse.into()),
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "key" => Ok(SortField::Key),
+            "size" => Ok(SortField::Size),
+            "mtime" => Ok(SortField::Mtime),
+            _ => Err(FindError::SortFieldParse.into()),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum FindTime {
-    Upper(i64),
-    Lower(i64),
+/// Format of the export-inventory files
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Parquet,
 }
 
-impl FromStr for FindTime {
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Error> {
-        let re = Regex::new(r"([+-]?)(\d*)([smhdw]?)$")?;
-        let m = re.captures(s).ok_or(FindError::TimeParse)?;
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(FindError::ExportFormatParse.into()),
+        }
+    }
+}
 
-        let sign = m
-            .get(1)
-            .ok_or(FindError::TimeParse)?
-            .as_str()
-            .chars()
-            .next();
-        let number: i64 = m.get(2).ok_or(FindError::TimeParse)?.as_str().parse()?;
-        let metric = m
-            .get(3)
-            .ok_or(FindError::TimeParse)?
-            .as_str()
-            .chars()
-            .next();
+/// Field of the export-inventory files
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InventoryField {
+    Bucket,
+    Key,
+    Size,
+    ETag,
+    StorageClass,
+    LastModified,
+    Tags,
+    ContentType,
+    Encryption,
+    Metadata,
+    Acl,
+}
 
-        let seconds = match metric {
-            None => number,
-            Some('s') => number,
-            Some('m') => number * 60,
-            Some('h') => number * 3600,
-            Some('d') => number * 3600 * 24,
-            Some('w') => number * 3600 * 24 * 7,
-            Some(_) => return Err(FindError::TimeParse.into()),
-        };
+impl InventoryField {
+    pub fn name(self) -> &'static str {
+        match self {
+            InventoryField::Bucket => "bucket",
+            InventoryField::Key => "key",
+            InventoryField::Size => "size",
+            InventoryField::ETag => "etag",
+            InventoryField::StorageClass => "storage_class",
+            InventoryField::LastModified => "last_modified",
+            InventoryField::Tags => "tags",
+            InventoryField::ContentType => "content_type",
+            InventoryField::Encryption => "encryption",
+            InventoryField::Metadata => "metadata",
+            InventoryField::Acl => "acl",
+        }
+    }
 
-        match sign {
-            Some('+') => Ok(FindTime::Upper(seconds)),
-            Some('-') => Ok(FindTime::Lower(seconds)),
-            None => Ok(FindTime::Upper(seconds)),
-            Some(_) => Err(FindError::TimeParse.into()),
+    /// The data fetched for the field, None when the listing has it
+    pub fn enrich_field(self) -> Option<EnrichField> {
+        match self {
+            InventoryField::Tags => Some(EnrichField::Tags),
+            InventoryField::ContentType | InventoryField::Encryption | InventoryField::Metadata => {
+                Some(EnrichField::Head)
+            }
+            InventoryField::Acl => Some(EnrichField::Acl),
+            _ => None,
         }
     }
 }
 
-pub type NameGlob = Pattern;
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct InameGlob(pub Pattern);
-
-impl FromStr for InameGlob {
+impl FromStr for InventoryField {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Error> {
-        let pattern = Pattern::from_str(s)?;
-        Ok(InameGlob(pattern))
+        match s {
+            "bucket" => Ok(InventoryField::Bucket),
+            "key" => Ok(InventoryField::Key),
+            "size" => Ok(InventoryField::Size),
+            "etag" => Ok(InventoryField::ETag),
+            "storage_class" => Ok(InventoryField::StorageClass),
+            "last_modified" => Ok(InventoryField::LastModified),
+            "tags" => Ok(InventoryField::Tags),
+            "content_type" => Ok(InventoryField::ContentType),
+            "encryption" => Ok(InventoryField::Encryption),
+            "metadata" => Ok(InventoryField::Metadata),
+            "acl" => Ok(InventoryField::Acl),
+            _ => Err(FindError::InventoryFieldParse.into()),
+        }
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct FindTag {
-    pub key: String,
-    pub value: String,
+pub enum TemplatePart {
+    Literal(String),
+    Path,
+    RelativeKey,
+    Key,
+    Bucket,
+    Size,
+    HumanSize,
+    Time,
+    ETag,
+    StorageClass,
+    Owner,
 }
 
-impl FromStr for FindTag {
+#[derive(Debug, PartialEq, Clone)]
+pub struct FormatTemplate(pub Vec<TemplatePart>);
+
+impl FromStr for FormatTemplate {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Error> {
-        let re = Regex::new(r"(\w+):(\w+)$")?;
-        let m = re.captures(s).ok_or(FindError::TagParseError)?;
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            let part = match c {
+                '%' => match chars.next().ok_or(FindError::TemplateParse)? {
+                    '%' => {
+                        literal.push('%');
+                        continue;
+                    }
+                    'p' => TemplatePart::Path,
+                    'P' => TemplatePart::RelativeKey,
+                    'k' => TemplatePart::Key,
+                    'b' => TemplatePart::Bucket,
+                    's' => TemplatePart::Size,
+                    'h' => TemplatePart::HumanSize,
+                    'T' => TemplatePart::Time,
+                    'e' => TemplatePart::ETag,
+                    'S' => TemplatePart::StorageClass,
+                    'o' => TemplatePart::Owner,
+                    _ => return Err(FindError::TemplateParse.into()),
+                },
+                '\\' => {
+                    match chars.next().ok_or(FindError::TemplateParse)? {
+                        't' => literal.push('\t'),
+                        'n' => literal.push('\n'),
+                        '\\' => literal.push('\\'),
+                        _ => return Err(FindError::TemplateParse.into()),
+                    }
+                    continue;
+                }
+                c => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+
+            if !literal.is_empty() {
+                parts.push(TemplatePart::Literal(literal.split_off(0)));
+            }
+            parts.push(part);
+        }
 
-        let key = m.get(1).ok_or(FindError::TagKeyParseError)?.as_str();
-        let value = m.get(2).ok_or(FindError::TagValueParseError)?.as_str();
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
 
-        Ok(FindTag {
-            key: key.to_string(),
-            value: value.to_string(),
-        })
+        Ok(FormatTemplate(parts))
     }
 }
 
@@ -452,6 +3317,79 @@ mod tests {
         );
     }
 
+    #[test]
+    fn s3path_access_point() {
+        assert_eq!(
+            "s3://arn:aws:s3:us-east-1:123456789012:accesspoint/my-ap/prefix/"
+                .parse()
+                .ok(),
+            Some(S3path {
+                bucket: "arn:aws:s3:us-east-1:123456789012:accesspoint/my-ap".to_owned(),
+                prefix: Some("prefix/".to_owned()),
+            })
+        );
+
+        let path: S3path =
+            "s3://arn:aws:s3-object-lambda:eu-west-1:123456789012:accesspoint:my-olap"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            path.bucket,
+            "arn:aws:s3-object-lambda:eu-west-1:123456789012:accesspoint/my-olap"
+        );
+        assert_eq!(path.prefix, None);
+        assert_eq!(
+            path.copy_source("a/b"),
+            "arn:aws:s3-object-lambda:eu-west-1:123456789012:accesspoint/my-olap/object/a/b"
+        );
+        assert_eq!(
+            "s3://bucket".parse::<S3path>().unwrap().copy_source("a/b"),
+            "bucket/a/b"
+        );
+    }
+
+    #[test]
+    fn s3path_bucket_pattern() {
+        let path: S3path = "s3://logs-*-prod/2024/".parse().unwrap();
+        assert!(path.has_bucket_pattern());
+        assert!(!"s3://logs/2024/"
+            .parse::<S3path>()
+            .unwrap()
+            .has_bucket_pattern());
+
+        let buckets = ["logs-api-prod", "logs-api-dev", "logs-web-prod", "other"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            path.expand(&buckets),
+            vec![
+                S3path {
+                    bucket: "logs-api-prod".to_owned(),
+                    prefix: Some("2024/".to_owned()),
+                },
+                S3path {
+                    bucket: "logs-web-prod".to_owned(),
+                    prefix: Some("2024/".to_owned()),
+                },
+            ]
+        );
+        assert!("s3://logs-[a/".parse::<S3path>().is_err());
+    }
+
+    #[test]
+    fn s3path_relative_key() {
+        let path: S3path = "s3://bucket/logs".parse().unwrap();
+        assert_eq!(path.relative_key("logs/2019/file.gz"), "2019/file.gz");
+        assert_eq!(path.relative_key("other/file.gz"), "other/file.gz");
+
+        let path: S3path = "s3://bucket/logs/".parse().unwrap();
+        assert_eq!(path.relative_key("logs/file.gz"), "file.gz");
+
+        let path: S3path = "s3://bucket".parse().unwrap();
+        assert_eq!(path.relative_key("logs/file.gz"), "logs/file.gz");
+    }
+
     #[test]
     fn s3path_incorrect() {
         assert!("testbucket".parse::<S3path>().is_err());
@@ -509,6 +3447,29 @@ mod tests {
         assert_eq!("-11".parse().ok(), Some(FindTime::Lower(11)));
     }
 
+    #[test]
+    fn date_corect() {
+        let date = |s: &str| s.parse::<FindDate>().ok().map(|x| x.0);
+        assert_eq!(date("1970-01-01"), Some(0));
+        assert_eq!(date("2023-01-01"), Some(1_672_531_200));
+        assert_eq!(date("2023-06-01T12:00:00Z"), Some(1_685_620_800));
+        assert_eq!(date("2023-06-01 12:00"), Some(1_685_620_800));
+        assert_eq!(date("2023-06-01T14:00:00.250+02:00"), Some(1_685_620_800));
+        assert_eq!(date("2023-06-01T07:30:00-0430"), Some(1_685_620_800));
+        assert_eq!(date("2024-02-29"), Some(1_709_164_800));
+        assert_eq!(date("1969-12-31T23:59:59Z"), Some(-1));
+
+        for invalid in &[
+            "2023-02-29",
+            "2023-13-01",
+            "2023-06-01T24:00",
+            "2023-6-1",
+            "-1d",
+        ] {
+            assert!(invalid.parse::<FindDate>().is_err(), "{}", invalid);
+        }
+    }
+
     #[test]
     fn time_incorect() {
         assert!("-".parse::<FindTime>().is_err());
@@ -534,4 +3495,412 @@ mod tests {
         assert!("tag1:value2:".parse::<FindTag>().is_err());
         assert!(":".parse::<FindTag>().is_err());
     }
+
+    #[test]
+    fn storage_price_ok() {
+        assert_eq!(
+            "STANDARD_IA=0.0125".parse().ok(),
+            Some(StoragePrice {
+                class: "STANDARD_IA".to_owned(),
+                price: 0.0125,
+            })
+        );
+        assert_eq!(
+            "GLACIER=1".parse().ok(),
+            Some(StoragePrice {
+                class: "GLACIER".to_owned(),
+                price: 1.0,
+            })
+        );
+    }
+
+    #[test]
+    fn storage_price_incorect() {
+        assert!("STANDARD".parse::<StoragePrice>().is_err());
+        assert!("STANDARD=".parse::<StoragePrice>().is_err());
+        assert!("=0.1".parse::<StoragePrice>().is_err());
+        assert!("STANDARD=abc".parse::<StoragePrice>().is_err());
+    }
+
+    #[test]
+    fn time_style() {
+        assert_eq!("local".parse().ok(), Some(TimeStyle::Local));
+        assert_eq!("iso".parse().ok(), Some(TimeStyle::Iso));
+        assert_eq!("full".parse().ok(), Some(TimeStyle::Full));
+        assert_eq!("relative".parse().ok(), Some(TimeStyle::Relative));
+        assert!("utc".parse::<TimeStyle>().is_err());
+    }
+
+    #[test]
+    fn severity() {
+        assert_eq!("critical".parse().ok(), Some(Severity::Critical));
+        assert!(Severity::Critical > Severity::High);
+        assert!(Severity::Medium > Severity::Low);
+        assert_eq!(Severity::Medium.to_string(), "medium");
+        assert!("severe".parse::<Severity>().is_err());
+    }
+
+    #[test]
+    fn print_format() {
+        assert_eq!("text".parse().ok(), Some(PrintFormat::Text));
+        assert_eq!("table".parse().ok(), Some(PrintFormat::Table));
+        assert_eq!("json".parse().ok(), Some(PrintFormat::Json));
+        assert_eq!("csv".parse().ok(), Some(PrintFormat::Csv));
+        assert!("xml".parse::<PrintFormat>().is_err());
+    }
+
+    #[test]
+    fn format_template_ok() {
+        assert_eq!(
+            "%s\\t%k\\n".parse().ok(),
+            Some(FormatTemplate(vec![
+                TemplatePart::Size,
+                TemplatePart::Literal("\t".to_owned()),
+                TemplatePart::Key,
+                TemplatePart::Literal("\n".to_owned()),
+            ]))
+        );
+        assert_eq!(
+            "size=%h 100%%".parse().ok(),
+            Some(FormatTemplate(vec![
+                TemplatePart::Literal("size=".to_owned()),
+                TemplatePart::HumanSize,
+                TemplatePart::Literal(" 100%".to_owned()),
+            ]))
+        );
+    }
+
+    #[test]
+    fn format_template_incorect() {
+        assert!("%".parse::<FormatTemplate>().is_err());
+        assert!("%x".parse::<FormatTemplate>().is_err());
+        assert!("\\".parse::<FormatTemplate>().is_err());
+        assert!("\\q".parse::<FormatTemplate>().is_err());
+    }
+
+    #[test]
+    fn enrich_fields() {
+        let opts = FindOpt::from_iter(&[
+            "s3find",
+            "s3://bucket/path",
+            "print",
+            "--format",
+            "json",
+            "--enrich",
+            "tags,head",
+        ]);
+
+        match opts.cmd {
+            Some(Cmd::Print(print)) => {
+                assert_eq!(print.format, PrintFormat::Json);
+                assert_eq!(print.enrich, vec![EnrichField::Tags, EnrichField::Head]);
+            }
+            _ => panic!("print command is expected"),
+        }
+
+        assert!("owner".parse::<EnrichField>().is_err());
+    }
+
+    #[test]
+    fn error_format() {
+        let opts = FindOpt::from_iter(&["s3find", "s3://bucket/path", "--errors", "json"]);
+        assert_eq!(opts.errors, ErrorFormat::Json);
+
+        assert_eq!("text".parse::<ErrorFormat>().unwrap(), ErrorFormat::Text);
+        assert!("xml".parse::<ErrorFormat>().is_err());
+    }
+
+    #[test]
+    fn color_and_protected_prefixes() {
+        let opts = FindOpt::from_iter(&["s3find", "s3://bucket/path"]);
+        assert_eq!(opts.color, ColorChoice::Auto);
+        assert!(opts.protected_prefix.is_empty());
+
+        let opts = FindOpt::from_iter(&[
+            "s3find",
+            "s3://bucket/path",
+            "--color",
+            "never",
+            "--protected-prefix",
+            "backups/",
+            "--protected-prefix",
+            "audit/",
+        ]);
+        assert_eq!(opts.color, ColorChoice::Never);
+        assert_eq!(opts.protected_prefix, vec!["backups/", "audit/"]);
+
+        assert!(ColorChoice::Always.enabled(false));
+        assert!(!ColorChoice::Never.enabled(true));
+        assert!(!ColorChoice::Auto.enabled(false));
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+    }
+
+    #[test]
+    fn list_dirs() {
+        let opts = FindOpt::from_iter(&["s3find", "s3://bucket/logs/", "ls-dirs", "--relative"]);
+        assert_eq!(opts.delimiter, None);
+        assert_eq!(
+            opts.cmd,
+            Some(Cmd::LsDirs(ListDirs {
+                key_format: KeyFormat {
+                    relative: true,
+                    encode_keys: false,
+                },
+            }))
+        );
+
+        let opts = FindOpt::from_iter_safe(&[
+            "s3find",
+            "s3://bucket/logs/",
+            "--delimiter",
+            "/",
+            "--list-concurrency",
+            "8",
+        ]);
+        assert!(opts.is_err());
+    }
+
+    #[test]
+    fn log_level() {
+        let opts = FindOpt::from_iter(&[
+            "s3find",
+            "s3://bucket/path",
+            "--log-level",
+            "debug",
+            "--log-file",
+            "s3find.log",
+        ]);
+        assert_eq!(opts.log_level, Some(LogLevel::Debug));
+        assert_eq!(opts.log_file, Some(PathBuf::from("s3find.log")));
+
+        assert!("verbose".parse::<LogLevel>().is_err());
+    }
+
+    #[test]
+    fn topic_arn() {
+        let topic: TopicArn = "arn:aws:sns:eu-west-1:123456789012:new-objects"
+            .parse()
+            .unwrap();
+        assert_eq!(topic.region(&Region::UsEast1), Region::EuWest1);
+        assert!("arn:aws:sns:us-east-1:123456789012:events.fifo"
+            .parse::<TopicArn>()
+            .is_ok());
+        assert!("arn:aws:sqs:us-east-1:123456789012:events"
+            .parse::<TopicArn>()
+            .is_err());
+        assert!("new-objects".parse::<TopicArn>().is_err());
+    }
+
+    #[test]
+    fn notify_url() {
+        let url: NotifyUrl = "https://hooks.example.com/services/T1/B2?token=a%20b"
+            .parse()
+            .unwrap();
+        assert_eq!(url.endpoint, "https://hooks.example.com");
+        assert_eq!(url.path, "/services/T1/B2");
+        assert_eq!(url.query.as_deref(), Some("token=a%20b"));
+
+        let local: NotifyUrl = "http://localhost:8080".parse().unwrap();
+        assert_eq!(local.endpoint, "http://localhost:8080");
+        assert_eq!(local.path, "/");
+        assert_eq!(local.query, None);
+        assert!("hooks.example.com/services".parse::<NotifyUrl>().is_err());
+        assert!("https:///services".parse::<NotifyUrl>().is_err());
+    }
+
+    #[test]
+    fn metrics_sink() {
+        assert_eq!(
+            "statsd://localhost:8125".parse::<MetricsSink>().unwrap(),
+            MetricsSink::Statsd("localhost:8125".to_owned())
+        );
+        assert_eq!(
+            "textfile:///var/lib/node_exporter/s3find.prom"
+                .parse::<MetricsSink>()
+                .unwrap(),
+            MetricsSink::Textfile(PathBuf::from("/var/lib/node_exporter/s3find.prom"))
+        );
+
+        assert!("statsd://localhost".parse::<MetricsSink>().is_err());
+        assert!("textfile://".parse::<MetricsSink>().is_err());
+        assert!("http://localhost:9091".parse::<MetricsSink>().is_err());
+    }
+
+    #[test]
+    fn page_size() {
+        assert_eq!("100".parse::<PageSize>().unwrap(), PageSize::Fixed(100));
+        assert_eq!("auto".parse::<PageSize>().unwrap(), PageSize::Auto);
+        assert!("0".parse::<PageSize>().is_err());
+        assert!("big".parse::<PageSize>().is_err());
+    }
+
+    #[test]
+    fn memory_size() {
+        assert_eq!(
+            "512M".parse::<MemorySize>().unwrap(),
+            MemorySize(512 * 1024 * 1024)
+        );
+        assert_eq!("4096".parse::<MemorySize>().unwrap(), MemorySize(4096));
+        assert!("+512M".parse::<MemorySize>().is_err());
+        assert!("0".parse::<MemorySize>().is_err());
+        assert!("512X".parse::<MemorySize>().is_err());
+    }
+
+    #[test]
+    fn progress_mode() {
+        let progress = |args: &[&str]| {
+            let mut argv = vec!["s3find", "s3://bucket/path"];
+            argv.extend(args);
+            FindOpt::from_iter(&argv).progress
+        };
+
+        assert_eq!(progress(&[]), None);
+        assert_eq!(progress(&["--progress"]), Some(None));
+        assert_eq!(progress(&["--progress", "ls"]), Some(None));
+        assert_eq!(
+            progress(&["--progress=full"]),
+            Some(Some(ProgressMode::Full))
+        );
+        assert!("bar".parse::<ProgressMode>().is_err());
+    }
+
+    #[test]
+    fn request_rate() {
+        assert_eq!("100".parse::<RequestRate>().unwrap(), RequestRate(100.0));
+        assert_eq!("0.5".parse::<RequestRate>().unwrap(), RequestRate(0.5));
+        assert!("0".parse::<RequestRate>().is_err());
+        assert!("-1".parse::<RequestRate>().is_err());
+        assert!("fast".parse::<RequestRate>().is_err());
+    }
+
+    #[test]
+    fn bandwidth() {
+        assert_eq!("100Mbit".parse::<Bandwidth>().unwrap(), Bandwidth(12.5e6));
+        assert_eq!("20MB/s".parse::<Bandwidth>().unwrap(), Bandwidth(20e6));
+        assert_eq!("1.5Gbit".parse::<Bandwidth>().unwrap(), Bandwidth(187.5e6));
+        assert!("0Mbit".parse::<Bandwidth>().is_err());
+        assert!("100".parse::<Bandwidth>().is_err());
+        assert!("100Mb".parse::<Bandwidth>().is_err());
+    }
+
+    #[test]
+    fn queue_url() {
+        let url: QueueUrl = "https://sqs.eu-west-1.amazonaws.com/123456789012/events"
+            .parse()
+            .unwrap();
+        assert_eq!(url.path, "/123456789012/events");
+        assert_eq!(
+            url.region(&Region::UsEast1),
+            Region::Custom {
+                name: "eu-west-1".to_owned(),
+                endpoint: "https://sqs.eu-west-1.amazonaws.com".to_owned(),
+            }
+        );
+
+        let local: QueueUrl = "http://localhost:4566/000000000000/events".parse().unwrap();
+        assert_eq!(local.region(&Region::UsWest2).name(), "us-west-2");
+        assert!("sqs.eu-west-1.amazonaws.com/1/events"
+            .parse::<QueueUrl>()
+            .is_err());
+        assert!("https://sqs.eu-west-1.amazonaws.com/"
+            .parse::<QueueUrl>()
+            .is_err());
+    }
+
+    #[test]
+    fn watch_interval() {
+        let secs = |x| Interval(Duration::from_secs(x));
+        assert_eq!("30".parse::<Interval>().unwrap(), secs(30));
+        assert_eq!("45s".parse::<Interval>().unwrap(), secs(45));
+        assert_eq!("5m".parse::<Interval>().unwrap(), secs(300));
+        assert_eq!("1h".parse::<Interval>().unwrap(), secs(3600));
+        assert!("0s".parse::<Interval>().is_err());
+        assert!("-5m".parse::<Interval>().is_err());
+        assert!("5x".parse::<Interval>().is_err());
+    }
+
+    #[test]
+    fn schedule() {
+        let daily: Schedule = "0 3 * * *".parse().unwrap();
+        assert_eq!(daily.minutes, 1);
+        assert_eq!(daily.hours, 1 << 3);
+        assert!(daily.any_day && daily.any_weekday);
+
+        let schedule: Schedule = "*/20 8-18/5 1,15 * 1-5,7".parse().unwrap();
+        assert_eq!(schedule.minutes, 1 | 1 << 20 | 1 << 40);
+        assert_eq!(schedule.hours, 1 << 8 | 1 << 13 | 1 << 18);
+        assert_eq!(schedule.days, 1 << 1 | 1 << 15);
+        assert_eq!(schedule.weekdays, 0b011_1111);
+        assert!(!schedule.any_day && !schedule.any_weekday);
+        assert_eq!(
+            "5/30 * * * *".parse::<Schedule>().unwrap().minutes,
+            1 << 5 | 1 << 35
+        );
+
+        assert!("0 3 * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("0 0 0 * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+        assert!("5-1 * * * *".parse::<Schedule>().is_err());
+        assert!("@daily".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn endpoint_url() {
+        let endpoint = "http://minio.local:9000/".parse::<EndpointUrl>().unwrap();
+        assert_eq!(endpoint, EndpointUrl("http://minio.local:9000".to_owned()));
+        assert_eq!(
+            endpoint.region(&Region::EuWest1),
+            Region::Custom {
+                name: "eu-west-1".to_owned(),
+                endpoint: "http://minio.local:9000".to_owned(),
+            }
+        );
+        assert!("https://s3.example.com".parse::<EndpointUrl>().is_ok());
+        assert!("minio.local:9000".parse::<EndpointUrl>().is_err());
+        assert!("http://".parse::<EndpointUrl>().is_err());
+    }
+
+    #[test]
+    fn object_source() {
+        let values = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(ObjectSource::from_values(&[]).unwrap(), ObjectSource::List);
+        assert_eq!(
+            ObjectSource::from_values(&values(&[
+                "inventory",
+                "s3://inventory/bucket/config/2020-01-01T00-00Z/manifest.json"
+            ]))
+            .unwrap(),
+            ObjectSource::Inventory {
+                bucket: "inventory".to_owned(),
+                manifest: "bucket/config/2020-01-01T00-00Z/manifest.json".to_owned(),
+            }
+        );
+
+        assert!(ObjectSource::from_values(&values(&["inventory", "s3://inventory"])).is_err());
+        assert!(ObjectSource::from_values(&values(&["inventory", "manifest.json"])).is_err());
+        assert!(
+            ObjectSource::from_values(&values(&["orc", "s3://inventory/manifest.json"])).is_err()
+        );
+    }
+
+    #[test]
+    fn quiet_action_commands() {
+        let opts = FindOpt::from_iter(&["s3find", "s3://bucket/path", "tags", "--quiet", "a:b"]);
+        assert_eq!(
+            opts.cmd,
+            Some(Cmd::Tags(SetTags {
+                tags: vec![FindTag {
+                    key: "a".to_owned(),
+                    value: "b".to_owned(),
+                }],
+                concurrency: 1,
+                quiet: true,
+            }))
+        );
+
+        let opts = FindOpt::from_iter(&["s3find", "s3://bucket/path", "delete"]);
+        assert_eq!(opts.cmd, Some(Cmd::Delete(MultipleDelete { quiet: false })));
+    }
 }
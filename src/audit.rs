@@ -0,0 +1,491 @@
+use failure::Error;
+use log::debug;
+use rusoto_core::Region;
+use rusoto_s3::{
+    GetBucketPolicyStatusRequest, GetPublicAccessBlockRequest, Grant, HeadObjectOutput, Object,
+    S3Client, S3,
+};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use crate::arg::{AuditPublic, AuditUnencrypted, EnrichField, S3path, Severity};
+use crate::buckets::not_configured;
+use crate::enrich::EnrichmentCache;
+use crate::error::FunctionError;
+use crate::function::RunCommand;
+use crate::listing::prefix_group;
+use crate::ops;
+use crate::output::OutputSink;
+use crate::parallel::parallel_map;
+use crate::report::ErrorReport;
+use crate::retry::with_retry;
+
+const ALL_USERS: &str = "http://acs.amazonaws.com/groups/global/AllUsers";
+const AUTHENTICATED_USERS: &str = "http://acs.amazonaws.com/groups/global/AuthenticatedUsers";
+
+/// Public access block and policy status of a bucket. The settings which
+/// cannot be read, e.g. without the permission, count as disabled. The public
+/// access block of the account is not read
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BucketExposure {
+    pub ignore_public_acls: bool,
+    pub restrict_public_buckets: bool,
+    pub public_policy: bool,
+}
+
+/// A public exposure of a key: a grant of its ACL or the bucket policy
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub reason: String,
+}
+
+/// The public exposures of a key with the grants of its ACL
+pub fn findings(bucket: &BucketExposure, grants: &[Grant]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for grant in grants {
+        let group = match grant.grantee.as_ref().and_then(|x| x.uri.as_deref()) {
+            Some(ALL_USERS) => "AllUsers",
+            Some(AUTHENTICATED_USERS) => "AuthenticatedUsers",
+            _ => continue,
+        };
+        let permission = grant.permission.as_deref().unwrap_or_default();
+        let reason = format!("{}:{}", group, permission);
+
+        let finding = if bucket.ignore_public_acls {
+            Finding {
+                severity: Severity::Low,
+                reason: format!("{} (ignored by the public access block)", reason),
+            }
+        } else {
+            let severity = match (group, permission) {
+                ("AllUsers", "READ") | ("AllUsers", "FULL_CONTROL") => Severity::Critical,
+                ("AllUsers", _) => Severity::High,
+                _ => Severity::Medium,
+            };
+            Finding { severity, reason }
+        };
+        findings.push(finding);
+    }
+
+    if bucket.public_policy {
+        findings.push(if bucket.restrict_public_buckets {
+            Finding {
+                severity: Severity::Low,
+                reason: "public bucket policy (restricted by the public access block)".to_owned(),
+            }
+        } else {
+            Finding {
+                severity: Severity::High,
+                reason: "public bucket policy".to_owned(),
+            }
+        });
+    }
+    findings
+}
+
+/// Command of audit-public, the exposure of a bucket is read once
+pub struct PublicAudit {
+    options: AuditPublic,
+    buckets: Mutex<HashMap<String, BucketExposure>>,
+}
+
+impl PublicAudit {
+    pub fn new(options: AuditPublic) -> Self {
+        PublicAudit {
+            options,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn bucket_exposure(&self, client: &S3Client, bucket: &str) -> BucketExposure {
+        let mut buckets = self.buckets.lock().unwrap();
+        *buckets
+            .entry(bucket.to_owned())
+            .or_insert_with(|| read_exposure(client, bucket))
+    }
+
+    /// The line of a key exposed with at least the minimal severity
+    fn report(&self, url: &str, findings: &[Finding]) -> Option<String> {
+        let severity = findings.iter().map(|x| x.severity).max()?;
+        if severity < self.options.min_severity {
+            return None;
+        }
+
+        if self.options.json {
+            let findings: Vec<_> = findings
+                .iter()
+                .map(|x| json!({ "severity": x.severity.to_string(), "reason": x.reason }))
+                .collect();
+            Some(
+                json!({
+                    "key": url,
+                    "severity": severity.to_string(),
+                    "findings": findings,
+                })
+                .to_string(),
+            )
+        } else {
+            let reasons: Vec<&str> = findings.iter().map(|x| x.reason.as_str()).collect();
+            Some(format!("{}\t{}\t{}", severity, url, reasons.join(", ")))
+        }
+    }
+
+    fn audit_object(
+        &self,
+        client: &S3Client,
+        bucket: &str,
+        exposure: &BucketExposure,
+        object: &Object,
+    ) -> Result<Option<String>, Error> {
+        let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+
+        let grants = match ops::block_on(ops::get_object_acl(client, bucket, key)) {
+            Ok(x) => x,
+            Err(report) => {
+                report.emit();
+                return Ok(None);
+            }
+        };
+
+        let url = format!("s3://{}/{}", bucket, key);
+        Ok(self.report(&url, &findings(exposure, &grants)))
+    }
+}
+
+fn read_exposure(client: &S3Client, bucket: &str) -> BucketExposure {
+    let request = GetPublicAccessBlockRequest {
+        bucket: bucket.to_owned(),
+    };
+    debug!("GetPublicAccessBlock {}", bucket);
+    let block = match with_retry("GetPublicAccessBlock", || {
+        client.get_public_access_block(request.clone())
+    }) {
+        Ok(x) => x.public_access_block_configuration.unwrap_or_default(),
+        Err(ref e) if not_configured(e) => Default::default(),
+        Err(e) => {
+            ErrorReport::from_rusoto("GetPublicAccessBlock", bucket, None, &e).emit();
+            Default::default()
+        }
+    };
+
+    let request = GetBucketPolicyStatusRequest {
+        bucket: bucket.to_owned(),
+    };
+    debug!("GetBucketPolicyStatus {}", bucket);
+    let public_policy = match with_retry("GetBucketPolicyStatus", || {
+        client.get_bucket_policy_status(request.clone())
+    }) {
+        Ok(x) => x.policy_status.and_then(|x| x.is_public).unwrap_or(false),
+        // no bucket policy
+        Err(ref e) if not_configured(e) => false,
+        Err(e) => {
+            ErrorReport::from_rusoto("GetBucketPolicyStatus", bucket, None, &e).emit();
+            false
+        }
+    };
+
+    BucketExposure {
+        ignore_public_acls: block.ignore_public_acls == Some(true),
+        restrict_public_buckets: block.restrict_public_buckets == Some(true),
+        public_policy,
+    }
+}
+
+impl RunCommand for PublicAudit {
+    fn execute(
+        &self,
+        client: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let exposure = self.bucket_exposure(client, &path.bucket);
+        for line in parallel_map(list, self.options.concurrency, |x| {
+            self.audit_object(client, &path.bucket, &exposure, x)
+        }) {
+            if let Some(line) = line? {
+                output.line(&line);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why a key is flagged by audit-unencrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EncryptionGap {
+    /// No server-side encryption
+    Unencrypted,
+    /// Encrypted with S3 managed or customer provided keys instead of KMS
+    NotKms,
+    /// Encrypted with another KMS key than the required one
+    OtherKmsKey,
+}
+
+impl EncryptionGap {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EncryptionGap::Unencrypted => "unencrypted",
+            EncryptionGap::NotKms => "not-kms",
+            EncryptionGap::OtherKmsKey => "other-kms-key",
+        }
+    }
+}
+
+/// The gap of a key with the HeadObject data, `kms_key` is the required key
+pub fn encryption_gap(head: &HeadObjectOutput, kms_key: Option<&str>) -> Option<EncryptionGap> {
+    let algorithm = head
+        .server_side_encryption
+        .as_deref()
+        .or(head.sse_customer_algorithm.as_deref());
+    match (algorithm, kms_key) {
+        (None, _) => Some(EncryptionGap::Unencrypted),
+        (Some(_), None) => None,
+        (Some(algorithm), Some(_)) if !algorithm.starts_with("aws:kms") => {
+            Some(EncryptionGap::NotKms)
+        }
+        (Some(_), Some(required)) => {
+            // HeadObject returns the ARN of the key
+            let key = head.ssekms_key_id.as_deref().unwrap_or_default();
+            if key == required || key.ends_with(&format!("/{}", required)) {
+                None
+            } else {
+                Some(EncryptionGap::OtherKmsKey)
+            }
+        }
+    }
+}
+
+/// Flagged keys of a prefix
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GapCount {
+    pub count: u64,
+    pub bytes: i64,
+}
+
+/// Command of audit-unencrypted, the counts by bucket, prefix and gap are
+/// printed at the end of the run
+pub struct EncryptionAudit {
+    options: AuditUnencrypted,
+    groups: Mutex<BTreeMap<(String, String, EncryptionGap), GapCount>>,
+}
+
+impl EncryptionAudit {
+    pub fn new(options: AuditUnencrypted) -> Self {
+        EncryptionAudit {
+            options,
+            groups: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn add(&self, path: &S3path, object: &Object, gap: EncryptionGap) {
+        let key = object.key.as_deref().unwrap_or_default();
+        let group = prefix_group(path, key, self.options.depth);
+        let mut groups = self.groups.lock().unwrap();
+        let count = groups.entry((path.bucket.clone(), group, gap)).or_default();
+        count.count += 1;
+        count.bytes += object.size.unwrap_or_default();
+    }
+
+    /// The lines of the report, the counts start again
+    fn report(&self) -> Vec<String> {
+        let groups = std::mem::take(&mut *self.groups.lock().unwrap());
+        groups
+            .into_iter()
+            .map(|((bucket, prefix, gap), count)| {
+                let url = format!("s3://{}/{}", bucket, prefix);
+                if self.options.json {
+                    json!({
+                        "prefix": url,
+                        "gap": gap.as_str(),
+                        "count": count.count,
+                        "bytes": count.bytes,
+                    })
+                    .to_string()
+                } else {
+                    format!(
+                        "{}\t{}\t{}\t{}",
+                        gap.as_str(),
+                        url,
+                        count.count,
+                        count.bytes
+                    )
+                }
+            })
+            .collect()
+    }
+}
+
+impl RunCommand for EncryptionAudit {
+    fn execute(
+        &self,
+        client: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let kms_key = self.options.kms_key.as_deref();
+        let gaps = parallel_map(list, self.options.concurrency, |x| -> Result<_, Error> {
+            let key = x.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+            let head = enrichment
+                .fetch(client, &path.bucket, key, &[EnrichField::Head])
+                .head;
+            Ok(head.and_then(|head| encryption_gap(&head, kms_key)))
+        });
+        for (object, gap) in list.iter().zip(gaps) {
+            if let Some(gap) = gap? {
+                self.add(path, object, gap);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self, output: &dyn OutputSink) -> Result<(), Error> {
+        for line in self.report() {
+            output.line(&line);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_s3::Grantee;
+
+    fn grant(uri: &str, permission: &str) -> Grant {
+        Grant {
+            grantee: Some(Grantee {
+                type_: "Group".to_owned(),
+                uri: Some(uri.to_owned()),
+                ..Default::default()
+            }),
+            permission: Some(permission.to_owned()),
+        }
+    }
+
+    #[test]
+    fn public_findings() {
+        let owner = Grant {
+            grantee: Some(Grantee {
+                type_: "CanonicalUser".to_owned(),
+                id: Some("1234".to_owned()),
+                ..Default::default()
+            }),
+            permission: Some("FULL_CONTROL".to_owned()),
+        };
+        let grants = vec![
+            owner.clone(),
+            grant(ALL_USERS, "READ"),
+            grant(AUTHENTICATED_USERS, "READ"),
+        ];
+
+        let open = BucketExposure::default();
+        let found = findings(&open, &grants);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].severity, Severity::Critical);
+        assert_eq!(found[0].reason, "AllUsers:READ");
+        assert_eq!(found[1].severity, Severity::Medium);
+        assert!(findings(&open, &[owner]).is_empty());
+        assert_eq!(
+            findings(&open, &[grant(ALL_USERS, "WRITE_ACP")])[0].severity,
+            Severity::High
+        );
+
+        let blocked = BucketExposure {
+            ignore_public_acls: true,
+            restrict_public_buckets: true,
+            public_policy: true,
+        };
+        let found = findings(&blocked, &grants);
+        assert!(found.iter().all(|x| x.severity == Severity::Low));
+        assert_eq!(
+            found[2].reason,
+            "public bucket policy (restricted by the public access block)"
+        );
+
+        let audit = PublicAudit::new(AuditPublic {
+            concurrency: 1,
+            min_severity: Severity::Medium,
+            json: false,
+        });
+        let found = findings(
+            &BucketExposure {
+                public_policy: true,
+                ..Default::default()
+            },
+            &grants,
+        );
+        assert_eq!(
+            audit.report("s3://bucket/a", &found).as_deref(),
+            Some("critical\ts3://bucket/a\tAllUsers:READ, AuthenticatedUsers:READ, public bucket policy")
+        );
+        assert_eq!(
+            audit.report("s3://bucket/a", &findings(&blocked, &grants)),
+            None
+        );
+        assert_eq!(audit.report("s3://bucket/a", &[]), None);
+    }
+
+    #[test]
+    fn unencrypted_groups() {
+        let head = |algorithm: Option<&str>, key: Option<&str>| HeadObjectOutput {
+            server_side_encryption: algorithm.map(str::to_owned),
+            ssekms_key_id: key.map(str::to_owned),
+            ..Default::default()
+        };
+        let arn = "arn:aws:kms:us-east-1:123456789012:key/1234abcd";
+        assert_eq!(
+            encryption_gap(&head(None, None), None),
+            Some(EncryptionGap::Unencrypted)
+        );
+        assert_eq!(encryption_gap(&head(Some("AES256"), None), None), None);
+        assert_eq!(
+            encryption_gap(&head(Some("AES256"), None), Some("1234abcd")),
+            Some(EncryptionGap::NotKms)
+        );
+        assert_eq!(
+            encryption_gap(&head(Some("aws:kms"), Some(arn)), Some("1234abcd")),
+            None
+        );
+        assert_eq!(
+            encryption_gap(&head(Some("aws:kms"), Some(arn)), Some("5678")),
+            Some(EncryptionGap::OtherKmsKey)
+        );
+
+        let path: S3path = "s3://bucket/data/".parse().unwrap();
+        assert_eq!(prefix_group(&path, "data/a/b/c.csv", 1), "data/a/");
+        assert_eq!(prefix_group(&path, "data/a/b/c.csv", 2), "data/a/b/");
+        assert_eq!(prefix_group(&path, "data/a/b/c.csv", 0), "data/");
+        assert_eq!(prefix_group(&path, "data/c.csv", 1), "data/");
+
+        let audit = EncryptionAudit::new(AuditUnencrypted {
+            concurrency: 1,
+            kms_key: None,
+            depth: 1,
+            json: false,
+        });
+        for (key, size) in &[("data/a/1", 10), ("data/a/2", 5), ("data/b/1", 1)] {
+            let object = Object {
+                key: Some((*key).to_owned()),
+                size: Some(*size),
+                ..Default::default()
+            };
+            audit.add(&path, &object, EncryptionGap::Unencrypted);
+        }
+        assert_eq!(
+            audit.report(),
+            vec![
+                "unencrypted\ts3://bucket/data/a/\t2\t15",
+                "unencrypted\ts3://bucket/data/b/\t1\t1",
+            ]
+        );
+        assert!(audit.report().is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+use humansize::{file_size_opts as options, FileSize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metrics::Metrics;
+use crate::quantile::QuantileSketch;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+struct State {
+    start: Instant,
+    latencies: BTreeMap<String, QuantileSketch>,
+    downloaded: u64,
+    downloads: u64,
+    download_time: Option<(Instant, Instant)>,
+}
+
+/// Start collecting request latencies and download transfers
+pub fn start() {
+    *STATE.lock().unwrap() = Some(State {
+        start: Instant::now(),
+        latencies: BTreeMap::new(),
+        downloaded: 0,
+        downloads: 0,
+        download_time: None,
+    });
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// An S3 call attempt of `operation` took `latency`
+pub fn request(operation: &str, latency: Duration) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if let Some(ref mut state) = *STATE.lock().unwrap() {
+        state
+            .latencies
+            .entry(operation.to_owned())
+            .or_default()
+            .add(latency.as_micros() as i64);
+    }
+}
+
+/// An object of `bytes` has been downloaded since `start`
+pub fn downloaded(bytes: u64, start: Instant) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if let Some(ref mut state) = *STATE.lock().unwrap() {
+        let end = Instant::now();
+        state.downloaded += bytes;
+        state.downloads += 1;
+        state.download_time = Some(match state.download_time {
+            Some((first, _)) => (first.min(start), end),
+            None => (start, end),
+        });
+    }
+}
+
+/// Request latency percentiles in microseconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Latency {
+    pub operation: String,
+    pub requests: u64,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub max: i64,
+}
+
+/// Throughput of the current run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Benchmark {
+    pub elapsed: Duration,
+    pub keys_scanned: usize,
+    pub latencies: Vec<Latency>,
+    pub downloaded: u64,
+    pub downloads: u64,
+    pub download_time: Duration,
+}
+
+impl Benchmark {
+    pub fn snapshot() -> Option<Self> {
+        let state = STATE.lock().unwrap();
+        let state = state.as_ref()?;
+
+        let latencies = state
+            .latencies
+            .iter()
+            .map(|(operation, sketch)| Latency {
+                operation: operation.clone(),
+                requests: sketch.count(),
+                p50: sketch.quantile(0.5).unwrap_or_default(),
+                p90: sketch.quantile(0.9).unwrap_or_default(),
+                p99: sketch.quantile(0.99).unwrap_or_default(),
+                max: sketch.quantile(1.0).unwrap_or_default(),
+            })
+            .collect();
+
+        Some(Benchmark {
+            elapsed: state.start.elapsed(),
+            keys_scanned: Metrics::snapshot().objects_scanned,
+            latencies,
+            downloaded: state.downloaded,
+            downloads: state.downloads,
+            download_time: state
+                .download_time
+                .map(|(first, last)| last - first)
+                .unwrap_or_default(),
+        })
+    }
+
+    pub fn keys_per_second(&self) -> f64 {
+        per_second(self.keys_scanned as f64, self.elapsed)
+    }
+
+    /// Bytes per second from the start of the first download to the end of the last one
+    pub fn download_bandwidth(&self) -> f64 {
+        per_second(self.downloaded as f64, self.download_time)
+    }
+}
+
+fn per_second(value: f64, duration: Duration) -> f64 {
+    if duration.as_secs_f64() > 0.0 {
+        value / duration.as_secs_f64()
+    } else {
+        0.0
+    }
+}
+
+fn millis(micros: i64) -> f64 {
+    micros as f64 / 1000.0
+}
+
+impl fmt::Display for Benchmark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Benchmark")?;
+        writeln!(f, "Elapsed: {:.3}s", self.elapsed.as_secs_f64())?;
+        writeln!(
+            f,
+            "Listing: {} keys, {:.1} keys/s",
+            self.keys_scanned,
+            self.keys_per_second()
+        )?;
+
+        for latency in &self.latencies {
+            writeln!(
+                f,
+                "{}: {} requests, latency p50 {:.1}ms p90 {:.1}ms p99 {:.1}ms max {:.1}ms",
+                latency.operation,
+                latency.requests,
+                millis(latency.p50),
+                millis(latency.p90),
+                millis(latency.p99),
+                millis(latency.max)
+            )?;
+        }
+
+        if self.downloads > 0 {
+            let size = |x: u64| {
+                x.file_size(options::CONVENTIONAL)
+                    .unwrap_or_else(|_| x.to_string())
+            };
+            writeln!(
+                f,
+                "Download: {} objects, {} in {:.3}s, {}/s",
+                self.downloads,
+                size(self.downloaded),
+                self.download_time.as_secs_f64(),
+                size(self.download_bandwidth() as u64)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmark_display() {
+        let benchmark = Benchmark {
+            elapsed: Duration::from_secs(2),
+            keys_scanned: 5000,
+            latencies: vec![Latency {
+                operation: "ListObjectsV2".to_owned(),
+                requests: 5,
+                p50: 120_000,
+                p90: 200_500,
+                p99: 450_000,
+                max: 450_000,
+            }],
+            downloaded: 4096,
+            downloads: 2,
+            download_time: Duration::from_secs(1),
+        };
+
+        assert_eq!(benchmark.keys_per_second(), 2500.0);
+        assert_eq!(benchmark.download_bandwidth(), 4096.0);
+        assert_eq!(
+            benchmark.to_string(),
+            "Benchmark
+Elapsed: 2.000s
+Listing: 5000 keys, 2500.0 keys/s
+ListObjectsV2: 5 requests, latency p50 120.0ms p90 200.5ms p99 450.0ms max 450.0ms
+Download: 2 objects, 4 KB in 1.000s, 4 KB/s
+"
+        );
+    }
+
+    #[test]
+    fn collect_requests() {
+        start();
+        request("HeadObject", Duration::from_millis(10));
+        request("HeadObject", Duration::from_millis(20));
+        downloaded(100, Instant::now());
+
+        let benchmark = Benchmark::snapshot().unwrap();
+        let latency = benchmark
+            .latencies
+            .iter()
+            .find(|x| x.operation == "HeadObject")
+            .unwrap();
+        assert_eq!(latency.requests, 2);
+        assert!(benchmark.downloaded >= 100);
+    }
+}
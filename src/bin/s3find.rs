@@ -1,27 +1,151 @@
-use failure::Error;
+use std::process;
+use structopt::clap::{self, ErrorKind};
 use structopt::StructOpt;
 
 use s3find::arg::*;
+use s3find::benchmark::{self, Benchmark};
+use s3find::buckets::list_buckets;
+use s3find::cancel::{self, handle_signals};
 use s3find::command::*;
-use s3find::run::list_filter_execute;
+use s3find::config;
+use s3find::daemon::daemon;
+use s3find::estimate::{confirm, EgressEstimate};
+use s3find::listing::set_list_api;
+use s3find::logger;
+use s3find::man;
+use s3find::metrics::Metrics;
+use s3find::ratelimit::set_rate_limit;
+use s3find::report::{exit_code, set_error_color, set_error_format, ErrorReport};
+use s3find::retry::set_retry_policy;
+use s3find::run::{summarize, walk, Walk};
+use s3find::snapshot::diff_snapshots;
+use s3find::sqs::listen;
+use s3find::watch::watch;
 
-fn main() -> Result<(), Error> {
-    let status: Find = FindOpt::from_args().into();
+fn main() {
+    let opts = config::from_args();
+    if let Err(e) = logger::init(opts.log_level, opts.log_file.as_deref()) {
+        eprintln!("Failed to initialize logging: {}", e);
+        process::exit(1);
+    }
 
-    let stats = list_filter_execute(
-        status.iter(),
-        status.limit,
-        status.stats(),
-        |x| status.filters.test_match(x),
-        |acc, x| status.exec(acc, x),
-    )?;
+    if opts.insecure {
+        eprintln!("Warning: TLS certificate verification is disabled (--insecure)");
+    }
 
-    if status.summarize {
-        println!("{}", stats.unwrap());
+    if let Err(e) = handle_signals() {
+        eprintln!("Failed to handle signals: {}", e);
     }
 
-    Ok(())
-}
+    set_retry_policy(opts.retries, opts.retry_base_delay);
+    set_rate_limit(opts.rps.map(|x| x.0));
+    set_list_api(opts.list_api);
+    if opts.benchmark {
+        benchmark::start();
+    }
+
+    if let Some(Cmd::Man(_)) = opts.cmd {
+        print!("{}", man::render(&FindOpt::clap()));
+        process::exit(0);
+    }
+
+    if let Some(Cmd::Buckets(ref list)) = opts.cmd {
+        if let Err(e) = list_buckets(&opts, list) {
+            ErrorReport::new("ListBuckets", "", None, &e).emit();
+            process::exit(exit_code(true));
+        }
+        process::exit(exit_code(false));
+    }
+
+    if let Some(Cmd::Snapshot(Snapshot::Diff(ref diff))) = opts.cmd {
+        if let Err(e) = diff_snapshots(diff) {
+            ErrorReport::new("s3find", "", None, &e).emit();
+            process::exit(exit_code(true));
+        }
+        process::exit(exit_code(false));
+    }
 
-#[cfg(test)]
-mod tests {}
+    let metrics_sink = opts.metrics.clone();
+    let save_resume = opts.save_resume.clone();
+    let watch_interval = Some(opts.interval.0).filter(|_| opts.watch);
+    let schedule = opts.schedule.filter(|_| opts.daemon);
+    let estimate = match opts.cmd {
+        Some(Cmd::Download(ref download)) if download.estimate => {
+            Some((download.bandwidth, download.resume_from.clone()))
+        }
+        _ => None,
+    };
+    let mut status = Find::new(opts).unwrap_or_else(|e| match e.downcast::<clap::Error>() {
+        Ok(e) => e.exit(),
+        Err(e) => clap::Error::with_description(&e.to_string(), ErrorKind::InvalidValue).exit(),
+    });
+    set_error_format(status.errors);
+    set_error_color(status.color);
+
+    if let Some((bandwidth, resume_from)) = estimate {
+        match EgressEstimate::collect(&mut status, bandwidth, resume_from.as_deref()) {
+            Ok(estimate) => eprint!("{}", estimate),
+            Err(e) => {
+                ErrorReport::new("s3find", &status.path.bucket, None, &e).emit();
+                process::exit(exit_code(true));
+            }
+        }
+        match confirm("Proceed with the download?") {
+            Ok(true) => {}
+            Ok(false) => process::exit(0),
+            Err(e) => {
+                ErrorReport::new("s3find", "", None, &e).emit();
+                process::exit(exit_code(true));
+            }
+        }
+    }
+
+    let mut failed = false;
+    let mut finish = |status: &Find, walk: &Walk| {
+        if walk.cancelled {
+            eprintln!("Interrupted, the run stopped after the running requests");
+            if let Some(ref file) = save_resume {
+                eprintln!("Continue the run with --resume {}", file.display());
+            }
+        }
+        failed |= walk.failed();
+        if let Err(e) = status.command.finish(&*status.output) {
+            ErrorReport::new("s3find", &status.path.bucket, None, &e).emit();
+            failed = true;
+        }
+        if let Err(e) = summarize(status, walk) {
+            ErrorReport::new("s3find", &status.path.bucket, None, &e).emit();
+            failed = true;
+        }
+        if let Some(ref notifier) = status.notifier {
+            if let Err(e) = notifier.notify(status, walk) {
+                ErrorReport::new("Notify", "", None, &e).emit();
+                failed = true;
+            }
+        }
+    };
+    match (watch_interval, status.queue.clone(), schedule) {
+        (_, Some(queue), _) => {
+            let walk = listen(&mut status, &queue);
+            finish(&status, &walk)
+        }
+        (Some(interval), None, _) => watch(&mut status, interval, finish),
+        (None, None, Some(schedule)) => daemon(&mut status, &schedule, finish),
+        (None, None, None) => {
+            let walk = walk(&mut status);
+            finish(&status, &walk)
+        }
+    }
+
+    if let Some(benchmark) = Benchmark::snapshot() {
+        eprint!("{}", benchmark);
+    }
+
+    if let Some(ref sink) = metrics_sink {
+        if let Err(e) = Metrics::snapshot().publish(sink) {
+            eprintln!("Failed to publish metrics: {}", e);
+        }
+    }
+
+    process::exit(cancel::exit_code().unwrap_or_else(|| exit_code(failed)));
+}
@@ -0,0 +1,363 @@
+use failure::Error;
+use log::debug;
+use rusoto_core::{Region, RusotoError};
+use rusoto_s3::{
+    GetBucketEncryptionRequest, GetBucketLocationRequest, GetBucketVersioningRequest,
+    GetPublicAccessBlockRequest, Object, PublicAccessBlockConfiguration, S3Client, S3,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::arg::{FindOpt, ListBuckets, S3path};
+use crate::command::get_client;
+use crate::filter::Filter;
+use crate::report::ErrorReport;
+use crate::retry::with_retry;
+
+/// A bucket of the account with the statuses requested by the options
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BucketInfo {
+    pub name: String,
+    pub creation_date: Option<String>,
+    pub region: String,
+    pub encryption: Option<String>,
+    pub versioning: Option<String>,
+    pub public_access_block: Option<String>,
+}
+
+impl BucketInfo {
+    pub fn to_text(&self) -> String {
+        let mut columns = vec![
+            self.name.as_str(),
+            self.creation_date.as_deref().unwrap_or("-"),
+            self.region.as_str(),
+        ];
+        columns.extend(
+            [
+                &self.encryption,
+                &self.versioning,
+                &self.public_access_block,
+            ]
+            .iter()
+            .filter_map(|x| x.as_deref()),
+        );
+        columns.join("\t")
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut value = json!({
+            "name": self.name,
+            "creation_date": self.creation_date,
+            "region": self.region,
+        });
+        for (name, status) in &[
+            ("encryption", &self.encryption),
+            ("versioning", &self.versioning),
+            ("public_access_block", &self.public_access_block),
+        ] {
+            if let Some(status) = status {
+                value[*name] = json!(status);
+            }
+        }
+        value
+    }
+}
+
+impl ListBuckets {
+    /// The name and creation date filters, the bucket as an object of its name
+    pub fn matches_name(&self, name: &str, creation_date: Option<&str>) -> bool {
+        let object = Object {
+            key: Some(name.to_owned()),
+            last_modified: creation_date.map(str::to_owned),
+            ..Default::default()
+        };
+
+        (self.name.is_empty() || self.name.iter().any(|x| x.filter(&object)))
+            && self.regex.iter().all(|x| x.0.filter(&object))
+            && self.created.iter().all(|x| x.filter(&object))
+    }
+
+    pub fn matches_region(&self, region: &str) -> bool {
+        self.region.is_empty() || self.region.iter().any(|x| x == region)
+    }
+}
+
+/// Print the buckets of the account matching the options of the buckets subcommand
+pub fn list_buckets(opts: &FindOpt, list: &ListBuckets) -> Result<(), Error> {
+    let client = get_client(opts, client_region(opts, &opts.aws_region))?;
+    let mut regional: HashMap<String, S3Client> = HashMap::new();
+
+    debug!("ListBuckets");
+    let buckets = with_retry("ListBuckets", || client.list_buckets())?
+        .buckets
+        .unwrap_or_default();
+
+    for bucket in buckets {
+        let name = bucket.name.unwrap_or_default();
+        if !list.matches_name(&name, bucket.creation_date.as_deref()) {
+            continue;
+        }
+
+        let region = match bucket_region(&client, &name) {
+            Some(region) => region,
+            None => continue,
+        };
+        if !list.matches_region(&region) {
+            continue;
+        }
+
+        // the bucket statuses are requested from the region of the bucket
+        if !regional.contains_key(&region) {
+            let bucket_region =
+                Region::from_str(&region).unwrap_or_else(|_| opts.aws_region.clone());
+            let client = get_client(opts, client_region(opts, &bucket_region))?;
+            regional.insert(region.clone(), client);
+        }
+        let client = &regional[&region];
+
+        let info = BucketInfo {
+            encryption: if list.encryption {
+                Some(encryption(client, &name))
+            } else {
+                None
+            },
+            versioning: if list.versioning {
+                Some(versioning(client, &name))
+            } else {
+                None
+            },
+            public_access_block: if list.public_access_block {
+                Some(public_access_block(client, &name))
+            } else {
+                None
+            },
+            name,
+            creation_date: bucket.creation_date,
+            region,
+        };
+
+        if list.json {
+            println!("{}", info.to_json());
+        } else {
+            println!("{}", info.to_text());
+        }
+    }
+
+    Ok(())
+}
+
+/// The client and region of each bucket of the paths, from GetBucketLocation
+/// with one client per region. The client of the options is kept for the
+/// buckets in its region and when the region is unknown
+pub(crate) fn bucket_clients(
+    opts: &FindOpt,
+    client: &S3Client,
+    region: &Region,
+    paths: &[S3path],
+) -> Result<HashMap<String, (S3Client, Region)>, Error> {
+    let mut regional: HashMap<String, (S3Client, Region)> = HashMap::new();
+    let mut clients = HashMap::new();
+
+    for path in paths {
+        let bucket_client =
+            match bucket_region(client, &path.bucket).and_then(|x| Region::from_str(&x).ok()) {
+                Some(bucket_region) if bucket_region != opts.aws_region => {
+                    let name = bucket_region.name().to_owned();
+                    if !regional.contains_key(&name) {
+                        let region = client_region(opts, &bucket_region);
+                        regional.insert(name.clone(), (get_client(opts, region.clone())?, region));
+                    }
+                    regional[&name].clone()
+                }
+                _ => (client.clone(), region.clone()),
+            };
+        clients.insert(path.bucket.clone(), bucket_client);
+    }
+    Ok(clients)
+}
+
+pub(crate) fn client_region(opts: &FindOpt, region: &Region) -> Region {
+    match opts.endpoint_url {
+        Some(ref endpoint) => endpoint.region(region),
+        None => region.clone(),
+    }
+}
+
+/// The location constraint is empty for us-east-1 and EU for the old eu-west-1 buckets
+fn location_region(constraint: Option<&str>) -> String {
+    match constraint {
+        None | Some("") => "us-east-1".to_owned(),
+        Some("EU") => "eu-west-1".to_owned(),
+        Some(region) => region.to_owned(),
+    }
+}
+
+pub(crate) fn bucket_region(client: &S3Client, bucket: &str) -> Option<String> {
+    let request = GetBucketLocationRequest {
+        bucket: bucket.to_owned(),
+    };
+    debug!("GetBucketLocation {}", bucket);
+    match with_retry("GetBucketLocation", || {
+        client.get_bucket_location(request.clone())
+    }) {
+        Ok(x) => Some(location_region(x.location_constraint.as_deref())),
+        Err(e) => {
+            ErrorReport::from_rusoto("GetBucketLocation", bucket, None, &e).emit();
+            None
+        }
+    }
+}
+
+/// The configuration is missing: S3 answers 404
+pub(crate) fn not_configured<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::Unknown(response) => response.status.as_u16() == 404,
+        _ => false,
+    }
+}
+
+fn encryption(client: &S3Client, bucket: &str) -> String {
+    let request = GetBucketEncryptionRequest {
+        bucket: bucket.to_owned(),
+    };
+    debug!("GetBucketEncryption {}", bucket);
+    match with_retry("GetBucketEncryption", || {
+        client.get_bucket_encryption(request.clone())
+    }) {
+        Ok(x) => x
+            .server_side_encryption_configuration
+            .and_then(|x| x.rules.into_iter().next())
+            .and_then(|x| x.apply_server_side_encryption_by_default)
+            .map(|x| x.sse_algorithm)
+            .unwrap_or_else(|| "none".to_owned()),
+        Err(ref e) if not_configured(e) => "none".to_owned(),
+        Err(e) => {
+            ErrorReport::from_rusoto("GetBucketEncryption", bucket, None, &e).emit();
+            "error".to_owned()
+        }
+    }
+}
+
+fn versioning(client: &S3Client, bucket: &str) -> String {
+    let request = GetBucketVersioningRequest {
+        bucket: bucket.to_owned(),
+    };
+    debug!("GetBucketVersioning {}", bucket);
+    match with_retry("GetBucketVersioning", || {
+        client.get_bucket_versioning(request.clone())
+    }) {
+        // never enabled
+        Ok(x) => x.status.unwrap_or_else(|| "Disabled".to_owned()),
+        Err(e) => {
+            ErrorReport::from_rusoto("GetBucketVersioning", bucket, None, &e).emit();
+            "error".to_owned()
+        }
+    }
+}
+
+fn public_access_block(client: &S3Client, bucket: &str) -> String {
+    let request = GetPublicAccessBlockRequest {
+        bucket: bucket.to_owned(),
+    };
+    debug!("GetPublicAccessBlock {}", bucket);
+    match with_retry("GetPublicAccessBlock", || {
+        client.get_public_access_block(request.clone())
+    }) {
+        Ok(x) => block_status(x.public_access_block_configuration.as_ref()).to_owned(),
+        Err(ref e) if not_configured(e) => "none".to_owned(),
+        Err(e) => {
+            ErrorReport::from_rusoto("GetPublicAccessBlock", bucket, None, &e).emit();
+            "error".to_owned()
+        }
+    }
+}
+
+fn block_status(configuration: Option<&PublicAccessBlockConfiguration>) -> &'static str {
+    let settings = match configuration {
+        Some(x) => [
+            x.block_public_acls,
+            x.ignore_public_acls,
+            x.block_public_policy,
+            x.restrict_public_buckets,
+        ],
+        None => return "none",
+    };
+
+    let enabled = settings.iter().filter(|x| **x == Some(true)).count();
+    match enabled {
+        4 => "blocked",
+        0 => "none",
+        _ => "partial",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg::{BucketRegex, FindTime};
+    use glob::Pattern;
+    use regex::Regex;
+
+    #[test]
+    fn buckets_filter() {
+        let list = ListBuckets {
+            name: vec![Pattern::new("logs-*").unwrap()],
+            regex: vec![BucketRegex(Regex::new("prod$").unwrap())],
+            created: vec![FindTime::Upper(3600)],
+            region: vec!["eu-west-1".to_owned()],
+            ..Default::default()
+        };
+
+        let old = Some("2020-01-01T00:00:00.000Z");
+        assert!(list.matches_name("logs-api-prod", old));
+        assert!(!list.matches_name("logs-api-dev", old));
+        assert!(!list.matches_name("data-prod", old));
+        assert!(!list.matches_name("logs-api-prod", None));
+        assert!(list.matches_region("eu-west-1"));
+        assert!(!list.matches_region("us-east-1"));
+        assert!(ListBuckets::default().matches_name("any", None));
+
+        assert_eq!(location_region(None), "us-east-1");
+        assert_eq!(location_region(Some("EU")), "eu-west-1");
+        assert_eq!(location_region(Some("ap-south-1")), "ap-south-1");
+    }
+
+    #[test]
+    fn buckets_output() {
+        let block = PublicAccessBlockConfiguration {
+            block_public_acls: Some(true),
+            ignore_public_acls: Some(true),
+            block_public_policy: Some(true),
+            restrict_public_buckets: Some(true),
+        };
+        assert_eq!(block_status(Some(&block)), "blocked");
+        let partial = PublicAccessBlockConfiguration {
+            block_public_policy: Some(false),
+            ..block
+        };
+        assert_eq!(block_status(Some(&partial)), "partial");
+        assert_eq!(block_status(None), "none");
+
+        let info = BucketInfo {
+            name: "logs".to_owned(),
+            creation_date: Some("2020-01-01T00:00:00.000Z".to_owned()),
+            region: "us-east-1".to_owned(),
+            versioning: Some("Enabled".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            info.to_text(),
+            "logs\t2020-01-01T00:00:00.000Z\tus-east-1\tEnabled"
+        );
+        assert_eq!(
+            info.to_json(),
+            json!({
+                "name": "logs",
+                "creation_date": "2020-01-01T00:00:00.000Z",
+                "region": "us-east-1",
+                "versioning": "Enabled",
+            })
+        );
+    }
+}
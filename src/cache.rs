@@ -0,0 +1,312 @@
+use failure::Error;
+use rusoto_s3::{Object, Owner};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Lines, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::arg::S3path;
+use crate::error::FunctionError;
+
+const CACHE_VERSION: u64 = 1;
+
+/// Bytes read from the end of the cache for the trailer line
+const TRAILER_SIZE: u64 = 64;
+
+/// Listing cache: a header line with the listed path, one JSON document per
+/// object and a trailer line written once the listing has been exhausted
+pub struct CacheWriter<I> {
+    inner: I,
+    output: BufWriter<File>,
+    finished: bool,
+}
+
+impl<I> CacheWriter<I>
+where
+    I: Iterator<Item = Result<Vec<Object>, Error>>,
+{
+    pub fn new(inner: I, file: &Path, path: &S3path) -> Result<Self, Error> {
+        let mut output = BufWriter::new(File::create(file)?);
+        let header = json!({
+            "version": CACHE_VERSION,
+            "bucket": path.bucket,
+            "prefix": path.prefix,
+        });
+        writeln!(output, "{}", header)?;
+
+        Ok(CacheWriter {
+            inner,
+            output,
+            finished: false,
+        })
+    }
+
+    fn write_page(&mut self, list: &[Object]) -> Result<(), Error> {
+        for object in list {
+            writeln!(self.output, "{}", object_to_cache(object))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        writeln!(self.output, "{}", json!({ "complete": true }))?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+impl<I> Iterator for CacheWriter<I>
+where
+    I: Iterator<Item = Result<Vec<Object>, Error>>,
+{
+    type Item = Result<Vec<Object>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        match self.inner.next() {
+            Some(Ok(list)) => Some(self.write_page(&list).map(|_| list)),
+            Some(Err(e)) => Some(Err(e)),
+            None => {
+                self.finished = true;
+                self.finish().err().map(Err)
+            }
+        }
+    }
+}
+
+/// Pages of the objects stored in a complete listing cache
+pub struct CacheReader {
+    lines: Lines<BufReader<File>>,
+    prefix: String,
+    page_size: usize,
+    complete: bool,
+}
+
+impl CacheReader {
+    /// The cached listing has to cover the requested path and be complete,
+    /// which is checked before any page is read
+    pub fn open(file: &Path, path: &S3path, page_size: usize) -> Result<Self, Error> {
+        let mut file = File::open(file)?;
+        if !has_trailer(&mut file)? {
+            return Err(FunctionError::CacheIncompleteError.into());
+        }
+        let mut lines = BufReader::new(file).lines();
+        let header: Value = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => return Err(FunctionError::CacheFormatError.into()),
+        };
+
+        if header["version"] != CACHE_VERSION {
+            return Err(FunctionError::CacheFormatError.into());
+        }
+
+        let cached_prefix = header["prefix"].as_str().unwrap_or_default();
+        let prefix = path.prefix.clone().unwrap_or_default();
+        if header["bucket"] != path.bucket.as_str() || !prefix.starts_with(cached_prefix) {
+            return Err(FunctionError::CachePathError.into());
+        }
+
+        Ok(CacheReader {
+            lines,
+            prefix,
+            page_size: page_size.max(1),
+            complete: false,
+        })
+    }
+
+    fn read_page(&mut self) -> Result<Vec<Object>, Error> {
+        let mut list = Vec::new();
+        while list.len() < self.page_size {
+            let line = match self.lines.next() {
+                Some(line) => line?,
+                None => return Err(FunctionError::CacheIncompleteError.into()),
+            };
+
+            let value: Value = serde_json::from_str(&line)?;
+            if value["complete"] == true {
+                self.complete = true;
+                break;
+            }
+
+            let object = object_from_cache(&value)?;
+            if object
+                .key
+                .as_deref()
+                .unwrap_or_default()
+                .starts_with(&self.prefix)
+            {
+                list.push(object);
+            }
+        }
+        Ok(list)
+    }
+}
+
+impl Iterator for CacheReader {
+    type Item = Result<Vec<Object>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.complete {
+            return None;
+        }
+
+        match self.read_page() {
+            Ok(ref list) if list.is_empty() && self.complete => None,
+            result => {
+                if result.is_err() {
+                    self.complete = true;
+                }
+                Some(result)
+            }
+        }
+    }
+}
+
+/// The last line is the trailer written once the listing has been exhausted,
+/// the file is rewound for the reader
+fn has_trailer(file: &mut File) -> Result<bool, Error> {
+    let size = file.metadata()?.len();
+    file.seek(SeekFrom::Start(size.saturating_sub(TRAILER_SIZE)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    // the cut may fall within a UTF-8 sequence of a key
+    let complete = match String::from_utf8_lossy(&tail).lines().last() {
+        Some(line) => serde_json::from_str::<Value>(line)
+            .map(|x| x["complete"] == true)
+            .unwrap_or(false),
+        None => false,
+    };
+    Ok(complete)
+}
+
+pub fn object_to_cache(object: &Object) -> Value {
+    json!({
+        "key": object.key,
+        "size": object.size,
+        "last_modified": object.last_modified,
+        "storage_class": object.storage_class,
+        "etag": object.e_tag,
+        "owner_id": object.owner.as_ref().and_then(|x| x.id.as_ref()),
+        "owner": object.owner.as_ref().and_then(|x| x.display_name.as_ref()),
+    })
+}
+
+pub fn object_from_cache(value: &Value) -> Result<Object, Error> {
+    let string = |field: &str| value[field].as_str().map(str::to_owned);
+
+    let key = string("key").ok_or(FunctionError::CacheFormatError)?;
+    let owner = match (string("owner_id"), string("owner")) {
+        (None, None) => None,
+        (id, display_name) => Some(Owner { id, display_name }),
+    };
+
+    Ok(Object {
+        key: Some(key),
+        size: value["size"].as_i64(),
+        last_modified: string("last_modified"),
+        storage_class: string("storage_class"),
+        e_tag: string("etag"),
+        owner,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn object(key: &str) -> Object {
+        Object {
+            key: Some(key.to_owned()),
+            size: Some(10),
+            last_modified: Some("2017-07-19T19:04:17.000Z".to_owned()),
+            storage_class: Some("STANDARD".to_owned()),
+            e_tag: Some("9d48114aa7c18f9d68aa20086dbb7756".to_owned()),
+            owner: Some(Owner {
+                id: Some("1234".to_owned()),
+                display_name: None,
+            }),
+        }
+    }
+
+    fn path(prefix: Option<&str>) -> S3path {
+        S3path {
+            bucket: "bucket".to_owned(),
+            prefix: prefix.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn cache_roundtrip() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let file = dir.path().join("listing.cache");
+
+        let pages = vec![
+            Ok(vec![object("logs/a"), object("logs/b")]),
+            Ok(vec![object("data/c")]),
+        ];
+        let written: Vec<_> =
+            CacheWriter::new(pages.into_iter(), &file, &path(None))?.collect::<Result<_, _>>()?;
+        assert_eq!(written.len(), 2);
+
+        let read: Vec<Vec<Object>> =
+            CacheReader::open(&file, &path(None), 2)?.collect::<Result<_, _>>()?;
+        assert_eq!(
+            read,
+            vec![
+                vec![object("logs/a"), object("logs/b")],
+                vec![object("data/c")]
+            ]
+        );
+
+        let read: Vec<Vec<Object>> =
+            CacheReader::open(&file, &path(Some("logs/")), 1000)?.collect::<Result<_, _>>()?;
+        assert_eq!(read, vec![vec![object("logs/a"), object("logs/b")]]);
+
+        assert!(CacheReader::open(&file, &path(None), 10).is_ok());
+        assert!(CacheReader::open(
+            &file,
+            &S3path {
+                bucket: "other".to_owned(),
+                prefix: None
+            },
+            10
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stopped_listing_cache() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let file = dir.path().join("listing.cache");
+
+        // --limit stops pulling pages once enough keys are matched
+        let pages = vec![Ok(vec![object("logs/a")]), Ok(vec![object("logs/b")])];
+        let first = CacheWriter::new(pages.into_iter(), &file, &path(None))?.next();
+        assert!(first.is_some());
+
+        assert!(CacheReader::open(&file, &path(None), 10).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn incomplete_cache() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let file = dir.path().join("listing.cache");
+        fs::write(
+            &file,
+            "{\"version\":1,\"bucket\":\"bucket\",\"prefix\":\"logs/\"}\n{\"key\":\"logs/a\"}\n",
+        )?;
+
+        assert!(CacheReader::open(&file, &path(None), 10).is_err());
+        assert!(CacheReader::open(&file, &path(Some("logs/a")), 10).is_err());
+        Ok(())
+    }
+}
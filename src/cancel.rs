@@ -0,0 +1,74 @@
+use signal_hook::{SIGINT, SIGTERM};
+use std::io;
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static SIGNAL: AtomicUsize = AtomicUsize::new(0);
+
+/// Stop the run on SIGINT and SIGTERM: no new page is listed and no new
+/// buffer of matched keys is run, the running command finishes its buffer.
+/// A second signal exits at once
+pub fn handle_signals() -> io::Result<()> {
+    for &signal in &[SIGINT, SIGTERM] {
+        unsafe { signal_hook::register(signal, move || on_signal(signal)) }?;
+    }
+    Ok(())
+}
+
+// only async-signal-safe calls in the handler
+fn on_signal(signal: c_int) {
+    if SIGNAL.swap(signal as usize, Ordering::SeqCst) != 0 {
+        unsafe { libc::_exit(128 + signal) };
+    }
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+/// Stop the run as on a signal, e.g. from another thread of a library caller
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+/// Allow a new run after a cancellation
+pub fn reset() {
+    CANCELLED.store(false, Ordering::SeqCst);
+    SIGNAL.store(0, Ordering::SeqCst);
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// The signal which cancelled the run
+pub fn signal() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal as i32),
+    }
+}
+
+/// Exit code of an interrupted run, 128 plus the signal as for a shell
+pub fn exit_code() -> Option<i32> {
+    signal().map(|x| 128 + x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancellation() {
+        on_signal(SIGTERM);
+        assert!(is_cancelled());
+        assert_eq!(signal(), Some(SIGTERM));
+        assert_eq!(exit_code(), Some(143));
+
+        reset();
+        assert!(!is_cancelled());
+        assert_eq!(exit_code(), None);
+        cancel();
+        assert!(is_cancelled());
+        assert_eq!(signal(), None);
+        reset();
+    }
+}
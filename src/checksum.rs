@@ -0,0 +1,114 @@
+use failure::Error;
+use log::debug;
+use rusoto_core::Region;
+use rusoto_s3::{GetObjectRequest, Object, S3Client, S3};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+
+use crate::arg::{ManifestChecksums, S3path};
+use crate::enrich::EnrichmentCache;
+use crate::error::FunctionError;
+use crate::function::RunCommand;
+use crate::output::OutputSink;
+use crate::parallel::parallel_map;
+use crate::report::ErrorReport;
+use crate::retry::with_retry;
+
+/// SHA-256 of the data in lowercase hex, like sha256sum prints it
+pub fn sha256_hex<R: Read>(reader: &mut R) -> Result<String, Error> {
+    let mut hash = Sha256::new();
+    io::copy(reader, &mut hash)?;
+    Ok(format!("{:x}", hash.finalize()))
+}
+
+/// SHA-256 of the content of a key, streamed from GetObject
+pub fn object_sha256(client: &S3Client, bucket: &str, key: &str) -> Result<String, Error> {
+    let request = GetObjectRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        ..Default::default()
+    };
+    debug!("GetObject s3://{}/{}", bucket, key);
+    let output = with_retry("GetObject", || client.get_object(request.clone()))?;
+    let mut body = output
+        .body
+        .ok_or(FunctionError::S3FetchBodyError)?
+        .into_blocking_read();
+
+    sha256_hex(&mut body)
+}
+
+/// Line of a key in the format of sha256sum: the digest, two spaces and the
+/// key, the path of the key under a download directory
+pub fn checksum_line(digest: &str, key: &str) -> String {
+    format!("{}  {}", digest, key)
+}
+
+impl ManifestChecksums {
+    /// Replace the file with an empty one, the lines are appended by the
+    /// command
+    pub fn create(&self) -> Result<(), Error> {
+        fs::write(&self.file, "")?;
+        Ok(())
+    }
+}
+
+impl RunCommand for ManifestChecksums {
+    /// The keys that could not be read are reported and left out
+    fn execute(
+        &self,
+        client: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let lines = parallel_map(list, self.concurrency, |x| {
+            let key = x.key.as_deref().unwrap_or_default();
+            match object_sha256(client, &path.bucket, key) {
+                Ok(digest) => Some(checksum_line(&digest, key)),
+                Err(e) => {
+                    ErrorReport::new("GetObject", &path.bucket, Some(key), &e).emit();
+                    None
+                }
+            }
+        });
+
+        let mut buffer = String::new();
+        for line in lines.into_iter().flatten() {
+            buffer.push_str(&line);
+            buffer.push('\n');
+        }
+        OpenOptions::new()
+            .append(true)
+            .open(&self.file)?
+            .write_all(buffer.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_digest() {
+        let hex = |data: &[u8]| sha256_hex(&mut io::Cursor::new(data)).unwrap();
+        assert_eq!(
+            hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        assert_eq!(
+            hex(&[b'a'; 1000]),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+        assert_eq!(checksum_line("e3b0", "data/a.txt"), "e3b0  data/a.txt");
+    }
+}
@@ -1,20 +1,79 @@
-use failure::Error;
+use chrono::prelude::*;
+use failure::{format_err, Error};
+use futures::Future;
 use humansize::{file_size_opts as options, FileSize};
-use rusoto_core::request::HttpClient;
-use rusoto_core::Region;
-use rusoto_credential::{DefaultCredentialsProvider, StaticProvider};
+use log::debug;
+use rusoto_core::{Client, Region, RusotoError};
+use rusoto_credential::{
+    AutoRefreshingProvider, AwsCredentials, CredentialsError, ProfileProvider,
+    ProvideAwsCredentials, StaticProvider,
+};
 use rusoto_s3::*;
 use rusoto_s3::{ListObjectsV2Request, Object, S3Client, Tag};
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::fmt;
+use std::fs;
+use std::iter;
 use std::ops::Add;
+use std::path::PathBuf;
+use std::slice;
+use std::sync::Arc;
+use std::time::Duration;
+use structopt::clap::{self, ErrorKind};
 
+use crate::addressing::AddressingDispatcher;
 use crate::arg::*;
-use crate::filter::Filter;
+use crate::buckets::bucket_clients;
+use crate::cache::{CacheReader, CacheWriter};
+use crate::cancel::is_cancelled;
+use crate::compliance::read_rules;
+use crate::cost::CostReporter;
+use crate::enrich::EnrichmentCache;
+use crate::error::FunctionError;
+use crate::events::FindEvents;
+use crate::filter::{ETagFilter, EnrichedFilter, ExtFilter, Filter, KeyDepth, Not, OwnerFilter};
 use crate::function::*;
+use crate::imds::CredentialChain;
+use crate::inventory::InventoryIter;
+use crate::keys::KeysIter;
+use crate::listing::{
+    is_prefix, list_objects, prefix_object, DepthIter, PartitionedIter, Prefetch,
+};
+use crate::memory::{self, OBJECT_MEMORY};
+use crate::mfa;
+use crate::notify::Notifier;
+use crate::output::{OutputSink, StdoutSink};
+use crate::pagesize::{PageSizeTuner, MAX_PAGE_SIZE};
+use crate::pricing::{PriceTable, DEFAULT_STORAGE_CLASS};
+use crate::quantile::QuantileSketch;
+use crate::reconcile::Reconciler;
+use crate::replicas::{regional_clients, ReplicaMerger};
+use crate::resume::{ListingState, Resumable, ResumeTracker, ResumeWriter};
+use crate::retry::{last_latency, with_retry};
+use crate::run::Matches;
+use crate::sns::SnsPublisher;
+use crate::sort::{SortOrder, SortedPages, SORT_RUN_SIZE};
+use crate::sqs::SqsQueue;
+use crate::sso::{SsoProfile, SsoProvider};
+use crate::state::JobState;
+use crate::tiering::TieringAdvisor;
+use crate::tls::{self, HttpClient, HttpSettings, WebClient};
 
+/// Listed objects, page by page
+pub type Pages = Box<dyn Iterator<Item = Result<Vec<Object>, Error>> + Send>;
+
+/// Filters of a search, a key matches when every filter matches
 pub struct FilterList(pub Vec<Box<dyn Filter>>);
 
 impl FilterList {
+    /// Add a filter, e.g. a domain-specific rule of a library user
+    pub fn push<F: Filter + 'static>(&mut self, filter: F) {
+        self.0.push(Box::new(filter));
+    }
+
     pub fn test_match(&self, object: &Object) -> bool {
         for item in &self.0 {
             if !item.filter(object) {
@@ -26,49 +85,371 @@ impl FilterList {
     }
 }
 
+/// A search built from the options: the client, the paths, the filters and
+/// the command run on the matched keys
 pub struct Find {
     pub client: S3Client,
     pub region: Region,
     pub path: S3path,
+    pub paths: Vec<S3path>,
+    /// Client and region of the buckets expanded from a pattern or --all-buckets
+    pub bucket_clients: HashMap<String, (S3Client, Region)>,
     pub filters: FilterList,
+    pub enriched_filters: Vec<Box<dyn EnrichedFilter>>,
+    /// Data of the enriched filters, reused by the command on the matched keys
+    pub enrichment: EnrichmentCache,
     pub limit: Option<usize>,
+    /// Matched keys still to be skipped, they are not passed to the command
+    pub skip: Cell<usize>,
+    /// Order of --sort and --stable-output, listing order when None
+    pub sort: Option<SortOrder>,
+    /// Keys sorted in memory before they are spilled to a temporary file
+    pub sort_run_size: usize,
     pub page_size: i64,
+    pub page_tuner: Option<Arc<PageSizeTuner>>,
+    pub buffer_size: usize,
+    pub list_concurrency: usize,
+    pub pipeline: usize,
+    pub cache: Option<PathBuf>,
+    pub from_cache: bool,
+    pub source: ObjectSource,
+    pub resume: Option<PathBuf>,
+    pub resume_tracker: Option<Arc<ResumeTracker>>,
+    pub job_state: Option<JobState>,
+    pub encoding_type: Option<String>,
+    pub fetch_owner: bool,
+    pub delimiter: Option<String>,
+    pub min_depth: Option<usize>,
+    pub max_depth: Option<usize>,
     pub stats: bool,
     pub summarize: bool,
+    pub summarize_json: Option<PathBuf>,
+    pub progress: Option<ProgressMode>,
+    pub errors: ErrorFormat,
+    pub color: ColorChoice,
+    /// Key prefixes of --protected-prefix, skipped by the commands changing the keys
+    pub protected: Vec<String>,
+    pub prices: PriceTable,
     pub command: Box<dyn RunCommand>,
+    pub output: Arc<dyn OutputSink>,
+    pub events: Vec<Arc<dyn FindEvents>>,
+    /// Queue of the S3 events of --listen-sqs, the paths are not listed
+    pub queue: Option<Arc<SqsQueue>>,
+    /// Webhook of --notify-url, also in the events
+    pub notifier: Option<Arc<Notifier>>,
 }
 
 impl Find {
     #![allow(unreachable_patterns)]
     pub fn exec(&self, acc: Option<FindStat>, list: &[Object]) -> Result<Option<FindStat>, Error> {
-        let status = match acc {
-            Some(stat) => Some(stat + list),
-            None => None,
+        // the protected keys are left untouched and out of the summary
+        let unprotected: Vec<Object>;
+        let list = if self.command.modifies_keys() && !self.protected.is_empty() {
+            unprotected = list
+                .iter()
+                .filter(|x| !self.is_protected(x))
+                .cloned()
+                .collect();
+            &unprotected
+        } else {
+            list
+        };
+
+        let status = acc.map(|stat| stat + list);
+
+        let keys: Vec<Object>;
+        let list = if self.delimiter.is_some() && !self.command.prefixes() {
+            keys = list.iter().filter(|x| !is_prefix(x)).cloned().collect();
+            &keys
+        } else {
+            list
         };
 
-        let region = &self.region.name();
-        self.command
-            .execute(&self.client, region, &self.path, list)?;
+        debug!("Executing command on {} objects", list.len());
+        let result = self.command.execute(
+            &self.client,
+            &self.region,
+            &self.path,
+            list,
+            &self.enrichment,
+            &*self.output,
+        );
+        self.enrichment.clear();
+        result?;
         Ok(status)
     }
 
+    fn is_protected(&self, object: &Object) -> bool {
+        let key = object.key.as_deref().unwrap_or_default();
+        let protected = self.protected.iter().any(|x| key.starts_with(x.as_str()));
+        // shown whatever the --log-level, like the errors
+        if protected {
+            eprintln!(
+                "Skipping s3://{}/{} under a protected prefix",
+                self.path.bucket, key
+            );
+        }
+        protected
+    }
+
+    /// Add callbacks of the listed pages, matched objects, completed commands and errors
+    pub fn subscribe<E: FindEvents + 'static>(&mut self, events: E) {
+        self.events.push(Arc::new(events));
+    }
+
+    /// Walk `path` next, with the client of the region of its bucket
+    pub fn set_path(&mut self, path: S3path) {
+        if let Some((client, region)) = self.bucket_clients.get(&path.bucket) {
+            self.client = client.clone();
+            self.region = region.clone();
+        }
+        self.path = path;
+    }
+
+    /// Add a filter on the data fetched with additional API calls
+    pub fn add_enriched_filter<F: EnrichedFilter + 'static>(&mut self, filter: F) {
+        self.enriched_filters.push(Box::new(filter));
+    }
+
+    /// The filters of the listed data, then the enriched filters with the data
+    /// of all of them fetched once per object. The data of the matched keys is
+    /// kept for the command until their buffer is executed
+    pub fn test_match(&self, object: &Object) -> bool {
+        if !self.filters.test_match(object) {
+            return false;
+        }
+        if self.enriched_filters.is_empty() {
+            return true;
+        }
+
+        let mut fields: Vec<EnrichField> = Vec::new();
+        for field in self.enriched_filters.iter().flat_map(|x| x.fields()) {
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        let key = object.key.as_deref().unwrap_or_default();
+        let enrichment = self
+            .enrichment
+            .fetch(&self.client, &self.path.bucket, key, &fields);
+        let matched = self
+            .enriched_filters
+            .iter()
+            .all(|x| x.filter(object, &enrichment));
+        if !matched {
+            self.enrichment.remove(&self.path.bucket, key);
+        }
+        matched
+    }
+
+    /// A listed key is passed on when the run is not cancelled, it matches
+    /// the filters, it was not processed by a previous run of --state-file
+    /// and it is not one of the --skip keys, which are consumed here
+    pub fn accept(&self, object: &Object) -> bool {
+        if is_cancelled()
+            || !self.test_match(object)
+            || self
+                .job_state
+                .as_ref()
+                .is_some_and(|x| x.is_processed(&self.path.bucket, object))
+        {
+            return false;
+        }
+        if self.skip.get() > 0 {
+            self.skip.set(self.skip.get() - 1);
+            return false;
+        }
+        true
+    }
+
     pub fn stats(&self) -> Option<FindStat> {
-        if self.summarize {
-            Some(FindStat::default())
+        if self.summarize || self.summarize_json.is_some() {
+            Some(FindStat {
+                prices: self.prices.clone(),
+                ..Default::default()
+            })
         } else {
             None
         }
     }
 
+    /// Matched objects of all the paths, listed lazily as the iterator is
+    /// consumed, for the library users processing them instead of a command
+    pub fn iter_matches(&mut self) -> Matches<'_> {
+        Matches::new(self)
+    }
+
+    /// Without filters every listed key is a match, so no more than
+    /// --skip and --limit keys are requested per page, but all the keys are
+    /// listed to be sorted
+    pub fn effective_page_size(&self) -> i64 {
+        match self.limit {
+            Some(limit)
+                if self.filters.0.is_empty()
+                    && self.enriched_filters.is_empty()
+                    && !self.sorts_listing() =>
+            {
+                let keys = limit.saturating_add(self.skip.get()).max(1);
+                self.page_size.min(keys.min(i64::MAX as usize) as i64)
+            }
+            _ => self.page_size,
+        }
+    }
+
     pub fn iter(&self) -> FindIter {
         FindIter {
             client: self.client.clone(),
             path: self.path.clone(),
             token: None,
-            page_size: self.page_size,
+            page_size: self.effective_page_size(),
+            tuner: self.page_tuner.clone(),
+            encoding_type: self.encoding_type.clone(),
+            fetch_owner: self.fetch_owner,
+            delimiter: self.delimiter.clone(),
             initial: true,
         }
     }
+
+    /// Listed pages: read from the cache or an inventory report, or sequential or
+    /// partitioned by prefix with --list-concurrency, saved to the cache when it is set
+    /// and listed ahead of the command with --pipeline
+    pub fn pages(&self) -> Result<Pages, Error> {
+        let listing = self.source_pages(true)?;
+        if self.from_cache {
+            return Ok(listing);
+        }
+
+        let listing: Pages = match self.cache {
+            Some(ref file) => Box::new(CacheWriter::new(listing, file, &self.path)?),
+            None => listing,
+        };
+
+        let listing: Pages = match self.pipeline {
+            0 => listing,
+            depth => Box::new(Prefetch::new(listing, depth)),
+        };
+
+        match self.sort {
+            Some(order) if self.sorts_listing() => Ok(Box::new(SortedPages::new(
+                listing,
+                order,
+                self.sort_run_size,
+                self.effective_page_size() as usize,
+            ))),
+            _ => Ok(listing),
+        }
+    }
+
+    /// The keys are sorted unless they already come in key order: the pages
+    /// of a single listing of the keys under the path
+    pub fn sorts_listing(&self) -> bool {
+        let key_ordered = !self.from_cache
+            && self.source == ObjectSource::List
+            && self.list_concurrency <= 1
+            && self.delimiter.is_none()
+            && self.min_depth.is_none()
+            && self.max_depth.is_none();
+        match self.sort {
+            Some(SortOrder {
+                field: SortField::Key,
+                reverse: false,
+            }) => !key_ordered,
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Pages of the counting pass of --progress=full: the same objects, without
+    /// saving the cache or the resume state
+    pub fn count_pages(&self) -> Result<Pages, Error> {
+        self.source_pages(false)
+    }
+
+    fn source_pages(&self, tracked: bool) -> Result<Pages, Error> {
+        let page_size = self.effective_page_size() as usize;
+        match self.cache {
+            Some(ref file) if self.from_cache => {
+                Ok(Box::new(CacheReader::open(file, &self.path, page_size)?))
+            }
+            _ => match self.source {
+                ObjectSource::Inventory {
+                    ref bucket,
+                    ref manifest,
+                } => Ok(Box::new(InventoryIter::new(
+                    self.client.clone(),
+                    self.path.clone(),
+                    bucket.clone(),
+                    manifest.clone(),
+                    page_size,
+                ))),
+                ObjectSource::Keys { ref file, head } => Ok(Box::new(KeysIter::open(
+                    self.client.clone(),
+                    self.path.clone(),
+                    file,
+                    head,
+                    self.list_concurrency,
+                    page_size,
+                )?)),
+                ObjectSource::List => self.listing(tracked),
+            },
+        }
+    }
+
+    /// Bucket listing, continued from the --resume state when it is set
+    fn listing(&self, tracked: bool) -> Result<Pages, Error> {
+        let state = match self.resume {
+            Some(ref file) => Some(ListingState::load(file, &self.path)?),
+            None => None,
+        };
+
+        match state {
+            Some(ListingState::Complete) => Ok(Box::new(iter::empty())),
+            Some(ListingState::Sequential { token }) => Ok(self.track(
+                FindIter {
+                    token,
+                    ..self.iter()
+                },
+                tracked,
+            )),
+            Some(ListingState::Partitioned { partitions }) => Ok(self.track(
+                PartitionedIter::resume(
+                    self.client.clone(),
+                    self.path.clone(),
+                    self.effective_page_size(),
+                    self.page_tuner.clone(),
+                    self.encoding_type.clone(),
+                    self.list_concurrency,
+                    partitions,
+                )
+                .fetch_owner(self.fetch_owner),
+                tracked,
+            )),
+            None if self.min_depth.is_some() || self.max_depth.is_some() => Ok(Box::new(
+                DepthIter::new(self.iter(), self.min_depth, self.max_depth),
+            )),
+            // one level of the path is listed with the delimiter by a single listing
+            None if self.list_concurrency > 1 && self.delimiter.is_none() => Ok(self.track(
+                PartitionedIter::new(
+                    self.client.clone(),
+                    self.path.clone(),
+                    self.effective_page_size(),
+                    self.page_tuner.clone(),
+                    self.encoding_type.clone(),
+                    self.list_concurrency,
+                )
+                .fetch_owner(self.fetch_owner),
+                tracked,
+            )),
+            None => Ok(self.track(self.iter(), tracked)),
+        }
+    }
+
+    fn track<I: Resumable + Send + 'static>(&self, listing: I, tracked: bool) -> Pages {
+        match self.resume_tracker {
+            Some(ref tracker) if tracked => Box::new(ResumeWriter::new(listing, tracker.clone())),
+            _ => Box::new(listing),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -77,6 +458,10 @@ pub struct FindIter {
     pub path: S3path,
     pub token: Option<String>,
     pub page_size: i64,
+    pub tuner: Option<Arc<PageSizeTuner>>,
+    pub encoding_type: Option<String>,
+    pub fetch_owner: bool,
+    pub delimiter: Option<String>,
     pub initial: bool,
 }
 
@@ -84,17 +469,20 @@ impl Iterator for FindIter {
     type Item = Result<Vec<Object>, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if !self.initial && self.token == None {
+        if !self.initial && self.token.is_none() {
             return None;
         }
 
         let request = ListObjectsV2Request {
             bucket: self.path.bucket.clone(),
             continuation_token: self.token.clone(),
-            delimiter: None,
-            encoding_type: None,
-            fetch_owner: None,
-            max_keys: Some(self.page_size),
+            delimiter: self.delimiter.clone(),
+            encoding_type: self.encoding_type.clone(),
+            fetch_owner: Some(true).filter(|_| self.fetch_owner),
+            max_keys: Some(match self.tuner {
+                Some(ref tuner) => tuner.page_size().min(self.page_size),
+                None => self.page_size,
+            }),
             prefix: self.path.prefix.clone(),
             request_payer: None,
             start_after: None,
@@ -103,80 +491,626 @@ impl Iterator for FindIter {
         self.initial = false;
         self.token = None;
 
-        self.client
-            .list_objects_v2(request)
-            .sync()
-            .map_err(|e| e.into())
-            .map(|x| {
+        let url_encoded = self.encoding_type.as_deref() == Some("url");
+
+        debug!(
+            "ListObjectsV2 s3://{}/{} continuation token: {:?}",
+            request.bucket,
+            request.prefix.as_deref().unwrap_or_default(),
+            request.continuation_token
+        );
+
+        let result = list_objects(&self.client, &request);
+        if let Some(ref tuner) = self.tuner {
+            tuner.observe(last_latency());
+        }
+
+        result
+            .and_then(|x| {
+                debug!(
+                    "ListObjectsV2 returned {} keys, truncated: {}",
+                    x.key_count.unwrap_or_default(),
+                    x.is_truncated.unwrap_or_default()
+                );
                 self.token = x.next_continuation_token;
-                x.contents
+                let prefixes = x.common_prefixes.map(|list| {
+                    list.into_iter()
+                        .filter_map(|x| x.prefix)
+                        .map(prefix_object)
+                        .collect::<Vec<_>>()
+                });
+                let list = match (x.contents, prefixes) {
+                    (None, None) => None,
+                    (contents, prefixes) => {
+                        Some(contents.into_iter().chain(prefixes).flatten().collect())
+                    }
+                };
+                match list {
+                    Some(list) if url_encoded => decode_keys(list).map(Some),
+                    list => Ok(list),
+                }
             })
             .transpose()
     }
 }
 
-impl From<FindOpt> for Find {
-    fn from(opts: FindOpt) -> Self {
-        let filters = opts.clone().into();
+impl Resumable for FindIter {
+    fn state(&self) -> ListingState {
+        if !self.initial && self.token.is_none() {
+            ListingState::Complete
+        } else {
+            ListingState::Sequential {
+                token: self.token.clone(),
+            }
+        }
+    }
+}
 
+fn decode_keys(list: Vec<Object>) -> Result<Vec<Object>, Error> {
+    list.into_iter()
+        .map(|mut x| {
+            x.key = x.key.as_deref().map(percent_decode).transpose()?;
+            Ok(x)
+        })
+        .collect()
+}
+
+/// An error of the options, reported like the errors of the command line
+fn usage_error(message: &str, kind: ErrorKind) -> Error {
+    clap::Error::with_description(message, kind).into()
+}
+
+impl Find {
+    /// The run of the options. The clients are created, and the --newer
+    /// reference and the bucket patterns are read, so it may call S3. The
+    /// invalid options are [`clap::Error`]s
+    pub fn new(opts: FindOpt) -> Result<Self, Error> {
+        let mut filters: FilterList = opts.clone().into();
+        let region = match opts.endpoint_url {
+            Some(ref endpoint) => endpoint.region(&opts.aws_region),
+            None => opts.aws_region.clone(),
+        };
+        let paths = match opts.all_buckets {
+            Some(ref pattern) => vec![S3path {
+                bucket: pattern.clone().unwrap_or_else(|| "*".to_owned()),
+                prefix: opts.prefix.clone(),
+            }],
+            None => opts.path.iter().chain(opts.paths.iter()).cloned().collect(),
+        };
+        // the path is optional only for the buckets, snapshot diff and man subcommands
+        if paths.is_empty() {
+            let message = "The following required arguments were not provided:\n    <path>";
+            return Err(usage_error(message, ErrorKind::MissingRequiredArgument));
+        }
+        let provider = credentials(&opts, &region)?;
+        let client = s3_client(provider.clone(), &opts, region.clone())?;
+        let sns = match opts.cmd {
+            Some(Cmd::PublishSns(_)) => {
+                Some(Client::new_with(provider.clone(), http_client(&opts)?))
+            }
+            _ => None,
+        };
+        let queue = match opts.listen_sqs {
+            Some(ref url) => {
+                let sqs = Client::new_with(provider, http_client(&opts)?);
+                Some(Arc::new(SqsQueue::new(url, &opts.aws_region, sqs)))
+            }
+            None => None,
+        };
+        let notifier = match opts.notify_url {
+            Some(ref url) => Some(Arc::new(Notifier::new(
+                url.clone(),
+                opts.notify_keys,
+                web_client(&opts)?,
+            ))),
+            None => None,
+        };
+        let newer = match opts.newer {
+            Some(ref reference) => Some(reference_time(&client, reference).map_err(|e| {
+                let message = format!("Failed to read the time of the --newer reference: {}", e);
+                usage_error(&message, ErrorKind::InvalidValue)
+            })?),
+            None => None,
+        };
+        if let Some(time) = newer {
+            filters.push(FindTime::After(time + 1));
+        }
+        let expanded = paths.iter().any(S3path::has_bucket_pattern);
+        let paths = expand_buckets(&client, paths)
+            .map_err(|e| usage_error(&e.to_string(), ErrorKind::InvalidValue))?;
+        // the buckets of a pattern or --all-buckets may be in any region
+        let bucket_clients = if expanded {
+            bucket_clients(&opts, &client, &region, &paths)?
+        } else {
+            HashMap::new()
+        };
+        if paths.len() > 1
+            && (opts.cache.is_some() || opts.resume.is_some() || opts.save_resume.is_some())
+        {
+            let message = "--cache, --resume and --save-resume need a single bucket";
+            return Err(usage_error(message, ErrorKind::ArgumentConflict));
+        }
+        if let Some(Cmd::Download(Download { estimate: true, .. })) = opts.cmd {
+            if opts.watch || opts.daemon || opts.listen_sqs.is_some() {
+                let message =
+                    "download --estimate cannot be used with --watch, --daemon or --listen-sqs";
+                return Err(usage_error(message, ErrorKind::ArgumentConflict));
+            }
+            // the keys would be read by the estimate, then the answer from the end of stdin
+            if opts
+                .keys_from
+                .as_ref()
+                .is_some_and(|x| x.as_os_str() == "-")
+            {
+                let message = "download --estimate cannot be used with --keys-from -";
+                return Err(usage_error(message, ErrorKind::ArgumentConflict));
+            }
+        }
+        let replica_clients = match opts.cmd {
+            Some(Cmd::Report(Report::Replicas(ref report))) => {
+                regional_clients(&opts, &client, &report.also)?
+            }
+            _ => Vec::new(),
+        };
+        // the destination of reconcile is listed in its region with the filters
+        let reconcile_destination = match opts.cmd {
+            Some(Cmd::Report(Report::Reconcile(ref report))) => {
+                let mut filters: FilterList = opts.clone().into();
+                if let Some(time) = newer {
+                    filters.push(FindTime::After(time + 1));
+                }
+                let clients =
+                    regional_clients(&opts, &client, slice::from_ref(&report.destination))?;
+                clients.into_iter().next().map(|x| (x, filters))
+            }
+            _ => None,
+        };
+
+        let head_filters = !opts.size.is_empty()
+            || !opts.mtime.is_empty()
+            || opts.mtime_before.is_some()
+            || opts.mtime_after.is_some()
+            || opts.newer.is_some()
+            || !opts.etag.is_empty()
+            || !opts.not_size.is_empty()
+            || !opts.not_mtime.is_empty()
+            || !opts.not_etag.is_empty()
+            || opts
+                .any_of
+                .iter()
+                .flat_map(|x| &x.0)
+                .any(FilterAtom::uses_head);
+        // the owner filters need the owner of the keys in the listings
+        let fetch_owner = opts.fetch_owner
+            || !opts.owner.is_empty()
+            || !opts.not_owner.is_empty()
+            || opts
+                .any_of
+                .iter()
+                .flat_map(|x| &x.0)
+                .any(FilterAtom::uses_owner);
         let FindOpt {
-            aws_access_key,
-            aws_secret_key,
-            aws_region,
-            path,
             cmd,
             page_size,
+            buffer_size,
+            list_concurrency,
+            pipeline,
+            max_memory,
+            cache,
+            from_cache,
+            source,
+            keys_from,
+            keys_head,
+            resume,
+            save_resume,
+            state,
+            job,
+            encoding_type,
+            delimiter,
+            min_depth,
+            max_depth,
             summarize,
+            summarize_json,
+            progress,
             limit,
+            skip,
+            sort,
+            reverse,
+            stable_output,
+            storage_price,
+            errors,
+            color,
+            protected_prefix,
             ..
         } = opts;
 
-        let region = aws_region.clone();
-        let client = get_client(aws_access_key, aws_secret_key, aws_region);
-        let command = cmd.unwrap_or_default().downcast();
+        let source = match keys_from {
+            // the size, mtime and etag filters need the data of HeadObject
+            Some(file) => ObjectSource::Keys {
+                file,
+                head: keys_head || head_filters,
+            },
+            None => ObjectSource::from_values(&source)
+                .map_err(|e| usage_error(&e.to_string(), ErrorKind::InvalidValue))?,
+        };
+        let prices = PriceTable::for_region(region.name()).with_overrides(&storage_price);
+        let delimiter = match cmd {
+            Some(Cmd::LsDirs(_)) => delimiter.or_else(|| Some("/".to_owned())),
+            _ => delimiter,
+        };
+        if let Some(Cmd::Snapshot(Snapshot::Save(ref save))) = cmd {
+            save.create().map_err(|e| {
+                let message = format!("Failed to create the snapshot file: {}", e);
+                usage_error(&message, ErrorKind::Io)
+            })?;
+        }
+        if let Some(Cmd::Public(ref public)) = cmd {
+            public.create().map_err(|e| {
+                let message = format!("Failed to create the URLs file: {}", e);
+                usage_error(&message, ErrorKind::Io)
+            })?;
+        }
+        if let Some(Cmd::ManifestChecksums(ref manifest)) = cmd {
+            manifest.create().map_err(|e| {
+                let message = format!("Failed to create the checksum file: {}", e);
+                usage_error(&message, ErrorKind::Io)
+            })?;
+        }
+        if let Some(Cmd::ExportInventory(ref export)) = cmd {
+            export.create().map_err(|e| {
+                let message = format!("Failed to create the export directory: {}", e);
+                usage_error(&message, ErrorKind::Io)
+            })?;
+        }
+        if let Some(Cmd::Report(Report::Compliance(ref report))) = cmd {
+            read_rules(&report.rules).map_err(|e| {
+                let message = format!("Failed to read the rules file: {}", e);
+                usage_error(&message, ErrorKind::Io)
+            })?;
+        }
+        let command: Box<dyn RunCommand> = match (cmd, sns) {
+            (Some(Cmd::PublishSns(publish)), Some(sns)) => {
+                Box::new(SnsPublisher::new(publish, &region, sns))
+            }
+            (Some(Cmd::Report(Report::Tiering(report))), _) => {
+                Box::new(TieringAdvisor::new(report, prices.clone()))
+            }
+            (Some(Cmd::Report(Report::Cost(report))), _) => {
+                Box::new(CostReporter::new(report, prices.clone()))
+            }
+            (Some(Cmd::Report(Report::Reconcile(report))), _) => {
+                let (client, filters) = reconcile_destination
+                    .ok_or_else(|| format_err!("No client for the reconcile destination"))?;
+                Box::new(Reconciler::new(report, client, filters))
+            }
+            (Some(Cmd::Report(Report::Replicas(report))), _) => {
+                Box::new(ReplicaMerger::new(report, replica_clients))
+            }
+            (cmd, _) => cmd.unwrap_or_default().downcast(),
+        };
+        let auto_page_size = page_size == PageSize::Auto;
+        let page_size = match page_size {
+            PageSize::Fixed(size) => size,
+            PageSize::Auto => MAX_PAGE_SIZE,
+        };
+        let (page_size, buffer_size) = match max_memory {
+            Some(MemorySize(bytes)) => {
+                let limits = memory::fit(bytes, page_size, buffer_size, pipeline, list_concurrency);
+                (limits.page_size, limits.buffer_size)
+            }
+            None => (page_size, buffer_size),
+        };
+        let page_tuner = if auto_page_size {
+            Some(Arc::new(PageSizeTuner::new(
+                buffer_size.max(1),
+                limit.map(|x| x.saturating_add(skip.unwrap_or_default())),
+            )))
+        } else {
+            None
+        };
+        let resume_tracker = save_resume
+            .or_else(|| resume.clone())
+            .map(|file| Arc::new(ResumeTracker::new(file, paths[0].clone())));
+        let job_state = match state.zip(job) {
+            Some((file, job)) => Some(JobState::open(&file, &job).map_err(|e| {
+                let message = format!("Failed to open the state file: {}", e);
+                usage_error(&message, ErrorKind::Io)
+            })?),
+            None => None,
+        };
+
+        let (client, region) = bucket_clients
+            .get(&paths[0].bucket)
+            .cloned()
+            .unwrap_or((client, region));
 
-        Find {
+        Ok(Find {
             client,
+            bucket_clients,
             filters,
+            enriched_filters: Vec::new(),
+            enrichment: EnrichmentCache::default(),
             region,
-            path,
+            path: paths[0].clone(),
+            paths,
             command,
+            output: Arc::new(StdoutSink),
+            events: notifier
+                .iter()
+                .map(|x| x.clone() as Arc<dyn FindEvents>)
+                .collect(),
+            queue,
+            notifier,
             page_size,
+            page_tuner,
+            buffer_size: buffer_size.max(1),
+            list_concurrency,
+            pipeline,
+            cache,
+            from_cache,
+            source,
+            resume_tracker,
+            resume,
+            job_state,
+            encoding_type,
+            fetch_owner,
+            delimiter,
+            min_depth,
+            max_depth,
             summarize,
+            summarize_json,
             limit,
+            skip: Cell::new(skip.unwrap_or_default()),
+            sort: sort
+                .or_else(|| Some(SortField::Key).filter(|_| stable_output))
+                .map(|field| SortOrder { field, reverse }),
+            sort_run_size: max_memory
+                .map(|MemorySize(bytes)| (bytes / OBJECT_MEMORY / 2) as usize)
+                .unwrap_or(SORT_RUN_SIZE),
             stats: summarize,
+            progress: progress.map(|x| x.unwrap_or(ProgressMode::Line)),
+            errors,
+            color,
+            protected: protected_prefix,
+            prices,
+        })
+    }
+}
+
+/// Modification time of the reference of --newer, in seconds since the epoch
+fn reference_time(client: &S3Client, reference: &NewerRef) -> Result<i64, Error> {
+    match reference {
+        NewerRef::File(file) => {
+            let modified = fs::metadata(file)?.modified()?;
+            Ok(DateTime::<Utc>::from(modified).timestamp())
+        }
+        NewerRef::Object(path) => {
+            let request = HeadObjectRequest {
+                bucket: path.bucket.clone(),
+                key: path.prefix.clone().unwrap_or_default(),
+                ..Default::default()
+            };
+            let url = format!("s3://{}/{}", request.bucket, request.key);
+            debug!("HeadObject {}", url);
+            // the answers of HeadObject have no body, so no error message
+            let head =
+                with_retry("HeadObject", || client.head_object(request.clone())).map_err(|e| {
+                    match e {
+                        RusotoError::Service(HeadObjectError::NoSuchKey(_)) => {
+                            FunctionError::NewerNotFoundError(url).into()
+                        }
+                        RusotoError::Unknown(ref response) if response.status.as_u16() == 404 => {
+                            FunctionError::NewerNotFoundError(url).into()
+                        }
+                        RusotoError::Unknown(ref response) => {
+                            format_err!("HeadObject {} answered {}", url, response.status)
+                        }
+                        e => Error::from(e),
+                    }
+                })?;
+            // HeadObject returns the time in the HTTP format
+            let modified = head.last_modified.ok_or(FunctionError::ObjectFieldError)?;
+            Ok(DateTime::parse_from_rfc2822(&modified)?.timestamp())
         }
     }
 }
 
-fn get_client(
-    aws_access_key: Option<String>,
-    aws_secret_key: Option<String>,
-    region: Region,
-) -> S3Client {
-    let dispatcher = HttpClient::new().unwrap();
-    match (aws_access_key, aws_secret_key) {
+/// Paths with a bucket pattern are replaced by a path for each matching bucket
+fn expand_buckets(client: &S3Client, paths: Vec<S3path>) -> Result<Vec<S3path>, Error> {
+    if !paths.iter().any(S3path::has_bucket_pattern) {
+        return Ok(paths);
+    }
+
+    debug!("ListBuckets");
+    let buckets: Vec<String> = with_retry("ListBuckets", || client.list_buckets())?
+        .buckets
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|x| x.name)
+        .collect();
+
+    let mut expanded = Vec::new();
+    for path in paths {
+        if !path.has_bucket_pattern() {
+            expanded.push(path);
+            continue;
+        }
+        let matched = path.expand(&buckets);
+        if matched.is_empty() {
+            return Err(FunctionError::BucketPatternError(path.bucket).into());
+        }
+        expanded.extend(matched);
+    }
+    Ok(expanded)
+}
+
+pub(crate) fn get_client(opts: &FindOpt, region: Region) -> Result<S3Client, Error> {
+    s3_client(credentials(opts, &region)?, opts, region)
+}
+
+/// Credentials of any provider, shared by the clients of a run
+#[derive(Clone)]
+pub struct SharedProvider(Arc<dyn Fn() -> CredentialsFuture + Send + Sync>);
+
+type CredentialsFuture = Box<dyn Future<Item = AwsCredentials, Error = CredentialsError> + Send>;
+
+impl SharedProvider {
+    pub fn new<P>(provider: P) -> Self
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+        P::Future: Send + 'static,
+    {
+        SharedProvider(Arc::new(move || Box::new(provider.credentials())))
+    }
+}
+
+impl ProvideAwsCredentials for SharedProvider {
+    type Future = CredentialsFuture;
+
+    fn credentials(&self) -> Self::Future {
+        (self.0)()
+    }
+}
+
+/// Credentials of the options: the keys, the profile or the default chain
+pub(crate) fn credentials(opts: &FindOpt, region: &Region) -> Result<SharedProvider, Error> {
+    if opts.no_sign_request {
+        // empty credentials are not signed
+        let provider = StaticProvider::from(AwsCredentials::default());
+        return with_mfa(provider, opts, region);
+    }
+
+    match (&opts.aws_access_key, &opts.aws_secret_key) {
         (Some(aws_access_key), Some(aws_secret_key)) => {
-            let provider = StaticProvider::new(aws_access_key, aws_secret_key, None, None);
-            S3Client::new_with(dispatcher, provider, region)
+            let provider =
+                StaticProvider::new(aws_access_key.clone(), aws_secret_key.clone(), None, None);
+            with_mfa(provider, opts, region)
         }
         _ => {
-            let provider = DefaultCredentialsProvider::new().unwrap();
-            S3Client::new_with(dispatcher, provider, region)
+            if let Some(provider) = sso_credentials(opts)? {
+                let provider = AutoRefreshingProvider::new(provider)?;
+                return with_mfa(provider, opts, region);
+            }
+            match opts.aws_profile {
+                Some(ref profile) => {
+                    let mut provider = ProfileProvider::new()?;
+                    provider.set_profile(profile.clone());
+                    with_mfa(provider, opts, region)
+                }
+                None => {
+                    let chain = CredentialChain::new(Duration::from_millis(opts.imds_timeout));
+                    let provider = AutoRefreshingProvider::new(chain)?;
+                    with_mfa(provider, opts, region)
+                }
+            }
         }
     }
 }
 
+/// Role credentials of an IAM Identity Center profile, the environment
+/// credentials come first unless the profile is given on the command line
+fn sso_credentials(opts: &FindOpt) -> Result<Option<SsoProvider>, Error> {
+    let profile = match opts.aws_profile {
+        Some(ref profile) => profile.clone(),
+        None if env::var_os("AWS_ACCESS_KEY_ID").is_some() => return Ok(None),
+        None => env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_owned()),
+    };
+    match SsoProfile::load(&profile)? {
+        Some(sso) => Ok(Some(sso.provider(http_client(opts)?)?)),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn http_client(opts: &FindOpt) -> Result<HttpClient, Error> {
+    tls::http_client(&http_settings(opts))
+        .map_err(|e| format_err!("Failed to create the HTTP client: {}", e))
+}
+
+fn web_client(opts: &FindOpt) -> Result<WebClient, Error> {
+    tls::web_client(&http_settings(opts))
+        .map_err(|e| format_err!("Failed to create the HTTP client: {}", e))
+}
+
+fn http_settings(opts: &FindOpt) -> HttpSettings {
+    HttpSettings {
+        ca_bundle: opts.ca_bundle.clone(),
+        insecure: opts.insecure,
+        connect_timeout: opts.connect_timeout.map(Duration::from_millis),
+        max_connections: opts.max_connections,
+        keep_alive: Duration::from_millis(opts.keep_alive),
+    }
+}
+
+/// With an MFA device the credentials are exchanged for session credentials
+/// first, or for the credentials of the role of --role-arn
+fn with_mfa<P>(provider: P, opts: &FindOpt, region: &Region) -> Result<SharedProvider, Error>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+    P::Future: Send,
+{
+    match opts.mfa_serial {
+        Some(ref serial) => mfa::authenticate(
+            provider,
+            http_client(opts)?,
+            region,
+            serial,
+            opts.mfa_token.clone(),
+            opts.role_arn.as_deref(),
+        ),
+        None => Ok(SharedProvider::new(provider)),
+    }
+}
+
+pub(crate) fn s3_client(
+    provider: SharedProvider,
+    opts: &FindOpt,
+    region: Region,
+) -> Result<S3Client, Error> {
+    let provider = Arc::new(provider);
+    let dispatcher = AddressingDispatcher::new(
+        http_client(opts)?,
+        provider.clone(),
+        opts.accelerate,
+        opts.request_timeout.map(Duration::from_millis),
+    );
+    Ok(S3Client::new_with(dispatcher, provider, region))
+}
+
 impl From<FindOpt> for FilterList {
     fn from(opts: FindOpt) -> Self {
         let mut list: Vec<Box<dyn Filter>> = Vec::new();
 
+        let paths = match opts.all_buckets {
+            Some(_) => vec![S3path {
+                bucket: String::new(),
+                prefix: opts.prefix.clone(),
+            }],
+            None => opts.path.iter().chain(opts.paths.iter()).cloned().collect(),
+        };
+
         let FindOpt {
             name,
             iname,
             regex,
+            ext,
             size,
             mtime,
+            mtime_before,
+            mtime_after,
+            etag,
+            owner,
+            not_name,
+            not_iname,
+            not_regex,
+            not_mtime,
+            not_size,
+            not_ext,
+            not_etag,
+            not_owner,
+            any_of,
+            expr,
+            min_depth,
+            max_depth,
             ..
         } = opts;
 
@@ -192,6 +1126,10 @@ impl From<FindOpt> for FilterList {
             list.push(Box::new(regex));
         }
 
+        if !ext.is_empty() {
+            list.push(Box::new(ExtFilter::new(&ext)));
+        }
+
         for size in size {
             list.push(Box::new(size));
         }
@@ -200,6 +1138,71 @@ impl From<FindOpt> for FilterList {
             list.push(Box::new(mtime));
         }
 
+        if let Some(FindDate(time)) = mtime_before {
+            list.push(Box::new(FindTime::Before(time)));
+        }
+
+        if let Some(FindDate(time)) = mtime_after {
+            list.push(Box::new(FindTime::After(time)));
+        }
+
+        if !etag.is_empty() {
+            list.push(Box::new(ETagFilter(etag)));
+        }
+
+        if !owner.is_empty() {
+            list.push(Box::new(OwnerFilter(owner)));
+        }
+
+        for name in not_name {
+            list.push(Box::new(Not(name)));
+        }
+
+        for iname in not_iname {
+            list.push(Box::new(Not(iname)));
+        }
+
+        for regex in not_regex {
+            list.push(Box::new(Not(regex)));
+        }
+
+        if !not_ext.is_empty() {
+            list.push(Box::new(Not(ExtFilter::new(&not_ext))));
+        }
+
+        for mtime in not_mtime {
+            list.push(Box::new(Not(mtime)));
+        }
+
+        for size in not_size {
+            list.push(Box::new(Not(size)));
+        }
+
+        if !not_etag.is_empty() {
+            list.push(Box::new(Not(ETagFilter(not_etag))));
+        }
+
+        if !not_owner.is_empty() {
+            list.push(Box::new(Not(OwnerFilter(not_owner))));
+        }
+
+        for group in any_of {
+            list.push(Box::new(group));
+        }
+
+        if let Some(expr) = expr {
+            list.push(Box::new(expr));
+        }
+
+        // already met by the keys listed level by level
+        if min_depth.is_some() || max_depth.is_some() {
+            list.push(Box::new(KeyDepth {
+                paths,
+                min_depth,
+                max_depth,
+            }));
+        }
+
         FilterList(list)
     }
 }
@@ -213,6 +1216,7 @@ impl From<FindTag> for Tag {
     }
 }
 
+/// Summary of the matched keys: counts, sizes, storage classes and costs
 #[derive(Debug, Clone, PartialEq)]
 pub struct FindStat {
     pub total_files: usize,
@@ -222,6 +1226,36 @@ pub struct FindStat {
     pub max_key: String,
     pub min_key: String,
     pub average_size: i64,
+    pub size_histogram: [usize; HISTOGRAM_BUCKETS],
+    pub size_quantiles: QuantileSketch,
+    pub age_quantiles: QuantileSketch,
+    pub class_space: BTreeMap<String, i64>,
+    pub prices: PriceTable,
+}
+
+const PERCENTILES: [(&str, f64); 3] = [("p50", 0.5), ("p90", 0.9), ("p99", 0.99)];
+
+const HISTOGRAM_BUCKETS: usize = 7;
+
+const HISTOGRAM_LABELS: [&str; HISTOGRAM_BUCKETS] = [
+    "< 1KB",
+    "1KB - 16KB",
+    "16KB - 256KB",
+    "256KB - 4MB",
+    "4MB - 64MB",
+    "64MB - 1GB",
+    ">= 1GB",
+];
+
+/// Index of the logarithmic (base 16) histogram bucket for the object size
+fn histogram_bucket(size: i64) -> usize {
+    let mut bucket = 0;
+    let mut bound = 1024;
+    while bucket < HISTOGRAM_BUCKETS - 1 && size >= bound {
+        bucket += 1;
+        bound *= 16;
+    }
+    bucket
 }
 
 impl Add<&[Object]> for FindStat {
@@ -229,7 +1263,11 @@ impl Add<&[Object]> for FindStat {
 
     #[allow(clippy::suspicious_arithmetic_impl)]
     fn add(mut self: FindStat, list: &[Object]) -> Self {
+        let now = Utc::now().timestamp();
         for x in list {
+            if is_prefix(x) {
+                continue;
+            }
             self.total_files += 1;
             let size = x.size.as_ref().unwrap_or(&0);
             self.total_space += size;
@@ -258,12 +1296,117 @@ impl Add<&[Object]> for FindStat {
                 _ => {}
             }
 
+            self.size_histogram[histogram_bucket(*size)] += 1;
+            let class = x
+                .storage_class
+                .clone()
+                .unwrap_or_else(|| DEFAULT_STORAGE_CLASS.to_owned());
+            *self.class_space.entry(class).or_insert(0) += size;
+            self.size_quantiles.add(*size);
+            if let Some(mtime) = x
+                .last_modified
+                .as_ref()
+                .and_then(|x| x.parse::<DateTime<Utc>>().ok())
+            {
+                self.age_quantiles.add(now - mtime.timestamp());
+            }
             self.average_size = self.total_space / (self.total_files as i64);
         }
         self
     }
 }
 
+/// Stats of all the paths from the stats of each path
+impl Add<FindStat> for FindStat {
+    type Output = FindStat;
+
+    fn add(mut self: FindStat, other: FindStat) -> Self {
+        if other.total_files == 0 {
+            return self;
+        }
+
+        self.total_files += other.total_files;
+        self.total_space += other.total_space;
+        for (count, other) in self
+            .size_histogram
+            .iter_mut()
+            .zip(other.size_histogram.iter())
+        {
+            *count += other;
+        }
+        self.size_quantiles.merge(&other.size_quantiles);
+        self.age_quantiles.merge(&other.age_quantiles);
+        for (class, space) in other.class_space {
+            *self.class_space.entry(class).or_insert(0) += space;
+        }
+        // other has matched objects, so its sizes are set
+        if other.max_size >= self.max_size {
+            self.max_size = other.max_size;
+            self.max_key = other.max_key;
+        }
+        if self.min_size.is_none() || other.min_size < self.min_size {
+            self.min_size = other.min_size;
+            self.min_key = other.min_key;
+        }
+        self.average_size = self.total_space / (self.total_files as i64);
+        self
+    }
+}
+
+impl FindStat {
+    /// Estimated monthly storage cost in USD of the matched objects
+    pub fn storage_cost(&self) -> f64 {
+        self.class_space
+            .iter()
+            .map(|(class, bytes)| self.prices.monthly_cost(class, *bytes))
+            .sum()
+    }
+
+    /// Machine-readable representation of the summary
+    pub fn to_json(&self) -> Value {
+        let histogram: Vec<Value> = HISTOGRAM_LABELS
+            .iter()
+            .zip(self.size_histogram.iter())
+            .map(|(label, count)| json!({ "bucket": label, "count": count }))
+            .collect();
+
+        let percentiles = |sketch: &QuantileSketch| -> Value {
+            PERCENTILES
+                .iter()
+                .map(|(label, q)| ((*label).to_owned(), json!(sketch.quantile(*q))))
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        };
+
+        let storage_classes: Vec<Value> = self
+            .class_space
+            .iter()
+            .map(|(class, bytes)| {
+                json!({
+                    "storage_class": class,
+                    "total_space": bytes,
+                    "estimated_cost": self.prices.monthly_cost(class, *bytes),
+                })
+            })
+            .collect();
+
+        json!({
+            "total_files": self.total_files,
+            "total_space": self.total_space,
+            "estimated_cost": self.storage_cost(),
+            "max_size": self.max_size,
+            "max_key": self.max_key,
+            "min_size": self.min_size,
+            "min_key": self.min_key,
+            "average_size": self.average_size,
+            "size_histogram": histogram,
+            "size_percentiles": percentiles(&self.size_quantiles),
+            "age_percentiles": percentiles(&self.age_quantiles),
+            "storage_classes": storage_classes,
+        })
+    }
+}
+
 impl Default for FindStat {
     fn default() -> Self {
         FindStat {
@@ -274,6 +1417,11 @@ impl Default for FindStat {
             max_key: "".to_owned(),
             min_key: "".to_owned(),
             average_size: 0,
+            size_histogram: [0; HISTOGRAM_BUCKETS],
+            size_quantiles: QuantileSketch::default(),
+            age_quantiles: QuantileSketch::default(),
+            class_space: BTreeMap::new(),
+            prices: PriceTable::default(),
         }
     }
 }
@@ -291,6 +1439,12 @@ impl fmt::Display for FindStat {
                 .file_size(options::CONVENTIONAL)
                 .map_err(|_| fmt::Error)?
         )?;
+        writeln!(
+            f,
+            "{:19} ${:.2}/month",
+            "Estimated cost:",
+            self.storage_cost()
+        )?;
         writeln!(f, "{:19} {}", "Largest file:", &self.max_key)?;
         writeln!(
             f,
@@ -322,15 +1476,54 @@ impl fmt::Display for FindStat {
                 .file_size(options::CONVENTIONAL)
                 .map_err(|_| fmt::Error)?
         )?;
+        writeln!(f, "Size histogram:")?;
+        for (label, count) in HISTOGRAM_LABELS.iter().zip(self.size_histogram.iter()) {
+            writeln!(f, "  {:17} {}", label, count)?;
+        }
+        for (label, q) in PERCENTILES.iter() {
+            writeln!(
+                f,
+                "{:19} {}",
+                format!("File size {}:", label),
+                self.size_quantiles
+                    .quantile(*q)
+                    .unwrap_or_default()
+                    .file_size(options::CONVENTIONAL)
+                    .map_err(|_| fmt::Error)?
+            )?;
+        }
+        for (label, q) in PERCENTILES.iter() {
+            writeln!(
+                f,
+                "{:19} {}",
+                format!("File age {}:", label),
+                format_age(self.age_quantiles.quantile(*q).unwrap_or_default())
+            )?;
+        }
         Ok(())
     }
 }
 
+/// Short human-readable rendering of an age in seconds, e.g. `3d 4h`
+fn format_age(seconds: i64) -> String {
+    let days = seconds / 86400;
+    let hours = (seconds % 86400) / 3600;
+    let minutes = (seconds % 3600) / 60;
+    match (days, hours, minutes) {
+        (0, 0, m) => format!("{}m {}s", m, seconds % 60),
+        (0, h, m) => format!("{}h {}m", h, m),
+        (d, h, _) => format!("{}d {}h", d, h),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::enrich::Enrichment;
+    use crate::filter::FilterFn;
     use regex::Regex;
     use std::str::FromStr;
+    use structopt::StructOpt;
 
     #[test]
     fn from_findtag() -> Result<(), Error> {
@@ -351,26 +1544,103 @@ mod tests {
     }
 
     #[test]
-    fn from_findopt_to_findcommand() {
-        let find: Find = FindOpt {
-            path: S3path {
+    fn from_findopt_to_findcommand() -> Result<(), Error> {
+        let find = Find::new(FindOpt {
+            path: Some(S3path {
                 bucket: "bucket".to_owned(),
                 prefix: Some("prefix".to_owned()),
-            },
+            }),
+            paths: Vec::new(),
+            all_buckets: None,
+            prefix: None,
             aws_access_key: Some("access".to_owned()),
             aws_secret_key: Some("secret".to_owned()),
+            config: None,
+            target: None,
+            aws_profile: None,
+            mfa_serial: None,
+            mfa_token: None,
+            role_arn: None,
             aws_region: Region::UsEast1,
+            endpoint_url: None,
+            accelerate: false,
+            ca_bundle: None,
+            insecure: false,
+            connect_timeout: None,
+            request_timeout: None,
+            max_connections: None,
+            keep_alive: 90000,
+            no_sign_request: false,
+            imds_timeout: 1000,
             name: vec![NameGlob::from_str("*ref*").unwrap()],
             iname: vec![InameGlob::from_str("Pre*").unwrap()],
             regex: vec![Regex::from_str("^pre").unwrap()],
+            ext: Vec::new(),
             mtime: Vec::new(),
             size: vec![FindSize::Lower(1000)],
+            mtime_before: None,
+            mtime_after: None,
+            newer: None,
+            etag: Vec::new(),
+            owner: Vec::new(),
+            not_name: Vec::new(),
+            not_iname: Vec::new(),
+            not_regex: Vec::new(),
+            not_mtime: Vec::new(),
+            not_size: Vec::new(),
+            not_ext: Vec::new(),
+            not_etag: Vec::new(),
+            not_owner: Vec::new(),
+            any_of: Vec::new(),
+            expr: None,
             limit: None,
-            page_size: 1000,
-            cmd: Some(Cmd::Ls(FastPrint {})),
+            skip: None,
+            sort: None,
+            reverse: false,
+            stable_output: false,
+            page_size: PageSize::Fixed(1000),
+            list_api: ListApi::Auto,
+            buffer_size: 0,
+            list_concurrency: 4,
+            pipeline: 0,
+            max_memory: None,
+            cache: None,
+            from_cache: false,
+            source: Vec::new(),
+            keys_from: None,
+            keys_head: false,
+            resume: None,
+            save_resume: None,
+            state: None,
+            job: None,
+            watch: false,
+            interval: Interval(std::time::Duration::from_secs(60)),
+            listen_sqs: None,
+            daemon: false,
+            schedule: None,
+            retries: 3,
+            retry_base_delay: 100,
+            rps: None,
+            encoding_type: None,
+            fetch_owner: false,
+            delimiter: None,
+            min_depth: None,
+            max_depth: None,
+            cmd: Some(Cmd::Ls(FastPrint::default())),
             summarize: false,
-        }
-        .into();
+            summarize_json: None,
+            benchmark: false,
+            progress: None,
+            storage_price: Vec::new(),
+            errors: ErrorFormat::Text,
+            color: ColorChoice::Auto,
+            protected_prefix: Vec::new(),
+            log_level: None,
+            log_file: None,
+            metrics: None,
+            notify_url: None,
+            notify_keys: false,
+        })?;
 
         assert_eq!(
             find.path,
@@ -379,7 +1649,10 @@ mod tests {
                 prefix: Some("prefix".to_owned()),
             }
         );
+        assert_eq!(find.paths, vec![find.path.clone()]);
         assert_eq!(find.region, Region::UsEast1);
+        assert_eq!(find.list_concurrency, 4);
+        assert_eq!(find.buffer_size, 1);
 
         let object_ok = Object {
             key: Some("pref".to_owned()),
@@ -394,5 +1667,352 @@ mod tests {
             ..Default::default()
         };
         assert!(!find.filters.test_match(&object_fail));
+
+        let mut find = find;
+        find.filters.push(FilterFn(|x: &Object| x.size > Some(5)));
+        assert!(find.test_match(&object_ok));
+
+        // no fields, no API calls
+        struct Untagged;
+        impl EnrichedFilter for Untagged {
+            fn fields(&self) -> Vec<EnrichField> {
+                Vec::new()
+            }
+            fn filter(&self, _object: &Object, enrichment: &Enrichment) -> bool {
+                enrichment.tags.is_none()
+            }
+        }
+        find.add_enriched_filter(Untagged);
+        assert!(find.test_match(&object_ok));
+        assert!(!find.test_match(&object_fail));
+        Ok(())
+    }
+
+    #[test]
+    fn protected_keys_are_not_counted() -> Result<(), Error> {
+        use crate::output::OutputSink;
+        use std::sync::Mutex;
+
+        struct Delete(Arc<Mutex<Vec<String>>>);
+        impl RunCommand for Delete {
+            fn execute(
+                &self,
+                _c: &S3Client,
+                _r: &Region,
+                _p: &S3path,
+                list: &[Object],
+                _e: &EnrichmentCache,
+                _o: &dyn OutputSink,
+            ) -> Result<(), Error> {
+                let keys = list.iter().filter_map(|x| x.key.clone());
+                self.0.lock().unwrap().extend(keys);
+                Ok(())
+            }
+            fn modifies_keys(&self) -> bool {
+                true
+            }
+        }
+
+        let mut find = Find::new(FindOpt::from_iter(&[
+            "s3find",
+            "s3://bucket/",
+            "--aws-access-key",
+            "access",
+            "--aws-secret-key",
+            "secret",
+            "--protected-prefix",
+            "keep/",
+        ]))?;
+        let deleted = Arc::new(Mutex::new(Vec::new()));
+        find.command = Box::new(Delete(deleted.clone()));
+
+        let object = |key: &str| Object {
+            key: Some(key.to_owned()),
+            size: Some(10),
+            ..Default::default()
+        };
+        let stats = find.exec(
+            Some(FindStat::default()),
+            &[object("keep/a"), object("tmp/b")],
+        )?;
+        assert_eq!(*deleted.lock().unwrap(), vec!["tmp/b"]);
+        assert_eq!(stats.unwrap().total_files, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn bucket_clients_by_path() -> Result<(), Error> {
+        let mut find = Find::new(FindOpt::from_iter(&[
+            "s3find",
+            "s3://bucket-a/prefix",
+            "s3://bucket-b/prefix",
+            "--aws-access-key",
+            "access",
+            "--aws-secret-key",
+            "secret",
+        ]))?;
+        let client = S3Client::new(Region::EuWest1);
+        find.bucket_clients
+            .insert("bucket-b".to_owned(), (client, Region::EuWest1));
+
+        find.set_path("s3://bucket-a/prefix".parse().unwrap());
+        assert_eq!(find.region, Region::UsEast1);
+        find.set_path("s3://bucket-b/prefix".parse().unwrap());
+        assert_eq!(find.region, Region::EuWest1);
+        assert_eq!(find.path.bucket, "bucket-b");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_options_are_errors() {
+        let error = |args: &[&str]| {
+            let mut argv = vec![
+                "s3find",
+                "--aws-access-key",
+                "access",
+                "--aws-secret-key",
+                "secret",
+            ];
+            argv.extend(args);
+            let e = Find::new(FindOpt::from_iter(&argv)).err().unwrap();
+            e.downcast::<clap::Error>().unwrap().kind
+        };
+
+        assert_eq!(error(&["ls"]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(
+            error(&["s3://a/", "report", "compliance", "--rules", "missing.yaml"]),
+            ErrorKind::Io
+        );
+    }
+
+    #[test]
+    fn limit_caps_page_size() {
+        let find = |args: &[&str]| -> Find {
+            let mut argv = vec![
+                "s3find",
+                "s3://bucket/prefix",
+                "--aws-access-key",
+                "access",
+                "--aws-secret-key",
+                "secret",
+            ];
+            argv.extend(args);
+            Find::new(FindOpt::from_iter(&argv)).unwrap()
+        };
+
+        assert_eq!(find(&[]).effective_page_size(), 1000);
+        assert_eq!(find(&["--limit", "10"]).effective_page_size(), 10);
+        assert_eq!(
+            find(&["--limit", "10", "--name", "*.log"]).effective_page_size(),
+            1000
+        );
+        assert_eq!(find(&["--limit", "5000"]).effective_page_size(), 1000);
+        assert_eq!(
+            find(&["--limit", "10", "--skip", "20"]).effective_page_size(),
+            30
+        );
+        // the keys are listed in key order, but sorted by size
+        assert!(!find(&["--stable-output"]).sorts_listing());
+        assert!(find(&["--stable-output", "--list-concurrency", "4"]).sorts_listing());
+        assert_eq!(
+            find(&["--limit", "10", "--sort", "size"]).effective_page_size(),
+            1000
+        );
+
+        let find = find(&[
+            "--max-memory",
+            "4M",
+            "--buffer-size",
+            "5000",
+            "--pipeline",
+            "2",
+        ]);
+        assert_eq!(find.buffer_size, 1024);
+        assert_eq!(find.effective_page_size(), 256);
+    }
+
+    #[test]
+    fn findstat_histogram() {
+        let sizes = [
+            0,
+            1023,
+            1024,
+            20_000,
+            1024 * 1024,
+            1024_i64.pow(3),
+            1024_i64.pow(4),
+        ];
+        let objects: Vec<Object> = sizes
+            .iter()
+            .map(|x| Object {
+                size: Some(*x),
+                ..Default::default()
+            })
+            .collect();
+
+        let stat = FindStat::default() + &objects[..];
+        assert_eq!(stat.size_histogram, [2, 1, 1, 1, 0, 0, 2]);
+        assert_eq!(stat.size_quantiles.count(), 7);
+    }
+
+    #[test]
+    fn findstat_merge() {
+        let objects: Vec<Object> = [("a", 10), ("b", 3000), ("c", 1), ("d", 3000)]
+            .iter()
+            .map(|(key, size)| Object {
+                key: Some(key.to_string()),
+                size: Some(*size),
+                ..Default::default()
+            })
+            .collect();
+
+        let all = FindStat::default() + &objects[..];
+        let merged = (FindStat::default() + &objects[..2]) + (FindStat::default() + &objects[2..]);
+        assert_eq!(merged, all);
+        assert_eq!(merged.max_key, "d");
+        assert_eq!(merged.min_key, "c");
+        assert_eq!(all.clone() + FindStat::default(), all);
+
+        // the common prefixes of --delimiter are not counted
+        let prefixes = [prefix_object("logs/".to_owned())];
+        assert_eq!(all.clone() + &prefixes[..], all);
+    }
+
+    #[test]
+    fn findstat_storage_cost() {
+        let objects = [
+            Object {
+                size: Some(1024_i64.pow(3)),
+                storage_class: Some("STANDARD".to_owned()),
+                ..Default::default()
+            },
+            Object {
+                size: Some(1024_i64.pow(3)),
+                storage_class: Some("GLACIER".to_owned()),
+                ..Default::default()
+            },
+            Object {
+                size: Some(1024_i64.pow(3)),
+                ..Default::default()
+            },
+        ];
+
+        let stat = FindStat::default() + &objects[..];
+        assert_eq!(
+            stat.class_space.get("STANDARD"),
+            Some(&(2 * 1024_i64.pow(3)))
+        );
+        assert!((stat.storage_cost() - (0.023 * 2.0 + 0.0036)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn findstat_json() {
+        let objects = [
+            Object {
+                key: Some("small".to_owned()),
+                size: Some(10),
+                ..Default::default()
+            },
+            Object {
+                key: Some("large".to_owned()),
+                size: Some(20_000),
+                storage_class: Some("GLACIER".to_owned()),
+                ..Default::default()
+            },
+        ];
+
+        let json = (FindStat::default() + &objects[..]).to_json();
+        assert_eq!(json["total_files"], 2);
+        assert_eq!(json["total_space"], 20_010);
+        assert_eq!(json["max_key"], "large");
+        assert_eq!(json["min_key"], "small");
+        assert_eq!(json["size_histogram"][0]["count"], 1);
+        assert_eq!(json["size_histogram"][2]["count"], 1);
+        assert_eq!(json["storage_classes"][0]["storage_class"], "GLACIER");
+        assert_eq!(json["storage_classes"][1]["total_space"], 10);
+    }
+
+    #[test]
+    fn format_age_test() {
+        assert_eq!(format_age(42), "0m 42s");
+        assert_eq!(format_age(3 * 3600 + 120), "3h 2m");
+        assert_eq!(format_age(2 * 86400 + 5 * 3600), "2d 5h");
+    }
+
+    #[test]
+    fn newer_reference() -> Result<(), Error> {
+        assert_eq!(
+            NewerRef::from_str("s3://bucket/markers/last-run")?,
+            NewerRef::Object(S3path {
+                bucket: "bucket".to_owned(),
+                prefix: Some("markers/last-run".to_owned()),
+            })
+        );
+        assert_eq!(
+            NewerRef::from_str("s3://bucket/dir2/f.gz")?,
+            NewerRef::Object(S3path {
+                bucket: "bucket".to_owned(),
+                prefix: Some("dir2/f.gz".to_owned()),
+            })
+        );
+        assert!(NewerRef::from_str("s3://bucket/markers/").is_err());
+        assert!(NewerRef::from_str("s3://bucket").is_err());
+
+        let file = tempfile::NamedTempFile::new()?;
+        let reference = NewerRef::from_str(file.path().to_str().unwrap())?;
+        assert_eq!(reference, NewerRef::File(file.path().to_owned()));
+
+        let time = reference_time(&S3Client::new(Region::UsEast1), &reference)?;
+        assert!((Utc::now().timestamp() - time).abs() < 60);
+        assert!(reference_time(
+            &S3Client::new(Region::UsEast1),
+            &NewerRef::File(file.path().with_extension("missing"))
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn newer_reference_not_found() -> Result<(), Error> {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+        let requests = std::thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let count = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..count]);
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let client = S3Client::new_with(
+            rusoto_core::HttpClient::new()?,
+            StaticProvider::new_minimal("access".to_owned(), "secret".to_owned()),
+            Region::Custom {
+                name: "us-east-1".to_owned(),
+                endpoint,
+            },
+        );
+        let reference = NewerRef::from_str("s3://bucket/dir2/f.gz")?;
+        let error = reference_time(&client, &reference).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The reference object s3://bucket/dir2/f.gz is not found"
+        );
+        assert!(requests
+            .join()
+            .unwrap()
+            .starts_with("HEAD /bucket/dir2/f.gz "));
+        Ok(())
     }
 }
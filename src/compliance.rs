@@ -0,0 +1,500 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use failure::Error;
+use regex::Regex;
+use rusoto_core::Region;
+use rusoto_s3::{Object, S3Client};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::arg::{ComplianceReport, EnrichField, Interval, ReportFormat, S3path};
+use crate::audit::{encryption_gap, findings, BucketExposure};
+use crate::enrich::{Enrichment, EnrichmentCache};
+use crate::error::FunctionError;
+use crate::function::RunCommand;
+use crate::output::OutputSink;
+use crate::parallel::parallel_map;
+
+/// Check of a compliance rule on a matched key
+#[derive(Debug, Clone)]
+pub enum Check {
+    /// Server-side encryption, with the KMS key when set
+    Encryption { kms_key: Option<String> },
+    /// The tags of these keys are set
+    Tags(Vec<String>),
+    /// Modified at most this long ago
+    MaxAge(Duration),
+    /// No ACL grant to everyone or to every AWS account
+    NoPublicAcl,
+    /// The key matches the regex
+    Naming(Regex),
+}
+
+impl Check {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Check::Encryption { .. } => "encryption",
+            Check::Tags(_) => "tags",
+            Check::MaxAge(_) => "max-age",
+            Check::NoPublicAcl => "no-public-acl",
+            Check::Naming(_) => "naming",
+        }
+    }
+
+    /// The data of the key fetched for the check
+    fn field(&self) -> Option<EnrichField> {
+        match self {
+            Check::Encryption { .. } => Some(EnrichField::Head),
+            Check::Tags(_) => Some(EnrichField::Tags),
+            Check::NoPublicAcl => Some(EnrichField::Acl),
+            Check::MaxAge(_) | Check::Naming(_) => None,
+        }
+    }
+
+    /// None when the data of the key could not be read
+    pub fn evaluate(&self, object: &Object, data: &Enrichment, now: DateTime<Utc>) -> Option<bool> {
+        match self {
+            Check::Encryption { kms_key } => {
+                let head = data.head.as_ref()?;
+                Some(encryption_gap(head, kms_key.as_deref()).is_none())
+            }
+            Check::Tags(keys) => {
+                let tags = data.tags.as_ref()?;
+                Some(keys.iter().all(|key| tags.iter().any(|x| x.key == *key)))
+            }
+            Check::MaxAge(age) => {
+                let modified =
+                    DateTime::parse_from_rfc3339(object.last_modified.as_deref()?).ok()?;
+                let elapsed = now
+                    .signed_duration_since(modified)
+                    .to_std()
+                    .unwrap_or_default();
+                Some(elapsed <= *age)
+            }
+            Check::NoPublicAcl => {
+                let grants = data.acl.as_ref()?;
+                Some(findings(&BucketExposure::default(), grants).is_empty())
+            }
+            Check::Naming(regex) => Some(regex.is_match(object.key.as_deref()?)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub check: Check,
+}
+
+/// A rule of the rules file, the fields besides name and check are those
+/// of the check
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RuleSpec {
+    name: String,
+    check: String,
+    kms_key: Option<String>,
+    keys: Option<TagKeys>,
+    age: Option<String>,
+    regex: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TagKeys {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The rules file, other top-level keys may hold the anchors of the rules
+#[derive(Debug, Deserialize)]
+struct RulesFile {
+    rules: Vec<RuleSpec>,
+}
+
+impl Rule {
+    fn from_spec(spec: RuleSpec) -> Result<Self, String> {
+        let name = spec.name;
+        let kind = spec.check.as_str();
+        let required = |value: Option<String>, field: &str| {
+            value.ok_or_else(|| format!("rule {}: {} without {}", name, kind, field))
+        };
+
+        let (check, fields): (Check, &[&str]) = match kind {
+            "encryption" => (
+                Check::Encryption {
+                    kms_key: spec.kms_key.clone(),
+                },
+                &["kms-key"],
+            ),
+            "tags" => {
+                let keys = match spec.keys {
+                    Some(TagKeys::One(ref key)) => vec![key.clone()],
+                    Some(TagKeys::Many(ref keys)) => keys.clone(),
+                    None => return Err(format!("rule {}: tags without keys", name)),
+                };
+                (Check::Tags(keys), &["keys"])
+            }
+            "max-age" => {
+                let age = required(spec.age.clone(), "age")?;
+                let age: Interval = age
+                    .parse()
+                    .map_err(|_| format!("rule {}: invalid age {}", name, age))?;
+                (Check::MaxAge(age.0), &["age"])
+            }
+            "no-public-acl" => (Check::NoPublicAcl, &[]),
+            "naming" => {
+                let regex = Regex::new(&required(spec.regex.clone(), "regex")?)
+                    .map_err(|e| format!("rule {}: {}", name, e))?;
+                (Check::Naming(regex), &["regex"])
+            }
+            check => return Err(format!("rule {}: unknown check {}", name, check)),
+        };
+
+        let set = [
+            ("kms-key", spec.kms_key.is_some()),
+            ("keys", spec.keys.is_some()),
+            ("age", spec.age.is_some()),
+            ("regex", spec.regex.is_some()),
+        ];
+        if let Some((field, _)) = set.iter().find(|x| x.1 && !fields.contains(&x.0)) {
+            return Err(format!("rule {}: unknown field {}", name, field));
+        }
+        Ok(Rule { name, check })
+    }
+}
+
+pub fn read_rules(file: &Path) -> Result<Vec<Rule>, Error> {
+    parse_rules(&fs::read_to_string(file)?)
+}
+
+/// The rules of a YAML file: a `rules` list of mappings
+pub fn parse_rules(data: &str) -> Result<Vec<Rule>, Error> {
+    let file: RulesFile =
+        serde_yaml::from_str(data).map_err(|e| FunctionError::RulesParseError(e.to_string()))?;
+    file.rules
+        .into_iter()
+        .map(Rule::from_spec)
+        .collect::<Result<_, _>>()
+        .map_err(|e| FunctionError::RulesParseError(e).into())
+}
+
+/// Outcome of a rule over the matched keys
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RuleResult {
+    pub passed: u64,
+    pub failed: u64,
+    /// The data of the key could not be read
+    pub unknown: u64,
+    pub failed_keys: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Results {
+    keys: u64,
+    compliant: u64,
+    rules: Vec<RuleResult>,
+}
+
+/// Command of report compliance, the report is printed at the end of the run
+pub struct ComplianceAudit {
+    options: ComplianceReport,
+    rules: OnceLock<Vec<Rule>>,
+    results: Mutex<Results>,
+}
+
+impl ComplianceAudit {
+    pub fn new(options: ComplianceReport) -> Self {
+        ComplianceAudit {
+            options,
+            rules: OnceLock::new(),
+            results: Mutex::new(Results::default()),
+        }
+    }
+
+    /// The rules of the file, read on the first use
+    fn rules(&self) -> Result<&[Rule], Error> {
+        if let Some(rules) = self.rules.get() {
+            return Ok(rules);
+        }
+        let rules = read_rules(&self.options.rules)?;
+        Ok(self.rules.get_or_init(|| rules))
+    }
+
+    fn add(&self, url: &str, outcomes: &[Option<bool>]) {
+        let mut results = self.results.lock().unwrap();
+        results.rules.resize(outcomes.len(), RuleResult::default());
+        results.keys += 1;
+        if outcomes.iter().all(|x| *x == Some(true)) {
+            results.compliant += 1;
+        }
+        for (result, outcome) in results.rules.iter_mut().zip(outcomes) {
+            match outcome {
+                Some(true) => result.passed += 1,
+                Some(false) => {
+                    result.failed += 1;
+                    if result.failed_keys.len() < self.options.max_failed_keys {
+                        result.failed_keys.push(url.to_owned());
+                    }
+                }
+                None => result.unknown += 1,
+            }
+        }
+    }
+
+    /// The report of the keys so far, the counts start again
+    fn document(&self, now: DateTime<Utc>) -> Result<Value, Error> {
+        let rules = self.rules()?;
+        let results = std::mem::take(&mut *self.results.lock().unwrap());
+        let empty = RuleResult::default();
+        let rules: Vec<Value> = rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                let result = results.rules.get(i).unwrap_or(&empty);
+                json!({
+                    "name": rule.name,
+                    "check": rule.check.name(),
+                    "passed": result.passed,
+                    "failed": result.failed,
+                    "unknown": result.unknown,
+                    "failed_keys": result.failed_keys,
+                })
+            })
+            .collect();
+
+        Ok(json!({
+            "generated": now.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "keys": results.keys,
+            "compliant": results.compliant,
+            "rules": rules,
+        }))
+    }
+}
+
+/// The report as an HTML page: the counts of the rules and their failed keys
+pub fn to_html(document: &Value) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>s3find compliance report</title>\n</head>\n<body>\n",
+    );
+    html += &format!(
+        "<h1>Compliance report</h1>\n<p>{} keys, {} compliant, generated {}</p>\n",
+        document["keys"],
+        document["compliant"],
+        escape_html(document["generated"].as_str().unwrap_or_default())
+    );
+    html += "<table>\n<tr><th>Rule</th><th>Check</th><th>Passed</th><th>Failed</th><th>Unknown</th></tr>\n";
+    let rules = document["rules"].as_array().cloned().unwrap_or_default();
+    for rule in &rules {
+        html += &format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(rule["name"].as_str().unwrap_or_default()),
+            escape_html(rule["check"].as_str().unwrap_or_default()),
+            rule["passed"],
+            rule["failed"],
+            rule["unknown"]
+        );
+    }
+    html += "</table>\n";
+
+    for rule in &rules {
+        let keys = rule["failed_keys"].as_array().cloned().unwrap_or_default();
+        if keys.is_empty() {
+            continue;
+        }
+        html += &format!(
+            "<h2>{}</h2>\n<ul>\n",
+            escape_html(rule["name"].as_str().unwrap_or_default())
+        );
+        for key in keys {
+            html += &format!(
+                "<li>{}</li>\n",
+                escape_html(key.as_str().unwrap_or_default())
+            );
+        }
+        html += "</ul>\n";
+    }
+    html + "</body>\n</html>\n"
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl RunCommand for ComplianceAudit {
+    fn execute(
+        &self,
+        client: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let rules = self.rules()?;
+        let mut fields = Vec::new();
+        for field in rules.iter().filter_map(|x| x.check.field()) {
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        let now = Utc::now();
+
+        let outcomes = parallel_map(list, self.options.concurrency, |x| {
+            let key = x.key.as_deref().unwrap_or_default();
+            let data = if fields.is_empty() {
+                Enrichment::default()
+            } else {
+                enrichment.fetch(client, &path.bucket, key, &fields)
+            };
+            let outcomes: Vec<Option<bool>> = rules
+                .iter()
+                .map(|rule| rule.check.evaluate(x, &data, now))
+                .collect();
+            (format!("s3://{}/{}", path.bucket, key), outcomes)
+        });
+        for (url, outcomes) in outcomes {
+            self.add(&url, &outcomes);
+        }
+        Ok(())
+    }
+
+    fn finish(&self, output: &dyn OutputSink) -> Result<(), Error> {
+        let document = self.document(Utc::now())?;
+        let report = match self.options.format {
+            ReportFormat::Json => serde_json::to_string_pretty(&document)?,
+            ReportFormat::Html => to_html(&document),
+        };
+        match self.options.output {
+            Some(ref file) => fs::write(file, report)?,
+            None => output.line(report.trim_end()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_s3::{HeadObjectOutput, Tag};
+
+    const RULES: &str = r#"
+# quarterly audit
+rules:
+  - name: encrypted
+    check: encryption
+  - name: owner-tag # the owner is required
+    check: tags
+    keys: [owner, 'cost-center']
+  - name: retention
+    check: max-age
+    age: 30d
+  - name: naming
+    check: naming
+    regex: '^[a-z0-9/._-]+$'
+"#;
+
+    #[test]
+    fn compliance_rules() -> Result<(), Error> {
+        let rules = parse_rules(RULES)?;
+        assert_eq!(rules.len(), 4);
+        assert_eq!(rules[1].name, "owner-tag");
+        match rules[1].check {
+            Check::Tags(ref keys) => assert_eq!(keys, &["owner", "cost-center"]),
+            ref check => panic!("unexpected check {:?}", check),
+        }
+        assert!(parse_rules("rules:\n  - name: x\n    check: size\n").is_err());
+        assert!(parse_rules("rules:\n  - name: x\n    check: tags\n").is_err());
+        assert!(parse_rules("rules:\n    check: naming\n").is_err());
+        assert!(parse_rules("checks:\n").is_err());
+        assert!(
+            parse_rules("rules:\n  - name: x\n    check: naming\n    regex: a\n    age: 1d\n")
+                .is_err()
+        );
+
+        // anchors, block scalars and quoted keys
+        let yaml = r#"
+required: &required [owner, "cost center"]
+rules:
+  - "name": owners
+    check: tags
+    keys: *required
+  - name: lowercase
+    check: naming
+    regex: >-
+      ^[a-z0-9/._-]+$
+"#;
+        let rules = parse_rules(yaml)?;
+        match rules[0].check {
+            Check::Tags(ref keys) => assert_eq!(keys, &["owner", "cost center"]),
+            ref check => panic!("unexpected check {:?}", check),
+        }
+        match rules[1].check {
+            Check::Naming(ref regex) => assert_eq!(regex.as_str(), "^[a-z0-9/._-]+$"),
+            ref check => panic!("unexpected check {:?}", check),
+        }
+        let rules = parse_rules(RULES)?;
+
+        let now: DateTime<Utc> = "2020-03-01T00:00:00Z".parse()?;
+        let object = Object {
+            key: Some("logs/A.csv".to_owned()),
+            last_modified: Some("2020-01-01T00:00:00.000Z".to_owned()),
+            ..Default::default()
+        };
+        let data = Enrichment {
+            tags: Some(vec![Tag {
+                key: "owner".to_owned(),
+                value: "data".to_owned(),
+            }]),
+            head: Some(HeadObjectOutput {
+                server_side_encryption: Some("AES256".to_owned()),
+                ..Default::default()
+            }),
+            acl: None,
+        };
+        let outcomes: Vec<Option<bool>> = rules
+            .iter()
+            .map(|x| x.check.evaluate(&object, &data, now))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![Some(true), Some(false), Some(false), Some(false)]
+        );
+        assert_eq!(Check::NoPublicAcl.evaluate(&object, &data, now), None);
+
+        let audit = ComplianceAudit::new(ComplianceReport {
+            rules: "rules.yaml".into(),
+            format: ReportFormat::Json,
+            output: None,
+            max_failed_keys: 1,
+            concurrency: 1,
+        });
+        audit.rules.set(rules).unwrap();
+        audit.add("s3://bucket/logs/A.csv", &outcomes);
+        audit.add(
+            "s3://bucket/logs/b.csv",
+            &[Some(true), Some(false), None, Some(true)],
+        );
+        let document = audit.document(now)?;
+        assert_eq!(document["keys"], 2);
+        assert_eq!(document["compliant"], 0);
+        assert_eq!(document["rules"][0]["passed"], 2);
+        assert_eq!(document["rules"][1]["failed"], 2);
+        assert_eq!(
+            document["rules"][1]["failed_keys"],
+            json!(["s3://bucket/logs/A.csv"])
+        );
+        assert_eq!(document["rules"][2]["unknown"], 1);
+        assert!(to_html(&document).contains("<td>owner-tag</td><td>tags</td><td>0</td><td>2</td>"));
+
+        // the next run counts again
+        assert_eq!(audit.document(now)?["keys"], 0);
+        Ok(())
+    }
+}
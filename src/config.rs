@@ -0,0 +1,321 @@
+use failure::Error;
+use serde_json::Value;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::clap::{self, ArgMatches, ErrorKind};
+use structopt::StructOpt;
+
+use crate::arg::FindOpt;
+use crate::error::FunctionError;
+
+/// Options of a config file section in the file order
+pub type Options = Vec<(String, Value)>;
+
+/// Default options of a TOML file: the top-level keys are the long options
+/// of s3find, the keys of a `[download]`, `[print]`, ... section are the
+/// options of the subcommand
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub options: Options,
+    pub sections: Vec<(String, Options)>,
+}
+
+impl Config {
+    pub fn load(file: &Path) -> Result<Self, Error> {
+        Config::parse(&fs::read_to_string(file)?)
+    }
+
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let table: toml::Table = toml::from_str(data).map_err(|e| {
+            let message = match e.span() {
+                Some(span) => {
+                    let line = data[..span.start].matches('\n').count() + 1;
+                    format!("line {}: {}", line, e.message())
+                }
+                None => e.message().to_owned(),
+            };
+            FunctionError::ConfigParseError(message)
+        })?;
+        let mut config = Config::default();
+        config.options = table_options(&mut config, None, table)?;
+        Ok(config)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Options> {
+        self.sections.iter().find(|x| x.0 == name).map(|x| &x.1)
+    }
+
+    /// Options of a `[target.<name>]` section, with the short names of the
+    /// connection options
+    pub fn target(&self, name: &str) -> Result<Options, Error> {
+        let options = self
+            .section(&format!("target.{}", name))
+            .ok_or_else(|| FunctionError::ConfigTargetError(name.to_owned()))?;
+
+        let mut result = Vec::new();
+        for (key, value) in options {
+            let key = match key.as_str() {
+                "endpoint" => "endpoint-url",
+                "region" => "aws-region",
+                "profile" => "aws-profile",
+                // the requests are always path-style
+                "path-style" if *value == Value::Bool(true) => continue,
+                "path-style" => {
+                    let message = "only path-style requests are supported".to_owned();
+                    return Err(FunctionError::ConfigParseError(message).into());
+                }
+                key => key,
+            };
+            result.push((key.to_owned(), value.clone()));
+        }
+        Ok(result)
+    }
+
+    /// Command line with the options of the config not set on it: the global
+    /// options go first, the options of the subcommand last. The options of
+    /// the --target override the top-level ones
+    pub fn args(&self, args: &[OsString], matches: &ArgMatches) -> Result<Vec<OsString>, Error> {
+        let mut options = match matches.value_of("target") {
+            Some(target) => self.target(target)?,
+            None => Vec::new(),
+        };
+        let defaults: Options = self
+            .options
+            .iter()
+            .filter(|x| !options.iter().any(|y| y.0 == x.0))
+            .cloned()
+            .collect();
+        options.extend(defaults);
+
+        let mut result: Vec<OsString> = args.iter().take(1).cloned().collect();
+        result.extend(option_args(&options, matches)?);
+        result.extend(args.iter().skip(1).cloned());
+
+        if let (name, Some(matches)) = matches.subcommand() {
+            if let Some(options) = self.section(name) {
+                result.extend(option_args(options, matches)?);
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn option_args(options: &Options, matches: &ArgMatches) -> Result<Vec<OsString>, Error> {
+    let mut args = Vec::new();
+    for (key, value) in options {
+        if matches.occurrences_of(key) > 0 {
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(values) => values.clone(),
+            value => vec![value.clone()],
+        };
+        for value in values {
+            args.push(
+                match value {
+                    Value::Bool(true) => format!("--{}", key),
+                    Value::Bool(false) => continue,
+                    Value::String(value) => format!("--{}={}", key, value),
+                    Value::Number(value) => format!("--{}={}", key, value),
+                    _ => {
+                        let message = format!("unsupported value of {}", key);
+                        return Err(FunctionError::ConfigParseError(message).into());
+                    }
+                }
+                .into(),
+            );
+        }
+    }
+    Ok(args)
+}
+
+/// ~/.config/s3find/config.toml, $XDG_CONFIG_HOME is used if set
+pub fn default_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("s3find").join("config.toml"))
+}
+
+/// Command line options with the defaults of the config file (--config or the default path),
+/// the file is required for --target
+pub fn from_args() -> FindOpt {
+    let args: Vec<OsString> = env::args_os().collect();
+    let matches = FindOpt::clap().get_matches_from(&args);
+
+    let file = match matches.value_of_os("config") {
+        Some(file) => PathBuf::from(file),
+        None => match default_path() {
+            Some(file) if file.exists() || matches.is_present("target") => file,
+            _ => return FindOpt::from_clap(&matches),
+        },
+    };
+
+    let args = Config::load(&file)
+        .and_then(|config| config.args(&args, &matches))
+        .unwrap_or_else(|e| {
+            let message = format!("Failed to read the config file {}: {}", file.display(), e);
+            clap::Error::with_description(&message, ErrorKind::InvalidValue).exit()
+        });
+
+    match FindOpt::clap().get_matches_from_safe(&args) {
+        Ok(matches) => FindOpt::from_clap(&matches),
+        Err(e) => {
+            eprintln!("Invalid option in the config file {}", file.display());
+            e.exit()
+        }
+    }
+}
+
+/// Options of a table, the tables nested in it are the sections `<name>.<key>`
+fn table_options(
+    config: &mut Config,
+    name: Option<&str>,
+    table: toml::Table,
+) -> Result<Options, Error> {
+    let mut options = Vec::new();
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(table) => {
+                let section = match name {
+                    Some(name) => format!("{}.{}", name, key),
+                    None => key,
+                };
+                let section_options = table_options(config, Some(&section), table)?;
+                config.sections.push((section, section_options));
+            }
+            value => options.push((key, serde_json::to_value(value)?)),
+        }
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const CONFIG: &str = r#"
+# defaults of the team
+aws-region = "eu-west-1"
+page-size = 500
+summarize = true
+benchmark = false
+color = "never"
+protected-prefix = ["backups/"]
+name = [
+    "*.log",  # logs
+    '*.gz',
+]
+
+[download]
+concurrency = 8
+
+[target.minio]
+endpoint = "http://minio.local:9000"
+region = "us-east-1"
+profile = "minio"
+path-style = true
+"#;
+
+    fn os_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn config_parse() -> Result<(), Error> {
+        let config = Config::parse(CONFIG)?;
+        assert_eq!(
+            config.options,
+            vec![
+                ("aws-region".to_owned(), json!("eu-west-1")),
+                ("page-size".to_owned(), json!(500)),
+                ("summarize".to_owned(), json!(true)),
+                ("benchmark".to_owned(), json!(false)),
+                ("color".to_owned(), json!("never")),
+                ("protected-prefix".to_owned(), json!(["backups/"])),
+                ("name".to_owned(), json!(["*.log", "*.gz"])),
+            ]
+        );
+        assert_eq!(
+            config.section("download"),
+            Some(&vec![("concurrency".to_owned(), json!(8))])
+        );
+
+        assert!(Config::parse("page-size = ").is_err());
+        assert!(Config::parse("name = \"*.log").is_err());
+        assert!(Config::parse("limit = 1 2").is_err());
+        assert!(Config::parse("limit = 1\nlimit = 2").is_err());
+        assert!(Config::parse("[download\nforce = true").is_err());
+        let error = Config::parse("limit = 1\n[download]\nforce = yes").unwrap_err();
+        assert!(error.to_string().contains("line 3"));
+        assert!(Config::parse("[download]\nforce = true\n[download]\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_args() -> Result<(), Error> {
+        let config = Config::parse(CONFIG)?;
+        let args = os_args(&[
+            "s3find",
+            "s3://bucket/path",
+            "--page-size",
+            "100",
+            "download",
+            "/tmp",
+        ]);
+        let matches = FindOpt::clap().get_matches_from(&args);
+
+        assert_eq!(
+            config.args(&args, &matches)?,
+            os_args(&[
+                "s3find",
+                "--aws-region=eu-west-1",
+                "--summarize",
+                "--color=never",
+                "--protected-prefix=backups/",
+                "--name=*.log",
+                "--name=*.gz",
+                "s3://bucket/path",
+                "--page-size",
+                "100",
+                "download",
+                "/tmp",
+                "--concurrency=8",
+            ])
+        );
+
+        let args = os_args(&["s3find", "--target", "minio", "s3://bucket/path"]);
+        let matches = FindOpt::clap().get_matches_from(&args);
+        assert_eq!(
+            config.args(&args, &matches)?,
+            os_args(&[
+                "s3find",
+                "--endpoint-url=http://minio.local:9000",
+                "--aws-region=us-east-1",
+                "--aws-profile=minio",
+                "--page-size=500",
+                "--summarize",
+                "--color=never",
+                "--protected-prefix=backups/",
+                "--name=*.log",
+                "--name=*.gz",
+                "--target",
+                "minio",
+                "s3://bucket/path",
+            ])
+        );
+
+        let args = os_args(&["s3find", "--target", "ceph", "s3://bucket/path"]);
+        let matches = FindOpt::clap().get_matches_from(&args);
+        assert!(config.args(&args, &matches).is_err());
+        assert!(Config::parse("[target.aws]\npath-style = false")?
+            .target("aws")
+            .is_err());
+        Ok(())
+    }
+}
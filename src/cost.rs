@@ -0,0 +1,299 @@
+use failure::{err_msg, Error};
+use humansize::{file_size_opts as options, FileSize};
+use rusoto_core::Region;
+use rusoto_s3::{Object, S3Client};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::arg::{CostReport, PlannedOperation, S3path};
+use crate::enrich::EnrichmentCache;
+use crate::function::RunCommand;
+use crate::listing::prefix_group;
+use crate::output::OutputSink;
+use crate::pricing::{PriceTable, DEFAULT_STORAGE_CLASS};
+
+/// Keys of a prefix and storage class
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ClassUsage {
+    pub count: u64,
+    pub bytes: i64,
+}
+
+/// A line of the report
+#[derive(Debug, Clone, PartialEq)]
+struct CostRow {
+    prefix: String,
+    class: String,
+    usage: ClassUsage,
+    storage: f64,
+    /// The costs of --operation, None when it does not apply to the class
+    operations: Vec<Option<f64>>,
+}
+
+/// Command of report cost, the costs by prefix and storage class are
+/// printed at the end of the run
+pub struct CostReporter {
+    options: CostReport,
+    prices: PriceTable,
+    groups: Mutex<BTreeMap<(String, String), ClassUsage>>,
+}
+
+impl CostReporter {
+    pub fn new(options: CostReport, prices: PriceTable) -> Self {
+        CostReporter {
+            options,
+            prices,
+            groups: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Cost of an operation on keys of the class, None when it does not
+    /// apply: the archived keys are restored before a download or a copy,
+    /// only they are restored
+    pub fn operation_cost(
+        &self,
+        operation: PlannedOperation,
+        class: &str,
+        usage: ClassUsage,
+    ) -> Option<f64> {
+        let (count, bytes) = (usage.count, usage.bytes);
+        match operation {
+            PlannedOperation::Download => self.prices.download_cost(class, count, bytes),
+            PlannedOperation::Copy => self.prices.copy_cost(class, count, bytes),
+            PlannedOperation::RestoreExpedited => {
+                self.prices.restore_cost(class, "Expedited", count, bytes)
+            }
+            PlannedOperation::RestoreStandard => {
+                self.prices.restore_cost(class, "Standard", count, bytes)
+            }
+            PlannedOperation::RestoreBulk => self.prices.restore_cost(class, "Bulk", count, bytes),
+        }
+    }
+
+    /// The rows of the report and of the total, the counts start again
+    fn rows(&self) -> Vec<CostRow> {
+        let groups = std::mem::take(&mut *self.groups.lock().unwrap());
+        let mut total = (
+            ClassUsage::default(),
+            0.0,
+            vec![0.0; self.options.operations.len()],
+        );
+
+        let mut rows = Vec::new();
+        for ((prefix, class), usage) in groups {
+            let storage = self.prices.monthly_cost(&class, usage.bytes);
+            let operations: Vec<Option<f64>> = self
+                .options
+                .operations
+                .iter()
+                .map(|x| self.operation_cost(*x, &class, usage))
+                .collect();
+
+            total.0.count += usage.count;
+            total.0.bytes += usage.bytes;
+            total.1 += storage;
+            for (sum, cost) in total.2.iter_mut().zip(operations.iter()) {
+                *sum += cost.unwrap_or_default();
+            }
+            rows.push(CostRow {
+                prefix,
+                class,
+                usage,
+                storage,
+                operations,
+            });
+        }
+        rows.push(CostRow {
+            prefix: "total".to_owned(),
+            class: String::new(),
+            usage: total.0,
+            storage: total.1,
+            operations: total.2.into_iter().map(Some).collect(),
+        });
+        rows
+    }
+
+    fn report(&self) -> Result<Vec<String>, Error> {
+        let rows = self.rows();
+        if self.options.json {
+            // the total is the last row
+            let last = rows.len() - 1;
+            return Ok(rows
+                .into_iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    let CostRow {
+                        prefix,
+                        class,
+                        usage,
+                        storage,
+                        operations,
+                    } = row;
+                    let operations: Map<String, Value> = self
+                        .options
+                        .operations
+                        .iter()
+                        .zip(operations)
+                        .map(|(x, cost)| (x.name().to_owned(), json!(cost)))
+                        .collect();
+                    let mut document = json!({
+                        "prefix": prefix,
+                        "storage_class": class,
+                        "count": usage.count,
+                        "bytes": usage.bytes,
+                        "monthly_cost": storage,
+                        "operations": operations,
+                    });
+                    if i == last {
+                        document = json!({ "total": document });
+                    }
+                    document.to_string()
+                })
+                .collect());
+        }
+
+        let mut table = vec![["prefix", "class", "keys", "size", "storage/month"]
+            .iter()
+            .map(|x| (*x).to_owned())
+            .chain(self.options.operations.iter().map(|x| x.name().to_owned()))
+            .collect::<Vec<String>>()];
+        for CostRow {
+            prefix,
+            class,
+            usage,
+            storage,
+            operations,
+        } in rows
+        {
+            let mut row = vec![
+                prefix,
+                class,
+                usage.count.to_string(),
+                usage
+                    .bytes
+                    .file_size(options::CONVENTIONAL)
+                    .map_err(err_msg)?,
+                format!("${:.2}", storage),
+            ];
+            row.extend(
+                operations
+                    .into_iter()
+                    .map(|x| x.map_or_else(|| "-".to_owned(), |x| format!("${:.2}", x))),
+            );
+            table.push(row);
+        }
+
+        let mut widths = vec![0; table[0].len()];
+        for row in &table {
+            for (width, field) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(field.chars().count());
+            }
+        }
+        Ok(table
+            .into_iter()
+            .map(|row| {
+                let line: Vec<String> = row
+                    .iter()
+                    .zip(widths.iter())
+                    .enumerate()
+                    .map(|(i, (field, width))| {
+                        if i < 2 {
+                            format!("{:1$}", field, width)
+                        } else {
+                            format!("{:>1$}", field, width)
+                        }
+                    })
+                    .collect();
+                line.join("  ").trim_end().to_owned()
+            })
+            .collect())
+    }
+}
+
+impl RunCommand for CostReporter {
+    fn execute(
+        &self,
+        _c: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let mut groups = self.groups.lock().unwrap();
+        for object in list {
+            let key = object.key.as_deref().unwrap_or_default();
+            let prefix = format!(
+                "s3://{}/{}",
+                path.bucket,
+                prefix_group(path, key, self.options.depth)
+            );
+            let class = object
+                .storage_class
+                .clone()
+                .unwrap_or_else(|| DEFAULT_STORAGE_CLASS.to_owned());
+            let usage = groups.entry((prefix, class)).or_default();
+            usage.count += 1;
+            usage.bytes += object.size.unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    fn finish(&self, output: &dyn OutputSink) -> Result<(), Error> {
+        for line in self.report()? {
+            output.line(&line);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::MemorySink;
+
+    #[test]
+    fn cost_report() -> Result<(), Error> {
+        let reporter = CostReporter::new(
+            CostReport {
+                depth: 1,
+                operations: vec![PlannedOperation::Download, PlannedOperation::RestoreBulk],
+                json: false,
+            },
+            PriceTable::default(),
+        );
+        let gigabyte = 1024 * 1024 * 1024;
+        let object = |key: &str, class: Option<&str>, size: i64| Object {
+            key: Some(key.to_owned()),
+            storage_class: class.map(str::to_owned),
+            size: Some(size),
+            ..Default::default()
+        };
+        reporter.execute(
+            &S3Client::new(Region::UsEast1),
+            &Region::UsEast1,
+            &"s3://bucket/data/".parse()?,
+            &[
+                object("data/logs/a", None, gigabyte * 100),
+                object("data/logs/b", Some("GLACIER"), gigabyte * 1000),
+                object("data/c", Some("STANDARD"), gigabyte),
+            ],
+            &EnrichmentCache::default(),
+            &MemorySink::default(),
+        )?;
+
+        assert_eq!(
+            reporter.report()?,
+            vec![
+                "prefix                  class     keys     size  storage/month  download  restore-bulk",
+                "s3://bucket/data/       STANDARD     1     1 GB          $0.02     $0.09             -",
+                "s3://bucket/data/logs/  GLACIER      1  1000 GB          $3.60         -         $2.50",
+                "s3://bucket/data/logs/  STANDARD     1   100 GB          $2.30     $9.00             -",
+                "total                                3  1.08 TB          $5.92     $9.09         $2.50",
+            ]
+        );
+        assert_eq!(reporter.report()?.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use log::{error, info};
+use std::thread;
+use std::time::{self, Instant};
+
+use crate::arg::Schedule;
+use crate::cancel::is_cancelled;
+use crate::command::Find;
+use crate::run::{walk, Walk};
+
+impl Schedule {
+    pub fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+        match (self.any_day, self.any_weekday) {
+            (true, _) => weekday,
+            (_, true) => day,
+            _ => day || weekday,
+        }
+    }
+
+    /// The first time of the schedule after `after`, none when it does not
+    /// match within 4 years, e.g. on the 30th of February
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time =
+            after.date().and_hms_opt(after.hour(), after.minute(), 0)? + Duration::minutes(1);
+        let end = time + Duration::days(4 * 366);
+
+        while time < end {
+            let date = time.date();
+            if self.months & 1 << date.month() == 0 {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & 1 << time.hour() == 0 {
+                time = date.and_hms_opt(time.hour(), 0, 0)? + Duration::hours(1);
+            } else if self.minutes & 1 << time.minute() == 0 {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+/// Walk the paths at the times of the schedule, in local time, until the walk
+/// is cancelled. `pass` gets the outcome of every run, e.g. to print its
+/// summary. A run due while the previous one is running is skipped
+pub fn daemon<F>(status: &mut Find, schedule: &Schedule, mut pass: F)
+where
+    F: FnMut(&Find, &Walk),
+{
+    let (limit, skip) = (status.limit, status.skip.get());
+
+    while !is_cancelled() {
+        let next = match schedule.next_after(Local::now().naive_local()) {
+            Some(next) => next,
+            None => {
+                error!("The schedule has no next run");
+                return;
+            }
+        };
+        info!("Next run at {}", next);
+
+        while !is_cancelled() && Local::now().naive_local() < next {
+            let left = (next - Local::now().naive_local())
+                .to_std()
+                .unwrap_or_default();
+            thread::sleep(left.min(time::Duration::from_millis(200)));
+        }
+        if is_cancelled() {
+            return;
+        }
+
+        info!("Run started");
+        let start = Instant::now();
+        status.limit = limit;
+        status.skip.set(skip);
+        let result = walk(status);
+        info!("{}", run_summary(&result, start.elapsed()));
+        pass(status, &result);
+    }
+}
+
+fn run_summary(walk: &Walk, elapsed: time::Duration) -> String {
+    let mut summary = format!(
+        "Run {} in {:.1}s",
+        if walk.cancelled {
+            "interrupted"
+        } else {
+            "finished"
+        },
+        elapsed.as_secs_f64()
+    );
+    if let Some(ref stats) = walk.stats {
+        summary += &format!(", {} keys matched", stats.total_files);
+    }
+    if walk.failed() {
+        summary += &format!(", {} paths failed", walk.errors.len());
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn schedule_times() {
+        let next = |schedule: &str, after| {
+            schedule
+                .parse::<Schedule>()
+                .unwrap()
+                .next_after(time(after))
+        };
+
+        assert_eq!(
+            next("0 3 * * *", "2020-01-01 02:59:30"),
+            Some(time("2020-01-01 03:00:00"))
+        );
+        assert_eq!(
+            next("0 3 * * *", "2020-01-01 03:00:00"),
+            Some(time("2020-01-02 03:00:00"))
+        );
+        assert_eq!(
+            next("*/15 * * * *", "2020-12-31 23:50:00"),
+            Some(time("2021-01-01 00:00:00"))
+        );
+        // 2020-01-04 is a Saturday
+        assert_eq!(
+            next("30 6 * * 1-5", "2020-01-03 07:00:00"),
+            Some(time("2020-01-06 06:30:00"))
+        );
+        // either the day of the month or of the week
+        assert_eq!(
+            next("0 0 15 * 0", "2020-01-06 00:00:00"),
+            Some(time("2020-01-12 00:00:00"))
+        );
+        assert_eq!(
+            next("0 0 29 2 *", "2020-03-01 00:00:00"),
+            Some(time("2024-02-29 00:00:00"))
+        );
+        assert_eq!(next("0 0 30 2 *", "2020-01-01 00:00:00"), None);
+    }
+}
@@ -0,0 +1,190 @@
+use failure::Error;
+use rusoto_core::Region;
+use rusoto_s3::{Object, S3Client};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::arg::{DuplicatesReport, S3path};
+use crate::enrich::EnrichmentCache;
+use crate::function::RunCommand;
+use crate::output::OutputSink;
+
+/// Keys with the same ETag and size. The ETag of a multipart upload depends
+/// on the part size, so the same content uploaded with other parts is missed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    pub e_tag: String,
+    pub size: i64,
+    pub keys: Vec<String>,
+}
+
+impl Cluster {
+    /// Bytes freed by keeping a single key of the cluster
+    pub fn wasted(&self) -> i64 {
+        self.size * (self.keys.len() as i64 - 1)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "etag": self.e_tag,
+            "size": self.size,
+            "count": self.keys.len(),
+            "wasted": self.wasted(),
+            "keys": self.keys,
+        })
+    }
+}
+
+/// Command of report duplicates, the clusters are printed at the end of the run
+pub struct DuplicateFinder {
+    options: DuplicatesReport,
+    keys: Mutex<HashMap<(String, i64), Vec<String>>>,
+}
+
+impl DuplicateFinder {
+    pub fn new(options: DuplicatesReport) -> Self {
+        DuplicateFinder {
+            options,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The clusters of the keys so far, the most wasteful first, the keys
+    /// start again
+    pub fn clusters(&self) -> Vec<Cluster> {
+        let keys = std::mem::take(&mut *self.keys.lock().unwrap());
+        let mut clusters: Vec<Cluster> = keys
+            .into_iter()
+            .filter(|x| x.1.len() > 1)
+            .map(|((e_tag, size), mut keys)| {
+                keys.sort();
+                Cluster { e_tag, size, keys }
+            })
+            .collect();
+        clusters.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then(a.keys.cmp(&b.keys)));
+        clusters
+    }
+
+    fn report(&self, clusters: &[Cluster]) -> Vec<String> {
+        let wasted: i64 = clusters.iter().map(Cluster::wasted).sum();
+        if self.options.json {
+            let document = json!({
+                "clusters": clusters.iter().map(Cluster::to_json).collect::<Vec<_>>(),
+                "wasted": wasted,
+            });
+            return vec![document.to_string()];
+        }
+
+        let mut lines = Vec::new();
+        for cluster in clusters {
+            lines.push(format!(
+                "{}\t{}\t{}\t{}",
+                cluster.e_tag,
+                cluster.size,
+                cluster.keys.len(),
+                cluster.wasted()
+            ));
+            lines.extend(cluster.keys.iter().map(|x| format!("\t{}", x)));
+        }
+        lines.push(format!(
+            "{} duplicate clusters, {} bytes reclaimable",
+            clusters.len(),
+            wasted
+        ));
+        lines
+    }
+}
+
+impl RunCommand for DuplicateFinder {
+    fn execute(
+        &self,
+        _c: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let min_size = self.options.min_size.map_or(1, |x| x.0 as i64).max(1);
+        let mut keys = self.keys.lock().unwrap();
+        for object in list {
+            let size = object.size.unwrap_or_default();
+            let e_tag = match object.e_tag {
+                Some(ref e_tag) if size >= min_size => e_tag.trim_matches('"'),
+                _ => continue,
+            };
+            keys.entry((e_tag.to_owned(), size))
+                .or_default()
+                .push(format!(
+                    "s3://{}/{}",
+                    path.bucket,
+                    object.key.as_deref().unwrap_or_default()
+                ));
+        }
+        Ok(())
+    }
+
+    fn finish(&self, output: &dyn OutputSink) -> Result<(), Error> {
+        for line in self.report(&self.clusters()) {
+            output.line(&line);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::MemorySink;
+
+    #[test]
+    fn duplicate_clusters() -> Result<(), Error> {
+        let finder = DuplicateFinder::new(DuplicatesReport {
+            json: false,
+            min_size: None,
+        });
+        let object = |key: &str, size: i64, e_tag: &str| Object {
+            key: Some(key.to_owned()),
+            size: Some(size),
+            e_tag: Some(format!("\"{}\"", e_tag)),
+            ..Default::default()
+        };
+        let client = S3Client::new(Region::UsEast1);
+        let sink = MemorySink::default();
+        let list = vec![
+            object("a", 10, "1"),
+            object("b", 10, "1"),
+            object("c", 10, "2"),
+            object("d", 100, "3"),
+            object("e", 100, "3"),
+            object("f", 100, "3"),
+            object("empty-1", 0, "d41d8"),
+            object("empty-2", 0, "d41d8"),
+        ];
+        finder.execute(
+            &client,
+            &Region::UsEast1,
+            &"s3://bucket/".parse()?,
+            &list,
+            &EnrichmentCache::default(),
+            &sink,
+        )?;
+
+        let clusters = finder.clusters();
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(
+            clusters[0].keys,
+            vec!["s3://bucket/d", "s3://bucket/e", "s3://bucket/f"]
+        );
+        assert_eq!(clusters[0].wasted(), 200);
+        assert_eq!(clusters[1].e_tag, "1");
+        assert_eq!(
+            finder.report(&clusters).last().map(String::as_str),
+            Some("2 duplicate clusters, 210 bytes reclaimable")
+        );
+        assert_eq!(clusters[1].to_json()["count"], 2);
+        assert!(finder.clusters().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+use log::debug;
+use rusoto_s3::{
+    GetObjectAclRequest, GetObjectTaggingRequest, Grant, HeadObjectOutput, HeadObjectRequest,
+    S3Client, Tag, S3,
+};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::arg::EnrichField;
+use crate::report::ErrorReport;
+use crate::retry::with_retry;
+
+/// Per-object data fetched with additional API calls: tags, HeadObject and ACL
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Enrichment {
+    pub tags: Option<Vec<Tag>>,
+    pub head: Option<HeadObjectOutput>,
+    pub acl: Option<Vec<Grant>>,
+}
+
+impl Enrichment {
+    /// Fetch the requested data, failed calls are reported and leave the field empty
+    pub fn fetch(client: &S3Client, bucket: &str, key: &str, fields: &[EnrichField]) -> Self {
+        let mut enrichment = Enrichment::default();
+
+        if fields.contains(&EnrichField::Tags) {
+            let request = GetObjectTaggingRequest {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                ..Default::default()
+            };
+            debug!("GetObjectTagging s3://{}/{}", bucket, key);
+            match with_retry("GetObjectTagging", || {
+                client.get_object_tagging(request.clone())
+            }) {
+                Ok(x) => enrichment.tags = Some(x.tag_set),
+                Err(e) => {
+                    ErrorReport::from_rusoto("GetObjectTagging", bucket, Some(key), &e).emit()
+                }
+            }
+        }
+
+        if fields.contains(&EnrichField::Head) {
+            let request = HeadObjectRequest {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                ..Default::default()
+            };
+            debug!("HeadObject s3://{}/{}", bucket, key);
+            match with_retry("HeadObject", || client.head_object(request.clone())) {
+                Ok(x) => enrichment.head = Some(x),
+                Err(e) => ErrorReport::from_rusoto("HeadObject", bucket, Some(key), &e).emit(),
+            }
+        }
+
+        if fields.contains(&EnrichField::Acl) {
+            let request = GetObjectAclRequest {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                ..Default::default()
+            };
+            debug!("GetObjectAcl s3://{}/{}", bucket, key);
+            match with_retry("GetObjectAcl", || client.get_object_acl(request.clone())) {
+                Ok(x) => enrichment.acl = Some(x.grants.unwrap_or_default()),
+                Err(e) => ErrorReport::from_rusoto("GetObjectAcl", bucket, Some(key), &e).emit(),
+            }
+        }
+
+        enrichment
+    }
+
+    fn has(&self, field: EnrichField) -> bool {
+        match field {
+            EnrichField::Tags => self.tags.is_some(),
+            EnrichField::Head => self.head.is_some(),
+            EnrichField::Acl => self.acl.is_some(),
+        }
+    }
+
+    pub fn to_json(&self) -> Map<String, Value> {
+        let mut map = Map::new();
+
+        if let Some(ref tags) = self.tags {
+            let tags: Map<String, Value> = tags
+                .iter()
+                .map(|x| (x.key.clone(), Value::String(x.value.clone())))
+                .collect();
+            map.insert("tags".to_owned(), tags.into());
+        }
+
+        if let Some(ref head) = self.head {
+            map.insert(
+                "head".to_owned(),
+                json!({
+                    "content_type": head.content_type,
+                    "content_length": head.content_length,
+                    "content_encoding": head.content_encoding,
+                    "cache_control": head.cache_control,
+                    "server_side_encryption": head.server_side_encryption,
+                    "ssekms_key_id": head.ssekms_key_id,
+                    "version_id": head.version_id,
+                    "metadata": head.metadata,
+                }),
+            );
+        }
+
+        if let Some(ref acl) = self.acl {
+            let grants: Vec<Value> = acl
+                .iter()
+                .map(|x| json!({ "grantee": grantee_name(x), "permission": x.permission }))
+                .collect();
+            map.insert("acl".to_owned(), grants.into());
+        }
+
+        map
+    }
+
+    /// Header names of the CSV columns for the requested fields
+    pub fn csv_header(fields: &[EnrichField]) -> Vec<&'static str> {
+        let mut header = Vec::new();
+        for field in fields {
+            match field {
+                EnrichField::Tags => header.push("tags"),
+                EnrichField::Head => {
+                    header.extend(&["content_type", "server_side_encryption", "metadata"])
+                }
+                EnrichField::Acl => header.push("acl"),
+            }
+        }
+        header
+    }
+
+    pub fn csv_fields(&self, fields: &[EnrichField]) -> Vec<String> {
+        let mut row = Vec::new();
+        for field in fields {
+            match field {
+                EnrichField::Tags => row.push(
+                    self.tags
+                        .iter()
+                        .flatten()
+                        .map(|x| format!("{}={}", x.key, x.value))
+                        .collect::<Vec<_>>()
+                        .join(";"),
+                ),
+                EnrichField::Head => {
+                    let head = self.head.clone().unwrap_or_default();
+                    row.push(head.content_type.unwrap_or_default());
+                    row.push(head.server_side_encryption.unwrap_or_default());
+                    let mut metadata: Vec<_> = head
+                        .metadata
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|(k, v)| format!("{}={}", k, v))
+                        .collect();
+                    metadata.sort();
+                    row.push(metadata.join(";"));
+                }
+                EnrichField::Acl => row.push(
+                    self.acl
+                        .iter()
+                        .flatten()
+                        .map(|x| {
+                            format!(
+                                "{}:{}",
+                                grantee_name(x),
+                                x.permission.as_deref().unwrap_or_default()
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join(";"),
+                ),
+            }
+        }
+        row
+    }
+}
+
+/// Enrichment fetched for the keys being processed, shared by the enriched
+/// filters and the command so that each API call is made once per key
+#[derive(Debug, Default)]
+pub struct EnrichmentCache {
+    entries: Mutex<HashMap<(String, String), Enrichment>>,
+}
+
+impl EnrichmentCache {
+    /// The requested data of a key, fetching only what is not cached yet
+    pub fn fetch(
+        &self,
+        client: &S3Client,
+        bucket: &str,
+        key: &str,
+        fields: &[EnrichField],
+    ) -> Enrichment {
+        let id = (bucket.to_owned(), key.to_owned());
+        let mut cached = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .unwrap_or_default();
+
+        let missing: Vec<EnrichField> = fields
+            .iter()
+            .filter(|x| !cached.has(**x))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            let fetched = Enrichment::fetch(client, bucket, key, &missing);
+            cached.tags = cached.tags.or(fetched.tags);
+            cached.head = cached.head.or(fetched.head);
+            cached.acl = cached.acl.or(fetched.acl);
+            self.entries.lock().unwrap().insert(id, cached.clone());
+        }
+
+        Enrichment {
+            tags: cached.tags.filter(|_| fields.contains(&EnrichField::Tags)),
+            head: cached.head.filter(|_| fields.contains(&EnrichField::Head)),
+            acl: cached.acl.filter(|_| fields.contains(&EnrichField::Acl)),
+        }
+    }
+
+    /// Drop a key once it has been processed
+    pub fn remove(&self, bucket: &str, key: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .remove(&(bucket.to_owned(), key.to_owned()));
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn grantee_name(grant: &Grant) -> String {
+    grant
+        .grantee
+        .as_ref()
+        .and_then(|x| {
+            x.uri
+                .clone()
+                .or_else(|| x.display_name.clone())
+                .or_else(|| x.email_address.clone())
+                .or_else(|| x.id.clone())
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::Region;
+    use rusoto_s3::Grantee;
+    use std::collections::HashMap;
+
+    fn enrichment() -> Enrichment {
+        let mut metadata = HashMap::new();
+        metadata.insert("owner".to_owned(), "team".to_owned());
+
+        Enrichment {
+            tags: Some(vec![Tag {
+                key: "env".to_owned(),
+                value: "prod".to_owned(),
+            }]),
+            head: Some(HeadObjectOutput {
+                content_type: Some("text/plain".to_owned()),
+                metadata: Some(metadata),
+                ..Default::default()
+            }),
+            acl: Some(vec![Grant {
+                grantee: Some(Grantee {
+                    uri: Some("http://acs.amazonaws.com/groups/global/AllUsers".to_owned()),
+                    type_: "Group".to_owned(),
+                    ..Default::default()
+                }),
+                permission: Some("READ".to_owned()),
+            }]),
+        }
+    }
+
+    #[test]
+    fn enrichment_json() {
+        let json = enrichment().to_json();
+        assert_eq!(json["tags"]["env"], "prod");
+        assert_eq!(json["head"]["content_type"], "text/plain");
+        assert_eq!(json["head"]["metadata"]["owner"], "team");
+        assert_eq!(json["acl"][0]["permission"], "READ");
+    }
+
+    #[test]
+    fn enrichment_csv() {
+        let fields = [EnrichField::Tags, EnrichField::Head, EnrichField::Acl];
+        assert_eq!(
+            Enrichment::csv_header(&fields),
+            vec![
+                "tags",
+                "content_type",
+                "server_side_encryption",
+                "metadata",
+                "acl"
+            ]
+        );
+        assert_eq!(
+            enrichment().csv_fields(&fields),
+            vec![
+                "env=prod",
+                "text/plain",
+                "",
+                "owner=team",
+                "http://acs.amazonaws.com/groups/global/AllUsers:READ"
+            ]
+        );
+    }
+
+    #[test]
+    fn cached_enrichment() {
+        let cache = EnrichmentCache::default();
+        cache
+            .entries
+            .lock()
+            .unwrap()
+            .insert(("bucket".to_owned(), "key".to_owned()), enrichment());
+        // everything is cached, the client is not called
+        let client = S3Client::new(Region::Custom {
+            name: "local".to_owned(),
+            endpoint: "http://127.0.0.1:1".to_owned(),
+        });
+
+        let tags = cache.fetch(&client, "bucket", "key", &[EnrichField::Tags]);
+        assert_eq!(tags.tags, enrichment().tags);
+        assert_eq!(tags.head, None);
+        assert_eq!(tags.acl, None);
+        assert_eq!(
+            cache.fetch(
+                &client,
+                "bucket",
+                "key",
+                &[EnrichField::Head, EnrichField::Acl]
+            ),
+            Enrichment {
+                tags: None,
+                ..enrichment()
+            }
+        );
+
+        cache.remove("bucket", "key");
+        assert!(cache.is_empty());
+    }
+}
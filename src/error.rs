@@ -1,19 +1,229 @@
 use failure::*;
+use std::fmt;
 
-#[derive(Fail, Debug)]
+use crate::report::ErrorReport;
+
+#[derive(thiserror::Error, Debug)]
 pub enum FunctionError {
-    #[fail(display = "Invalid command line value")]
+    #[error("Invalid command line value")]
     CommandlineParse,
-    #[fail(display = "Invalid path value")]
+    #[error("Invalid path value")]
     ParentPathParse,
-    #[fail(display = "Cannot parse filename")]
+    #[error("Cannot parse filename")]
     FileNameParseError,
-    #[fail(display = "Cannot convert path to string")]
+    #[error("Cannot convert path to string")]
     PathConverError,
-    #[fail(display = "Cannot fetch body from s3 response")]
+    #[error("Cannot fetch body from s3 response")]
     S3FetchBodyError,
-    #[fail(display = "File is already present")]
+    #[error("File is already present")]
     PresentFileError,
-    #[fail(display = "S3 Object is not complete")]
+    #[error("S3 Object is not complete")]
     ObjectFieldError,
+    #[error("Cannot decode url-encoded key")]
+    KeyDecodeError,
+    #[error("Invalid listing cache file")]
+    CacheFormatError,
+    #[error("Listing cache is incomplete, list the bucket again with --cache")]
+    CacheIncompleteError,
+    #[error("Listing cache does not cover the requested path")]
+    CachePathError,
+    #[error("Invalid inventory manifest")]
+    InventoryManifestError,
+    #[error("Unsupported inventory format {0}, only CSV and Parquet are supported")]
+    InventoryFormatError(String),
+    #[error("Inventory manifest is for another bucket")]
+    InventoryBucketError,
+    #[error("Invalid inventory data file row")]
+    InventoryRowError,
+    #[error("Invalid resume state file")]
+    ResumeFormatError,
+    #[error("Resume state is for another path")]
+    ResumePathError,
+    #[error("MFA authentication failed: {0}")]
+    MfaError(String),
+    #[error("Invalid config file, {0}")]
+    ConfigParseError(String),
+    #[error("No [target.{0}] section in the config file")]
+    ConfigTargetError(String),
+    #[error("No PEM certificates in the CA bundle")]
+    CaBundleError,
+    #[error(
+        "The SSO session of the profile {0} has expired or is missing, run: aws sso login --profile {0}"
+    )]
+    SsoLoginError(String),
+    #[error("SSO authentication failed: {0}")]
+    SsoError(String),
+    #[error("No bucket matches {0}")]
+    BucketPatternError(String),
+    #[error("The reference object {0} is not found")]
+    NewerNotFoundError(String),
+    #[error("Invalid snapshot file")]
+    SnapshotFormatError,
+    #[error("Invalid rules file, {0}")]
+    RulesParseError(String),
+    #[error("The filter command failed with {0}: {1}")]
+    FilterCommandError(String, String),
+}
+
+/// Error of the library API, by kind. The report has the operation, the
+/// bucket and key, and the AWS error code and request id of S3 failures
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum S3FindError {
+    #[error("{0}")]
+    Credentials(ErrorReport),
+    #[error("{0}")]
+    Throttling(ErrorReport),
+    #[error("{0}")]
+    AccessDenied(ErrorReport),
+    #[error("{0}")]
+    NotFound(ErrorReport),
+    #[error("{0}")]
+    Io(ErrorReport),
+    #[error("{0}")]
+    Other(ErrorReport),
+}
+
+impl S3FindError {
+    pub fn kind(&self) -> ErrorKind {
+        self.report().kind
+    }
+
+    pub fn report(&self) -> &ErrorReport {
+        match self {
+            S3FindError::Credentials(report)
+            | S3FindError::Throttling(report)
+            | S3FindError::AccessDenied(report)
+            | S3FindError::NotFound(report)
+            | S3FindError::Io(report)
+            | S3FindError::Other(report) => report,
+        }
+    }
+
+    /// AWS error code, e.g. NoSuchKey
+    pub fn code(&self) -> Option<&str> {
+        self.report().code.as_deref()
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.report().request_id.as_deref()
+    }
+
+    /// Print the error on stderr and count it, see [`ErrorReport::emit`]
+    pub fn emit(&self) {
+        self.report().emit()
+    }
+}
+
+impl From<ErrorReport> for S3FindError {
+    fn from(report: ErrorReport) -> Self {
+        match report.kind {
+            ErrorKind::Credentials => S3FindError::Credentials(report),
+            ErrorKind::Throttling => S3FindError::Throttling(report),
+            ErrorKind::AccessDenied => S3FindError::AccessDenied(report),
+            ErrorKind::NotFound => S3FindError::NotFound(report),
+            ErrorKind::Io => S3FindError::Io(report),
+            ErrorKind::Other => S3FindError::Other(report),
+        }
+    }
+}
+
+/// Kind of a failed operation, for the exit code of the CLI and for library
+/// callers branching on the cause of an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ErrorKind {
+    /// Missing, invalid or expired credentials
+    Credentials,
+    /// The request rate is too high, SlowDown and the like
+    Throttling,
+    /// The bucket or the key is not accessible with the credentials
+    AccessDenied,
+    /// No such bucket, key or version
+    NotFound,
+    /// Local file or stream error
+    Io,
+    #[default]
+    Other,
+}
+
+impl ErrorKind {
+    /// Kind of an AWS error code
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "CredentialsError"
+            | "InvalidAccessKeyId"
+            | "SignatureDoesNotMatch"
+            | "ExpiredToken"
+            | "InvalidToken"
+            | "TokenRefreshRequired" => ErrorKind::Credentials,
+            "SlowDown"
+            | "Throttling"
+            | "ThrottlingException"
+            | "RequestLimitExceeded"
+            | "TooManyRequests" => ErrorKind::Throttling,
+            "AccessDenied" | "AllAccessDisabled" | "AccountProblem" => ErrorKind::AccessDenied,
+            "NoSuchKey" | "NoSuchBucket" | "NoSuchVersion" | "NoSuchUpload" | "NotFound" => {
+                ErrorKind::NotFound
+            }
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Kind of an HTTP status of S3 without an error code, as for HEAD requests
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            403 => ErrorKind::AccessDenied,
+            404 => ErrorKind::NotFound,
+            429 | 503 => ErrorKind::Throttling,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Kind of an error that did not come from an S3 response
+    pub fn of(error: &Error) -> Self {
+        if error.downcast_ref::<std::io::Error>().is_some() {
+            return ErrorKind::Io;
+        }
+        if error
+            .downcast_ref::<rusoto_credential::CredentialsError>()
+            .is_some()
+        {
+            return ErrorKind::Credentials;
+        }
+        if let Some(report) = error.downcast_ref::<ErrorReport>() {
+            return report.kind;
+        }
+        if let Some(error) = error.downcast_ref::<S3FindError>() {
+            return error.kind();
+        }
+        match error.downcast_ref::<FunctionError>() {
+            Some(FunctionError::MfaError(_))
+            | Some(FunctionError::SsoLoginError(_))
+            | Some(FunctionError::SsoError(_)) => ErrorKind::Credentials,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Exit code of the CLI when an error of this kind has been reported
+    pub fn exit_code(self) -> Option<i32> {
+        match self {
+            ErrorKind::Credentials => Some(3),
+            ErrorKind::AccessDenied => Some(4),
+            ErrorKind::Throttling => Some(5),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Credentials => "credentials",
+            ErrorKind::Throttling => "throttling",
+            ErrorKind::AccessDenied => "access-denied",
+            ErrorKind::NotFound => "not-found",
+            ErrorKind::Io => "io",
+            ErrorKind::Other => "other",
+        };
+        f.write_str(name)
+    }
 }
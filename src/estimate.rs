@@ -0,0 +1,183 @@
+use failure::Error;
+use humansize::{file_size_opts as options, FileSize};
+use rusoto_s3::Object;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::arg::Bandwidth;
+use crate::command::Find;
+use crate::function::{is_downloaded, read_manifest};
+use crate::pricing::{PriceTable, DEFAULT_STORAGE_CLASS};
+
+/// Objects, bytes and egress cost of downloading the matched keys, shown by
+/// download --estimate before starting
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EgressEstimate {
+    pub count: u64,
+    pub bytes: i64,
+    pub cost: f64,
+    /// Keys of the archived classes, not downloadable before a restore
+    pub archived: u64,
+    /// Keys already downloaded according to the manifest of --resume-from
+    pub completed: u64,
+    pub bandwidth: f64,
+}
+
+impl EgressEstimate {
+    pub fn new(bandwidth: Bandwidth) -> Self {
+        EgressEstimate {
+            bandwidth: bandwidth.0,
+            ..Default::default()
+        }
+    }
+
+    /// Estimate of the keys matched by the run, they are listed without
+    /// running the command. The keys of the --resume-from manifest are not
+    /// downloaded again and left out
+    pub fn collect(
+        status: &mut Find,
+        bandwidth: Bandwidth,
+        resume_from: Option<&Path>,
+    ) -> Result<Self, Error> {
+        let completed = match resume_from {
+            Some(file) => read_manifest(file)?,
+            None => HashMap::new(),
+        };
+        let prices = status.prices.clone();
+        let mut estimate = EgressEstimate::new(bandwidth);
+        for object in status.iter_matches() {
+            let object = object?;
+            let key = object.key.as_deref().unwrap_or_default();
+            if is_downloaded(&completed, key, &object) {
+                estimate.completed += 1;
+            } else {
+                estimate.add(&prices, &object);
+            }
+        }
+        Ok(estimate)
+    }
+
+    pub fn add(&mut self, prices: &PriceTable, object: &Object) {
+        let class = object
+            .storage_class
+            .as_deref()
+            .unwrap_or(DEFAULT_STORAGE_CLASS);
+        let size = object.size.unwrap_or_default();
+
+        self.count += 1;
+        self.bytes += size;
+        match prices.download_cost(class, 1, size) {
+            Some(cost) => self.cost += cost,
+            None => self.archived += 1,
+        }
+    }
+
+    /// Transfer time in seconds at the bandwidth
+    pub fn seconds(&self) -> f64 {
+        self.bytes as f64 / self.bandwidth
+    }
+}
+
+fn format_seconds(seconds: f64) -> String {
+    let seconds = seconds.ceil() as u64;
+    match seconds {
+        x if x < 60 => format!("{}s", x),
+        x if x < 3600 => format!("{}m {}s", x / 60, x % 60),
+        x if x < 86400 => format!("{}h {}m", x / 3600, x % 3600 / 60),
+        x => format!("{}d {}h", x / 86400, x % 86400 / 3600),
+    }
+}
+
+impl fmt::Display for EgressEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bandwidth = (self.bandwidth as u64)
+            .file_size(options::DECIMAL)
+            .unwrap_or_default();
+        writeln!(f, "Objects: {}", self.count)?;
+        writeln!(
+            f,
+            "Bytes: {}",
+            self.bytes
+                .file_size(options::CONVENTIONAL)
+                .unwrap_or_default()
+        )?;
+        writeln!(
+            f,
+            "Transfer time: {} at {}/s",
+            format_seconds(self.seconds()),
+            bandwidth
+        )?;
+        writeln!(f, "Data transfer cost: ${:.2}", self.cost)?;
+        if self.archived > 0 {
+            writeln!(
+                f,
+                "Archived objects: {} (not downloadable before a restore, not in the cost)",
+                self.archived
+            )?;
+        }
+        if self.completed > 0 {
+            writeln!(
+                f,
+                "Already downloaded: {} (in the --resume-from manifest, skipped)",
+                self.completed
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Ask a yes/no question on the terminal, anything but y or yes is a no.
+/// The answer is read from stdin when there is no terminal
+pub fn confirm(question: &str) -> Result<bool, Error> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    match File::open("/dev/tty") {
+        Ok(tty) => BufReader::new(tty).read_line(&mut answer)?,
+        Err(_) => io::stdin().lock().read_line(&mut answer)?,
+    };
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn egress_estimate() {
+        let prices = PriceTable::default();
+        let object = |size: i64, class: Option<&str>| Object {
+            size: Some(size),
+            storage_class: class.map(str::to_owned),
+            ..Default::default()
+        };
+        let gigabyte = 1024 * 1024 * 1024;
+
+        let mut estimate = EgressEstimate::new(Bandwidth(12.5e6));
+        estimate.add(&prices, &object(gigabyte, None));
+        estimate.add(&prices, &object(gigabyte, Some("STANDARD")));
+        estimate.add(&prices, &object(100, Some("GLACIER")));
+
+        assert_eq!(estimate.count, 3);
+        assert_eq!(estimate.bytes, 2 * gigabyte + 100);
+        assert_eq!(estimate.archived, 1);
+        assert!((estimate.cost - 0.1800008).abs() < 1e-6);
+        assert_eq!(
+            estimate.to_string(),
+            "Objects: 3\n\
+             Bytes: 2.00 GB\n\
+             Transfer time: 2m 52s at 12.50 MB/s\n\
+             Data transfer cost: $0.18\n\
+             Archived objects: 1 (not downloadable before a restore, not in the cost)\n"
+        );
+
+        estimate.completed = 2;
+        assert!(estimate
+            .to_string()
+            .ends_with("Already downloaded: 2 (in the --resume-from manifest, skipped)\n"));
+    }
+}
@@ -0,0 +1,73 @@
+use rusoto_s3::Object;
+use std::sync::{Arc, Mutex};
+
+use crate::report::ErrorReport;
+
+/// Callbacks of a search for progress displays and telemetry, used by the
+/// CLI for its progress line and metrics. All of them do nothing by default
+pub trait FindEvents: Send + Sync {
+    /// A page of objects has been listed, before the filters
+    fn on_page_listed(&self, _page: &[Object]) {}
+
+    /// An object has matched the filters
+    fn on_object_matched(&self, _object: &Object) {}
+
+    /// The command has run on a buffer of matched objects, the objects whose
+    /// command has failed are left out
+    fn on_action_completed(&self, _objects: &[Object]) {}
+
+    /// A failure has been reported, the search goes on
+    fn on_error(&self, _report: &ErrorReport) {}
+}
+
+static ERROR_LISTENERS: Mutex<Vec<Arc<dyn FindEvents>>> = Mutex::new(Vec::new());
+
+/// Listeners of the errors reported while a search is walked
+pub fn set_error_listeners(listeners: Vec<Arc<dyn FindEvents>>) {
+    *ERROR_LISTENERS.lock().unwrap() = listeners;
+}
+
+/// The listeners are called outside of the lock, so they may report errors too
+pub(crate) fn error_reported(report: &ErrorReport) {
+    let listeners = ERROR_LISTENERS.lock().unwrap().clone();
+    for listener in listeners {
+        listener.on_error(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counter {
+        errors: AtomicUsize,
+    }
+
+    impl FindEvents for Counter {
+        fn on_error(&self, report: &ErrorReport) {
+            // other tests may report errors at the same time
+            if report.operation == "EventsTest" {
+                self.errors.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn error_listeners() {
+        let counter = Arc::new(Counter::default());
+        set_error_listeners(vec![counter.clone()]);
+
+        let report = ErrorReport {
+            operation: "EventsTest".to_owned(),
+            ..Default::default()
+        };
+        report.emit();
+        report.emit();
+        set_error_listeners(Vec::new());
+        report.emit();
+
+        assert_eq!(counter.errors.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,514 @@
+use chrono::{SecondsFormat, Utc};
+use failure::{format_err, Error};
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use rusoto_core::Region;
+use rusoto_s3::{Object, S3Client};
+use serde_json::{json, Map, Value};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::arg::{EnrichField, ExportFormat, ExportInventory, InventoryField, S3path};
+use crate::enrich::{Enrichment, EnrichmentCache};
+use crate::function::{csv_escape, RunCommand};
+use crate::output::OutputSink;
+use crate::parallel::parallel_map;
+
+/// Name of the manifest written in the export directory at the end of the run
+pub const MANIFEST: &str = "manifest.json";
+
+/// Keys of a row group of the Parquet files, buffered before it is written
+const ROW_GROUP_SIZE: usize = 10_000;
+
+/// A file of the export, listed in the manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportFile {
+    pub name: String,
+    pub rows: usize,
+    pub bytes: u64,
+}
+
+/// A row of the export: a line of the CSV and JSON files or the values of
+/// the columns of the Parquet files
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportRow {
+    Line(String),
+    Values(Vec<Value>),
+}
+
+/// The current file, the Parquet rows are written by row groups
+enum ExportWriter {
+    Text(BufWriter<File>),
+    Parquet {
+        writer: SerializedFileWriter<File>,
+        rows: Vec<Vec<Value>>,
+    },
+}
+
+#[derive(Default)]
+struct ExportState {
+    writer: Option<ExportWriter>,
+    files: Vec<ExportFile>,
+}
+
+/// Command of export-inventory, the rows are appended to the current file
+/// of the directory and the next one is started every --rows-per-file keys
+pub struct InventoryExporter {
+    options: ExportInventory,
+    state: Mutex<ExportState>,
+}
+
+impl ExportInventory {
+    /// The directory is created before the walk, to fail early
+    pub fn create(&self) -> Result<(), Error> {
+        fs::create_dir_all(&self.directory)?;
+        Ok(())
+    }
+
+    fn enrich_fields(&self) -> Vec<EnrichField> {
+        let mut fields: Vec<EnrichField> = Vec::new();
+        for field in self.fields.iter().filter_map(|x| x.enrich_field()) {
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        fields
+    }
+}
+
+/// The JSON value of a field of a key
+pub fn field_json(
+    field: InventoryField,
+    bucket: &str,
+    object: &Object,
+    enrichment: &Enrichment,
+) -> Value {
+    let head = enrichment.head.as_ref();
+    match field {
+        InventoryField::Bucket => json!(bucket),
+        InventoryField::Key => json!(object.key),
+        InventoryField::Size => json!(object.size),
+        InventoryField::ETag => json!(object.e_tag.as_deref().map(|x| x.trim_matches('"'))),
+        InventoryField::StorageClass => json!(object.storage_class),
+        InventoryField::LastModified => json!(object.last_modified),
+        InventoryField::Tags => enrichment.to_json().remove("tags").unwrap_or_default(),
+        InventoryField::ContentType => json!(head.and_then(|x| x.content_type.as_deref())),
+        InventoryField::Encryption => {
+            json!(head.and_then(|x| x.server_side_encryption.as_deref()))
+        }
+        InventoryField::Metadata => json!(head.and_then(|x| x.metadata.as_ref())),
+        InventoryField::Acl => enrichment.to_json().remove("acl").unwrap_or_default(),
+    }
+}
+
+/// The CSV value of a field of a key, the tags, metadata and ACL are
+/// written like print --format csv does
+pub fn field_csv(
+    field: InventoryField,
+    bucket: &str,
+    object: &Object,
+    enrichment: &Enrichment,
+) -> String {
+    match field {
+        InventoryField::Tags => enrichment.csv_fields(&[EnrichField::Tags]).remove(0),
+        InventoryField::Metadata => enrichment.csv_fields(&[EnrichField::Head]).remove(2),
+        InventoryField::Acl => enrichment.csv_fields(&[EnrichField::Acl]).remove(0),
+        field => match field_json(field, bucket, object, enrichment) {
+            Value::Null => String::new(),
+            Value::String(x) => x,
+            x => x.to_string(),
+        },
+    }
+}
+
+impl InventoryExporter {
+    pub fn new(options: ExportInventory) -> Self {
+        InventoryExporter {
+            options,
+            state: Mutex::new(ExportState::default()),
+        }
+    }
+
+    fn row(&self, bucket: &str, object: &Object, enrichment: &Enrichment) -> ExportRow {
+        match self.options.format {
+            ExportFormat::Csv => {
+                let row: Vec<String> = self
+                    .options
+                    .fields
+                    .iter()
+                    .map(|x| csv_escape(&field_csv(*x, bucket, object, enrichment)))
+                    .collect();
+                ExportRow::Line(row.join(","))
+            }
+            ExportFormat::Json => {
+                let document: Map<String, Value> = self
+                    .options
+                    .fields
+                    .iter()
+                    .map(|x| {
+                        (
+                            x.name().to_owned(),
+                            field_json(*x, bucket, object, enrichment),
+                        )
+                    })
+                    .collect();
+                ExportRow::Line(Value::Object(document).to_string())
+            }
+            ExportFormat::Parquet => ExportRow::Values(
+                self.options
+                    .fields
+                    .iter()
+                    .map(|x| field_json(*x, bucket, object, enrichment))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Columns of the Parquet files, the size is an integer and the other
+    /// fields are strings, the tags, metadata and ACL as JSON documents
+    fn parquet_writer(&self, file: File) -> Result<SerializedFileWriter<File>, Error> {
+        let columns: Vec<String> = self
+            .options
+            .fields
+            .iter()
+            .map(|x| match x {
+                InventoryField::Size => format!("optional int64 {};", x.name()),
+                x => format!("optional binary {} (UTF8);", x.name()),
+            })
+            .collect();
+        let schema = parse_message_type(&format!("message export {{ {} }}", columns.join(" ")))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        Ok(SerializedFileWriter::new(
+            file,
+            Arc::new(schema),
+            Arc::new(properties),
+        )?)
+    }
+
+    /// Starts the next file, the CSV files begin with the header
+    fn next_file(&self, state: &mut ExportState) -> Result<(), Error> {
+        self.close_file(state)?;
+        let name = format!(
+            "part-{:05}.{}",
+            state.files.len(),
+            self.options.format.extension()
+        );
+        let file = File::create(self.options.directory.join(&name))?;
+        let mut bytes = 0;
+        let writer = match self.options.format {
+            ExportFormat::Parquet => ExportWriter::Parquet {
+                writer: self.parquet_writer(file)?,
+                rows: Vec::new(),
+            },
+            ExportFormat::Csv => {
+                let mut writer = BufWriter::new(file);
+                let header: Vec<&str> = self.options.fields.iter().map(|x| x.name()).collect();
+                let header = format!("{}\n", header.join(","));
+                writer.write_all(header.as_bytes())?;
+                bytes = header.len() as u64;
+                ExportWriter::Text(writer)
+            }
+            ExportFormat::Json => ExportWriter::Text(BufWriter::new(file)),
+        };
+        state.writer = Some(writer);
+        state.files.push(ExportFile {
+            name,
+            rows: 0,
+            bytes,
+        });
+        Ok(())
+    }
+
+    /// Flushes the current file, the size of a Parquet file is known once
+    /// its footer is written
+    fn close_file(&self, state: &mut ExportState) -> Result<(), Error> {
+        match state.writer.take() {
+            Some(ExportWriter::Text(mut writer)) => writer.flush()?,
+            Some(ExportWriter::Parquet {
+                mut writer,
+                mut rows,
+            }) => {
+                self.write_row_group(&mut writer, &mut rows)?;
+                writer.close()?;
+                if let Some(file) = state.files.last_mut() {
+                    file.bytes = fs::metadata(self.options.directory.join(&file.name))?.len();
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn write_row_group(
+        &self,
+        writer: &mut SerializedFileWriter<File>,
+        rows: &mut Vec<Vec<Value>>,
+    ) -> Result<(), Error> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut group = writer.next_row_group()?;
+        for (i, field) in self.options.fields.iter().enumerate() {
+            let mut column = group
+                .next_column()?
+                .ok_or_else(|| format_err!("no column {} in the schema", field.name()))?;
+            // the definition level of a null value is 0
+            let levels: Vec<i16> = rows.iter().map(|x| i16::from(!x[i].is_null())).collect();
+            if *field == InventoryField::Size {
+                let values: Vec<i64> = rows.iter().filter_map(|x| x[i].as_i64()).collect();
+                column
+                    .typed::<Int64Type>()
+                    .write_batch(&values, Some(&levels), None)?;
+            } else {
+                let values: Vec<ByteArray> = rows
+                    .iter()
+                    .filter_map(|x| match x[i] {
+                        Value::Null => None,
+                        Value::String(ref x) => Some(x.as_str().into()),
+                        ref x => Some(x.to_string().as_str().into()),
+                    })
+                    .collect();
+                column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            column.close()?;
+        }
+        group.close()?;
+        rows.clear();
+        Ok(())
+    }
+
+    fn write(&self, rows: Vec<ExportRow>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        for row in rows {
+            let full = match state.files.last() {
+                Some(file) => file.rows >= self.options.rows_per_file.max(1),
+                None => true,
+            };
+            if full {
+                self.next_file(&mut state)?;
+            }
+            let ExportState { writer, files } = &mut *state;
+            if let (Some(writer), Some(file)) = (writer.as_mut(), files.last_mut()) {
+                match (writer, row) {
+                    (ExportWriter::Text(writer), ExportRow::Line(line)) => {
+                        writer.write_all(line.as_bytes())?;
+                        writer.write_all(b"\n")?;
+                        file.bytes += line.len() as u64 + 1;
+                    }
+                    (ExportWriter::Parquet { writer, rows }, ExportRow::Values(values)) => {
+                        rows.push(values);
+                        if rows.len() >= ROW_GROUP_SIZE {
+                            self.write_row_group(writer, rows)?;
+                        }
+                    }
+                    _ => unreachable!("the rows and the files have the format of --format"),
+                }
+                file.rows += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// The manifest of the files written so far, like the manifest of an
+    /// S3 Inventory
+    pub fn manifest(&self, files: &[ExportFile]) -> Value {
+        json!({
+            "created": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            "format": self.options.format.extension(),
+            "fields": self.options.fields.iter().map(|x| x.name()).collect::<Vec<_>>(),
+            "rows": files.iter().map(|x| x.rows).sum::<usize>(),
+            "files": files
+                .iter()
+                .map(|x| json!({ "name": x.name, "rows": x.rows, "bytes": x.bytes }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl RunCommand for InventoryExporter {
+    fn execute(
+        &self,
+        client: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let fields = self.options.enrich_fields();
+        let rows = parallel_map(list, self.options.concurrency, |x| {
+            let data = if fields.is_empty() {
+                Enrichment::default()
+            } else {
+                let key = x.key.as_deref().unwrap_or_default();
+                enrichment.fetch(client, &path.bucket, key, &fields)
+            };
+            self.row(&path.bucket, x, &data)
+        });
+        self.write(rows)
+    }
+
+    /// The last file is flushed and the manifest written
+    fn finish(&self, _o: &dyn OutputSink) -> Result<(), Error> {
+        let mut state = std::mem::take(&mut *self.state.lock().unwrap());
+        self.close_file(&mut state)?;
+        let manifest = serde_json::to_string_pretty(&self.manifest(&state.files))?;
+        fs::write(self.options.directory.join(MANIFEST), manifest)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::SerializedFileReader;
+    use rusoto_s3::{HeadObjectOutput, Tag};
+    use tempfile::tempdir;
+
+    #[test]
+    fn export_inventory() -> Result<(), Error> {
+        let directory = tempdir()?;
+        let exporter = InventoryExporter::new(ExportInventory {
+            directory: directory.path().to_owned(),
+            format: ExportFormat::Csv,
+            fields: vec![
+                InventoryField::Key,
+                InventoryField::Size,
+                InventoryField::ETag,
+                InventoryField::Tags,
+                InventoryField::ContentType,
+            ],
+            rows_per_file: 2,
+            concurrency: 1,
+        });
+        exporter.options.create()?;
+        assert_eq!(
+            exporter.options.enrich_fields(),
+            vec![EnrichField::Tags, EnrichField::Head]
+        );
+
+        let object = |key: &str| Object {
+            key: Some(key.to_owned()),
+            size: Some(10),
+            e_tag: Some("\"abc\"".to_owned()),
+            ..Default::default()
+        };
+        let enrichment = Enrichment {
+            tags: Some(vec![Tag {
+                key: "team".to_owned(),
+                value: "data".to_owned(),
+            }]),
+            head: Some(HeadObjectOutput {
+                content_type: Some("text/csv".to_owned()),
+                ..Default::default()
+            }),
+            acl: None,
+        };
+        let rows: Vec<ExportRow> = ["a", "b,c", "d"]
+            .iter()
+            .map(|x| exporter.row("bucket", &object(x), &enrichment))
+            .collect();
+        assert_eq!(
+            rows[1],
+            ExportRow::Line("\"b,c\",10,abc,team=data,text/csv".to_owned())
+        );
+        exporter.write(rows)?;
+        exporter.finish(&crate::output::MemorySink::default())?;
+
+        assert_eq!(
+            fs::read_to_string(directory.path().join("part-00000.csv"))?,
+            "key,size,etag,tags,content_type\na,10,abc,team=data,text/csv\n\"b,c\",10,abc,team=data,text/csv\n"
+        );
+        let manifest: Value =
+            serde_json::from_str(&fs::read_to_string(directory.path().join(MANIFEST))?)?;
+        assert_eq!(manifest["rows"], 3);
+        assert_eq!(manifest["files"][1]["name"], "part-00001.csv");
+        assert_eq!(manifest["files"][1]["rows"], 1);
+        assert_eq!(
+            manifest["files"][1]["bytes"],
+            "key,size,etag,tags,content_type\nd,10,abc,team=data,text/csv\n".len()
+        );
+
+        let exporter = InventoryExporter::new(ExportInventory {
+            format: ExportFormat::Json,
+            ..exporter.options.clone()
+        });
+        assert_eq!(
+            exporter.row("bucket", &object("a"), &enrichment),
+            ExportRow::Line(
+                r#"{"content_type":"text/csv","etag":"abc","key":"a","size":10,"tags":{"team":"data"}}"#
+                    .to_owned()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn export_parquet() -> Result<(), Error> {
+        let directory = tempdir()?;
+        let exporter = InventoryExporter::new(ExportInventory {
+            directory: directory.path().to_owned(),
+            format: ExportFormat::Parquet,
+            fields: vec![
+                InventoryField::Key,
+                InventoryField::Size,
+                InventoryField::StorageClass,
+                InventoryField::Tags,
+            ],
+            rows_per_file: 2,
+            concurrency: 1,
+        });
+        let enrichment = Enrichment {
+            tags: Some(vec![Tag {
+                key: "team".to_owned(),
+                value: "data".to_owned(),
+            }]),
+            ..Default::default()
+        };
+        let rows = ["a", "b", "c"]
+            .iter()
+            .map(|x| {
+                let object = Object {
+                    key: Some(x.to_string()),
+                    size: Some(10),
+                    ..Default::default()
+                };
+                exporter.row("bucket", &object, &enrichment)
+            })
+            .collect();
+        exporter.write(rows)?;
+        exporter.finish(&crate::output::MemorySink::default())?;
+
+        let file = File::open(directory.path().join("part-00000.parquet"))?;
+        let rows: Vec<String> = SerializedFileReader::new(file)?
+            .into_iter()
+            .map(|x| x.map(|row| row.to_string()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(
+            rows,
+            vec![
+                r##"{key: "a", size: 10, storage_class: null, tags: "{"team":"data"}"}"##,
+                r##"{key: "b", size: 10, storage_class: null, tags: "{"team":"data"}"}"##,
+            ]
+        );
+
+        let manifest: Value =
+            serde_json::from_str(&fs::read_to_string(directory.path().join(MANIFEST))?)?;
+        assert_eq!(manifest["format"], "parquet");
+        assert_eq!(manifest["files"][1]["name"], "part-00001.parquet");
+        assert_eq!(manifest["files"][1]["rows"], 1);
+        assert_eq!(
+            manifest["files"][1]["bytes"],
+            fs::metadata(directory.path().join("part-00001.parquet"))?.len()
+        );
+        Ok(())
+    }
+}
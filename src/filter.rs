@@ -5,11 +5,42 @@ use rusoto_s3::Object;
 use std::convert::AsRef;
 
 use crate::arg::*;
+use crate::enrich::Enrichment;
+use crate::listing::key_depth;
 
+/// Match of a listed object, implemented by the CLI filters and open to the
+/// domain-specific rules of library users
 pub trait Filter {
     fn filter(&self, object: &Object) -> bool;
 }
 
+/// Filter of a closure, e.g. `FilterFn(|x: &Object| x.size > Some(0))`
+pub struct FilterFn<F>(pub F);
+
+impl<F: Fn(&Object) -> bool> Filter for FilterFn<F> {
+    fn filter(&self, object: &Object) -> bool {
+        (self.0)(object)
+    }
+}
+
+/// Inverse of a filter, for the --not-* options, e.g. `Not(NameGlob::new("*.tmp")?)`
+pub struct Not<F>(pub F);
+
+impl<F: Filter> Filter for Not<F> {
+    fn filter(&self, object: &Object) -> bool {
+        !self.0.filter(object)
+    }
+}
+
+/// Match on the data fetched for the object with additional API calls (tags,
+/// HeadObject, ACL). Run after the filters of the listed data have matched
+pub trait EnrichedFilter {
+    /// Data fetched for every object reaching the filter
+    fn fields(&self) -> Vec<EnrichField>;
+
+    fn filter(&self, object: &Object, enrichment: &Enrichment) -> bool;
+}
+
 impl Filter for FindSize {
     fn filter(&self, object: &Object) -> bool {
         let object_size = object.size.as_ref().unwrap_or(&0);
@@ -36,6 +67,8 @@ impl Filter for FindTime {
         match *self {
             FindTime::Upper(seconds) => (now - last_modified_time) >= seconds,
             FindTime::Lower(seconds) => (now - last_modified_time) <= seconds,
+            FindTime::Before(time) => last_modified_time < time,
+            FindTime::After(time) => last_modified_time >= time,
         }
     }
 }
@@ -43,7 +76,7 @@ impl Filter for FindTime {
 impl Filter for NameGlob {
     fn filter(&self, object: &Object) -> bool {
         let object_key = object.key.as_ref().map(AsRef::as_ref).unwrap_or_default();
-        self.matches(&object_key)
+        self.matches(object_key)
     }
 }
 
@@ -51,7 +84,7 @@ impl Filter for InameGlob {
     fn filter(&self, object: &Object) -> bool {
         let object_key = object.key.as_ref().map(AsRef::as_ref).unwrap_or_default();
         self.0.matches_with(
-            &object_key,
+            object_key,
             MatchOptions {
                 case_sensitive: false,
                 require_literal_separator: false,
@@ -61,10 +94,117 @@ impl Filter for InameGlob {
     }
 }
 
+/// Match of any of the extensions of --ext, by the suffix of the key
+pub struct ExtFilter(Vec<String>);
+
+impl ExtFilter {
+    /// The extensions with or without their leading dot
+    pub fn new<S: AsRef<str>>(extensions: &[S]) -> Self {
+        ExtFilter(
+            extensions
+                .iter()
+                .map(|x| format!(".{}", x.as_ref().trim_start_matches('.')))
+                .collect(),
+        )
+    }
+}
+
+impl Filter for ExtFilter {
+    fn filter(&self, object: &Object) -> bool {
+        let key = object.key.as_deref().unwrap_or_default();
+        self.0.iter().any(|x| key.ends_with(x.as_str()))
+    }
+}
+
+/// Match of any of the ETags of --etag
+pub struct ETagFilter(pub Vec<FindETag>);
+
+impl Filter for ETagFilter {
+    fn filter(&self, object: &Object) -> bool {
+        let e_tag = object.e_tag.as_deref().unwrap_or_default();
+        self.0.iter().any(|x| x.matches(e_tag))
+    }
+}
+
+/// Match of any of the owner display names of --owner
+pub struct OwnerFilter(pub Vec<String>);
+
+impl Filter for OwnerFilter {
+    fn filter(&self, object: &Object) -> bool {
+        match object.owner.as_ref().and_then(|x| x.display_name.as_ref()) {
+            Some(name) => self.0.contains(name),
+            None => false,
+        }
+    }
+}
+
+impl Filter for FilterAtom {
+    fn filter(&self, object: &Object) -> bool {
+        match self {
+            FilterAtom::Name(x) => x.filter(object),
+            FilterAtom::Iname(x) => x.filter(object),
+            FilterAtom::Regex(x) => x.filter(object),
+            FilterAtom::Mtime(x) => x.filter(object),
+            FilterAtom::Size(x) => x.filter(object),
+            FilterAtom::ETag(x) => {
+                let e_tag = object.e_tag.as_deref().unwrap_or_default();
+                x.matches(e_tag)
+            }
+            FilterAtom::Owner(name) => match object.owner.as_ref() {
+                Some(owner) => owner.display_name.as_ref() == Some(name),
+                None => false,
+            },
+        }
+    }
+}
+
+impl Filter for AnyOf {
+    fn filter(&self, object: &Object) -> bool {
+        self.0.iter().any(|x| x.filter(object))
+    }
+}
+
+/// Evaluation of the tree of --expr, the operands are evaluated lazily as
+/// find does
+impl Filter for FilterExpr {
+    fn filter(&self, object: &Object) -> bool {
+        match self {
+            FilterExpr::Atom(atom) => atom.filter(object),
+            FilterExpr::Not(expr) => !expr.filter(object),
+            FilterExpr::And(exprs) => exprs.iter().all(|x| x.filter(object)),
+            FilterExpr::Or(exprs) => exprs.iter().any(|x| x.filter(object)),
+        }
+    }
+}
+
+/// Match of the depth of --min-depth and --max-depth, for the keys not
+/// listed level by level. The depth is counted below the longest prefix of
+/// the paths the key is under
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyDepth {
+    pub paths: Vec<S3path>,
+    pub min_depth: Option<usize>,
+    pub max_depth: Option<usize>,
+}
+
+impl Filter for KeyDepth {
+    fn filter(&self, object: &Object) -> bool {
+        let key = object.key.as_deref().unwrap_or_default();
+        let depth = self
+            .paths
+            .iter()
+            .filter(|x| key.starts_with(x.prefix.as_deref().unwrap_or_default()))
+            .max_by_key(|x| x.prefix.as_ref().map(String::len))
+            .map(|path| key_depth(path, key))
+            .unwrap_or_else(|| key.matches('/').count() + 1);
+        self.min_depth.is_none_or(|x| depth >= x) && self.max_depth.is_none_or(|x| depth <= x)
+    }
+}
+
 impl Filter for Regex {
     fn filter(&self, object: &Object) -> bool {
         let object_key = object.key.as_ref().map(AsRef::as_ref).unwrap_or_default();
-        self.is_match(&object_key)
+        self.is_match(object_key)
     }
 }
 
@@ -72,6 +212,7 @@ impl Filter for Regex {
 mod tests {
     use super::*;
     use chrono::Duration;
+    use rusoto_s3::Owner;
     use std::str::FromStr;
 
     #[test]
@@ -104,6 +245,13 @@ mod tests {
 
         assert!(!FindTime::Upper(4000).filter(&object));
         assert!(!FindTime::Lower(10).filter(&object));
+
+        let time = current.timestamp();
+        assert!(FindTime::Before(time + 1).filter(&object));
+        assert!(FindTime::After(time).filter(&object));
+
+        assert!(!FindTime::Before(time).filter(&object));
+        assert!(!FindTime::After(time + 1).filter(&object));
     }
 
     #[test]
@@ -138,6 +286,18 @@ mod tests {
         assert!(!InameGlob::from_str("other").unwrap().filter(&object));
     }
 
+    #[test]
+    fn closure_filter() {
+        let object = Object {
+            key: Some("some_key".to_owned()),
+            size: Some(10),
+            ..Default::default()
+        };
+
+        assert!(FilterFn(|x: &Object| x.size > Some(5)).filter(&object));
+        assert!(!FilterFn(|x: &Object| x.key.as_deref() == Some("other")).filter(&object));
+    }
+
     #[test]
     fn regex_filter() {
         let object = Object {
@@ -157,4 +317,170 @@ mod tests {
         assert!(!Regex::from_str("Ome").unwrap().filter(&object));
         assert!(!Regex::from_str("some_Key").unwrap().filter(&object));
     }
+
+    #[test]
+    fn not_filter() {
+        let object = Object {
+            key: Some("data/part-0001.tmp".to_owned()),
+            size: Some(10),
+            ..Default::default()
+        };
+
+        assert!(!Not(NameGlob::from_str("*.tmp").unwrap()).filter(&object));
+        assert!(Not(NameGlob::from_str("*.gz").unwrap()).filter(&object));
+        assert!(Not(FindSize::Bigger(11)).filter(&object));
+        assert!(!Not(Regex::from_str("part-").unwrap()).filter(&object));
+        assert!(Not(Not(FindSize::Equal(10))).filter(&object));
+        assert!(!Not(ExtFilter::new(&["tmp"])).filter(&object));
+        assert!(Not(ExtFilter::new(&["gz", "log"])).filter(&object));
+    }
+
+    #[test]
+    fn any_of_filter() {
+        let object = |key: &str, size: i64| Object {
+            key: Some(key.to_owned()),
+            size: Some(size),
+            ..Default::default()
+        };
+        let logs = AnyOf::from_str("name=*.log, name=*.txt").unwrap();
+
+        assert!(logs.filter(&object("app.log", 1)));
+        assert!(logs.filter(&object("notes.txt", 1)));
+        assert!(!logs.filter(&object("data.csv", 1)));
+
+        let large_or_tmp = AnyOf::from_str("size=+1k,regex=^tmp/(a\\,b)").unwrap();
+        assert!(large_or_tmp.filter(&object("data.csv", 2048)));
+        assert!(large_or_tmp.filter(&object("tmp/a,b", 1)));
+        assert!(!large_or_tmp.filter(&object("tmp/ab", 1)));
+
+        assert!(AnyOf::from_str("name").is_err());
+        assert!(AnyOf::from_str("key=*.log").is_err());
+        assert!(AnyOf::from_str("size=+1x").is_err());
+    }
+
+    #[test]
+    fn expr_filter() {
+        let day = 3600 * 24;
+        let object = |key: &str, size: i64, days: i64| Object {
+            key: Some(key.to_owned()),
+            size: Some(size),
+            last_modified: Some(format!(
+                "{:?}",
+                Utc::now() - Duration::seconds(days * day + 60)
+            )),
+            ..Default::default()
+        };
+        let expr = FilterExpr::from_str(r"\( -name '*.gz' -o -size +1G \) ! -mtime +30").unwrap();
+
+        assert!(expr.filter(&object("a.gz", 1, 2)));
+        assert!(expr.filter(&object("a.csv", 2 << 30, 2)));
+        assert!(!expr.filter(&object("a.csv", 1, 2)));
+        assert!(!expr.filter(&object("a.gz", 1, 31)));
+
+        // -a binds tighter than -o
+        let expr = FilterExpr::from_str("-name a -o -name b -a -size +10").unwrap();
+        assert!(expr.filter(&object("a", 1, 0)));
+        assert!(!expr.filter(&object("b", 1, 0)));
+        assert!(expr.filter(&object("b", 20, 0)));
+
+        let expr = FilterExpr::from_str(r#"-not -iname "* copy.TXT""#).unwrap();
+        assert!(!expr.filter(&object("notes copy.txt", 1, 0)));
+        assert_eq!(expr.atoms().len(), 1);
+
+        for invalid in &[
+            "",
+            "( -name a",
+            "-name",
+            "-name a )",
+            "-size x",
+            "-name 'a",
+            "name a",
+        ] {
+            assert!(FilterExpr::from_str(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn key_depth_filter() {
+        let object = |key: &str| Object {
+            key: Some(key.to_owned()),
+            ..Default::default()
+        };
+        let depth = |min_depth, max_depth| KeyDepth {
+            paths: vec![
+                S3path::from_str("s3://bucket/logs").unwrap(),
+                S3path::from_str("s3://bucket/logs/app/").unwrap(),
+            ],
+            min_depth,
+            max_depth,
+        };
+
+        assert!(depth(None, Some(1)).filter(&object("logs/a.log")));
+        assert!(!depth(None, Some(1)).filter(&object("logs/2023/a.log")));
+        // below the longest prefix
+        assert!(depth(None, Some(1)).filter(&object("logs/app/a.log")));
+        assert!(depth(Some(2), Some(3)).filter(&object("logs/2023/01/a.log")));
+        assert!(!depth(Some(2), None).filter(&object("logs/a.log")));
+        assert!(!depth(None, Some(2)).filter(&object("other/x/y/z")));
+    }
+
+    #[test]
+    fn ext_filter() {
+        let object = |key: &str| Object {
+            key: Some(key.to_owned()),
+            ..Default::default()
+        };
+        let extensions = ExtFilter::new(&["log", ".gz", "tar.gz"]);
+
+        assert!(extensions.filter(&object("logs/app.log")));
+        assert!(extensions.filter(&object("logs/app.log.gz")));
+        assert!(extensions.filter(&object("backup.tar.gz")));
+
+        assert!(!extensions.filter(&object("logs/app.LOG")));
+        assert!(!extensions.filter(&object("logs/catalog")));
+        assert!(!extensions.filter(&object("logs/gz")));
+    }
+
+    #[test]
+    fn etag_filter() {
+        let object = Object {
+            e_tag: Some("\"9b2cf535f27731c974343645a3985328\"".to_owned()),
+            ..Default::default()
+        };
+        let etags = |values: &[&str]| {
+            ETagFilter(
+                values
+                    .iter()
+                    .map(|x| FindETag::from_str(x).unwrap())
+                    .collect(),
+            )
+        };
+
+        assert!(etags(&["9b2cf535f27731c974343645a3985328"]).filter(&object));
+        assert!(etags(&["\"9B2CF535F27731C974343645A3985328\""]).filter(&object));
+        assert!(etags(&[
+            "d41d8cd98f00b204e9800998ecf8427e",
+            "9b2cf535f27731c974343645a3985328"
+        ])
+        .filter(&object));
+
+        assert!(!etags(&["d41d8cd98f00b204e9800998ecf8427e"]).filter(&object));
+        assert!(!etags(&["9b2cf535"]).filter(&Object::default()));
+    }
+
+    #[test]
+    fn owner_filter() {
+        let object = Object {
+            owner: Some(Owner {
+                display_name: Some("data-team".to_owned()),
+                id: Some("75aa57f09aa0c8caeab4f8c24e99d10f".to_owned()),
+            }),
+            ..Default::default()
+        };
+        let owners = OwnerFilter(vec!["ops".to_owned(), "data-team".to_owned()]);
+
+        assert!(owners.filter(&object));
+        assert!(!OwnerFilter(vec!["Data-Team".to_owned()]).filter(&object));
+        assert!(!owners.filter(&Object::default()));
+    }
 }
@@ -1,38 +1,103 @@
-use rusoto_s3::{
-    CopyObjectRequest, Delete, DeleteObjectsRequest, GetObjectRequest, GetObjectTaggingRequest,
-    Object, ObjectIdentifier, PutObjectAclRequest, PutObjectTaggingRequest, S3Client, Tagging, S3,
-};
+use rusoto_core::{Client, Region};
+use rusoto_s3::{Object, S3Client};
 use std::process::Command;
 use std::process::ExitStatus;
 
+use std::collections::HashMap;
 use std::fs;
-use std::fs::File;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
 
-use failure::Error;
-use futures::stream::Stream;
-use futures::Future;
+use chrono::prelude::*;
+use chrono::Duration;
+use failure::{err_msg, Error};
+use humansize::{file_size_opts as options, FileSize};
+use log::{debug, info};
 
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde_json::{json, Map, Value};
 
 use crate::arg::*;
+use crate::audit::{EncryptionAudit, PublicAudit};
+use crate::command::FilterList;
+use crate::compliance::ComplianceAudit;
+use crate::cost::CostReporter;
+use crate::duplicates::DuplicateFinder;
+use crate::enrich::{Enrichment, EnrichmentCache};
 use crate::error::*;
+use crate::export::InventoryExporter;
+use crate::hygiene::KeyHygieneAudit;
+use crate::lifecycle::LifecycleSimulator;
+use crate::listing::is_prefix;
+use crate::ops;
+use crate::output::OutputSink;
+use crate::parallel::parallel_map;
+use crate::pricing::PriceTable;
+use crate::reconcile::Reconciler;
+use crate::replicas::ReplicaMerger;
+use crate::report::ErrorReport;
+use crate::sns::SnsPublisher;
+use crate::tiering::TieringAdvisor;
 
 impl Cmd {
+    /// The command run on the matched keys
     pub fn downcast(self) -> Box<dyn RunCommand> {
         match self {
             Cmd::Print(l) => Box::new(l),
             Cmd::Ls(l) => Box::new(l),
+            Cmd::LsDirs(l) => Box::new(l),
+            Cmd::Printf(l) => Box::new(l),
             Cmd::Exec(l) => Box::new(l),
             Cmd::Delete(l) => Box::new(l),
-            Cmd::Download(l) => Box::new(l),
+            Cmd::Download(l) => Box::new(Downloader::new(l)),
             Cmd::Tags(l) => Box::new(l),
             Cmd::LsTags(l) => Box::new(l),
             Cmd::Public(l) => Box::new(l),
+            Cmd::AuditPublic(l) => Box::new(PublicAudit::new(l)),
+            Cmd::AuditUnencrypted(l) => Box::new(EncryptionAudit::new(l)),
+            // the topic is called with the default credentials, the CLI
+            // builds the publisher with the credentials of the options
+            Cmd::PublishSns(l) => {
+                Box::new(SnsPublisher::new(l, &Region::default(), Client::shared()))
+            }
             Cmd::Copy(l) => Box::new(l),
             Cmd::Move(l) => Box::new(l),
             Cmd::Nothing(l) => Box::new(l),
+            Cmd::Snapshot(Snapshot::Save(l)) => Box::new(l),
+            Cmd::SimulateLifecycle(l) => Box::new(LifecycleSimulator::new(l)),
+            Cmd::ExportInventory(l) => Box::new(InventoryExporter::new(l)),
+            Cmd::Transform(l) => Box::new(l),
+            Cmd::ManifestChecksums(l) => Box::new(l),
+            Cmd::Report(Report::Compliance(l)) => Box::new(ComplianceAudit::new(l)),
+            Cmd::Report(Report::Duplicates(l)) => Box::new(DuplicateFinder::new(l)),
+            Cmd::Report(Report::KeyHygiene(l)) => Box::new(KeyHygieneAudit::new(l)),
+            // the CLI passes the prices of the region and of --storage-price
+            Cmd::Report(Report::Tiering(l)) => {
+                Box::new(TieringAdvisor::new(l, PriceTable::default()))
+            }
+            Cmd::Report(Report::Cost(l)) => Box::new(CostReporter::new(l, PriceTable::default())),
+            // the CLI lists the destination in the region of its bucket with the filters
+            Cmd::Report(Report::Reconcile(l)) => Box::new(Reconciler::new(
+                l,
+                S3Client::new(Region::default()),
+                FilterList(Vec::new()),
+            )),
+            // the CLI lists every replica with a client of the region of its bucket
+            Cmd::Report(Report::Replicas(l)) => {
+                let clients = l
+                    .also
+                    .iter()
+                    .map(|_| S3Client::new(Region::default()))
+                    .collect();
+                Box::new(ReplicaMerger::new(l, clients))
+            }
+            // the buckets are listed, the snapshots compared or the man page
+            // printed instead of walking a path
+            Cmd::Buckets(_) | Cmd::Snapshot(Snapshot::Diff(_)) | Cmd::Man(_) => {
+                Box::new(DoNothing {})
+            }
         }
     }
 }
@@ -43,61 +108,496 @@ pub struct ExecStatus {
     pub runcommand: String,
 }
 
+/// Command run on the buffers of the matched keys
 pub trait RunCommand {
     fn execute(
         &self,
         client: &S3Client,
-        region: &str,
+        region: &Region,
         path: &S3path,
         list: &[Object],
+        enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
     ) -> Result<(), Error>;
+
+    /// Common prefixes listed with --delimiter are passed only to the commands printing them
+    fn prefixes(&self) -> bool {
+        false
+    }
+
+    /// The matched keys are deleted or changed in place, the keys under
+    /// --protected-prefix are not passed
+    fn modifies_keys(&self) -> bool {
+        false
+    }
+
+    /// Called at the end of a run of the paths (a walk, a pass of --watch or
+    /// --daemon) by the commands printing a report of all the matched keys,
+    /// the report of the next run starts again
+    fn finish(&self, _output: &dyn OutputSink) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 impl RunCommand for FastPrint {
     fn execute(
         &self,
         _c: &S3Client,
-        _r: &str,
+        _r: &Region,
         path: &S3path,
         list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
     ) -> Result<(), Error> {
         for x in list {
-            println!(
-                "s3://{}/{}",
-                &path.bucket,
-                x.key.as_ref().unwrap_or(&"".to_string())
-            );
+            output.line(&self.key_format.format(path, x));
         }
         Ok(())
     }
+
+    fn prefixes(&self) -> bool {
+        true
+    }
 }
 
-impl RunCommand for AdvancedPrint {
+impl RunCommand for ListDirs {
     fn execute(
         &self,
         _c: &S3Client,
-        _r: &str,
+        _r: &Region,
         path: &S3path,
         list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
     ) -> Result<(), Error> {
-        for x in list {
-            println!(
-                "{0} {1:?} {2} {3} s3://{4}/{5} {6}",
+        for x in list.iter().filter(|x| is_prefix(x)) {
+            output.line(&self.key_format.format(path, x));
+        }
+        Ok(())
+    }
+
+    fn prefixes(&self) -> bool {
+        true
+    }
+}
+
+impl KeyFormat {
+    pub fn format(&self, path: &S3path, object: &Object) -> String {
+        let key = object.key.as_deref().unwrap_or("");
+        let key = if self.relative {
+            path.relative_key(key)
+        } else {
+            key
+        };
+        let key = if self.encode_keys {
+            percent_encode(key)
+        } else {
+            key.to_owned()
+        };
+
+        if self.relative {
+            key
+        } else {
+            format!("s3://{}/{}", &path.bucket, key)
+        }
+    }
+}
+
+/// Percent-encode everything except unreserved characters and `/`
+pub fn percent_encode(s: &str) -> String {
+    let mut output = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                output.push(byte as char)
+            }
+            _ => output.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    output
+}
+
+/// Decode a percent-encoded (application/x-www-form-urlencoded) string
+pub fn percent_decode(s: &str) -> Result<String, Error> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(byte) = iter.next() {
+        match byte {
+            b'%' => {
+                let hex = [
+                    iter.next().ok_or(FunctionError::KeyDecodeError)?,
+                    iter.next().ok_or(FunctionError::KeyDecodeError)?,
+                ];
+                let hex = std::str::from_utf8(&hex).map_err(|_| FunctionError::KeyDecodeError)?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| FunctionError::KeyDecodeError)?);
+            }
+            b'+' => bytes.push(b' '),
+            x => bytes.push(x),
+        }
+    }
+    Ok(String::from_utf8(bytes).map_err(|_| FunctionError::KeyDecodeError)?)
+}
+
+impl AdvancedPrint {
+    fn format_size(&self, object: &Object) -> Result<String, Error> {
+        let size = object.size.unwrap_or_default();
+        if self.human_readable {
+            Ok(size.file_size(options::CONVENTIONAL).map_err(err_msg)?)
+        } else {
+            Ok(size.to_string())
+        }
+    }
+
+    fn format_time(&self, object: &Object) -> String {
+        object
+            .last_modified
+            .as_ref()
+            .map(|x| format_time(x, self.time_style))
+            .unwrap_or_else(|| "NoTime".to_string())
+    }
+
+    /// Fields fetched for every key: the --enrich ones only for the formats
+    /// printing them (json, csv)
+    fn enrich_fields(&self) -> Vec<EnrichField> {
+        let mut fields = match self.format {
+            PrintFormat::Json | PrintFormat::Csv => self.enrich.clone(),
+            PrintFormat::Text | PrintFormat::Table => Vec::new(),
+        };
+        if self.show_version_id && !fields.contains(&EnrichField::Head) {
+            fields.push(EnrichField::Head);
+        }
+        fields
+    }
+
+    /// Enrichment of every object, fetched with up to `concurrency` parallel
+    /// requests unless the enriched filters already did
+    fn enrichments(
+        &self,
+        client: &S3Client,
+        path: &S3path,
+        list: &[Object],
+        cache: &EnrichmentCache,
+    ) -> Vec<Enrichment> {
+        let fields = self.enrich_fields();
+        parallel_map(list, self.concurrency, |object| match object.key {
+            Some(ref key) if !fields.is_empty() => cache.fetch(client, &path.bucket, key, &fields),
+            _ => Enrichment::default(),
+        })
+    }
+
+    /// Version id column, `null` for objects stored without versioning
+    fn version_id(&self, enrichment: &Enrichment) -> Option<String> {
+        if self.show_version_id {
+            Some(
+                enrichment
+                    .head
+                    .as_ref()
+                    .and_then(|x| x.version_id.clone())
+                    .unwrap_or_else(|| "null".to_owned()),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn print_text(
+        &self,
+        client: &S3Client,
+        path: &S3path,
+        list: &[Object],
+        cache: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let enrichments = self.enrichments(client, path, list, cache);
+        for (x, enrichment) in list.iter().zip(enrichments) {
+            let version_id = self
+                .version_id(&enrichment)
+                .map(|x| format!(" {}", x))
+                .unwrap_or_default();
+
+            output.line(&format!(
+                "{0} {1} {2} {3} {4} {5}{6}",
                 x.e_tag.as_ref().unwrap_or(&"NoEtag".to_string()),
-                x.owner.as_ref().map(|x| x.display_name.as_ref()),
-                x.size.as_ref().unwrap_or(&0),
-                x.last_modified.as_ref().unwrap_or(&"NoTime".to_string()),
-                &path.bucket,
-                x.key.as_ref().unwrap_or(&"".to_string()),
+                owner_name(x).unwrap_or("None"),
+                self.format_size(x)?,
+                self.format_time(x),
+                self.key_format.format(path, x),
                 x.storage_class.as_ref().unwrap_or(&"NoStorage".to_string()),
+                version_id,
+            ));
+        }
+        Ok(())
+    }
+
+    fn print_json(
+        &self,
+        client: &S3Client,
+        path: &S3path,
+        list: &[Object],
+        cache: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let enrichments = self.enrichments(client, path, list, cache);
+        for (x, enrichment) in list.iter().zip(enrichments) {
+            let mut json = object_to_json(x);
+            json.insert(
+                "path".to_owned(),
+                Value::String(self.key_format.format(path, x)),
             );
+            if let Some(version_id) = self.version_id(&enrichment) {
+                json.insert("version_id".to_owned(), Value::String(version_id));
+            }
+            json.extend(enrichment.to_json());
+            output.json(&Value::Object(json));
+        }
+        Ok(())
+    }
+
+    fn print_csv(
+        &self,
+        client: &S3Client,
+        path: &S3path,
+        list: &[Object],
+        cache: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let fields = self.enrich_fields();
+        if !self.header_printed.replace(true) {
+            let mut header = vec!["path", "size", "last_modified", "storage_class", "etag"];
+            if self.show_version_id {
+                header.push("version_id");
+            }
+            header.extend(Enrichment::csv_header(&fields));
+            output.line(&header.join(","));
+        }
+
+        let enrichments = self.enrichments(client, path, list, cache);
+        for (x, enrichment) in list.iter().zip(enrichments) {
+            let mut row = vec![
+                self.key_format.format(path, x),
+                self.format_size(x)?,
+                self.format_time(x),
+                x.storage_class.clone().unwrap_or_default(),
+                x.e_tag.clone().unwrap_or_default(),
+            ];
+            row.extend(self.version_id(&enrichment));
+            row.extend(enrichment.csv_fields(&fields));
+
+            let row: Vec<_> = row.iter().map(|x| csv_escape(x)).collect();
+            output.line(&row.join(","));
+        }
+        Ok(())
+    }
+
+    fn print_table(
+        &self,
+        client: &S3Client,
+        path: &S3path,
+        list: &[Object],
+        cache: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let enrichments = self.enrichments(client, path, list, cache);
+        let rows = list
+            .iter()
+            .zip(enrichments)
+            .map(|(x, enrichment)| {
+                let mut row = vec![
+                    self.format_size(x)?,
+                    self.format_time(x),
+                    x.storage_class
+                        .clone()
+                        .unwrap_or_else(|| "NoStorage".to_string()),
+                    x.e_tag.clone().unwrap_or_else(|| "NoEtag".to_string()),
+                ];
+                row.extend(self.version_id(&enrichment));
+                row.push(self.key_format.format(path, x));
+                Ok(row)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // minimal widths keep the columns stable between the printed chunks
+        let mut widths = vec![12, 24, 19, 34];
+        if self.show_version_id {
+            widths.push(32);
+        }
+        for row in &rows {
+            for (width, field) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(field.chars().count());
+            }
+        }
+
+        for row in rows {
+            let line: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(i, field)| match widths.get(i) {
+                    Some(width) if i == 0 => format!("{:>1$}", field, width),
+                    Some(width) => format!("{:1$}", field, width),
+                    None => field.to_owned(),
+                })
+                .collect();
+            output.line(&line.join("  "));
         }
         Ok(())
     }
 }
 
+impl RunCommand for AdvancedPrint {
+    fn execute(
+        &self,
+        client: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        match self.format {
+            PrintFormat::Text => self.print_text(client, path, list, enrichment, output),
+            PrintFormat::Table => self.print_table(client, path, list, enrichment, output),
+            PrintFormat::Json => self.print_json(client, path, list, enrichment, output),
+            PrintFormat::Csv => self.print_csv(client, path, list, enrichment, output),
+        }
+    }
+
+    // the prefixes have no tags, head or version
+    fn prefixes(&self) -> bool {
+        self.enrich.is_empty() && !self.show_version_id
+    }
+}
+
+impl TemplatePrint {
+    pub fn render(&self, path: &S3path, object: &Object) -> Result<String, Error> {
+        let mut output = String::new();
+        for part in &self.format.0 {
+            match part {
+                TemplatePart::Literal(x) => output.push_str(x),
+                TemplatePart::Path => output.push_str(&format!(
+                    "s3://{}/{}",
+                    &path.bucket,
+                    object.key.as_deref().unwrap_or("")
+                )),
+                TemplatePart::RelativeKey => {
+                    output.push_str(path.relative_key(object.key.as_deref().unwrap_or("")))
+                }
+                TemplatePart::Key => output.push_str(object.key.as_deref().unwrap_or("")),
+                TemplatePart::Bucket => output.push_str(&path.bucket),
+                TemplatePart::Size => output.push_str(&object.size.unwrap_or_default().to_string()),
+                TemplatePart::HumanSize => output.push_str(
+                    &object
+                        .size
+                        .unwrap_or_default()
+                        .file_size(options::CONVENTIONAL)
+                        .map_err(err_msg)?,
+                ),
+                TemplatePart::Time => {
+                    output.push_str(object.last_modified.as_deref().unwrap_or(""))
+                }
+                TemplatePart::ETag => output.push_str(object.e_tag.as_deref().unwrap_or("")),
+                TemplatePart::StorageClass => {
+                    output.push_str(object.storage_class.as_deref().unwrap_or(""))
+                }
+                TemplatePart::Owner => output.push_str(owner_name(object).unwrap_or("")),
+            }
+        }
+        Ok(output)
+    }
+}
+
+impl RunCommand for TemplatePrint {
+    fn execute(
+        &self,
+        _c: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        for x in list {
+            output.text(&self.render(path, x)?);
+        }
+        Ok(())
+    }
+
+    fn prefixes(&self) -> bool {
+        true
+    }
+}
+
+/// Display name of the owner of the key, or its ID when the region does not
+/// return display names. Only listed with --fetch-owner or ListObjects
+pub fn owner_name(object: &Object) -> Option<&str> {
+    let owner = object.owner.as_ref()?;
+    owner.display_name.as_deref().or(owner.id.as_deref())
+}
+
+/// Fields of the listed object as a JSON map
+pub fn object_to_json(object: &Object) -> Map<String, Value> {
+    match json!({
+        "key": object.key,
+        "size": object.size,
+        "last_modified": object.last_modified,
+        "storage_class": object.storage_class,
+        "etag": object.e_tag,
+        "owner": object.owner.as_ref().and_then(|x| x.display_name.as_ref()),
+        "owner_id": object.owner.as_ref().and_then(|x| x.id.as_ref()),
+    }) {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+/// Quote a CSV field when it contains separators, quotes or line breaks
+pub fn csv_escape(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Render an S3 timestamp in the requested style, unparsable values are kept as is
+pub fn format_time(time: &str, style: Option<TimeStyle>) -> String {
+    let parsed = match (style, time.parse::<DateTime<Utc>>()) {
+        (Some(style), Ok(parsed)) => (style, parsed),
+        _ => return time.to_owned(),
+    };
+
+    match parsed {
+        (TimeStyle::Local, time) => time
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+        (TimeStyle::Iso, time) => time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        (TimeStyle::Full, time) => time
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S%.3f %z")
+            .to_string(),
+        (TimeStyle::Relative, time) => format_relative(Utc::now().signed_duration_since(time)),
+    }
+}
+
+fn format_relative(duration: Duration) -> String {
+    let (value, unit) = match duration.num_seconds() {
+        x if x < 60 => return "just now".to_owned(),
+        x if x < 3600 => (x / 60, "minute"),
+        x if x < 86400 => (x / 3600, "hour"),
+        x if x < 86400 * 30 => (x / 86400, "day"),
+        x if x < 86400 * 365 => (x / (86400 * 30), "month"),
+        x => (x / (86400 * 365), "year"),
+    };
+
+    if value == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", value, unit)
+    }
+}
+
 impl Exec {
-    pub fn exec(&self, key: &str) -> Result<ExecStatus, Error> {
+    pub fn exec(&self, key: &str, output: &dyn OutputSink) -> Result<ExecStatus, Error> {
         let scommand = self.utility.replace("{}", key);
 
         let mut command_args = scommand.split(' ');
@@ -108,23 +608,32 @@ impl Exec {
             rcommand.arg(arg);
         }
 
-        let output = rcommand.output()?;
-        let output_str = String::from_utf8_lossy(&output.stdout).to_string();
-        print!("{}", &output_str);
+        info!("Running command: {}", scommand);
+        let result = rcommand.output()?;
+        debug!("Command exited with {}", result.status);
+        output.text(&String::from_utf8_lossy(&result.stdout));
 
         Ok(ExecStatus {
-            status: output.status,
+            status: result.status,
             runcommand: scommand.clone(),
         })
     }
 }
 
 impl RunCommand for Exec {
-    fn execute(&self, _: &S3Client, _r: &str, path: &S3path, list: &[Object]) -> Result<(), Error> {
+    fn execute(
+        &self,
+        _: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
         for x in list {
-            let key = x.key.as_ref().map(String::as_str).unwrap_or("");
+            let key = x.key.as_deref().unwrap_or("");
             let path = format!("s3://{}/{}", &path.bucket, key);
-            self.exec(&path)?;
+            self.exec(&path, output)?;
         }
         Ok(())
     }
@@ -134,77 +643,108 @@ impl RunCommand for MultipleDelete {
     fn execute(
         &self,
         client: &S3Client,
-        _r: &str,
+        _r: &Region,
         path: &S3path,
         list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
     ) -> Result<(), Error> {
-        let key_list: Vec<_> = list
-            .iter()
-            .flat_map(|x| match x.key.as_ref() {
-                Some(key) => Some(ObjectIdentifier {
-                    key: key.to_string(),
-                    version_id: None,
-                }),
-                _ => None,
-            })
-            .collect();
-
-        let request = DeleteObjectsRequest {
-            bucket: path.bucket.to_string(),
-            delete: Delete {
-                objects: key_list,
-                quiet: None,
-            },
-            ..Default::default()
-        };
-
-        let result = client.delete_objects(request).sync();
-
-        match result {
-            Ok(r) => {
-                if let Some(deleted_list) = r.deleted {
-                    for object in deleted_list {
-                        println!(
-                            "deleted: s3://{}/{}",
-                            &path.bucket,
-                            object.key.as_ref().unwrap_or(&"".to_string())
-                        );
+        let keys = list.iter().filter_map(|x| x.key.clone()).collect();
+
+        match ops::block_on(ops::delete_objects(client, &path.bucket, keys)) {
+            Ok(results) => {
+                for x in results {
+                    match x.result {
+                        Ok(()) if !self.quiet => {
+                            output.line(&format!("deleted: s3://{}/{}", &path.bucket, x.key))
+                        }
+                        Ok(()) => {}
+                        Err(report) => report.emit(),
                     }
                 }
             }
-            Err(e) => eprintln!("{}", e),
+            Err(report) => report.emit(),
         }
         Ok(())
     }
+
+    fn modifies_keys(&self) -> bool {
+        true
+    }
+}
+
+impl SetTags {
+    fn tag_object(
+        &self,
+        client: &S3Client,
+        bucket: &str,
+        object: &Object,
+    ) -> Result<Option<String>, Error> {
+        let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+        let tags = self.tags.iter().map(|x| x.clone().into()).collect();
+
+        if let Err(report) = ops::block_on(ops::put_object_tags(client, bucket, key, tags)) {
+            report.emit();
+            return Ok(None);
+        }
+
+        if self.quiet {
+            Ok(None)
+        } else {
+            Ok(Some(format!("tags are set for: s3://{}/{}", bucket, key)))
+        }
+    }
 }
 
 impl RunCommand for SetTags {
     fn execute(
         &self,
         client: &S3Client,
-        _r: &str,
+        _r: &Region,
         path: &S3path,
         list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
     ) -> Result<(), Error> {
-        for object in list {
-            let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+        for line in parallel_map(list, self.concurrency, |x| {
+            self.tag_object(client, &path.bucket, x)
+        }) {
+            if let Some(line) = line? {
+                output.line(&line);
+            }
+        }
+        Ok(())
+    }
 
-            let tags = Tagging {
-                tag_set: self.tags.iter().map(|x| x.clone().into()).collect(),
-            };
+    fn modifies_keys(&self) -> bool {
+        true
+    }
+}
 
-            let request = PutObjectTaggingRequest {
-                bucket: path.bucket.to_owned(),
-                key: key.to_owned(),
-                tagging: tags,
-                ..Default::default()
-            };
+impl ListTags {
+    fn object_tags(
+        &self,
+        client: &S3Client,
+        bucket: &str,
+        object: &Object,
+    ) -> Result<Option<String>, Error> {
+        let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+
+        let tag_set = match ops::block_on(ops::get_object_tags(client, bucket, key)) {
+            Ok(x) => x,
+            Err(report) => {
+                report.emit();
+                return Ok(None);
+            }
+        };
 
-            client.put_object_tagging(request).sync()?;
+        let tags: String = tag_set
+            .into_iter()
+            .map(|x| format!("{}:{}", x.key, x.value))
+            .collect::<Vec<String>>()
+            .join(",");
 
-            println!("tags are set for: s3://{}/{}", &path.bucket, &key);
-        }
-        Ok(())
+        Ok(Some(format!("s3://{}/{} {}", bucket, key, tags)))
     }
 }
 
@@ -212,181 +752,358 @@ impl RunCommand for ListTags {
     fn execute(
         &self,
         client: &S3Client,
-        _r: &str,
+        _r: &Region,
         path: &S3path,
         list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
     ) -> Result<(), Error> {
-        for object in list {
-            let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
-
-            let request = GetObjectTaggingRequest {
-                bucket: path.bucket.to_string(),
-                key: key.to_owned(),
-                ..Default::default()
-            };
-
-            let tag_output = client.get_object_tagging(request).sync()?;
+        for line in parallel_map(list, self.concurrency, |x| {
+            self.object_tags(client, &path.bucket, x)
+        }) {
+            if let Some(line) = line? {
+                output.line(&line);
+            }
+        }
+        Ok(())
+    }
+}
 
-            let tags: String = tag_output
-                .tag_set
-                .into_iter()
-                .map(|x| format!("{}:{}", x.key, x.value))
-                .collect::<Vec<String>>()
-                .join(",");
-
-            println!(
-                "s3://{}/{} {}",
-                &path.bucket,
-                object.key.as_ref().unwrap_or(&"".to_string()),
-                tags,
-            );
+impl SetPublic {
+    /// Replace the --urls-to file with an empty one, the URLs are appended
+    /// by the command
+    pub fn create(&self) -> Result<(), Error> {
+        if let Some(ref file) = self.urls_to {
+            fs::write(file, "")?;
         }
         Ok(())
     }
+
+    /// The key and its URL once it is public
+    fn publish_object(
+        &self,
+        client: &S3Client,
+        region: &Region,
+        bucket: &str,
+        object: &Object,
+    ) -> Result<Option<(String, String)>, Error> {
+        let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+
+        if let Err(report) = ops::block_on(ops::set_public_read(client, bucket, key)) {
+            report.emit();
+            return Ok(None);
+        }
+
+        let url = match self.url_base {
+            Some(ref base) => ops::cdn_url(base, key),
+            None => ops::public_url(region, bucket, key),
+        };
+        Ok(Some((key.to_owned(), url)))
+    }
 }
 
 impl RunCommand for SetPublic {
     fn execute(
         &self,
         client: &S3Client,
-        region: &str,
+        region: &Region,
         path: &S3path,
         list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
     ) -> Result<(), Error> {
-        // let region_str = self.0.name();
-        for object in list {
-            let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+        let mut urls = String::new();
+        for published in parallel_map(list, self.concurrency, |x| {
+            self.publish_object(client, region, &path.bucket, x)
+        }) {
+            if let Some((key, url)) = published? {
+                output.line(&format!("{} {}", key, url));
+                urls.push_str(&url);
+                urls.push('\n');
+            }
+        }
 
-            let request = PutObjectAclRequest {
-                bucket: path.bucket.to_owned(),
-                key: key.to_owned(),
-                acl: Some("public-read".to_string()),
-                ..Default::default()
-            };
+        if let Some(ref file) = self.urls_to {
+            fs::OpenOptions::new()
+                .append(true)
+                .open(file)?
+                .write_all(urls.as_bytes())?;
+        }
+        Ok(())
+    }
 
-            client.put_object_acl(request).sync()?;
+    fn modifies_keys(&self) -> bool {
+        true
+    }
+}
 
-            let url = match region {
-                "us-east-1" => format!("http://{}.s3.amazonaws.com/{}", &path.bucket, key),
-                _ => format!(
-                    "http://{}.s3-{}.amazonaws.com/{}",
-                    &path.bucket, region, key
-                ),
-            };
-            println!("{} {}", &key, url);
+/// The download command with the state of its manifest: the keys read from
+/// --resume-from and the --manifest file the downloaded keys are appended to
+pub struct Downloader {
+    options: Download,
+    completed: OnceLock<HashMap<String, String>>,
+    manifest: Mutex<Option<fs::File>>,
+}
+
+impl Downloader {
+    pub fn new(options: Download) -> Self {
+        Downloader {
+            options,
+            completed: OnceLock::new(),
+            manifest: Mutex::new(None),
+        }
+    }
+
+    /// Keys and ETags of the --resume-from manifest, read on the first use
+    fn completed(&self) -> Result<&HashMap<String, String>, Error> {
+        if let Some(completed) = self.completed.get() {
+            return Ok(completed);
+        }
+
+        let completed = match self.options.resume_from {
+            Some(ref file) => read_manifest(file)?,
+            None => HashMap::new(),
+        };
+        Ok(self.completed.get_or_init(|| completed))
+    }
+
+    fn is_completed(&self, key: &str, object: &Object) -> Result<bool, Error> {
+        if self.options.resume_from.is_none() {
+            return Ok(false);
+        }
+        Ok(is_downloaded(self.completed()?, key, object))
+    }
+
+    /// Lines are appended with a single write, so parallel downloads do not
+    /// interleave them. The file is opened on the first download and the
+    /// line cut by a killed run is ended first
+    fn record_completed(&self, key: &str, object: &Object) -> Result<(), Error> {
+        let file = match self.options.manifest {
+            Some(ref file) => file,
+            None => return Ok(()),
+        };
+
+        let mut manifest = self.manifest.lock().unwrap();
+        if manifest.is_none() {
+            let torn = fs::read(file)
+                .map(|data| !data.is_empty() && !data.ends_with(b"\n"))
+                .unwrap_or(false);
+            let mut output = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)?;
+            if torn {
+                output.write_all(b"\n")?;
+            }
+            *manifest = Some(output);
+        }
+
+        let line = json!({
+            "key": key,
+            "etag": object.e_tag.as_deref().unwrap_or_default(),
+            "size": object.size,
+        });
+        if let Some(ref mut output) = *manifest {
+            output.write_all(format!("{}\n", line).as_bytes())?;
         }
         Ok(())
     }
-}
 
-impl RunCommand for Download {
-    fn execute(
+    fn download_object(
         &self,
         client: &S3Client,
-        _r: &str,
-        path: &S3path,
-        list: &[Object],
+        bucket: &str,
+        object: &Object,
+        pb: &ProgressBar,
+        output: &dyn OutputSink,
     ) -> Result<(), Error> {
-        for object in list {
-            let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+        let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+        if self.is_completed(key, object)? {
+            return Ok(());
+        }
 
-            let request = GetObjectRequest {
-                bucket: path.bucket.to_owned(),
-                key: key.to_owned(),
-                ..Default::default()
-            };
-
-            let size = (*object
-                .size
-                .as_ref()
-                .ok_or(FunctionError::ObjectFieldError)
-                .unwrap()) as u64;
-            let file_path = Path::new(&self.destination).join(key);
-            let dir_path = file_path.parent().ok_or(FunctionError::ParentPathParse)?;
-
-            let mut count: u64 = 0;
-            let pb = ProgressBar::new(size);
-            pb.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .progress_chars("#>-"));
+        let size = object.size.ok_or(FunctionError::ObjectFieldError)? as u64;
+        let file_path = Path::new(&self.options.destination).join(key);
 
-            println!(
+        pb.set_length(size);
+        pb.set_position(0);
+
+        if !self.options.quiet {
+            output.line(&format!(
                 "downloading: s3://{}/{} => {}",
-                &path.bucket,
+                bucket,
                 &key,
                 file_path
                     .to_str()
-                    .ok_or(FunctionError::FileNameParseError)
-                    .unwrap()
-            );
+                    .ok_or(FunctionError::FileNameParseError)?
+            ));
+        }
 
-            if file_path.exists() && !self.force {
-                return Ok(());
+        if file_path.exists() && !self.options.force {
+            return Ok(());
+        }
+
+        let bar = pb.clone();
+        let download =
+            ops::download_object(client, bucket, key, file_path, move |x| bar.set_position(x));
+        match ops::block_on(download) {
+            Ok(_) => {
+                if let Err(e) = self.record_completed(key, object) {
+                    ErrorReport::new("WriteManifest", bucket, Some(key), &e).emit();
+                }
             }
+            Err(report) => report.emit(),
+        }
+        Ok(())
+    }
 
-            let result = client.get_object(request).sync()?;
+    fn progress_bar(&self) -> ProgressBar {
+        let pb = if self.options.quiet {
+            ProgressBar::hidden()
+        } else {
+            ProgressBar::new(0)
+        };
+        pb.set_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+            .progress_chars("#>-"));
+        pb
+    }
 
-            let stream = result.body.ok_or(FunctionError::S3FetchBodyError)?;
+    /// Download with a pool of workers, each one showing its current object
+    /// under a shared total of downloaded files
+    fn download_concurrently(
+        &self,
+        client: &S3Client,
+        bucket: &str,
+        list: &[Object],
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let multi = if self.options.quiet {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        } else {
+            MultiProgress::new()
+        };
+
+        let total = multi.add(ProgressBar::new(list.len() as u64));
+        total.set_style(
+            ProgressStyle::default_bar().template("[{elapsed_precise}] {pos}/{len} files"),
+        );
 
-            fs::create_dir_all(&dir_path)?;
-            let mut output = File::create(&file_path)?;
+        let bars: Vec<ProgressBar> = (0..self.options.concurrency.min(list.len()))
+            .map(|_| multi.add(self.progress_bar()))
+            .collect();
 
-            stream
-                .for_each(|buf| {
-                    output.write_all(&buf)?;
-                    count += buf.len() as u64;
-                    pb.set_position(count);
-                    Ok(())
+        let queue = Mutex::new(list.iter());
+
+        thread::scope(|scope| {
+            let drawing = scope.spawn(|| multi.join_and_clear());
+
+            let workers: Vec<_> = bars
+                .into_iter()
+                .map(|pb| {
+                    let queue = &queue;
+                    let total = &total;
+                    scope.spawn(move || {
+                        let mut result = Ok(());
+                        loop {
+                            let object = queue.lock().unwrap().next();
+                            match object {
+                                Some(object) => {
+                                    result =
+                                        self.download_object(client, bucket, object, &pb, output);
+                                    total.inc(1);
+                                    if result.is_err() {
+                                        break;
+                                    }
+                                }
+                                None => break,
+                            }
+                        }
+                        pb.finish_and_clear();
+                        result
+                    })
                 })
-                .wait()?;
+                .collect();
+
+            let results: Vec<Result<(), Error>> =
+                workers.into_iter().map(|x| x.join().unwrap()).collect();
+
+            total.finish_and_clear();
+            drawing.join().unwrap()?;
+            results.into_iter().collect()
+        })
+    }
+}
+
+impl RunCommand for Downloader {
+    fn execute(
+        &self,
+        client: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        if self.options.concurrency > 1 {
+            return self.download_concurrently(client, &path.bucket, list, output);
+        }
+
+        for object in list {
+            let pb = self.progress_bar();
+            self.download_object(client, &path.bucket, object, &pb, output)?;
         }
         Ok(())
     }
 }
 
+/// Keys and ETags of a download manifest, the last line may be cut by a crash
+pub fn read_manifest(file: &Path) -> Result<HashMap<String, String>, Error> {
+    let mut completed = HashMap::new();
+    if file.exists() {
+        for line in fs::read_to_string(file)?.lines() {
+            if let Ok(entry) = serde_json::from_str::<Value>(line) {
+                if let (Some(key), Some(etag)) = (entry["key"].as_str(), entry["etag"].as_str()) {
+                    completed.insert(key.to_owned(), etag.to_owned());
+                }
+            }
+        }
+    }
+    Ok(completed)
+}
+
+/// The key is in the manifest read by read_manifest with the same ETag
+pub fn is_downloaded(completed: &HashMap<String, String>, key: &str, object: &Object) -> bool {
+    completed.get(key).map(String::as_str) == Some(object.e_tag.as_deref().unwrap_or_default())
+}
+
 impl RunCommand for S3Copy {
     fn execute(
         &self,
         client: &S3Client,
-        _r: &str,
+        _r: &Region,
         path: &S3path,
         list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
     ) -> Result<(), Error> {
         for object in list {
             let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+            let target_key = ops::target_key(key, &self.destination, self.flat)?;
+
+            if !self.quiet {
+                output.line(&format!(
+                    "copying: s3://{0} => s3://{1}/{2}",
+                    path.copy_source(key),
+                    &self.destination.bucket,
+                    target_key,
+                ));
+            }
 
-            let key2 = if self.flat {
-                Path::new(key)
-                    .file_name()
-                    .ok_or(FunctionError::PathConverError)?
-                    .to_str()
-                    .ok_or(FunctionError::PathConverError)?
-            } else {
-                key
-            };
-
-            let target_key = if let Some(ref r) = self.destination.prefix {
-                Path::new(r).join(key2)
-            } else {
-                PathBuf::from(key2)
-            };
-
-            let target_key_str = target_key.to_str().ok_or(FunctionError::PathConverError)?;
-            let source_path = format!("{0}/{1}", &path.bucket, key);
-
-            println!(
-                "copying: s3://{0} => s3://{1}/{2}",
-                source_path, &self.destination.bucket, target_key_str,
-            );
-
-            let request = CopyObjectRequest {
-                bucket: self.destination.bucket.clone(),
-                key: target_key_str.to_owned(),
-                copy_source: source_path,
-                ..Default::default()
-            };
-
-            client.copy_object(request).sync()?;
+            let copy = ops::copy_object(client, path, key, &self.destination.bucket, &target_key);
+            if let Err(report) = ops::block_on(copy) {
+                report.emit();
+            }
         }
         Ok(())
     }
@@ -396,74 +1113,64 @@ impl RunCommand for S3Move {
     fn execute(
         &self,
         client: &S3Client,
-        _r: &str,
+        _r: &Region,
         path: &S3path,
         list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
     ) -> Result<(), Error> {
+        let mut copied = Vec::new();
+
         for object in list {
             let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+            let target_key = ops::target_key(key, &self.destination, self.flat)?;
+
+            if !self.quiet {
+                output.line(&format!(
+                    "moving: s3://{0} => s3://{1}/{2}",
+                    path.copy_source(key),
+                    &self.destination.bucket,
+                    target_key,
+                ));
+            }
 
-            let key2 = if self.flat {
-                Path::new(key)
-                    .file_name()
-                    .ok_or(FunctionError::PathConverError)?
-                    .to_str()
-                    .ok_or(FunctionError::PathConverError)?
-            } else {
-                key
-            };
-
-            let target_key = if let Some(ref r) = self.destination.prefix {
-                Path::new(r).join(key2)
-            } else {
-                PathBuf::from(key2)
-            };
-
-            let target_key_str = target_key.to_str().ok_or(FunctionError::PathConverError)?;
-            let source_path = format!("{0}/{1}", &path.bucket, key);
-
-            println!(
-                "moving: s3://{0} => s3://{1}/{2}",
-                source_path, &self.destination.bucket, target_key_str,
-            );
-
-            let request = CopyObjectRequest {
-                bucket: self.destination.bucket.to_owned(),
-                key: target_key_str.to_owned(),
-                copy_source: source_path,
-                ..Default::default()
-            };
-
-            client.copy_object(request).sync()?;
+            let copy = ops::copy_object(client, path, key, &self.destination.bucket, &target_key);
+            match ops::block_on(copy) {
+                Ok(()) => copied.push(key.to_owned()),
+                Err(report) => report.emit(),
+            }
         }
 
-        let key_list: Vec<_> = list
-            .iter()
-            .flat_map(|x| match x.key.as_ref() {
-                Some(key) => Some(ObjectIdentifier {
-                    key: key.to_string(),
-                    version_id: None,
-                }),
-                _ => None,
-            })
-            .collect();
-
-        let request = DeleteObjectsRequest {
-            bucket: path.bucket.clone(),
-            delete: Delete {
-                objects: key_list,
-                quiet: None,
-            },
-            ..Default::default()
-        };
+        // only successfully copied keys are removed from the source
+        if copied.is_empty() {
+            return Ok(());
+        }
 
-        client.delete_objects(request).sync()?;
+        match ops::block_on(ops::delete_objects(client, &path.bucket, copied)) {
+            Ok(results) => results
+                .into_iter()
+                .filter_map(|x| x.result.err())
+                .for_each(|report| report.emit()),
+            Err(report) => report.emit(),
+        }
         Ok(())
     }
+
+    fn modifies_keys(&self) -> bool {
+        true
+    }
 }
 
 impl RunCommand for DoNothing {
-    fn execute(&self, _c: &S3Client, _r: &str, _p: &S3path, _l: &[Object]) -> Result<(), Error> {
+    fn execute(
+        &self,
+        _c: &S3Client,
+        _r: &Region,
+        _p: &S3path,
+        _l: &[Object],
+        _enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
         Ok(())
     }
 }
@@ -471,7 +1178,8 @@ impl RunCommand for DoNothing {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rusoto_core::Region;
+    use crate::output::{MemorySink, Output, StdoutSink};
+    use rusoto_s3::Owner;
 
     #[test]
     fn advanced_print_test() -> Result<(), Error> {
@@ -484,15 +1192,85 @@ mod tests {
             storage_class: Some("STANDARD".to_string()),
         };
 
-        let cmd = AdvancedPrint {};
-        let region = "us-east-1";
+        let cmd = AdvancedPrint {
+            human_readable: false,
+            time_style: None,
+            format: PrintFormat::Text,
+            enrich: Vec::new(),
+            show_version_id: false,
+            concurrency: 1,
+            key_format: KeyFormat::default(),
+            header_printed: Default::default(),
+        };
+        let region = &Region::UsEast1;
         let client = S3Client::new(Region::UsEast1);
         let path = S3path {
             bucket: "test".to_owned(),
             prefix: None,
         };
 
-        cmd.execute(&client, region, &path, &[object])
+        let owned = Object {
+            owner: Some(Owner {
+                display_name: None,
+                id: Some("75aa57f09aa0c8caeab4f8c24e99d10f".to_string()),
+            }),
+            ..object.clone()
+        };
+
+        let output = MemorySink::new();
+        cmd.execute(
+            &client,
+            region,
+            &path,
+            &[object, owned],
+            &EnrichmentCache::default(),
+            &output,
+        )?;
+        assert_eq!(
+            output.lines(),
+            vec![
+                "9d48114aa7c18f9d68aa20086dbb7756 None 4997288 2017-07-19T19:04:17.000Z s3://test/somepath/otherpath STANDARD",
+                "9d48114aa7c18f9d68aa20086dbb7756 75aa57f09aa0c8caeab4f8c24e99d10f 4997288 2017-07-19T19:04:17.000Z s3://test/somepath/otherpath STANDARD",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn advanced_print_human_readable_test() -> Result<(), Error> {
+        let object = Object {
+            key: Some("somepath/otherpath".to_string()),
+            size: Some(4_997_288),
+            ..Default::default()
+        };
+
+        let cmd = AdvancedPrint {
+            human_readable: true,
+            time_style: Some(TimeStyle::Relative),
+            format: PrintFormat::Table,
+            enrich: Vec::new(),
+            show_version_id: false,
+            concurrency: 4,
+            key_format: KeyFormat {
+                relative: true,
+                encode_keys: true,
+            },
+            header_printed: Default::default(),
+        };
+        let client = S3Client::new(Region::UsEast1);
+        let path = S3path {
+            bucket: "test".to_owned(),
+            prefix: None,
+        };
+
+        cmd.execute(
+            &client,
+            &Region::UsEast1,
+            &path,
+            &[object],
+            &EnrichmentCache::default(),
+            &StdoutSink,
+        )
     }
 
     #[test]
@@ -506,14 +1284,276 @@ mod tests {
             storage_class: Some("STANDARD".to_string()),
         };
 
-        let cmd = FastPrint {};
-        let region = "us-east-1";
+        let cmd = FastPrint::default();
+        let region = &Region::UsEast1;
         let client = S3Client::new(Region::UsEast1);
         let path = S3path {
             bucket: "test".to_owned(),
             prefix: None,
         };
 
-        cmd.execute(&client, region, &path, &[object])
+        let output = MemorySink::new();
+        cmd.execute(
+            &client,
+            region,
+            &path,
+            &[object],
+            &EnrichmentCache::default(),
+            &output,
+        )?;
+        assert_eq!(
+            output.take(),
+            vec![Output::Line("s3://test/somepath/otherpath".to_owned())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn format_time_test() {
+        let time = "2017-07-19T19:04:17.000Z";
+        assert_eq!(format_time(time, None), time);
+        assert_eq!(format_time("NoTime", Some(TimeStyle::Iso)), "NoTime");
+        assert_eq!(
+            format_time(time, Some(TimeStyle::Iso)),
+            "2017-07-19T19:04:17Z"
+        );
+
+        let time = format!("{:?}", Utc::now() - Duration::hours(50));
+        assert_eq!(format_time(&time, Some(TimeStyle::Relative)), "2 days ago");
+        let time = format!("{:?}", Utc::now() - Duration::minutes(61));
+        assert_eq!(format_time(&time, Some(TimeStyle::Relative)), "1 hour ago");
+    }
+
+    #[test]
+    fn template_print_render() -> Result<(), Error> {
+        let object = Object {
+            e_tag: Some("9d48114aa7c18f9d68aa20086dbb7756".to_string()),
+            key: Some("somepath/otherpath".to_string()),
+            size: Some(2048),
+            storage_class: Some("STANDARD".to_string()),
+            ..Default::default()
+        };
+
+        let cmd = TemplatePrint {
+            format: "%s\\t%h\\t%p %P %S %e%%\\n".parse()?,
+        };
+        let path = S3path {
+            bucket: "test".to_owned(),
+            prefix: Some("somepath".to_owned()),
+        };
+
+        assert_eq!(
+            cmd.render(&path, &object)?,
+            "2048\t2 KB\ts3://test/somepath/otherpath otherpath STANDARD 9d48114aa7c18f9d68aa20086dbb7756%\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn percent_encoding() -> Result<(), Error> {
+        assert_eq!(
+            percent_encode("path/to file\n.txt"),
+            "path/to%20file%0A.txt"
+        );
+        assert_eq!(percent_encode("ключ"), "%D0%BA%D0%BB%D1%8E%D1%87");
+        assert_eq!(
+            percent_decode("path/to%20file%0A.txt")?,
+            "path/to file\n.txt"
+        );
+        assert_eq!(percent_decode("a+b")?, "a b");
+        assert_eq!(percent_decode(&percent_encode("ключ"))?, "ключ");
+        assert!(percent_decode("bad%2").is_err());
+        assert!(percent_decode("bad%zz").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn key_format_test() {
+        let object = Object {
+            key: Some("prefix/some key".to_string()),
+            ..Default::default()
+        };
+        let path = S3path {
+            bucket: "test".to_owned(),
+            prefix: Some("prefix".to_owned()),
+        };
+
+        let format = KeyFormat::default();
+        assert_eq!(format.format(&path, &object), "s3://test/prefix/some key");
+
+        let format = KeyFormat {
+            relative: true,
+            encode_keys: true,
+        };
+        assert_eq!(format.format(&path, &object), "some%20key");
+    }
+
+    #[test]
+    fn object_json_and_csv() {
+        let object = Object {
+            key: Some("somepath/otherpath".to_string()),
+            size: Some(10),
+            storage_class: Some("STANDARD".to_string()),
+            ..Default::default()
+        };
+
+        let json = object_to_json(&object);
+        assert_eq!(json["key"], "somepath/otherpath");
+        assert_eq!(json["size"], 10);
+        assert_eq!(json["etag"], Value::Null);
+
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn advanced_print_version_id() {
+        let cmd = AdvancedPrint {
+            human_readable: false,
+            time_style: None,
+            format: PrintFormat::Text,
+            enrich: vec![EnrichField::Tags],
+            show_version_id: true,
+            concurrency: 1,
+            key_format: KeyFormat::default(),
+            header_printed: Default::default(),
+        };
+
+        // the text format does not print the tags
+        assert_eq!(cmd.enrich_fields(), vec![EnrichField::Head]);
+        let json = AdvancedPrint {
+            format: PrintFormat::Json,
+            ..cmd.clone()
+        };
+        assert_eq!(
+            json.enrich_fields(),
+            vec![EnrichField::Tags, EnrichField::Head]
+        );
+        assert_eq!(
+            cmd.version_id(&Enrichment::default()),
+            Some("null".to_owned())
+        );
+
+        let enrichment = Enrichment {
+            head: Some(rusoto_s3::HeadObjectOutput {
+                version_id: Some("3HL4kqtJlcpXroDTDmJ".to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            cmd.version_id(&enrichment),
+            Some("3HL4kqtJlcpXroDTDmJ".to_owned())
+        );
+    }
+
+    #[test]
+    fn download_concurrently_skips_present_files() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let keys = ["a/1", "a/2", "b/3", "4", "5"];
+        for key in &keys {
+            let file = dir.path().join(key);
+            fs::create_dir_all(file.parent().unwrap())?;
+            fs::write(file, "present")?;
+        }
+
+        let list: Vec<Object> = keys
+            .iter()
+            .map(|x| Object {
+                key: Some((*x).to_owned()),
+                size: Some(7),
+                ..Default::default()
+            })
+            .collect();
+
+        let cmd = Downloader::new(Download {
+            force: false,
+            destination: dir.path().to_str().unwrap().to_owned(),
+            concurrency: 3,
+            quiet: true,
+            manifest: None,
+            resume_from: None,
+            estimate: false,
+            bandwidth: Bandwidth(12.5e6),
+        });
+        let client = S3Client::new(Region::UsEast1);
+        let path = S3path {
+            bucket: "test".to_owned(),
+            prefix: None,
+        };
+
+        cmd.execute(
+            &client,
+            &Region::UsEast1,
+            &path,
+            &list,
+            &EnrichmentCache::default(),
+            &StdoutSink,
+        )?;
+        cmd.execute(
+            &client,
+            &Region::UsEast1,
+            &path,
+            &[],
+            &EnrichmentCache::default(),
+            &StdoutSink,
+        )
+    }
+
+    #[test]
+    fn download_manifest_resume() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let manifest = dir.path().join("manifest.json");
+        let object = |key: &str, etag: &str| Object {
+            key: Some(key.to_owned()),
+            e_tag: Some(etag.to_owned()),
+            size: Some(7),
+            ..Default::default()
+        };
+
+        let cmd = Downloader::new(Download {
+            force: false,
+            destination: dir.path().join("files").to_str().unwrap().to_owned(),
+            concurrency: 2,
+            quiet: true,
+            manifest: Some(manifest.clone()),
+            resume_from: Some(manifest.clone()),
+            estimate: false,
+            bandwidth: Bandwidth(12.5e6),
+        });
+
+        cmd.record_completed("a/1", &object("a/1", "\"etag1\""))?;
+        cmd.record_completed("a/2", &object("a/2", "\"etag2\""))?;
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&manifest)?
+            .write_all(b"{\"key\":\"a/3\",\"et")?;
+
+        assert!(cmd.is_completed("a/1", &object("a/1", "\"etag1\""))?);
+        assert!(!cmd.is_completed("a/2", &object("a/2", "\"changed\""))?);
+        assert!(!cmd.is_completed("a/3", &object("a/3", "\"etag3\""))?);
+
+        // the next run ends the cut line before appending
+        let next = Downloader::new(cmd.options.clone());
+        next.record_completed("a/4", &object("a/4", "\"etag4\""))?;
+        let completed = read_manifest(&manifest)?;
+        assert_eq!(completed.len(), 3);
+        assert_eq!(completed["a/4"], "\"etag4\"");
+
+        // completed objects are skipped without any request
+        let client = S3Client::new(Region::UsEast1);
+        let path = S3path {
+            bucket: "test".to_owned(),
+            prefix: None,
+        };
+        cmd.execute(
+            &client,
+            &Region::UsEast1,
+            &path,
+            &[object("a/1", "\"etag1\"")],
+            &EnrichmentCache::default(),
+            &StdoutSink,
+        )
     }
 }
@@ -0,0 +1,235 @@
+use failure::Error;
+use rusoto_core::Region;
+use rusoto_s3::{Object, S3Client};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use crate::arg::{KeyHygieneReport, S3path};
+use crate::enrich::EnrichmentCache;
+use crate::function::RunCommand;
+use crate::output::OutputSink;
+
+/// Why a key is flagged by report key-hygiene
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum KeyIssue {
+    /// Control characters or U+FFFD, the replacement of the bytes that are
+    /// not valid UTF-8
+    InvalidCharacters,
+    /// Characters outside of the safe set of the S3 documentation:
+    /// alphanumeric ASCII and `!-_.*'()/`
+    UnsafeCharacters,
+    /// A level of the key ends with a whitespace
+    TrailingSpace,
+    /// The key is at least --max-length UTF-8 bytes long
+    NearLengthLimit,
+    /// The key starts with `./` or has a `.` or `..` level
+    DotSegment,
+    /// Another key differs only by case
+    CaseCollision,
+}
+
+impl KeyIssue {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeyIssue::InvalidCharacters => "invalid-characters",
+            KeyIssue::UnsafeCharacters => "unsafe-characters",
+            KeyIssue::TrailingSpace => "trailing-space",
+            KeyIssue::NearLengthLimit => "near-length-limit",
+            KeyIssue::DotSegment => "dot-segment",
+            KeyIssue::CaseCollision => "case-collision",
+        }
+    }
+}
+
+fn is_invalid(c: char) -> bool {
+    c.is_control() || c == '\u{FFFD}'
+}
+
+fn is_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!-_.*'()/".contains(c)
+}
+
+/// The issues of a key, but the case collisions needing the other keys
+pub fn key_issues(key: &str, max_length: usize) -> Vec<KeyIssue> {
+    let mut issues = Vec::new();
+    if key.chars().any(is_invalid) {
+        issues.push(KeyIssue::InvalidCharacters);
+    }
+    if key.chars().any(|c| !is_invalid(c) && !is_safe(c)) {
+        issues.push(KeyIssue::UnsafeCharacters);
+    }
+    if key.split('/').any(|x| x.ends_with(char::is_whitespace)) {
+        issues.push(KeyIssue::TrailingSpace);
+    }
+    if key.len() >= max_length {
+        issues.push(KeyIssue::NearLengthLimit);
+    }
+    if key.split('/').any(|x| x == "." || x == "..") {
+        issues.push(KeyIssue::DotSegment);
+    }
+    issues
+}
+
+/// Command of report key-hygiene, the keys are flagged as they are listed,
+/// the case collisions and the counts per issue at the end of the run
+pub struct KeyHygieneAudit {
+    options: KeyHygieneReport,
+    counts: Mutex<BTreeMap<KeyIssue, u64>>,
+    /// The keys by bucket and lowercase key
+    cases: Mutex<HashMap<(String, String), BTreeSet<String>>>,
+}
+
+impl KeyHygieneAudit {
+    pub fn new(options: KeyHygieneReport) -> Self {
+        KeyHygieneAudit {
+            options,
+            counts: Mutex::new(BTreeMap::new()),
+            cases: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn count(&self, issue: KeyIssue) {
+        *self.counts.lock().unwrap().entry(issue).or_default() += 1;
+    }
+
+    fn line(&self, url: &str, issues: &[KeyIssue]) -> String {
+        let issues: Vec<&str> = issues.iter().map(|x| x.as_str()).collect();
+        if self.options.json {
+            json!({ "key": url, "issues": issues }).to_string()
+        } else {
+            format!("{}\t{}", issues.join(","), url)
+        }
+    }
+
+    /// The groups of keys differing only by case, the keys start again
+    pub fn collisions(&self) -> Vec<Vec<String>> {
+        let cases = std::mem::take(&mut *self.cases.lock().unwrap());
+        let mut collisions: Vec<Vec<String>> = cases
+            .into_values()
+            .map(|keys| keys.into_iter().collect::<Vec<_>>())
+            .filter(|x| x.len() > 1)
+            .collect();
+        collisions.sort();
+        collisions
+    }
+
+    /// The lines of the case collisions and of the counts, they start again
+    fn report(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for keys in self.collisions() {
+            self.count(KeyIssue::CaseCollision);
+            lines.push(if self.options.json {
+                json!({ "issue": KeyIssue::CaseCollision.as_str(), "keys": keys }).to_string()
+            } else {
+                format!("{}\t{}", KeyIssue::CaseCollision.as_str(), keys.join("\t"))
+            });
+        }
+
+        let counts = std::mem::take(&mut *self.counts.lock().unwrap());
+        if self.options.json {
+            let counts: BTreeMap<&str, u64> =
+                counts.into_iter().map(|(x, n)| (x.as_str(), n)).collect();
+            lines.push(json!({ "totals": counts }).to_string());
+        } else {
+            lines.extend(
+                counts
+                    .into_iter()
+                    .map(|(x, n)| format!("total\t{}\t{}", x.as_str(), n)),
+            );
+        }
+        lines
+    }
+}
+
+impl RunCommand for KeyHygieneAudit {
+    fn execute(
+        &self,
+        _c: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        for object in list {
+            let key = object.key.as_deref().unwrap_or_default();
+            let url = format!("s3://{}/{}", path.bucket, key);
+            let issues = key_issues(key, self.options.max_length);
+            if !issues.is_empty() {
+                issues.iter().for_each(|x| self.count(*x));
+                output.line(&self.line(&url, &issues));
+            }
+            if !self.options.no_case_collisions {
+                self.cases
+                    .lock()
+                    .unwrap()
+                    .entry((path.bucket.clone(), key.to_lowercase()))
+                    .or_default()
+                    .insert(url);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self, output: &dyn OutputSink) -> Result<(), Error> {
+        for line in self.report() {
+            output.line(&line);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::MemorySink;
+
+    #[test]
+    fn key_hygiene() -> Result<(), Error> {
+        assert!(key_issues("data/2020/report-v1.csv", 900).is_empty());
+        assert_eq!(
+            key_issues("data/my file /a.csv", 900),
+            vec![KeyIssue::UnsafeCharacters, KeyIssue::TrailingSpace]
+        );
+        assert_eq!(
+            key_issues("./data/\u{FFFD}.csv", 900),
+            vec![KeyIssue::InvalidCharacters, KeyIssue::DotSegment]
+        );
+        assert_eq!(
+            key_issues(&"a".repeat(1000), 900),
+            vec![KeyIssue::NearLengthLimit]
+        );
+
+        let audit = KeyHygieneAudit::new(KeyHygieneReport {
+            max_length: 900,
+            no_case_collisions: false,
+            json: false,
+        });
+        let object = |key: &str| Object {
+            key: Some(key.to_owned()),
+            ..Default::default()
+        };
+        let sink = MemorySink::default();
+        audit.execute(
+            &S3Client::new(Region::UsEast1),
+            &Region::UsEast1,
+            &"s3://bucket/".parse()?,
+            &[object("Data/a.csv"), object("data/a.csv"), object("b c")],
+            &EnrichmentCache::default(),
+            &sink,
+        )?;
+
+        assert_eq!(sink.lines(), vec!["unsafe-characters\ts3://bucket/b c"]);
+        assert_eq!(
+            audit.report(),
+            vec![
+                "case-collision\ts3://bucket/Data/a.csv\ts3://bucket/data/a.csv",
+                "total\tunsafe-characters\t1",
+                "total\tcase-collision\t1",
+            ]
+        );
+        assert!(audit.report().is_empty());
+        Ok(())
+    }
+}
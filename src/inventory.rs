@@ -0,0 +1,497 @@
+use chrono::{SecondsFormat, TimeZone, Utc};
+use failure::Error;
+use flate2::read::MultiGzDecoder;
+use log::{debug, info};
+use parquet::file::reader::SerializedFileReader;
+use parquet::record::{Field, Row};
+use rusoto_s3::{GetObjectRequest, Object, S3Client, S3};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+use std::sync::Arc;
+
+use crate::arg::S3path;
+use crate::error::FunctionError;
+use crate::function::percent_decode;
+use crate::retry::with_retry;
+
+/// Format of the data files of an inventory report, ORC is not supported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InventoryFormat {
+    Csv,
+    Parquet,
+}
+
+/// S3 Inventory manifest: the report location, its format and the data files
+#[derive(Debug, PartialEq)]
+pub struct Manifest {
+    pub source_bucket: String,
+    pub destination_bucket: String,
+    pub format: InventoryFormat,
+    /// Columns of the CSV files, the Parquet files name their columns
+    pub columns: Vec<String>,
+    pub files: Vec<String>,
+}
+
+impl Manifest {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let value: Value =
+            serde_json::from_slice(data).map_err(|_| FunctionError::InventoryManifestError)?;
+        let string = |field: &str| {
+            value[field]
+                .as_str()
+                .ok_or(FunctionError::InventoryManifestError)
+        };
+
+        let format = match string("fileFormat")? {
+            x if x.eq_ignore_ascii_case("csv") => InventoryFormat::Csv,
+            x if x.eq_ignore_ascii_case("parquet") => InventoryFormat::Parquet,
+            x => return Err(FunctionError::InventoryFormatError(x.to_owned()).into()),
+        };
+
+        // arn:aws:s3:::bucket
+        let destination_bucket = string("destinationBucket")?
+            .rsplit(':')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+
+        let columns = match format {
+            InventoryFormat::Csv => string("fileSchema")?
+                .split(',')
+                .map(|x| x.trim().to_owned())
+                .collect(),
+            InventoryFormat::Parquet => Vec::new(),
+        };
+
+        let files = value["files"]
+            .as_array()
+            .ok_or(FunctionError::InventoryManifestError)?
+            .iter()
+            .map(|x| {
+                x["key"]
+                    .as_str()
+                    .map(str::to_owned)
+                    .ok_or(FunctionError::InventoryManifestError)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Manifest {
+            source_bucket: string("sourceBucket")?.to_owned(),
+            destination_bucket,
+            format,
+            columns,
+            files,
+        })
+    }
+
+    /// Object of a CSV row, none for the old versions, the delete markers
+    /// and the keys outside of the prefix
+    pub fn parse_row(&self, line: &str, prefix: &str) -> Result<Option<Object>, Error> {
+        let column = |name: &str| self.columns.iter().position(|x| x == name);
+        let row = parse_csv_line(line)?;
+        let field = |name: &str| {
+            column(name)
+                .and_then(|x| row.get(x))
+                .filter(|x| !x.is_empty())
+                .map(String::as_str)
+        };
+
+        if field("IsLatest") == Some("false") || field("IsDeleteMarker") == Some("true") {
+            return Ok(None);
+        }
+
+        let key = percent_decode(field("Key").ok_or(FunctionError::InventoryRowError)?)?;
+        if !key.starts_with(prefix) {
+            return Ok(None);
+        }
+
+        let size = match field("Size") {
+            Some(size) => Some(
+                size.parse::<i64>()
+                    .map_err(|_| FunctionError::InventoryRowError)?,
+            ),
+            None => None,
+        };
+
+        Ok(Some(Object {
+            key: Some(key),
+            size,
+            last_modified: field("LastModifiedDate").map(str::to_owned),
+            // quoted as in the list responses
+            e_tag: field("ETag").map(|x| format!("\"{}\"", x)),
+            storage_class: field("StorageClass").map(str::to_owned),
+            owner: None,
+        }))
+    }
+}
+
+/// Object of a Parquet row as parse_row does, the keys are not encoded
+pub fn parquet_object(row: &Row, prefix: &str) -> Result<Option<Object>, Error> {
+    let field = |name: &str| {
+        row.get_column_iter()
+            .find(|(x, _)| x.as_str() == name)
+            .map(|(_, x)| x)
+    };
+    let string = |name: &str| match field(name) {
+        Some(Field::Str(x)) if !x.is_empty() => Some(x.clone()),
+        _ => None,
+    };
+
+    if let Some(Field::Bool(false)) = field("is_latest") {
+        return Ok(None);
+    }
+    if let Some(Field::Bool(true)) = field("is_delete_marker") {
+        return Ok(None);
+    }
+
+    let key = string("key").ok_or(FunctionError::InventoryRowError)?;
+    if !key.starts_with(prefix) {
+        return Ok(None);
+    }
+
+    let last_modified = match field("last_modified_date") {
+        Some(Field::TimestampMillis(x)) => Utc.timestamp_millis_opt(*x).single(),
+        Some(Field::TimestampMicros(x)) => Utc.timestamp_millis_opt(*x / 1000).single(),
+        _ => None,
+    };
+
+    Ok(Some(Object {
+        key: Some(key),
+        size: match field("size") {
+            Some(Field::Long(x)) => Some(*x),
+            _ => None,
+        },
+        last_modified: last_modified.map(|x| x.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        e_tag: string("e_tag").map(|x| format!("\"{}\"", x)),
+        storage_class: string("storage_class"),
+        owner: None,
+    }))
+}
+
+/// Quoted CSV fields, a quote inside of a field is doubled
+fn parse_csv_line(line: &str) -> Result<Vec<String>, Error> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err(FunctionError::InventoryRowError.into()),
+                }
+            }
+        } else {
+            while let Some(c) = chars.peek() {
+                if *c == ',' {
+                    break;
+                }
+                field.push(*c);
+                chars.next();
+            }
+        }
+        fields.push(field);
+
+        match chars.next() {
+            Some(',') => continue,
+            None => return Ok(fields),
+            Some(_) => return Err(FunctionError::InventoryRowError.into()),
+        }
+    }
+}
+
+/// Objects of the data file being read
+type Rows = Box<dyn Iterator<Item = Result<Object, Error>> + Send>;
+
+/// Pages of the objects listed in an S3 Inventory report, the data files are
+/// read one at a time while the pages are consumed
+pub struct InventoryIter {
+    client: S3Client,
+    path: S3path,
+    bucket: String,
+    manifest_key: String,
+    page_size: usize,
+    manifest: Option<Arc<Manifest>>,
+    files: VecDeque<String>,
+    rows: Option<Rows>,
+    finished: bool,
+}
+
+impl InventoryIter {
+    pub fn new(
+        client: S3Client,
+        path: S3path,
+        bucket: String,
+        manifest_key: String,
+        page_size: usize,
+    ) -> Self {
+        InventoryIter {
+            client,
+            path,
+            bucket,
+            manifest_key,
+            page_size: page_size.max(1),
+            manifest: None,
+            files: VecDeque::new(),
+            rows: None,
+            finished: false,
+        }
+    }
+
+    fn fetch(&self, bucket: &str, key: &str) -> Result<impl Read + Send, Error> {
+        let request = GetObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            ..Default::default()
+        };
+
+        debug!("GetObject s3://{}/{}", request.bucket, request.key);
+        let output = with_retry("GetObject", || self.client.get_object(request.clone()))?;
+        Ok(output
+            .body
+            .ok_or(FunctionError::S3FetchBodyError)?
+            .into_blocking_read())
+    }
+
+    fn load_manifest(&mut self) -> Result<(), Error> {
+        let mut data = Vec::new();
+        self.fetch(&self.bucket, &self.manifest_key)?
+            .read_to_end(&mut data)?;
+        let manifest = Manifest::parse(&data)?;
+        if manifest.source_bucket != self.path.bucket {
+            return Err(FunctionError::InventoryBucketError.into());
+        }
+
+        info!(
+            "Inventory of s3://{}: {} data files",
+            manifest.source_bucket,
+            manifest.files.len()
+        );
+        self.files = manifest.files.iter().cloned().collect();
+        self.manifest = Some(Arc::new(manifest));
+        Ok(())
+    }
+
+    /// The CSV files are streamed, a Parquet file is downloaded to a
+    /// temporary file first as its reader seeks to the row groups
+    fn open_file(&self, manifest: Arc<Manifest>, key: &str) -> Result<Rows, Error> {
+        let mut body = self.fetch(&manifest.destination_bucket, key)?;
+        let prefix = self.path.prefix.clone().unwrap_or_default();
+
+        match manifest.format {
+            InventoryFormat::Csv => {
+                let reader: Box<dyn Read + Send> = if key.ends_with(".gz") {
+                    Box::new(MultiGzDecoder::new(body))
+                } else {
+                    Box::new(body)
+                };
+                let rows = BufReader::new(reader)
+                    .lines()
+                    .filter_map(move |line| match line {
+                        Ok(ref line) if line.is_empty() => None,
+                        Ok(line) => manifest.parse_row(&line, &prefix).transpose(),
+                        Err(e) => Some(Err(e.into())),
+                    });
+                Ok(Box::new(rows))
+            }
+            InventoryFormat::Parquet => {
+                let mut file = tempfile::tempfile()?;
+                io::copy(&mut body, &mut file)?;
+                let rows = SerializedFileReader::new(file)?
+                    .into_iter()
+                    .filter_map(move |row| match row {
+                        Ok(row) => parquet_object(&row, &prefix).transpose(),
+                        Err(e) => Some(Err(e.into())),
+                    });
+                Ok(Box::new(rows))
+            }
+        }
+    }
+
+    fn read_page(&mut self) -> Result<Vec<Object>, Error> {
+        if self.manifest.is_none() {
+            self.load_manifest()?;
+        }
+        let manifest = match self.manifest {
+            Some(ref manifest) => manifest.clone(),
+            None => return Ok(Vec::new()),
+        };
+
+        let mut page = Vec::new();
+        while page.len() < self.page_size {
+            if self.rows.is_none() {
+                match self.files.pop_front() {
+                    Some(key) => self.rows = Some(self.open_file(manifest.clone(), &key)?),
+                    None => break,
+                }
+            }
+            match self.rows.as_mut().and_then(Iterator::next) {
+                Some(object) => page.push(object?),
+                None => self.rows = None,
+            }
+        }
+        Ok(page)
+    }
+}
+
+impl Iterator for InventoryIter {
+    type Item = Result<Vec<Object>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        match self.read_page() {
+            Ok(ref list) if list.is_empty() => {
+                self.finished = true;
+                None
+            }
+            result => {
+                if result.is_err() {
+                    self.finished = true;
+                }
+                Some(result)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"{
+        "sourceBucket": "bucket",
+        "destinationBucket": "arn:aws:s3:::inventory",
+        "version": "2016-11-30",
+        "creationTimestamp": "1514944800000",
+        "fileFormat": "CSV",
+        "fileSchema": "Bucket, Key, VersionId, IsLatest, IsDeleteMarker, Size, LastModifiedDate, ETag, StorageClass",
+        "files": [
+            {
+                "key": "bucket/config/data/939c6d46-85a9-4ba8-87bd-9db705a579ce.csv.gz",
+                "size": 2147483647,
+                "MD5checksum": "f11166069f1990abeb9c97ace9cdfabc"
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn manifest_parse() -> Result<(), Error> {
+        let manifest = Manifest::parse(MANIFEST.as_bytes())?;
+        assert_eq!(manifest.source_bucket, "bucket");
+        assert_eq!(manifest.destination_bucket, "inventory");
+        assert_eq!(manifest.columns[1], "Key");
+        assert_eq!(
+            manifest.files,
+            vec!["bucket/config/data/939c6d46-85a9-4ba8-87bd-9db705a579ce.csv.gz"]
+        );
+
+        assert_eq!(manifest.format, InventoryFormat::Csv);
+
+        let parquet = MANIFEST.replace("\"CSV\"", "\"Parquet\"");
+        let manifest = Manifest::parse(parquet.as_bytes())?;
+        assert_eq!(manifest.format, InventoryFormat::Parquet);
+        assert!(manifest.columns.is_empty());
+        let orc = MANIFEST.replace("\"CSV\"", "\"ORC\"");
+        assert!(Manifest::parse(orc.as_bytes()).is_err());
+        assert!(Manifest::parse(b"{}").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn inventory_rows() -> Result<(), Error> {
+        let manifest = Manifest::parse(MANIFEST.as_bytes())?;
+        let data = r#""bucket","logs/a%20b.txt","v2","true","false","10","2020-01-01T10:00:00.000Z","9d48114aa7c18f9d68aa20086dbb7756","STANDARD"
+"bucket","logs/a%20b.txt","v1","false","false","8","2019-01-01T10:00:00.000Z","8d48114aa7c18f9d68aa20086dbb7756","STANDARD"
+"bucket","logs/deleted","v3","true","true","","2020-01-01T10:00:00.000Z","",""
+"bucket","data/c","","","","5","2020-01-01T10:00:00.000Z","7d48114aa7c18f9d68aa20086dbb7756","GLACIER"
+"#;
+
+        let rows = |prefix: &str| -> Result<Vec<Object>, Error> {
+            data.lines()
+                .filter_map(|x| manifest.parse_row(x, prefix).transpose())
+                .collect()
+        };
+        let objects = rows("")?;
+        assert_eq!(
+            objects,
+            vec![
+                Object {
+                    key: Some("logs/a b.txt".to_owned()),
+                    size: Some(10),
+                    last_modified: Some("2020-01-01T10:00:00.000Z".to_owned()),
+                    e_tag: Some("\"9d48114aa7c18f9d68aa20086dbb7756\"".to_owned()),
+                    storage_class: Some("STANDARD".to_owned()),
+                    owner: None,
+                },
+                Object {
+                    key: Some("data/c".to_owned()),
+                    size: Some(5),
+                    last_modified: Some("2020-01-01T10:00:00.000Z".to_owned()),
+                    e_tag: Some("\"7d48114aa7c18f9d68aa20086dbb7756\"".to_owned()),
+                    storage_class: Some("GLACIER".to_owned()),
+                    owner: None,
+                },
+            ]
+        );
+
+        assert_eq!(rows("data/")?.len(), 1);
+
+        assert!(manifest.parse_row("\"bucket\",\"unterminated", "").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parquet_rows() -> Result<(), Error> {
+        let row = |key: &str, latest: bool| {
+            Row::new(vec![
+                ("bucket".to_owned(), Field::Str("bucket".to_owned())),
+                ("key".to_owned(), Field::Str(key.to_owned())),
+                ("is_latest".to_owned(), Field::Bool(latest)),
+                ("is_delete_marker".to_owned(), Field::Bool(false)),
+                ("size".to_owned(), Field::Long(10)),
+                (
+                    "last_modified_date".to_owned(),
+                    Field::TimestampMillis(1_577_872_800_000),
+                ),
+                ("e_tag".to_owned(), Field::Str("9d48114a".to_owned())),
+                ("storage_class".to_owned(), Field::Null),
+            ])
+        };
+
+        assert_eq!(
+            parquet_object(&row("logs/a b.txt", true), "logs/")?,
+            Some(Object {
+                key: Some("logs/a b.txt".to_owned()),
+                size: Some(10),
+                last_modified: Some("2020-01-01T10:00:00.000Z".to_owned()),
+                e_tag: Some("\"9d48114a\"".to_owned()),
+                storage_class: None,
+                owner: None,
+            })
+        );
+        assert_eq!(parquet_object(&row("logs/a b.txt", false), "")?, None);
+        assert_eq!(parquet_object(&row("data/c", true), "logs/")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn csv_line() -> Result<(), Error> {
+        assert_eq!(
+            parse_csv_line(r#""a","b ""quoted""",,c"#)?,
+            vec!["a", "b \"quoted\"", "", "c"]
+        );
+        assert!(parse_csv_line(r#""a"b"#).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use failure::Error;
+use log::debug;
+use rusoto_s3::{HeadObjectOutput, HeadObjectRequest, Object, S3Client, S3};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::arg::S3path;
+use crate::parallel::parallel_map;
+use crate::report::ErrorReport;
+use crate::retry::with_retry;
+
+/// Objects of the keys read from a file or stdin (--keys-from), one key or
+/// s3:// URL per line, instead of listing the bucket. With `head` the size,
+/// modification time, ETag and storage class come from HeadObject, with up
+/// to `concurrency` parallel requests
+pub struct KeysIter {
+    client: S3Client,
+    path: S3path,
+    lines: Box<dyn BufRead + Send>,
+    head: bool,
+    concurrency: usize,
+    page_size: usize,
+    done: bool,
+}
+
+impl KeysIter {
+    /// `-` reads the keys from stdin
+    pub fn open(
+        client: S3Client,
+        path: S3path,
+        file: &Path,
+        head: bool,
+        concurrency: usize,
+        page_size: usize,
+    ) -> Result<Self, Error> {
+        let lines: Box<dyn BufRead + Send> = if file.as_os_str() == "-" {
+            Box::new(BufReader::new(io::stdin()))
+        } else {
+            Box::new(BufReader::new(File::open(file)?))
+        };
+
+        Ok(KeysIter {
+            client,
+            path,
+            lines,
+            head,
+            concurrency,
+            page_size: page_size.max(1),
+            done: false,
+        })
+    }
+
+    fn read_page(&mut self) -> Result<Vec<Object>, Error> {
+        let mut keys = Vec::new();
+        let mut line = String::new();
+
+        while keys.len() < self.page_size {
+            line.clear();
+            if self.lines.read_line(&mut line)? == 0 {
+                self.done = true;
+                break;
+            }
+            if let Some(key) = parse_key(&self.path, &line) {
+                keys.push(key);
+            }
+        }
+
+        if !self.head {
+            return Ok(keys
+                .into_iter()
+                .map(|key| Object {
+                    key: Some(key),
+                    ..Default::default()
+                })
+                .collect());
+        }
+
+        let (client, bucket) = (&self.client, &self.path.bucket);
+        Ok(parallel_map(&keys, self.concurrency, |key| {
+            head_object(client, bucket, key)
+        })
+        .into_iter()
+        .flatten()
+        .collect())
+    }
+}
+
+/// Missing keys are reported and skipped
+fn head_object(client: &S3Client, bucket: &str, key: &str) -> Option<Object> {
+    let request = HeadObjectRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        ..Default::default()
+    };
+
+    debug!("HeadObject s3://{}/{}", request.bucket, request.key);
+    match with_retry("HeadObject", || client.head_object(request.clone())) {
+        Ok(head) => Some(head_to_object(request.key, head)),
+        Err(e) => {
+            ErrorReport::from_rusoto("HeadObject", &request.bucket, Some(&request.key), &e).emit();
+            None
+        }
+    }
+}
+
+impl Iterator for KeysIter {
+    type Item = Result<Vec<Object>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_page() {
+            Ok(page) if page.is_empty() && self.done => None,
+            result => {
+                if result.is_err() {
+                    self.done = true;
+                }
+                Some(result)
+            }
+        }
+    }
+}
+
+/// Key of a line: the key itself or an s3:// URL of the bucket. Empty lines,
+/// URLs of other buckets and keys outside of the prefix are skipped
+fn parse_key(path: &S3path, line: &str) -> Option<String> {
+    let line = line.trim_end_matches(&['\r', '\n'][..]);
+    if line.is_empty() {
+        return None;
+    }
+
+    let key = match line.strip_prefix("s3://") {
+        Some(url) => match url.split_once('/') {
+            Some((bucket, key)) if bucket == path.bucket => key,
+            _ => return None,
+        },
+        None => line,
+    };
+
+    if key.is_empty() || !key.starts_with(path.prefix.as_deref().unwrap_or_default()) {
+        return None;
+    }
+    Some(key.to_owned())
+}
+
+/// HeadObject returns the time in the HTTP format, the list responses in ISO 8601
+fn head_to_object(key: String, head: HeadObjectOutput) -> Object {
+    let last_modified = head.last_modified.map(|x| {
+        DateTime::parse_from_rfc2822(&x)
+            .map(|time| {
+                time.with_timezone(&Utc)
+                    .to_rfc3339_opts(SecondsFormat::Millis, true)
+            })
+            .unwrap_or(x)
+    });
+
+    Object {
+        key: Some(key),
+        size: head.content_length,
+        last_modified,
+        e_tag: head.e_tag,
+        // HeadObject omits the default class
+        storage_class: Some(head.storage_class.unwrap_or_else(|| "STANDARD".to_owned())),
+        owner: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_from_lines() {
+        let path = S3path {
+            bucket: "bucket".to_owned(),
+            prefix: Some("logs/".to_owned()),
+        };
+
+        assert_eq!(
+            parse_key(&path, "logs/a.gz\n").as_deref(),
+            Some("logs/a.gz")
+        );
+        assert_eq!(
+            parse_key(&path, "s3://bucket/logs/b.gz\r\n").as_deref(),
+            Some("logs/b.gz")
+        );
+        assert_eq!(parse_key(&path, "s3://other/logs/c.gz\n"), None);
+        assert_eq!(parse_key(&path, "data/d.gz\n"), None);
+        assert_eq!(parse_key(&path, "\n"), None);
+
+        let object = head_to_object(
+            "logs/a.gz".to_owned(),
+            HeadObjectOutput {
+                content_length: Some(10),
+                last_modified: Some("Wed, 19 Jul 2017 19:04:17 GMT".to_owned()),
+                e_tag: Some("\"etag\"".to_owned()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            object,
+            Object {
+                key: Some("logs/a.gz".to_owned()),
+                size: Some(10),
+                last_modified: Some("2017-07-19T19:04:17.000Z".to_owned()),
+                e_tag: Some("\"etag\"".to_owned()),
+                storage_class: Some("STANDARD".to_owned()),
+                owner: None,
+            }
+        );
+    }
+}
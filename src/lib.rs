@@ -1,6 +1,125 @@
+//! Walk an Amazon S3 hierarchy like find: list the keys under a path, match
+//! them with filters and run a command on the matched keys.
+//!
+//! The `s3find` binary is a thin CLI over this crate. The options are parsed
+//! into [`arg::FindOpt`] and [`command::Find::new`] builds a [`command::Find`]
+//! of them, holding the client, the [`arg::S3path`]s, the [`command::FilterList`] and the command
+//! ([`function::RunCommand`]). [`run::walk`] lists, filters and runs the
+//! command path by path, with the [`command::FindStat`] summary of the keys.
+//!
+//! ```no_run
+//! use s3find::arg::FindOpt;
+//! use s3find::command::Find;
+//! use s3find::run::{summarize, walk};
+//! use structopt::StructOpt;
+//!
+//! let opts = FindOpt::from_iter(&[
+//!     "s3find",
+//!     "s3://example-bucket/logs/",
+//!     "--name",
+//!     "*.gz",
+//!     "--summarize",
+//!     "ls",
+//! ]);
+//! let mut find = Find::new(opts).unwrap();
+//! let result = walk(&mut find);
+//! summarize(&find, &result).unwrap();
+//! ```
+//!
+//! Domain-specific rules are added to the filters of the options with
+//! [`command::FilterList::push`] (any [`filter::Filter`], or a closure in
+//! [`filter::FilterFn`]), and rules on tags or HeadObject data with
+//! [`command::Find::add_enriched_filter`] ([`filter::EnrichedFilter`]).
+//! [`filter::Not`] inverts a filter, as the `--not-*` options do.
+//!
+//! The commands write their results to the [`output::OutputSink`] of
+//! [`command::Find::output`]: stdout by default, JSON documents with
+//! [`output::JsonSink`] or collected with [`output::MemorySink`]. The commands
+//! reporting on all the matched keys, like audit-unencrypted, write their
+//! report with [`function::RunCommand::finish`], called once the walk is done.
+//!
+//! Progress and telemetry hook into the walk with [`command::Find::subscribe`]:
+//! a [`events::FindEvents`] gets the listed pages, the matched objects, the
+//! buffers processed by the command and the reported errors.
+//!
+//! Failures are [`error::S3FindError`]s, in [`run::Walk::errors`] for the
+//! paths that could not be listed. The variants tell credentials, throttling,
+//! access denied, not found and IO errors apart, and their
+//! [`report::ErrorReport`] keeps the AWS error code and request id.
+//!
+//! The S3 operations of the commands on single keys (delete, copy, download,
+//! tags and ACL) are futures in [`ops`], for tokio services. They do not print,
+//! fail with an [`error::S3FindError`] and return per-key results for batches.
+//!
+//! [`watch::watch`] walks the paths again and again, running the command only
+//! on the keys new or changed since the previous pass, and [`sqs::listen`]
+//! runs it on the objects of the S3 event notifications of an SQS queue.
+//! [`daemon::daemon`] walks them at the times of a cron [`arg::Schedule`].
+//!
+//! [`cancel::cancel`] stops a walk from another thread, as Ctrl-C does for the
+//! CLI: no new page is listed and the running batch of keys finishes.
+//!
+//! Other sources of objects can be searched with [`run::list_filter_execute`],
+//! which takes any iterator of pages.
+//!
+//! [`command::Find::iter_matches`] returns the matched objects instead of
+//! running the command, listing a page only when the objects of the previous
+//! one are consumed, so that the library user drives the processing loop.
+
+pub mod addressing;
 pub mod arg;
+pub mod audit;
+pub mod benchmark;
+pub mod buckets;
+pub mod cache;
+pub mod cancel;
+pub mod checksum;
 pub mod command;
+pub mod compliance;
+pub mod config;
+pub mod cost;
+pub mod daemon;
+pub mod duplicates;
+pub mod enrich;
 pub mod error;
+pub mod estimate;
+pub mod events;
+pub mod export;
 pub mod filter;
 pub mod function;
+pub mod hygiene;
+pub mod imds;
+pub mod inventory;
+pub mod keys;
+pub mod lifecycle;
+pub mod listing;
+pub mod logger;
+pub mod man;
+pub mod memory;
+pub mod metrics;
+pub mod mfa;
+pub mod notify;
+pub mod ops;
+pub mod output;
+pub mod pagesize;
+pub mod parallel;
+pub mod pricing;
+pub mod progress;
+pub mod quantile;
+pub mod ratelimit;
+pub mod reconcile;
+pub mod replicas;
+pub mod report;
+pub mod resume;
+pub mod retry;
 pub mod run;
+pub mod snapshot;
+pub mod sns;
+pub mod sort;
+pub mod sqs;
+pub mod sso;
+pub mod state;
+pub mod tiering;
+pub mod tls;
+pub mod transform;
+pub mod watch;
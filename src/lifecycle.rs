@@ -0,0 +1,361 @@
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use failure::Error;
+use log::debug;
+use rusoto_core::Region;
+use rusoto_s3::{GetBucketLifecycleConfigurationRequest, LifecycleRule, Object, S3Client, Tag, S3};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::arg::{EnrichField, S3path, SimulateLifecycle};
+use crate::buckets::not_configured;
+use crate::enrich::EnrichmentCache;
+use crate::function::RunCommand;
+use crate::output::OutputSink;
+use crate::parallel::parallel_map;
+use crate::report::ErrorReport;
+use crate::retry::with_retry;
+
+/// A transition or the expiration of a key by a lifecycle rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct LifecycleAction {
+    pub rule: String,
+    /// "expire" or "transition:<storage class>"
+    pub action: String,
+    pub date: DateTime<Utc>,
+}
+
+/// The prefix and the tags of the filter of a rule
+fn rule_filter(rule: &LifecycleRule) -> (&str, Vec<&Tag>) {
+    let filter = match rule.filter {
+        Some(ref filter) => filter,
+        None => return ("", Vec::new()),
+    };
+    match filter.and {
+        Some(ref and) => (
+            and.prefix.as_deref().unwrap_or_default(),
+            and.tags.iter().flatten().collect(),
+        ),
+        None => (
+            filter.prefix.as_deref().unwrap_or_default(),
+            filter.tag.iter().collect(),
+        ),
+    }
+}
+
+fn filters_tags(rule: &LifecycleRule) -> bool {
+    !rule_filter(rule).1.is_empty()
+}
+
+/// The enabled rule applies to the key, None when the rule filters on tags
+/// and the tags of the key are not known
+pub fn rule_matches(rule: &LifecycleRule, key: &str, tags: Option<&[Tag]>) -> Option<bool> {
+    let (prefix, required) = rule_filter(rule);
+    if rule.status != "Enabled" || !key.starts_with(prefix) {
+        return Some(false);
+    }
+    if required.is_empty() {
+        return Some(true);
+    }
+    let tags = tags?;
+    Some(required.iter().all(|x| tags.contains(x)))
+}
+
+/// The actions of a rule on a key modified at `modified`. S3 runs the
+/// actions after `days`, at the next midnight UTC
+pub fn rule_actions(rule: &LifecycleRule, modified: DateTime<Utc>) -> Vec<LifecycleAction> {
+    let id = rule.id.clone().unwrap_or_else(|| "(no id)".to_owned());
+    let date = |days: Option<i64>, date: Option<&str>| -> Option<DateTime<Utc>> {
+        match (days, date) {
+            (Some(days), _) => {
+                let due = modified + Duration::days(days);
+                let midnight = due.date_naive().and_hms_opt(0, 0, 0)?.and_utc();
+                Some(if midnight < due {
+                    midnight + Duration::days(1)
+                } else {
+                    midnight
+                })
+            }
+            (None, Some(date)) => DateTime::parse_from_rfc3339(date)
+                .ok()
+                .map(|x| x.with_timezone(&Utc)),
+            (None, None) => None,
+        }
+    };
+
+    let mut actions: Vec<LifecycleAction> = rule
+        .transitions
+        .iter()
+        .flatten()
+        .filter_map(|x| {
+            Some(LifecycleAction {
+                rule: id.clone(),
+                action: format!("transition:{}", x.storage_class.as_deref()?),
+                date: date(x.days, x.date.as_deref())?,
+            })
+        })
+        .collect();
+    if let Some(ref expiration) = rule.expiration {
+        if let Some(date) = date(expiration.days, expiration.date.as_deref()) {
+            actions.push(LifecycleAction {
+                rule: id,
+                action: "expire".to_owned(),
+                date,
+            });
+        }
+    }
+    actions.sort_by_key(|x| x.date);
+    actions
+}
+
+/// Command of simulate-lifecycle, the rules of a bucket are read once and
+/// the totals per rule and action are printed at the end of the run
+pub struct LifecycleSimulator {
+    options: SimulateLifecycle,
+    buckets: Mutex<HashMap<String, Arc<Vec<LifecycleRule>>>>,
+    totals: Mutex<BTreeMap<(String, String), (u64, i64)>>,
+}
+
+impl LifecycleSimulator {
+    pub fn new(options: SimulateLifecycle) -> Self {
+        LifecycleSimulator {
+            options,
+            buckets: Mutex::new(HashMap::new()),
+            totals: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn bucket_rules(&self, client: &S3Client, bucket: &str) -> Arc<Vec<LifecycleRule>> {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets
+            .entry(bucket.to_owned())
+            .or_insert_with(|| Arc::new(read_rules(client, bucket)))
+            .clone()
+    }
+
+    /// The actions of the rules on a key
+    fn simulate(
+        &self,
+        client: &S3Client,
+        bucket: &str,
+        rules: &[LifecycleRule],
+        object: &Object,
+        enrichment: &EnrichmentCache,
+    ) -> Vec<LifecycleAction> {
+        let key = object.key.as_deref().unwrap_or_default();
+        let modified = match object
+            .last_modified
+            .as_deref()
+            .and_then(|x| DateTime::parse_from_rfc3339(x).ok())
+        {
+            Some(x) => x.with_timezone(&Utc),
+            None => return Vec::new(),
+        };
+        let tags = if rules.iter().any(filters_tags) {
+            enrichment
+                .fetch(client, bucket, key, &[EnrichField::Tags])
+                .tags
+        } else {
+            None
+        };
+
+        rules
+            .iter()
+            .filter(|x| rule_matches(x, key, tags.as_deref()) == Some(true))
+            .flat_map(|x| rule_actions(x, modified))
+            // a key is not moved to its own storage class
+            .filter(|x| x.action.strip_prefix("transition:") != object.storage_class.as_deref())
+            .collect()
+    }
+
+    fn add(&self, action: &LifecycleAction, size: i64) {
+        let mut totals = self.totals.lock().unwrap();
+        let total = totals
+            .entry((action.rule.clone(), action.action.clone()))
+            .or_default();
+        total.0 += 1;
+        total.1 += size;
+    }
+
+    fn line(&self, url: &str, size: i64, action: &LifecycleAction) -> String {
+        let date = action.date.to_rfc3339_opts(SecondsFormat::Secs, true);
+        if self.options.json {
+            json!({
+                "key": url,
+                "size": size,
+                "rule": action.rule,
+                "action": action.action,
+                "date": date,
+            })
+            .to_string()
+        } else {
+            format!(
+                "{}\t{}\t{}\t{}\t{}",
+                date, action.rule, action.action, url, size
+            )
+        }
+    }
+
+    /// The lines of the totals, they start again
+    fn totals(&self) -> Vec<String> {
+        let totals = std::mem::take(&mut *self.totals.lock().unwrap());
+        totals
+            .into_iter()
+            .map(|((rule, action), (count, bytes))| {
+                if self.options.json {
+                    json!({
+                        "rule": rule,
+                        "action": action,
+                        "count": count,
+                        "bytes": bytes,
+                    })
+                    .to_string()
+                } else {
+                    format!("total\t{}\t{}\t{}\t{}", rule, action, count, bytes)
+                }
+            })
+            .collect()
+    }
+}
+
+fn read_rules(client: &S3Client, bucket: &str) -> Vec<LifecycleRule> {
+    let request = GetBucketLifecycleConfigurationRequest {
+        bucket: bucket.to_owned(),
+    };
+    debug!("GetBucketLifecycleConfiguration {}", bucket);
+    match with_retry("GetBucketLifecycleConfiguration", || {
+        client.get_bucket_lifecycle_configuration(request.clone())
+    }) {
+        Ok(x) => x.rules.unwrap_or_default(),
+        Err(ref e) if not_configured(e) => Vec::new(),
+        Err(e) => {
+            ErrorReport::from_rusoto("GetBucketLifecycleConfiguration", bucket, None, &e).emit();
+            Vec::new()
+        }
+    }
+}
+
+impl RunCommand for LifecycleSimulator {
+    fn execute(
+        &self,
+        client: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let rules = self.bucket_rules(client, &path.bucket);
+        if rules.is_empty() {
+            return Ok(());
+        }
+
+        let actions = parallel_map(list, self.options.concurrency, |x| {
+            self.simulate(client, &path.bucket, &rules, x, enrichment)
+        });
+        for (object, actions) in list.iter().zip(actions) {
+            let size = object.size.unwrap_or_default();
+            let url = format!(
+                "s3://{}/{}",
+                path.bucket,
+                object.key.as_deref().unwrap_or_default()
+            );
+            for action in actions {
+                self.add(&action, size);
+                if !self.options.totals {
+                    output.line(&self.line(&url, size, &action));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self, output: &dyn OutputSink) -> Result<(), Error> {
+        for line in self.totals() {
+            output.line(&line);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_s3::{
+        LifecycleExpiration, LifecycleRuleAndOperator, LifecycleRuleFilter, Transition,
+    };
+
+    #[test]
+    fn lifecycle_actions() {
+        let tag = Tag {
+            key: "tier".to_owned(),
+            value: "cold".to_owned(),
+        };
+        let rule = LifecycleRule {
+            id: Some("archive-logs".to_owned()),
+            status: "Enabled".to_owned(),
+            filter: Some(LifecycleRuleFilter {
+                and: Some(LifecycleRuleAndOperator {
+                    prefix: Some("logs/".to_owned()),
+                    tags: Some(vec![tag.clone()]),
+                }),
+                ..Default::default()
+            }),
+            transitions: Some(vec![Transition {
+                days: Some(30),
+                storage_class: Some("GLACIER".to_owned()),
+                ..Default::default()
+            }]),
+            expiration: Some(LifecycleExpiration {
+                days: Some(365),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(rule_matches(&rule, "logs/a.gz", None), None);
+        assert_eq!(
+            rule_matches(&rule, "logs/a.gz", Some(std::slice::from_ref(&tag))),
+            Some(true)
+        );
+        assert_eq!(rule_matches(&rule, "logs/a.gz", Some(&[])), Some(false));
+        assert_eq!(rule_matches(&rule, "data/a.gz", Some(&[tag])), Some(false));
+        let disabled = LifecycleRule {
+            status: "Disabled".to_owned(),
+            filter: None,
+            ..rule.clone()
+        };
+        assert_eq!(rule_matches(&disabled, "logs/a.gz", None), Some(false));
+
+        let modified = "2020-01-01T10:00:00Z".parse().unwrap();
+        let actions = rule_actions(&rule, modified);
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions[0].action, "transition:GLACIER");
+        assert_eq!(
+            actions[0].date,
+            "2020-02-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(actions[1].action, "expire");
+        assert_eq!(
+            actions[1].date,
+            "2021-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        let simulator = LifecycleSimulator::new(SimulateLifecycle {
+            concurrency: 1,
+            totals: false,
+            json: false,
+        });
+        assert_eq!(
+            simulator.line("s3://bucket/logs/a.gz", 10, &actions[0]),
+            "2020-02-01T00:00:00Z\tarchive-logs\ttransition:GLACIER\ts3://bucket/logs/a.gz\t10"
+        );
+        simulator.add(&actions[1], 10);
+        simulator.add(&actions[1], 5);
+        assert_eq!(
+            simulator.totals(),
+            vec!["total\tarchive-logs\texpire\t2\t15"]
+        );
+        assert!(simulator.totals().is_empty());
+    }
+}
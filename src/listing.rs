@@ -0,0 +1,668 @@
+use failure::Error;
+use log::{debug, warn};
+use rusoto_core::RusotoError;
+use rusoto_s3::{
+    ListObjectsOutput, ListObjectsRequest, ListObjectsV2Output, ListObjectsV2Request, Object,
+    S3Client, S3,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::arg::{ListApi, S3path};
+use crate::command::{FindIter, Pages};
+use crate::function::percent_decode;
+use crate::pagesize::PageSizeTuner;
+use crate::report::xml_field;
+use crate::resume::{ListingState, Partition, Resumable};
+use crate::retry::with_retry;
+
+const DELIMITER: &str = "/";
+
+/// Storage class of the common prefixes listed with --delimiter, passed
+/// along the keys as objects of the prefix
+pub const PREFIX_CLASS: &str = "PREFIX";
+
+static LIST_V1: AtomicBool = AtomicBool::new(false);
+static LIST_FALLBACK: AtomicBool = AtomicBool::new(true);
+
+/// Select the list API, with auto ListObjectsV2 until the endpoint answers NotImplemented
+pub fn set_list_api(api: ListApi) {
+    LIST_V1.store(api == ListApi::V1, Ordering::Relaxed);
+    LIST_FALLBACK.store(api == ListApi::Auto, Ordering::Relaxed);
+}
+
+/// A page of the listing with ListObjectsV2, or with the legacy ListObjects
+/// when selected or after the fallback. The markers of ListObjects are passed
+/// as the continuation tokens, so the callers page both APIs the same way
+pub fn list_objects(
+    client: &S3Client,
+    request: &ListObjectsV2Request,
+) -> Result<ListObjectsV2Output, Error> {
+    if !LIST_V1.load(Ordering::Relaxed) {
+        match with_retry("ListObjectsV2", || client.list_objects_v2(request.clone())) {
+            Err(ref e) if LIST_FALLBACK.load(Ordering::Relaxed) && not_implemented(e) => {
+                warn!("ListObjectsV2 is not implemented by the endpoint, using ListObjects");
+                LIST_V1.store(true, Ordering::Relaxed);
+            }
+            result => return result.map_err(Error::from),
+        }
+    }
+
+    let request = ListObjectsRequest {
+        bucket: request.bucket.clone(),
+        delimiter: request.delimiter.clone(),
+        encoding_type: request.encoding_type.clone(),
+        marker: request
+            .continuation_token
+            .clone()
+            .or_else(|| request.start_after.clone()),
+        max_keys: request.max_keys,
+        prefix: request.prefix.clone(),
+        request_payer: request.request_payer.clone(),
+    };
+    let output = with_retry("ListObjects", || client.list_objects(request.clone()))?;
+    v2_output(output)
+}
+
+pub fn prefix_object(prefix: String) -> Object {
+    Object {
+        key: Some(prefix),
+        storage_class: Some(PREFIX_CLASS.to_owned()),
+        ..Default::default()
+    }
+}
+
+pub fn is_prefix(object: &Object) -> bool {
+    object.storage_class.as_deref() == Some(PREFIX_CLASS)
+}
+
+fn not_implemented<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::Unknown(response) => {
+            response.status.as_u16() == 501
+                || xml_field(response.body_as_str(), "Code").as_deref() == Some("NotImplemented")
+        }
+        _ => false,
+    }
+}
+
+/// NextMarker is returned only with a delimiter, otherwise the next page
+/// starts after the last key of the page
+fn v2_output(output: ListObjectsOutput) -> Result<ListObjectsV2Output, Error> {
+    let next_marker = if output.is_truncated.unwrap_or_default() {
+        let last_key = output
+            .contents
+            .iter()
+            .flatten()
+            .filter_map(|x| x.key.as_ref())
+            .chain(
+                output
+                    .common_prefixes
+                    .iter()
+                    .flatten()
+                    .filter_map(|x| x.prefix.as_ref()),
+            )
+            .max()
+            .cloned();
+        match output.next_marker.or(last_key) {
+            // the marker of the request is not encoded
+            Some(marker) if output.encoding_type.as_deref() == Some("url") => {
+                Some(percent_decode(&marker)?)
+            }
+            marker => marker,
+        }
+    } else {
+        None
+    };
+
+    let key_count = output.contents.as_ref().map_or(0, Vec::len)
+        + output.common_prefixes.as_ref().map_or(0, Vec::len);
+    Ok(ListObjectsV2Output {
+        common_prefixes: output.common_prefixes,
+        contents: output.contents,
+        continuation_token: output.marker,
+        delimiter: output.delimiter,
+        encoding_type: output.encoding_type,
+        is_truncated: output.is_truncated,
+        key_count: Some(key_count as i64),
+        max_keys: output.max_keys,
+        name: output.name,
+        next_continuation_token: next_marker,
+        prefix: output.prefix,
+        start_after: None,
+    })
+}
+
+/// A page of a prefix and the token of the next page of the prefix
+type PartitionPage = (String, Result<Vec<Object>, Error>, Option<String>);
+
+/// Listing split by the common prefixes under the search path, with every
+/// prefix paginated by one of the worker threads. Pages arrive in no
+/// particular order.
+pub struct PartitionedIter {
+    client: S3Client,
+    path: S3path,
+    page_size: i64,
+    tuner: Option<Arc<PageSizeTuner>>,
+    encoding_type: Option<String>,
+    fetch_owner: bool,
+    concurrency: usize,
+    // prefixes not listed to the end yet, with the token of their next page
+    partitions: Option<BTreeMap<String, Option<String>>>,
+    receiver: Option<Receiver<PartitionPage>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl PartitionedIter {
+    pub fn new(
+        client: S3Client,
+        path: S3path,
+        page_size: i64,
+        tuner: Option<Arc<PageSizeTuner>>,
+        encoding_type: Option<String>,
+        concurrency: usize,
+    ) -> Self {
+        PartitionedIter {
+            client,
+            path,
+            page_size,
+            tuner,
+            encoding_type,
+            fetch_owner: false,
+            concurrency,
+            partitions: None,
+            receiver: None,
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Continue a listing from the saved partitions, without the discovery
+    pub fn resume(
+        client: S3Client,
+        path: S3path,
+        page_size: i64,
+        tuner: Option<Arc<PageSizeTuner>>,
+        encoding_type: Option<String>,
+        concurrency: usize,
+        partitions: Vec<Partition>,
+    ) -> Self {
+        let mut iter =
+            PartitionedIter::new(client, path, page_size, tuner, encoding_type, concurrency);
+        iter.partitions = Some(
+            partitions
+                .into_iter()
+                .map(|x| (x.prefix, x.token))
+                .collect(),
+        );
+        iter
+    }
+
+    /// Ask for the owner of the keys in the list requests
+    pub fn fetch_owner(mut self, fetch_owner: bool) -> Self {
+        self.fetch_owner = fetch_owner;
+        self
+    }
+
+    /// List the search path with a delimiter: objects directly under it and the common prefixes
+    fn discover(&self) -> Result<(Vec<Object>, Vec<String>), Error> {
+        let url_encoded = self.encoding_type.as_deref() == Some("url");
+        let mut objects = Vec::new();
+        let mut prefixes = Vec::new();
+        let mut token = None;
+
+        loop {
+            let request = ListObjectsV2Request {
+                bucket: self.path.bucket.clone(),
+                continuation_token: token,
+                delimiter: Some(DELIMITER.to_owned()),
+                encoding_type: self.encoding_type.clone(),
+                fetch_owner: Some(true).filter(|_| self.fetch_owner),
+                max_keys: Some(self.page_size),
+                prefix: self.path.prefix.clone(),
+                ..Default::default()
+            };
+
+            debug!(
+                "ListObjectsV2 s3://{}/{} delimiter: {}",
+                request.bucket,
+                request.prefix.as_deref().unwrap_or_default(),
+                DELIMITER
+            );
+            let output = list_objects(&self.client, &request)?;
+
+            for mut object in output.contents.unwrap_or_default() {
+                if url_encoded {
+                    object.key = object.key.as_deref().map(percent_decode).transpose()?;
+                }
+                objects.push(object);
+            }
+
+            for prefix in output.common_prefixes.unwrap_or_default() {
+                if let Some(prefix) = prefix.prefix {
+                    let prefix = if url_encoded {
+                        percent_decode(&prefix)?
+                    } else {
+                        prefix
+                    };
+                    prefixes.push(prefix);
+                }
+            }
+
+            token = output.next_continuation_token;
+            if token.is_none() {
+                break;
+            }
+        }
+
+        debug!("Listing partitioned into {} prefixes", prefixes.len());
+        Ok((objects, prefixes))
+    }
+
+    fn spawn_workers(&self, partitions: Vec<Partition>) -> Receiver<PartitionPage> {
+        let (sender, receiver) = sync_channel(self.concurrency * 2);
+        let queue = Arc::new(Mutex::new(VecDeque::from(partitions)));
+
+        for _ in 0..self.concurrency {
+            let sender = sender.clone();
+            let queue = queue.clone();
+            let client = self.client.clone();
+            let path = self.path.clone();
+            let page_size = self.page_size;
+            let tuner = self.tuner.clone();
+            let encoding_type = self.encoding_type.clone();
+            let fetch_owner = self.fetch_owner;
+            let stopped = self.stopped.clone();
+
+            thread::spawn(move || loop {
+                if stopped.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                let partition = match queue.lock().unwrap().pop_front() {
+                    Some(partition) => partition,
+                    None => return,
+                };
+
+                let mut iter = FindIter {
+                    client: client.clone(),
+                    path: S3path {
+                        prefix: Some(partition.prefix.clone()),
+                        ..path.clone()
+                    },
+                    token: partition.token,
+                    page_size,
+                    tuner: tuner.clone(),
+                    encoding_type: encoding_type.clone(),
+                    fetch_owner,
+                    delimiter: None,
+                    initial: true,
+                };
+
+                while let Some(page) = iter.next() {
+                    let page = (partition.prefix.clone(), page, iter.token.clone());
+                    // the receiver is gone once the consumer has stopped, e.g. on --limit
+                    if sender.send(page).is_err() || stopped.load(Ordering::Relaxed) {
+                        return;
+                    }
+                }
+            });
+        }
+
+        receiver
+    }
+
+    fn state_partitions(&self) -> Vec<Partition> {
+        self.partitions
+            .iter()
+            .flatten()
+            .map(|(prefix, token)| Partition {
+                prefix: prefix.clone(),
+                token: token.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Workers stop before fetching their next page once the consumer is done
+impl Drop for PartitionedIter {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Iterator for PartitionedIter {
+    type Item = Result<Vec<Object>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(ref receiver) = self.receiver {
+            let (prefix, page, token) = receiver.recv().ok()?;
+            if let (Ok(_), Some(ref mut partitions)) = (&page, &mut self.partitions) {
+                match token {
+                    Some(token) => partitions.insert(prefix, Some(token)),
+                    None => partitions.remove(&prefix),
+                };
+            }
+            return Some(page);
+        }
+
+        let objects = match self.partitions {
+            Some(_) => Vec::new(),
+            None => match self.discover() {
+                Ok((objects, prefixes)) => {
+                    self.partitions = Some(prefixes.into_iter().map(|x| (x, None)).collect());
+                    objects
+                }
+                Err(e) => return Some(Err(e)),
+            },
+        };
+
+        let receiver = self.spawn_workers(self.state_partitions());
+        self.receiver = Some(receiver);
+
+        if objects.is_empty() {
+            self.next()
+        } else {
+            Some(Ok(objects))
+        }
+    }
+}
+
+impl Resumable for PartitionedIter {
+    fn state(&self) -> ListingState {
+        match self.partitions {
+            Some(ref partitions) if partitions.is_empty() && self.receiver.is_some() => {
+                ListingState::Complete
+            }
+            _ => ListingState::Partitioned {
+                partitions: self.state_partitions(),
+            },
+        }
+    }
+}
+
+/// Listing descending the common prefixes level by level with the delimiter
+/// (--max-depth, --min-depth): the prefixes deeper than `max_depth` are not
+/// listed at all, the keys above `min_depth` are skipped. Without `max_depth`
+/// a prefix at `min_depth` is listed without the delimiter.
+/// The keys directly under the path are at depth 1
+pub struct DepthIter {
+    template: FindIter,
+    min_depth: usize,
+    max_depth: Option<usize>,
+    // prefixes to list with the depth of their keys, the last one first
+    pending: Vec<(String, usize)>,
+    current: Option<(FindIter, usize)>,
+    children: Vec<(String, usize)>,
+}
+
+impl DepthIter {
+    /// The prefixes are listed with the client and the options of `template`
+    pub fn new(template: FindIter, min_depth: Option<usize>, max_depth: Option<usize>) -> Self {
+        let root = template.path.prefix.clone().unwrap_or_default();
+        DepthIter {
+            template,
+            min_depth: min_depth.unwrap_or(1),
+            max_depth,
+            pending: vec![(root, 1)],
+            current: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Everything under the prefix is deep enough when there is no maximum
+    fn flat(&self, depth: usize) -> bool {
+        self.max_depth.is_none() && depth >= self.min_depth
+    }
+
+    fn list_prefix(&self, prefix: String, depth: usize) -> FindIter {
+        FindIter {
+            path: S3path {
+                prefix: Some(prefix),
+                ..self.template.path.clone()
+            },
+            delimiter: if self.flat(depth) {
+                None
+            } else {
+                Some(DELIMITER.to_owned())
+            },
+            ..self.template.clone()
+        }
+    }
+}
+
+/// Depth of a key below the path, 1 for the keys directly under it
+pub fn key_depth(path: &S3path, key: &str) -> usize {
+    path.relative_key(key).matches(DELIMITER).count() + 1
+}
+
+/// The prefix grouping a key: the prefix of the path and up to `depth`
+/// levels below it
+pub fn prefix_group(path: &S3path, key: &str, depth: usize) -> String {
+    let relative = path.relative_key(key);
+    let base = &key[..key.len() - relative.len()];
+    let levels: usize = relative
+        .split_inclusive('/')
+        .filter(|x| x.ends_with('/'))
+        .take(depth)
+        .map(str::len)
+        .sum();
+    format!("{}{}", base, &relative[..levels])
+}
+
+impl Iterator for DepthIter {
+    type Item = Result<Vec<Object>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (page, depth) = match self.current {
+                Some((ref mut iter, depth)) => (iter.next(), depth),
+                None => {
+                    let (prefix, depth) = self.pending.pop()?;
+                    self.current = Some((self.list_prefix(prefix, depth), depth));
+                    continue;
+                }
+            };
+
+            let page = match page {
+                Some(Ok(page)) => page,
+                Some(Err(e)) => {
+                    self.pending.clear();
+                    self.current = None;
+                    return Some(Err(e));
+                }
+                None => {
+                    // the sub-prefixes are listed in the listing order
+                    self.pending.extend(self.children.drain(..).rev());
+                    self.current = None;
+                    continue;
+                }
+            };
+
+            if self.flat(depth) {
+                return Some(Ok(page));
+            }
+
+            let path = &self.template.path;
+            let mut keys = Vec::with_capacity(page.len());
+            for object in page {
+                if !is_prefix(&object) {
+                    if depth >= self.min_depth {
+                        keys.push(object);
+                    }
+                    continue;
+                }
+                // with the trailing delimiter, the depth of the keys under the prefix
+                let prefix = object.key.unwrap_or_default();
+                let depth = key_depth(path, &prefix);
+                if self.max_depth.is_none_or(|max| depth <= max) {
+                    self.children.push((prefix, depth));
+                }
+            }
+            return Some(Ok(keys));
+        }
+    }
+}
+
+/// Pages listed ahead by a background thread while the consumer filters them
+/// and runs the command, up to `depth` pages wait in the queue
+pub struct Prefetch {
+    receiver: Receiver<Result<Vec<Object>, Error>>,
+}
+
+impl Prefetch {
+    pub fn new(pages: Pages, depth: usize) -> Self {
+        let (sender, receiver) = sync_channel(depth);
+        thread::spawn(move || {
+            for page in pages {
+                // the receiver is gone once the consumer has stopped, e.g. on --limit
+                if sender.send(page).is_err() {
+                    return;
+                }
+            }
+        });
+        Prefetch { receiver }
+    }
+}
+
+impl Iterator for Prefetch {
+    type Item = Result<Vec<Object>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn object(key: &str) -> Object {
+        Object {
+            key: Some(key.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn list_objects_v1_markers() {
+        let output = ListObjectsOutput {
+            contents: Some(vec![object("logs/a"), object("logs/b%20c")]),
+            encoding_type: Some("url".to_owned()),
+            is_truncated: Some(true),
+            ..Default::default()
+        };
+        let output = v2_output(output).unwrap();
+        assert_eq!(output.next_continuation_token.as_deref(), Some("logs/b c"));
+        assert_eq!(output.key_count, Some(2));
+
+        let output = ListObjectsOutput {
+            common_prefixes: Some(vec![rusoto_s3::CommonPrefix {
+                prefix: Some("logs/2024/".to_owned()),
+            }]),
+            next_marker: Some("logs/2024/".to_owned()),
+            is_truncated: Some(true),
+            ..Default::default()
+        };
+        let output = v2_output(output).unwrap();
+        assert_eq!(
+            output.next_continuation_token.as_deref(),
+            Some("logs/2024/")
+        );
+
+        let output = ListObjectsOutput {
+            contents: Some(vec![object("logs/a")]),
+            is_truncated: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(v2_output(output).unwrap().next_continuation_token, None);
+    }
+
+    #[test]
+    fn depth_of_keys() {
+        let path = S3path {
+            bucket: "bucket".to_owned(),
+            prefix: Some("logs".to_owned()),
+        };
+        assert_eq!(key_depth(&path, "logs/a.gz"), 1);
+        assert_eq!(key_depth(&path, "logs.txt"), 1);
+        assert_eq!(key_depth(&path, "logs/2024/01/a.gz"), 3);
+
+        let root = S3path {
+            bucket: "bucket".to_owned(),
+            prefix: None,
+        };
+        assert_eq!(key_depth(&root, "a.gz"), 1);
+        assert_eq!(key_depth(&root, "logs/"), 2);
+        assert_eq!(key_depth(&path, "logs/"), 1);
+    }
+
+    #[test]
+    fn depth_listing_levels() {
+        let template = FindIter {
+            client: S3Client::new(rusoto_core::Region::UsEast1),
+            path: S3path {
+                bucket: "bucket".to_owned(),
+                prefix: Some("data/".to_owned()),
+            },
+            token: None,
+            page_size: 1000,
+            tuner: None,
+            encoding_type: None,
+            fetch_owner: false,
+            delimiter: None,
+            initial: true,
+        };
+
+        let min = DepthIter::new(template.clone(), Some(3), None);
+        assert_eq!(
+            min.list_prefix("data/".to_owned(), 1).delimiter.as_deref(),
+            Some("/")
+        );
+        assert_eq!(min.list_prefix("data/a/b/".to_owned(), 3).delimiter, None);
+
+        let bounded = DepthIter::new(template, Some(3), Some(4));
+        assert!(!bounded.flat(3));
+        assert_eq!(
+            bounded
+                .list_prefix("data/a/b/".to_owned(), 3)
+                .path
+                .prefix
+                .as_deref(),
+            Some("data/a/b/")
+        );
+    }
+
+    #[test]
+    fn prefetch_keeps_order() {
+        let pages = vec![
+            vec![object("a"), object("b")],
+            vec![object("c")],
+            vec![object("d")],
+        ];
+        let prefetched: Vec<_> = Prefetch::new(Box::new(pages.clone().into_iter().map(Ok)), 1)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(prefetched, pages);
+    }
+
+    #[test]
+    fn prefetch_is_bounded() {
+        let listed = Arc::new(AtomicUsize::new(0));
+        let counter = listed.clone();
+        let pages = (0..100).map(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![object("a")])
+        });
+
+        let mut prefetch = Prefetch::new(Box::new(pages), 2);
+        assert!(prefetch.next().is_some());
+        thread::sleep(std::time::Duration::from_millis(100));
+
+        // one page consumed, two queued and one waiting to be sent
+        assert!(listed.load(Ordering::SeqCst) <= 4);
+    }
+}
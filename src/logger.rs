@@ -0,0 +1,109 @@
+use chrono::{SecondsFormat, Utc};
+use failure::Error;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::arg::LogLevel;
+
+/// Crates logging at the selected level, the others log only warnings
+const VERBOSE_TARGETS: [&str; 2] = ["s3find", "rusoto"];
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// Line-oriented logger writing to stderr or to a file
+pub struct Logger {
+    level: LevelFilter,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Logger {
+    pub fn new(level: LevelFilter, output: Box<dyn Write + Send>) -> Self {
+        Logger {
+            level,
+            output: Mutex::new(output),
+        }
+    }
+
+    fn target_level(&self, target: &str) -> LevelFilter {
+        if VERBOSE_TARGETS.iter().any(|x| target.starts_with(x)) {
+            self.level
+        } else {
+            self.level.min(LevelFilter::Warn)
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.target_level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut output = self.output.lock().unwrap();
+        let _ = writeln!(
+            output,
+            "{} {:5} {}: {}",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        let _ = self.output.lock().unwrap().flush();
+    }
+}
+
+/// Install the global logger, logging is disabled when neither a level nor a file is given
+pub fn init(level: Option<LogLevel>, file: Option<&Path>) -> Result<(), Error> {
+    let level: LevelFilter = match (level, file) {
+        (Some(level), _) => level.into(),
+        (None, Some(_)) => LevelFilter::Info,
+        (None, None) => return Ok(()),
+    };
+
+    let output: Box<dyn Write + Send> = match file {
+        Some(file) => Box::new(OpenOptions::new().create(true).append(true).open(file)?),
+        None => Box::new(io::stderr()),
+    };
+
+    log::set_boxed_logger(Box::new(Logger::new(level, output)))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logger_target_levels() {
+        let logger = Logger::new(LevelFilter::Debug, Box::new(io::sink()));
+
+        let metadata = |level, target| Metadata::builder().level(level).target(target).build();
+
+        assert!(logger.enabled(&metadata(log::Level::Debug, "s3find::command")));
+        assert!(logger.enabled(&metadata(log::Level::Debug, "rusoto_core::request")));
+        assert!(!logger.enabled(&metadata(log::Level::Trace, "s3find::command")));
+        assert!(!logger.enabled(&metadata(log::Level::Debug, "hyper::client")));
+        assert!(logger.enabled(&metadata(log::Level::Warn, "hyper::client")));
+    }
+}
@@ -0,0 +1,262 @@
+use structopt::clap::{App, ArgSettings};
+
+/// Examples of the EXAMPLES section: a description and a command
+const EXAMPLES: [(&str, &str); 6] = [
+    (
+        "Print the keys of the gzip files bigger than 10 megabytes",
+        "s3find 's3://example-bucket/logs/' --name '*.gz' --size +10M",
+    ),
+    (
+        "Delete the keys modified more than 30 days ago",
+        "s3find 's3://example-bucket/tmp/' --name '*' --mtime +30d delete",
+    ),
+    (
+        "Download the CSV files modified in the last hour",
+        "s3find 's3://example-bucket/in/' --name '*.csv' --mtime -1h download /data/in",
+    ),
+    (
+        "Print the summary of the keys of two buckets",
+        "s3find 's3://bucket-a/' 's3://bucket-b/' --name '*' --summarize nothing",
+    ),
+    (
+        "Set a tag on the keys matching a regex",
+        "s3find 's3://example-bucket/' --regex '\\.pdf$' tags 'type:pdf'",
+    ),
+    (
+        "Print the buckets of the account with their versioning status",
+        "s3find buckets --versioning",
+    ),
+];
+
+/// Option or argument of a section: its name with the value, and its help
+struct Entry {
+    sort: String,
+    name: String,
+    help: String,
+}
+
+/// Man page in roff of the options, arguments and subcommands of the app,
+/// with the examples, e.g. for `s3find man > s3find.1`
+pub fn render(app: &App) -> String {
+    // clap 2 has no man page generator, the definitions are read from its
+    // parser, its fields are public but hidden from the docs
+    let meta = &app.p.meta;
+    let version = meta.version.unwrap_or_default();
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{} {}\" \"User Commands\"\n",
+        meta.name.to_uppercase(),
+        meta.name,
+        version
+    );
+
+    page += &format!(
+        ".SH NAME\n{} \\- {}\n",
+        meta.name,
+        escape(meta.about.unwrap_or_default())
+    );
+    page += &format!(
+        ".SH SYNOPSIS\n\\fB{0}\\fR [\\fIOPTIONS\\fR] \\fIpath\\fR [\\fIpaths\\fR...] [\\fICOMMAND\\fR]\n.br\n\\fB{0}\\fR [\\fIOPTIONS\\fR] \\fICOMMAND\\fR\n",
+        meta.name
+    );
+    if let Some(about) = meta.long_about.or(meta.about) {
+        page += &format!(".SH DESCRIPTION\n{}\n", text(about));
+    }
+    page += &section("ARGUMENTS", &arguments(app));
+    page += &section("OPTIONS", &options(app));
+
+    page += ".SH COMMANDS\n";
+    let mut commands: Vec<&App> = app.p.subcommands.iter().collect();
+    commands.sort_by_key(|x| x.p.meta.name.clone());
+    for command in commands {
+        let meta = &command.p.meta;
+        page += &format!(".SS {}\n", escape(&meta.name));
+        if let Some(about) = meta.long_about.or(meta.about) {
+            page += &format!("{}\n", text(about));
+        }
+        for entry in arguments(command).iter().chain(options(command).iter()) {
+            page += &format!(".TP\n{}\n{}\n", entry.name, entry.help);
+        }
+    }
+
+    page += ".SH EXAMPLES\n";
+    for (description, command) in EXAMPLES.iter() {
+        page += &format!(".PP\n{}\n.IP\n\\fB{}\\fR\n", description, escape(command));
+    }
+    if let Some(notes) = meta.more_help {
+        page += &format!(".SH NOTES\n{}\n", text(notes));
+    }
+    page
+}
+
+fn section(title: &str, entries: &[Entry]) -> String {
+    if entries.is_empty() {
+        return String::new();
+    }
+    let mut section = format!(".SH {}\n", title);
+    for entry in entries {
+        section += &format!(".TP\n{}\n{}\n", entry.name, entry.help);
+    }
+    section
+}
+
+fn arguments(app: &App) -> Vec<Entry> {
+    app.p
+        .positionals
+        .values()
+        .filter(|x| !x.b.is_set(ArgSettings::Hidden))
+        .map(|x| {
+            let multiple = if x.b.is_set(ArgSettings::Multiple) {
+                "..."
+            } else {
+                ""
+            };
+            Entry {
+                sort: x.b.name.to_owned(),
+                name: format!("\\fI{}\\fR{}", escape(x.b.name), multiple),
+                help: text(x.b.long_help.or(x.b.help).unwrap_or_default()),
+            }
+        })
+        .collect()
+}
+
+/// The flags and the options with a value, by long name
+fn options(app: &App) -> Vec<Entry> {
+    let flags = app
+        .p
+        .flags
+        .iter()
+        .filter(|x| !x.b.is_set(ArgSettings::Hidden))
+        .map(|x| Entry {
+            sort: switch_sort(x.s.long, x.s.short),
+            name: switch(x.s.long, x.s.short),
+            help: text(x.b.long_help.or(x.b.help).unwrap_or_default()),
+        });
+
+    let opts = app
+        .p
+        .opts
+        .iter()
+        .filter(|x| !x.b.is_set(ArgSettings::Hidden))
+        .map(|x| {
+            let value =
+                x.v.val_names
+                    .as_ref()
+                    .and_then(|names| names.values().next().cloned())
+                    .unwrap_or(x.b.name);
+            let mut help = text(x.b.long_help.or(x.b.help).unwrap_or_default());
+            if let Some(values) = x.v.possible_vals.as_ref() {
+                help += &format!("\n.br\nPossible values: {}", escape(&values.join(", ")));
+            }
+            if let Some(default) = x.v.default_val {
+                help += &format!("\n.br\nDefault: {}", escape(&default.to_string_lossy()));
+            }
+            Entry {
+                sort: switch_sort(x.s.long, x.s.short),
+                name: format!(
+                    "{} \\fI<{}>\\fR",
+                    switch(x.s.long, x.s.short),
+                    escape(value)
+                ),
+                help,
+            }
+        });
+
+    let mut entries: Vec<Entry> = flags.chain(opts).collect();
+    entries.sort_by(|a, b| a.sort.cmp(&b.sort));
+    entries
+}
+
+fn switch(long: Option<&str>, short: Option<char>) -> String {
+    let long = long.map(|x| format!("\\fB\\-\\-{}\\fR", escape(x)));
+    match (short, long) {
+        (Some(short), Some(long)) => format!("\\fB\\-{}\\fR, {}", short, long),
+        (Some(short), None) => format!("\\fB\\-{}\\fR", short),
+        (None, long) => long.unwrap_or_default(),
+    }
+}
+
+fn switch_sort(long: Option<&str>, short: Option<char>) -> String {
+    long.map(str::to_owned)
+        .or_else(|| short.map(|x| x.to_string()))
+        .unwrap_or_default()
+}
+
+/// Escape the roff special characters of a text
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+/// A help text in roff: the blank lines separate paragraphs and the indented
+/// lines, e.g. the examples of an option, are kept as they are
+fn text(help: &str) -> String {
+    let mut lines = Vec::new();
+    let mut verbatim = false;
+    for line in help.trim().lines() {
+        let indented = line.starts_with(' ') && !line.trim().is_empty();
+        if indented != verbatim {
+            lines.push(if indented { ".nf" } else { ".fi" }.to_owned());
+            verbatim = indented;
+        }
+
+        let line = escape(line.trim_end());
+        if line.is_empty() {
+            lines.push(".sp".to_owned());
+        } else if line.starts_with('.') || line.starts_with('\'') {
+            lines.push(format!("\\&{}", line));
+        } else {
+            lines.push(line);
+        }
+    }
+    if verbatim {
+        lines.push(".fi".to_owned());
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg::FindOpt;
+    use crate::command::FilterList;
+    use chrono::{Duration, Utc};
+    use rusoto_s3::Object;
+    use structopt::StructOpt;
+
+    #[test]
+    fn man_page() {
+        let page = render(&FindOpt::clap());
+        assert!(page.starts_with(".TH S3FIND 1"));
+        assert!(page.contains(".SH NAME\ns3find \\- Walk an Amazon S3 path hierarchy\n"));
+        assert!(page.contains("\\fB\\-\\-mtime\\fR \\fI<time>\\fR"));
+        assert!(page.contains(".SS publish\\-sns\n"));
+        assert!(page.contains(".SH EXAMPLES\n"));
+
+        let help = text("Size for match:\n    5k - exact match\n\n.hidden");
+        assert_eq!(
+            help,
+            "Size for match:\n.nf\n    5k \\- exact match\n.fi\n.sp\n\\&.hidden"
+        );
+    }
+
+    #[test]
+    fn examples_match_their_description() {
+        let modified = |age: Duration| Object {
+            key: Some("in/file.csv".to_owned()),
+            last_modified: Some((Utc::now() - age).to_rfc3339()),
+            ..Default::default()
+        };
+        // example, age of a key it must match, age of a key it must not match
+        let cases = [
+            (1, Duration::days(40), Duration::days(2)),
+            (2, Duration::minutes(10), Duration::hours(2)),
+        ];
+
+        for (example, matched, skipped) in cases.iter() {
+            let (description, command) = EXAMPLES[*example];
+            let args = command.split_whitespace().map(|x| x.trim_matches('\''));
+            let filters: FilterList = FindOpt::from_iter_safe(args).unwrap().into();
+            assert!(filters.test_match(&modified(*matched)), "{}", description);
+            assert!(!filters.test_match(&modified(*skipped)), "{}", description);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+/// Upper estimate of the memory held by a listed object: a key of up to 1024
+/// bytes, the other fields and the allocation overhead
+pub const OBJECT_MEMORY: u64 = 2048;
+
+/// Sizes of the stages holding listed objects
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryLimits {
+    pub page_size: i64,
+    pub buffer_size: usize,
+}
+
+/// Shrink the command buffer and the pages so that the objects held at once
+/// fit into `max_memory`: half of it for the buffer, the rest shared by the
+/// pages in flight (the current one, the --pipeline queue and the partitioned
+/// listing queues). The reports and the other state accumulated over the
+/// matched keys are not bounded
+pub fn fit(
+    max_memory: u64,
+    page_size: i64,
+    buffer_size: usize,
+    pipeline: usize,
+    list_concurrency: usize,
+) -> MemoryLimits {
+    let objects = (max_memory / OBJECT_MEMORY).max(2) as usize;
+    let buffer_size = buffer_size.min(objects / 2).max(1);
+
+    let mut pages = 1;
+    if pipeline > 0 {
+        // a full queue and a page waiting to be sent
+        pages += pipeline + 1;
+    }
+    if list_concurrency > 1 {
+        // the channel of the workers and a page in every worker
+        pages += list_concurrency * 3;
+    }
+
+    let page_size = page_size.min(((objects - buffer_size) / pages).max(1) as i64);
+
+    MemoryLimits {
+        page_size,
+        buffer_size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_memory() {
+        let mb = 1024 * 1024;
+
+        assert_eq!(
+            fit(512 * mb, 1000, 1000, 0, 1),
+            MemoryLimits {
+                page_size: 1000,
+                buffer_size: 1000
+            }
+        );
+        // 2048 objects: 1024 for the buffer, 1024 for the pages
+        assert_eq!(
+            fit(4 * mb, 1000, 5000, 0, 1),
+            MemoryLimits {
+                page_size: 1000,
+                buffer_size: 1024
+            }
+        );
+        assert_eq!(
+            fit(4 * mb, 1000, 5000, 2, 1),
+            MemoryLimits {
+                page_size: 256,
+                buffer_size: 1024
+            }
+        );
+        assert_eq!(
+            fit(4 * mb, 1000, 100, 0, 4),
+            MemoryLimits {
+                page_size: 149,
+                buffer_size: 100
+            }
+        );
+        assert_eq!(
+            fit(1024, 1000, 1000, 4, 8),
+            MemoryLimits {
+                page_size: 1,
+                buffer_size: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use failure::Error;
+use rusoto_s3::Object;
+use std::fs;
+use std::net::UdpSocket;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arg::MetricsSink;
+use crate::events::FindEvents;
+use crate::report::error_count;
+
+static SCANNED: AtomicUsize = AtomicUsize::new(0);
+static MATCHED: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+static API_CALLS: AtomicUsize = AtomicUsize::new(0);
+static RETRIES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn api_call() {
+    API_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn retry() {
+    RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn scanned(list: &[Object]) {
+    SCANNED.fetch_add(list.len(), Ordering::Relaxed);
+}
+
+pub fn matched(object: &Object) {
+    MATCHED.fetch_add(1, Ordering::Relaxed);
+    BYTES.fetch_add(object.size.unwrap_or_default() as usize, Ordering::Relaxed);
+}
+
+/// The counters updated by the events of a search
+pub struct MetricsEvents;
+
+impl FindEvents for MetricsEvents {
+    fn on_page_listed(&self, page: &[Object]) {
+        scanned(page);
+    }
+
+    fn on_object_matched(&self, object: &Object) {
+        matched(object);
+    }
+}
+
+/// Counters of the current run
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Metrics {
+    pub objects_scanned: usize,
+    pub objects_matched: usize,
+    pub bytes_processed: usize,
+    pub api_calls: usize,
+    pub retries: usize,
+    pub errors: usize,
+}
+
+impl Metrics {
+    pub fn snapshot() -> Self {
+        Metrics {
+            objects_scanned: SCANNED.load(Ordering::Relaxed),
+            objects_matched: MATCHED.load(Ordering::Relaxed),
+            bytes_processed: BYTES.load(Ordering::Relaxed),
+            api_calls: API_CALLS.load(Ordering::Relaxed),
+            retries: RETRIES.load(Ordering::Relaxed),
+            errors: error_count(),
+        }
+    }
+
+    fn counters(&self) -> [(&'static str, &'static str, usize); 6] {
+        [
+            (
+                "objects_scanned",
+                "Objects returned by the listing",
+                self.objects_scanned,
+            ),
+            (
+                "objects_matched",
+                "Objects matching the filters",
+                self.objects_matched,
+            ),
+            (
+                "bytes_processed",
+                "Total size of the matched objects",
+                self.bytes_processed,
+            ),
+            ("api_calls", "S3 API calls", self.api_calls),
+            ("retries", "Retried S3 API calls", self.retries),
+            ("errors", "Failed operations", self.errors),
+        ]
+    }
+
+    /// StatsD counter lines, one datagram per run
+    pub fn to_statsd(&self) -> String {
+        self.counters()
+            .iter()
+            .map(|(name, _, value)| format!("s3find.{}:{}|c\n", name, value))
+            .collect()
+    }
+
+    /// Prometheus text exposition format, for node_exporter textfile collector
+    pub fn to_prometheus(&self) -> String {
+        self.counters()
+            .iter()
+            .map(|(name, help, value)| {
+                format!(
+                    "# HELP s3find_{0}_total {1}\n# TYPE s3find_{0}_total counter\ns3find_{0}_total {2}\n",
+                    name, help, value
+                )
+            })
+            .collect()
+    }
+
+    pub fn publish(&self, sink: &MetricsSink) -> Result<(), Error> {
+        match sink {
+            MetricsSink::Statsd(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.send_to(self.to_statsd().as_bytes(), address.as_str())?;
+            }
+            MetricsSink::Textfile(path) => write_atomically(path, &self.to_prometheus())?,
+        }
+        Ok(())
+    }
+}
+
+/// The textfile collector may read the file at any time, so it is replaced with a rename
+pub fn write_atomically(path: &Path, content: &str) -> Result<(), Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn metrics() -> Metrics {
+        Metrics {
+            objects_scanned: 10,
+            objects_matched: 2,
+            bytes_processed: 2048,
+            api_calls: 3,
+            retries: 0,
+            errors: 1,
+        }
+    }
+
+    #[test]
+    fn metrics_statsd() {
+        let statsd = metrics().to_statsd();
+        assert!(statsd.starts_with("s3find.objects_scanned:10|c\n"));
+        assert!(statsd.contains("s3find.bytes_processed:2048|c\n"));
+        assert!(statsd.ends_with("s3find.errors:1|c\n"));
+    }
+
+    #[test]
+    fn metrics_textfile() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("s3find.prom");
+        metrics().publish(&MetricsSink::Textfile(path.clone()))?;
+
+        let content = fs::read_to_string(&path)?;
+        assert!(content.contains("# TYPE s3find_objects_matched_total counter\n"));
+        assert!(content.contains("s3find_objects_matched_total 2\n"));
+        assert!(content.contains("s3find_api_calls_total 3\n"));
+        Ok(())
+    }
+}
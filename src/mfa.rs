@@ -0,0 +1,87 @@
+use chrono::Duration;
+use failure::Error;
+use futures::Future;
+use log::debug;
+use rusoto_core::Region;
+use rusoto_credential::{AutoRefreshingProvider, ProvideAwsCredentials};
+use rusoto_sts::{
+    StsAssumeRoleSessionCredentialsProvider, StsClient, StsSessionCredentialsProvider,
+};
+use std::io::{self, BufRead, Write};
+
+use crate::command::SharedProvider;
+use crate::error::FunctionError;
+use crate::tls::HttpClient;
+
+/// Name of the sessions of the roles assumed with --role-arn
+const SESSION_NAME: &str = "s3find";
+
+/// Lifetime of the GetSessionToken credentials, the default of the AWS CLI
+const SESSION_HOURS: i64 = 12;
+
+/// Ask for the current code of the MFA device on the terminal
+pub fn read_token(serial: &str) -> Result<String, Error> {
+    eprint!("Enter MFA code for {}: ", serial);
+    io::stderr().flush()?;
+
+    let mut token = String::new();
+    io::stdin().lock().read_line(&mut token)?;
+    Ok(token.trim().to_owned())
+}
+
+/// Session credentials for the MFA device, signed with the credentials of
+/// `provider`: the role is assumed with the code (AssumeRole), or without a
+/// role the credentials are exchanged (GetSessionToken). The code is read
+/// from the terminal if not given, and checked before the run starts
+pub fn authenticate<P>(
+    provider: P,
+    dispatcher: HttpClient,
+    region: &Region,
+    serial: &str,
+    token: Option<String>,
+    role_arn: Option<&str>,
+) -> Result<SharedProvider, Error>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+    P::Future: Send,
+{
+    let token = match token {
+        Some(token) => token,
+        None => read_token(serial)?,
+    };
+    let sts = StsClient::new_with(dispatcher, provider, region.clone());
+
+    // the code cannot be used again, the session ends with its credentials
+    let provider = match role_arn {
+        Some(role_arn) => {
+            debug!("AssumeRole {} {}", role_arn, serial);
+            let mut provider = StsAssumeRoleSessionCredentialsProvider::new(
+                sts,
+                role_arn.to_owned(),
+                SESSION_NAME.to_owned(),
+                None,
+                None,
+                None,
+                Some(serial.to_owned()),
+            );
+            provider.set_mfa_code(token);
+            SharedProvider::new(AutoRefreshingProvider::new(provider)?)
+        }
+        None => {
+            debug!("GetSessionToken {}", serial);
+            let mut provider = StsSessionCredentialsProvider::new(
+                sts,
+                Some(Duration::hours(SESSION_HOURS)),
+                Some(serial.to_owned()),
+            );
+            provider.set_mfa_code(token);
+            SharedProvider::new(AutoRefreshingProvider::new(provider)?)
+        }
+    };
+
+    let credentials = Box::new(provider.credentials().map_err(|e| e.to_string()));
+    tokio::runtime::Runtime::new()?
+        .block_on(credentials)
+        .map_err(FunctionError::MfaError)?;
+    Ok(provider)
+}
@@ -0,0 +1,232 @@
+use failure::{format_err, Error};
+use futures::{Future, Stream};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Request};
+use log::debug;
+use rusoto_s3::Object;
+use serde_json::{json, Value};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::timer::Timeout;
+
+use crate::arg::{NotifyUrl, S3path};
+use crate::command::Find;
+use crate::events::FindEvents;
+use crate::ops::block_on;
+use crate::report::ErrorReport;
+use crate::run::Walk;
+use crate::tls::WebClient;
+
+/// Matched keys added to the document with --notify-keys
+pub const MAX_KEYS: usize = 1000;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Webhook of --notify-url, it counts the matched keys of a run
+pub struct Notifier {
+    client: WebClient,
+    url: NotifyUrl,
+    keys: bool,
+    matched: Mutex<Matched>,
+}
+
+#[derive(Debug, Default)]
+struct Matched {
+    count: usize,
+    keys: Vec<String>,
+}
+
+impl FindEvents for Notifier {
+    fn on_object_matched(&self, object: &Object) {
+        let mut matched = self.matched.lock().unwrap();
+        matched.count += 1;
+        if self.keys && matched.keys.len() < MAX_KEYS {
+            matched.keys.push(object.key.clone().unwrap_or_default());
+        }
+    }
+}
+
+impl Notifier {
+    /// With `keys` the matched keys are added to the document
+    pub fn new(url: NotifyUrl, keys: bool, client: WebClient) -> Self {
+        Notifier {
+            client,
+            url,
+            keys,
+            matched: Mutex::new(Matched::default()),
+        }
+    }
+
+    /// The document of a run of the paths, the count of the matched keys
+    /// starts again
+    pub fn document(&self, paths: &[S3path], walk: &Walk) -> Value {
+        let matched = std::mem::take(&mut *self.matched.lock().unwrap());
+        let outcome = if walk.cancelled {
+            "interrupted"
+        } else if walk.failed() {
+            "failed"
+        } else {
+            "completed"
+        };
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|x| {
+                format!(
+                    "s3://{}/{}",
+                    x.bucket,
+                    x.prefix.as_deref().unwrap_or_default()
+                )
+            })
+            .collect();
+        let errors: Vec<String> = walk.errors.iter().map(ToString::to_string).collect();
+
+        let mut document = json!({
+            "paths": paths,
+            "status": outcome,
+            "matched": matched.count,
+            "errors": errors,
+        });
+        if let Some(ref stats) = walk.stats {
+            document["summary"] = stats.to_json();
+        }
+        if self.keys {
+            document["keys_truncated"] = json!(matched.count > matched.keys.len());
+            document["keys"] = json!(matched.keys);
+        }
+        document
+    }
+
+    /// POST the document of the run, an answer other than 2xx is an error
+    pub fn notify(&self, status: &Find, walk: &Walk) -> Result<(), Error> {
+        let mut url = format!("{}{}", self.url.endpoint, self.url.path);
+        if let Some(ref query) = self.url.query {
+            url = format!("{}?{}", url, query);
+        }
+        let request = Request::post(url.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(self.document(&status.paths, walk).to_string()))?;
+
+        debug!("POST {}", url);
+        let response = self.client.request(request).and_then(|response| {
+            let status = response.status();
+            response
+                .into_body()
+                .concat2()
+                .map(move |body| (status, body))
+        });
+        let (status, body) = block_on(Box::new(
+            Timeout::new(response, TIMEOUT)
+                .map_err(|e| match e.into_inner() {
+                    Some(e) => Error::from(e),
+                    None => format_err!("no answer within {:?}", TIMEOUT),
+                })
+                .map_err(|e| ErrorReport::new("Notify", "", None, &e).into()),
+        ))?;
+
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(format_err!(
+                "The notification URL answered {}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg::FindOpt;
+    use crate::tls::{web_client, HttpSettings};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use structopt::StructOpt;
+
+    #[test]
+    fn notify_document() {
+        let url: NotifyUrl = "http://localhost:8080/hook".parse().unwrap();
+        let notifier = Notifier::new(url, true, web_client(&HttpSettings::default()).unwrap());
+        for key in &["a.csv", "b.csv"] {
+            notifier.on_object_matched(&Object {
+                key: Some((*key).to_owned()),
+                ..Default::default()
+            });
+        }
+
+        let paths = vec!["s3://bucket/logs".parse().unwrap()];
+        let walk = Walk {
+            errors: vec![ErrorReport::default().into()],
+            ..Default::default()
+        };
+        let document = notifier.document(&paths, &walk);
+        assert_eq!(document["paths"], json!(["s3://bucket/logs"]));
+        assert_eq!(document["status"], "failed");
+        assert_eq!(document["matched"], 2);
+        assert_eq!(document["keys"], json!(["a.csv", "b.csv"]));
+        assert_eq!(document["keys_truncated"], false);
+        assert!(document.get("summary").is_none());
+
+        // the next run counts again
+        let document = notifier.document(&paths, &Walk::default());
+        assert_eq!(document["status"], "completed");
+        assert_eq!(document["matched"], 0);
+    }
+
+    #[test]
+    fn notify_post() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                // the document may come with the headers
+                while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+                    let count = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..count]);
+                }
+                let request = String::from_utf8(request).unwrap().to_lowercase();
+                let status = if request.starts_with("post /hook?run=a%20b ")
+                    && request.contains("content-type: application/json")
+                {
+                    "204 No Content"
+                } else {
+                    "404 Not Found"
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let find = |path: &str| -> Find {
+            Find::new(FindOpt::from_iter(&[
+                "s3find",
+                "s3://bucket/logs",
+                "--aws-access-key",
+                "access",
+                "--aws-secret-key",
+                "secret",
+                "--notify-url",
+                &format!("http://{}{}", endpoint, path),
+            ]))
+            .unwrap()
+        };
+        let notify = |find: &Find| {
+            find.notifier
+                .as_ref()
+                .unwrap()
+                .notify(find, &Walk::default())
+        };
+
+        assert!(notify(&find("/hook?run=a%20b")).is_ok());
+        let error = notify(&find("/other")).unwrap_err();
+        assert!(error.to_string().contains("404"));
+    }
+}
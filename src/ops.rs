@@ -0,0 +1,426 @@
+use failure::Error;
+use futures::future::{self, Either};
+use futures::sync::oneshot;
+use futures::{Future, Stream};
+use log::debug;
+use rusoto_core::Region;
+use rusoto_s3::{
+    CopyObjectRequest, Delete, DeleteObjectsRequest, GetObjectAclRequest, GetObjectRequest,
+    GetObjectTaggingRequest, Grant, ObjectIdentifier, PutObjectAclRequest, PutObjectTaggingRequest,
+    S3Client, S3Error, Tag, Tagging, S3,
+};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Instant;
+use tokio::executor::{DefaultExecutor, Executor};
+use tokio::runtime::Runtime;
+
+use crate::arg::S3path;
+use crate::benchmark;
+use crate::error::{ErrorKind, FunctionError, S3FindError};
+use crate::function::percent_encode;
+use crate::report::ErrorReport;
+use crate::retry::retry_future;
+
+/// Future of an operation, failing with the kind and report of the S3 error
+pub type OpFuture<T> = Box<dyn Future<Item = T, Error = S3FindError> + Send>;
+
+/// Outcome of an operation on one key of a batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyResult<T> {
+    pub key: String,
+    pub result: Result<T, S3FindError>,
+}
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Wait for an operation outside of a tokio runtime, it runs on a runtime
+/// shared by the commands. Within a runtime, spawn or chain the OpFuture instead.
+///
+/// # Panics
+///
+/// When called from a thread of a tokio runtime, which would wait forever
+/// on the operation it has to poll itself
+#[allow(clippy::result_large_err)]
+pub fn block_on<T: Send + 'static>(operation: OpFuture<T>) -> Result<T, S3FindError> {
+    assert!(
+        DefaultExecutor::current().status().is_err(),
+        "ops::block_on called from a tokio runtime thread"
+    );
+    let runtime = RUNTIME.get_or_init(|| Runtime::new().expect("Cannot start the tokio runtime"));
+    oneshot::spawn(operation, &runtime.executor()).wait()
+}
+
+/// Delete keys of a bucket with a single DeleteObjects call, at most 1000 keys
+#[allow(clippy::result_large_err)]
+pub fn delete_objects(
+    client: &S3Client,
+    bucket: &str,
+    keys: Vec<String>,
+) -> OpFuture<Vec<KeyResult<()>>> {
+    let request = DeleteObjectsRequest {
+        bucket: bucket.to_owned(),
+        delete: Delete {
+            objects: keys
+                .into_iter()
+                .map(|key| ObjectIdentifier {
+                    key,
+                    version_id: None,
+                })
+                .collect(),
+            quiet: None,
+        },
+        ..Default::default()
+    };
+
+    debug!(
+        "DeleteObjects s3://{} ({} keys)",
+        request.bucket,
+        request.delete.objects.len()
+    );
+    let client = client.clone();
+    let bucket = bucket.to_owned();
+    Box::new(
+        retry_future("DeleteObjects", move || {
+            client.delete_objects(request.clone())
+        })
+        .then(move |result| match result {
+            Ok(output) => {
+                let deleted = output
+                    .deleted
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|x| KeyResult {
+                        key: x.key.unwrap_or_default(),
+                        result: Ok(()),
+                    });
+                let failed = output
+                    .errors
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|x| KeyResult {
+                        key: x.key.clone().unwrap_or_default(),
+                        result: Err(delete_error(&bucket, x).into()),
+                    });
+                Ok(deleted.chain(failed).collect())
+            }
+            Err(e) => Err(ErrorReport::from_rusoto("DeleteObjects", &bucket, None, &e).into()),
+        }),
+    )
+}
+
+fn delete_error(bucket: &str, error: S3Error) -> ErrorReport {
+    ErrorReport {
+        kind: error
+            .code
+            .as_deref()
+            .map(ErrorKind::from_code)
+            .unwrap_or_default(),
+        operation: "DeleteObjects".to_owned(),
+        bucket: bucket.to_owned(),
+        key: error.key,
+        code: error.code,
+        request_id: None,
+        message: error.message.unwrap_or_default(),
+    }
+}
+
+/// Key of a copy under the prefix of the destination, only the file name of
+/// the key when `flat`
+pub fn target_key(key: &str, destination: &S3path, flat: bool) -> Result<String, Error> {
+    let key = if flat {
+        Path::new(key)
+            .file_name()
+            .ok_or(FunctionError::PathConverError)?
+            .to_str()
+            .ok_or(FunctionError::PathConverError)?
+    } else {
+        key
+    };
+
+    let target_key = match destination.prefix {
+        Some(ref prefix) => Path::new(prefix).join(key),
+        None => PathBuf::from(key),
+    };
+    target_key
+        .to_str()
+        .map(str::to_owned)
+        .ok_or_else(|| FunctionError::PathConverError.into())
+}
+
+/// Copy a key of `source` to `target_key` of the destination bucket
+pub fn copy_object(
+    client: &S3Client,
+    source: &S3path,
+    key: &str,
+    bucket: &str,
+    target_key: &str,
+) -> OpFuture<()> {
+    let request = CopyObjectRequest {
+        bucket: bucket.to_owned(),
+        key: target_key.to_owned(),
+        copy_source: source.copy_source(key),
+        ..Default::default()
+    };
+
+    debug!(
+        "CopyObject {} to s3://{}/{}",
+        request.copy_source, request.bucket, request.key
+    );
+    let client = client.clone();
+    let (source, key) = (source.bucket.clone(), key.to_owned());
+    Box::new(
+        retry_future("CopyObject", move || client.copy_object(request.clone()))
+            .map(|_| ())
+            .map_err(move |e| {
+                ErrorReport::from_rusoto("CopyObject", &source, Some(&key), &e).into()
+            }),
+    )
+}
+
+/// Replace the tags of a key
+pub fn put_object_tags(client: &S3Client, bucket: &str, key: &str, tags: Vec<Tag>) -> OpFuture<()> {
+    let request = PutObjectTaggingRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        tagging: Tagging { tag_set: tags },
+        ..Default::default()
+    };
+
+    debug!("PutObjectTagging s3://{}/{}", request.bucket, request.key);
+    let client = client.clone();
+    let (bucket, key) = (bucket.to_owned(), key.to_owned());
+    Box::new(
+        retry_future("PutObjectTagging", move || {
+            client.put_object_tagging(request.clone())
+        })
+        .map(|_| ())
+        .map_err(move |e| {
+            ErrorReport::from_rusoto("PutObjectTagging", &bucket, Some(&key), &e).into()
+        }),
+    )
+}
+
+pub fn get_object_tags(client: &S3Client, bucket: &str, key: &str) -> OpFuture<Vec<Tag>> {
+    let request = GetObjectTaggingRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        ..Default::default()
+    };
+
+    debug!("GetObjectTagging s3://{}/{}", request.bucket, request.key);
+    let client = client.clone();
+    let (bucket, key) = (bucket.to_owned(), key.to_owned());
+    Box::new(
+        retry_future("GetObjectTagging", move || {
+            client.get_object_tagging(request.clone())
+        })
+        .map(|x| x.tag_set)
+        .map_err(move |e| {
+            ErrorReport::from_rusoto("GetObjectTagging", &bucket, Some(&key), &e).into()
+        }),
+    )
+}
+
+/// The grants of the ACL of a key
+pub fn get_object_acl(client: &S3Client, bucket: &str, key: &str) -> OpFuture<Vec<Grant>> {
+    let request = GetObjectAclRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        ..Default::default()
+    };
+
+    debug!("GetObjectAcl s3://{}/{}", request.bucket, request.key);
+    let client = client.clone();
+    let (bucket, key) = (bucket.to_owned(), key.to_owned());
+    Box::new(
+        retry_future("GetObjectAcl", move || {
+            client.get_object_acl(request.clone())
+        })
+        .map(|x| x.grants.unwrap_or_default())
+        .map_err(move |e| ErrorReport::from_rusoto("GetObjectAcl", &bucket, Some(&key), &e).into()),
+    )
+}
+
+/// Make a key readable by anyone with the public-read ACL
+pub fn set_public_read(client: &S3Client, bucket: &str, key: &str) -> OpFuture<()> {
+    let request = PutObjectAclRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        acl: Some("public-read".to_string()),
+        ..Default::default()
+    };
+
+    debug!("PutObjectAcl s3://{}/{}", request.bucket, request.key);
+    let client = client.clone();
+    let (bucket, key) = (bucket.to_owned(), key.to_owned());
+    Box::new(
+        retry_future("PutObjectAcl", move || {
+            client.put_object_acl(request.clone())
+        })
+        .map(|_| ())
+        .map_err(move |e| ErrorReport::from_rusoto("PutObjectAcl", &bucket, Some(&key), &e).into()),
+    )
+}
+
+/// Public URL of a key, once it is readable by anyone
+pub fn public_url(region: &Region, bucket: &str, key: &str) -> String {
+    match region {
+        Region::UsEast1 => format!("http://{}.s3.amazonaws.com/{}", bucket, key),
+        Region::Custom { endpoint, .. } => format!("{}/{}/{}", endpoint, bucket, key),
+        _ => format!(
+            "http://{}.s3-{}.amazonaws.com/{}",
+            bucket,
+            region.name(),
+            key
+        ),
+    }
+}
+
+/// URL of a key under the base URL of a CDN, e.g. a CloudFront domain with
+/// the bucket as origin
+pub fn cdn_url(base: &str, key: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), percent_encode(key))
+}
+
+/// Download a key to `file`, creating its directory. `progress` gets the
+/// number of bytes written so far, the future the size of the file
+#[allow(clippy::result_large_err)]
+pub fn download_object<P>(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    file: PathBuf,
+    mut progress: P,
+) -> OpFuture<u64>
+where
+    P: FnMut(u64) + Send + 'static,
+{
+    let request = GetObjectRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        ..Default::default()
+    };
+
+    debug!("GetObject s3://{}/{}", request.bucket, request.key);
+    let start = Instant::now();
+    let client = client.clone();
+    let (bucket, key) = (bucket.to_owned(), key.to_owned());
+    Box::new(
+        retry_future("GetObject", move || client.get_object(request.clone())).then(move |result| {
+            let output = match result {
+                Ok(x) => x,
+                Err(e) => {
+                    let report = ErrorReport::from_rusoto("GetObject", &bucket, Some(&key), &e);
+                    return Either::A(future::err(report.into()));
+                }
+            };
+
+            let written = output
+                .body
+                .ok_or_else(|| FunctionError::S3FetchBodyError.into())
+                .and_then(|stream| -> Result<_, Error> {
+                    if let Some(dir) = file.parent() {
+                        fs::create_dir_all(dir)?;
+                    }
+                    Ok((stream, File::create(&file)?))
+                });
+
+            let (stream, file) = match written {
+                Ok(x) => x,
+                Err(e) => {
+                    let report = ErrorReport::new("GetObject", &bucket, Some(&key), &e);
+                    return Either::A(future::err(report.into()));
+                }
+            };
+
+            Either::B(
+                stream
+                    .map_err(Error::from)
+                    .fold((file, 0), move |(mut file, count), buf| {
+                        file.write_all(&buf)?;
+                        let count = count + buf.len() as u64;
+                        progress(count);
+                        Ok::<_, Error>((file, count))
+                    })
+                    .then(move |result| match result {
+                        Ok((_, count)) => {
+                            benchmark::downloaded(count, start);
+                            Ok(count)
+                        }
+                        Err(e) => {
+                            Err(ErrorReport::new("GetObject", &bucket, Some(&key), &e).into())
+                        }
+                    }),
+            )
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry::set_retry_policy;
+    use rusoto_core::{HttpDispatchError, RusotoError};
+    use rusoto_s3::GetObjectError;
+
+    #[test]
+    fn retried_operation() {
+        set_retry_policy(3, 0);
+        let mut attempts = 0;
+        let operation = retry_future("GetObject", move || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(RusotoError::HttpDispatch(HttpDispatchError::new(
+                    "connection reset".to_owned(),
+                )))
+                .into()
+            } else {
+                Ok(attempts).into()
+            }
+        })
+        .map_err(|e: RusotoError<GetObjectError>| {
+            ErrorReport::from_rusoto("GetObject", "bucket", None, &e).into()
+        });
+        assert_eq!(block_on(Box::new(operation)), Ok(3));
+    }
+
+    #[test]
+    fn block_on_within_runtime() {
+        let nested = future::lazy(|| {
+            let result = std::panic::catch_unwind(|| block_on(Box::new(future::ok(1))).is_ok());
+            Ok::<_, ()>(result.is_err())
+        });
+        assert_eq!(Runtime::new().unwrap().block_on(nested), Ok(true));
+    }
+
+    #[test]
+    fn copy_targets() {
+        let destination: S3path = "s3://target/archive".parse().unwrap();
+        assert_eq!(
+            target_key("logs/2020/a.gz", &destination, false).unwrap(),
+            "archive/logs/2020/a.gz"
+        );
+        assert_eq!(
+            target_key("logs/2020/a.gz", &destination, true).unwrap(),
+            "archive/a.gz"
+        );
+        let bucket: S3path = "s3://target".parse().unwrap();
+        assert_eq!(target_key("a.gz", &bucket, false).unwrap(), "a.gz");
+
+        assert_eq!(
+            public_url(&Region::UsEast1, "bucket", "a.gz"),
+            "http://bucket.s3.amazonaws.com/a.gz"
+        );
+        assert_eq!(
+            public_url(&Region::EuWest1, "bucket", "a.gz"),
+            "http://bucket.s3-eu-west-1.amazonaws.com/a.gz"
+        );
+        assert_eq!(
+            cdn_url("https://cdn.example.com/", "img/a b.png"),
+            "https://cdn.example.com/img/a%20b.png"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// Destination of the results printed by the commands: keys, listings,
+/// per-key status lines and JSON documents
+pub trait OutputSink: Send + Sync {
+    /// Text written as is, e.g. a printf template or the output of exec
+    fn text(&self, text: &str);
+
+    fn line(&self, line: &str) {
+        self.text(&format!("{}\n", line));
+    }
+
+    /// A JSON document, one per line on stdout
+    fn json(&self, value: &Value) {
+        self.line(&value.to_string());
+    }
+}
+
+/// Results printed on stdout, the default of the CLI
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn text(&self, text: &str) {
+        print!("{}", text);
+    }
+
+    fn line(&self, line: &str) {
+        println!("{}", line);
+    }
+}
+
+/// Every result written to `W` as a JSON document per line, text as
+/// {"text": ...} and lines as {"line": ...}
+pub struct JsonSink<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonSink {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+
+    fn write(&self, value: &Value) {
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writeln!(writer, "{}", value) {
+            // the same as println on a closed stdout, but without the panic
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                eprintln!("Failed to write the output: {}", e);
+            }
+        }
+    }
+}
+
+impl<W: Write + Send> OutputSink for JsonSink<W> {
+    fn text(&self, text: &str) {
+        self.write(&json!({ "text": text }));
+    }
+
+    fn line(&self, line: &str) {
+        self.write(&json!({ "line": line }));
+    }
+
+    fn json(&self, value: &Value) {
+        self.write(value);
+    }
+}
+
+/// A result collected by MemorySink
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    Text(String),
+    Line(String),
+    Json(Value),
+}
+
+/// Results collected in memory, for library users and tests
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    outputs: Mutex<Vec<Output>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        MemorySink::default()
+    }
+
+    /// The results collected so far, the sink is emptied
+    pub fn take(&self) -> Vec<Output> {
+        std::mem::take(&mut *self.outputs.lock().unwrap())
+    }
+
+    /// The collected lines, JSON documents as lines
+    pub fn lines(&self) -> Vec<String> {
+        self.outputs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|x| match x {
+                Output::Text(text) => text.clone(),
+                Output::Line(line) => line.clone(),
+                Output::Json(value) => value.to_string(),
+            })
+            .collect()
+    }
+}
+
+impl OutputSink for MemorySink {
+    fn text(&self, text: &str) {
+        self.outputs
+            .lock()
+            .unwrap()
+            .push(Output::Text(text.to_owned()));
+    }
+
+    fn line(&self, line: &str) {
+        self.outputs
+            .lock()
+            .unwrap()
+            .push(Output::Line(line.to_owned()));
+    }
+
+    fn json(&self, value: &Value) {
+        self.outputs
+            .lock()
+            .unwrap()
+            .push(Output::Json(value.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sinks() {
+        let memory = MemorySink::new();
+        memory.line("s3://bucket/a");
+        memory.json(&json!({"key": "b"}));
+        memory.text("c\t");
+        assert_eq!(
+            memory.lines(),
+            vec!["s3://bucket/a", r#"{"key":"b"}"#, "c\t"]
+        );
+        assert_eq!(memory.take().len(), 3);
+        assert!(memory.take().is_empty());
+
+        let sink = JsonSink::new(Vec::new());
+        sink.line("s3://bucket/a");
+        sink.json(&json!({"key": "b"}));
+        sink.text("c");
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            "{\"line\":\"s3://bucket/a\"}\n{\"key\":\"b\"}\n{\"text\":\"c\"}\n"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// ListObjectsV2 returns up to 1000 keys per response
+pub const MAX_PAGE_SIZE: i64 = 1000;
+const MIN_PAGE_SIZE: i64 = 50;
+
+/// Responses slower than this halve the page, faster than FAST_RESPONSE double it
+const SLOW_RESPONSE: Duration = Duration::from_secs(2);
+const FAST_RESPONSE: Duration = Duration::from_millis(500);
+
+/// Keys scanned before the match rate is taken into account
+const MIN_SAMPLE: usize = 1000;
+
+/// Page size of the listing requests (--page-size auto), adjusted after every
+/// response to its latency and to the rate of the matched keys
+pub struct PageSizeTuner {
+    buffer_size: usize,
+    limit: Option<usize>,
+    state: Mutex<TunerState>,
+}
+
+struct TunerState {
+    page_size: i64,
+    scanned: usize,
+    matched: usize,
+}
+
+impl PageSizeTuner {
+    pub fn new(buffer_size: usize, limit: Option<usize>) -> Self {
+        PageSizeTuner {
+            buffer_size,
+            limit,
+            state: Mutex::new(TunerState {
+                page_size: MAX_PAGE_SIZE,
+                scanned: 0,
+                matched: 0,
+            }),
+        }
+    }
+
+    pub fn page_size(&self) -> i64 {
+        self.state.lock().unwrap().page_size
+    }
+
+    /// A key has been filtered
+    pub fn scanned(&self, matched: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.scanned += 1;
+        if matched {
+            state.matched += 1;
+        }
+    }
+
+    /// A list response has been received in `latency`
+    pub fn observe(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let remaining = self.limit.map(|x| x.saturating_sub(state.matched));
+        state.page_size = next_page_size(
+            state.page_size,
+            latency,
+            state.scanned,
+            state.matched,
+            self.buffer_size,
+            remaining,
+        );
+    }
+}
+
+pub fn next_page_size(
+    page_size: i64,
+    latency: Duration,
+    scanned: usize,
+    matched: usize,
+    buffer_size: usize,
+    remaining: Option<usize>,
+) -> i64 {
+    let mut size = if latency > SLOW_RESPONSE {
+        page_size / 2
+    } else if latency < FAST_RESPONSE {
+        page_size * 2
+    } else {
+        page_size
+    };
+
+    if scanned >= MIN_SAMPLE && matched > 0 {
+        let rate = matched as f64 / scanned as f64;
+        // the matches of a page fit into a command buffer and the rest of the limit
+        let keys = |matches: usize| (matches as f64 / rate).ceil() as i64;
+        size = size.min(keys(buffer_size));
+        if let Some(remaining) = remaining {
+            size = size.min(keys(remaining));
+        }
+    }
+
+    size.clamp(MIN_PAGE_SIZE, MAX_PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_size_latency() {
+        let slow = Duration::from_secs(5);
+        let fast = Duration::from_millis(100);
+        let normal = Duration::from_secs(1);
+
+        assert_eq!(next_page_size(1000, slow, 0, 0, 1000, None), 500);
+        assert_eq!(next_page_size(60, slow, 0, 0, 1000, None), MIN_PAGE_SIZE);
+        assert_eq!(next_page_size(500, normal, 0, 0, 1000, None), 500);
+        assert_eq!(next_page_size(500, fast, 0, 0, 1000, None), 1000);
+        assert_eq!(next_page_size(1000, fast, 0, 0, 1000, None), MAX_PAGE_SIZE);
+    }
+
+    #[test]
+    fn page_size_match_rate() {
+        let fast = Duration::from_millis(100);
+
+        // every second key matches: 100 keys fill a buffer of 50
+        assert_eq!(next_page_size(1000, fast, 2000, 1000, 50, None), 100);
+        // 10 keys left to the limit
+        assert_eq!(
+            next_page_size(1000, fast, 2000, 1000, 1000, Some(10)),
+            MIN_PAGE_SIZE
+        );
+        // sparse matches do not limit the page
+        assert_eq!(
+            next_page_size(1000, fast, 100_000, 10, 1000, Some(10)),
+            1000
+        );
+        // too few keys to estimate the rate
+        assert_eq!(next_page_size(1000, fast, 10, 10, 50, None), 1000);
+    }
+
+    #[test]
+    fn tuner_observe() {
+        let tuner = PageSizeTuner::new(1000, None);
+        assert_eq!(tuner.page_size(), MAX_PAGE_SIZE);
+
+        tuner.observe(Duration::from_secs(3));
+        tuner.observe(Duration::from_secs(3));
+        assert_eq!(tuner.page_size(), 250);
+
+        tuner.scanned(true);
+        tuner.observe(Duration::from_millis(10));
+        assert_eq!(tuner.page_size(), 500);
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Apply `f` to every item with up to `concurrency` threads,
+/// the results keep the order of the items
+pub fn parallel_map<T, R, F>(items: &[T], concurrency: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if concurrency <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..concurrency.min(items.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        match items.get(index) {
+                            Some(item) => done.push((index, f(item))),
+                            None => return done,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|x| x.join().unwrap())
+            .collect()
+    });
+
+    results.sort_by_key(|x| x.0);
+    results.into_iter().map(|x| x.1).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parallel_map_keeps_order() {
+        let items: Vec<u64> = (0..50).collect();
+        let results = parallel_map(&items, 8, |x| {
+            thread::sleep(Duration::from_millis((50 - x) % 7));
+            x * 2
+        });
+
+        assert_eq!(results, items.iter().map(|x| x * 2).collect::<Vec<_>>());
+        assert_eq!(parallel_map(&items, 1, |x| x + 1)[49], 50);
+        assert!(parallel_map(&[] as &[u64], 4, |x| *x).is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+
+use crate::arg::StoragePrice;
+
+const GIGABYTE: f64 = 1024.0 * 1024.0 * 1024.0;
+
+pub const DEFAULT_STORAGE_CLASS: &str = "STANDARD";
+
+/// Storage prices in USD per GB-month: (class, us-east-1 price)
+const BASE_PRICES: [(&str, f64); 8] = [
+    ("STANDARD", 0.023),
+    ("REDUCED_REDUNDANCY", 0.024),
+    ("INTELLIGENT_TIERING", 0.023),
+    ("STANDARD_IA", 0.0125),
+    ("ONEZONE_IA", 0.01),
+    ("GLACIER_IR", 0.004),
+    ("GLACIER", 0.0036),
+    ("DEEP_ARCHIVE", 0.00099),
+];
+
+/// Prices in USD of 1000 lifecycle transitions to a class, us-east-1
+const TRANSITION_PRICES: [(&str, f64); 6] = [
+    ("STANDARD_IA", 0.01),
+    ("ONEZONE_IA", 0.01),
+    ("INTELLIGENT_TIERING", 0.01),
+    ("GLACIER_IR", 0.02),
+    ("GLACIER", 0.03),
+    ("DEEP_ARCHIVE", 0.05),
+];
+
+/// Prices in USD of 1000 GET requests and of the retrieval of a GB, us-east-1
+const READ_PRICES: [(&str, f64, f64); 6] = [
+    ("STANDARD", 0.0004, 0.0),
+    ("REDUCED_REDUNDANCY", 0.0004, 0.0),
+    ("INTELLIGENT_TIERING", 0.0004, 0.0),
+    ("STANDARD_IA", 0.001, 0.01),
+    ("ONEZONE_IA", 0.001, 0.01),
+    ("GLACIER_IR", 0.01, 0.03),
+];
+
+/// Prices in USD of the restore of a GB and of 1000 restore requests by
+/// retrieval tier, us-east-1: (class, tier, per GB, per 1000 requests)
+const RESTORE_PRICES: [(&str, &str, f64, f64); 5] = [
+    ("GLACIER", "Expedited", 0.03, 10.0),
+    ("GLACIER", "Standard", 0.01, 0.05),
+    ("GLACIER", "Bulk", 0.0025, 0.025),
+    ("DEEP_ARCHIVE", "Standard", 0.02, 0.10),
+    ("DEEP_ARCHIVE", "Bulk", 0.0025, 0.025),
+];
+
+/// Price in USD of 1000 PUT, COPY or LIST requests, us-east-1
+const WRITE_PRICE: f64 = 0.005;
+
+/// Price in USD of a GB transferred out to the internet, first 10 TB of the
+/// month in us-east-1
+const TRANSFER_OUT_PRICE: f64 = 0.09;
+
+/// Regional prices differing from the us-east-1 ones
+const REGIONAL_PRICES: [(&str, &[(&str, f64)]); 5] = [
+    (
+        "us-west-1",
+        &[
+            ("STANDARD", 0.026),
+            ("INTELLIGENT_TIERING", 0.026),
+            ("STANDARD_IA", 0.019),
+            ("ONEZONE_IA", 0.0152),
+            ("GLACIER", 0.0045),
+            ("DEEP_ARCHIVE", 0.002),
+        ],
+    ),
+    (
+        "eu-central-1",
+        &[
+            ("STANDARD", 0.0245),
+            ("INTELLIGENT_TIERING", 0.0245),
+            ("STANDARD_IA", 0.0135),
+            ("ONEZONE_IA", 0.0108),
+            ("GLACIER", 0.0036),
+            ("DEEP_ARCHIVE", 0.0018),
+        ],
+    ),
+    (
+        "ap-southeast-1",
+        &[
+            ("STANDARD", 0.025),
+            ("INTELLIGENT_TIERING", 0.025),
+            ("STANDARD_IA", 0.0138),
+            ("ONEZONE_IA", 0.011),
+            ("GLACIER", 0.005),
+            ("DEEP_ARCHIVE", 0.002),
+        ],
+    ),
+    (
+        "ap-northeast-1",
+        &[
+            ("STANDARD", 0.025),
+            ("INTELLIGENT_TIERING", 0.025),
+            ("STANDARD_IA", 0.0138),
+            ("ONEZONE_IA", 0.011),
+            ("GLACIER", 0.005),
+            ("DEEP_ARCHIVE", 0.002),
+        ],
+    ),
+    (
+        "sa-east-1",
+        &[
+            ("STANDARD", 0.0405),
+            ("INTELLIGENT_TIERING", 0.0405),
+            ("STANDARD_IA", 0.0221),
+            ("ONEZONE_IA", 0.0177),
+            ("GLACIER", 0.0063),
+            ("DEEP_ARCHIVE", 0.002),
+        ],
+    ),
+];
+
+/// Built-in per-region storage price table, can be overridden by users
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceTable {
+    prices: BTreeMap<String, f64>,
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        PriceTable {
+            prices: BASE_PRICES
+                .iter()
+                .map(|(class, price)| ((*class).to_owned(), *price))
+                .collect(),
+        }
+    }
+}
+
+impl PriceTable {
+    pub fn for_region(region: &str) -> Self {
+        let mut table = PriceTable::default();
+        if let Some((_, prices)) = REGIONAL_PRICES.iter().find(|(name, _)| *name == region) {
+            for (class, price) in prices.iter() {
+                table.prices.insert((*class).to_owned(), *price);
+            }
+        }
+        table
+    }
+
+    pub fn with_overrides(mut self, overrides: &[StoragePrice]) -> Self {
+        for x in overrides {
+            self.prices.insert(x.class.clone(), x.price);
+        }
+        self
+    }
+
+    /// Price per GB-month, unknown classes are charged as STANDARD
+    pub fn storage_price(&self, class: &str) -> f64 {
+        self.prices
+            .get(class)
+            .or_else(|| self.prices.get(DEFAULT_STORAGE_CLASS))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn monthly_cost(&self, class: &str, bytes: i64) -> f64 {
+        bytes as f64 / GIGABYTE * self.storage_price(class)
+    }
+
+    /// One-time cost of moving `count` keys to the class with lifecycle
+    /// transitions, the us-east-1 prices are used in every region
+    pub fn transition_cost(&self, class: &str, count: u64) -> f64 {
+        let price = TRANSITION_PRICES
+            .iter()
+            .find(|x| x.0 == class)
+            .map_or(0.0, |x| x.1);
+        count as f64 / 1000.0 * price
+    }
+
+    /// Cost of the GET requests and of the retrieval of keys of the class,
+    /// the archived classes cannot be read before a restore
+    pub fn read_cost(&self, class: &str, count: u64, bytes: i64) -> Option<f64> {
+        let (_, request, retrieval) = READ_PRICES.iter().find(|x| x.0 == class)?;
+        Some(count as f64 / 1000.0 * request + bytes as f64 / GIGABYTE * retrieval)
+    }
+
+    /// Cost of downloading keys of the class out of AWS, None for the
+    /// archived classes
+    pub fn download_cost(&self, class: &str, count: u64, bytes: i64) -> Option<f64> {
+        let read = self.read_cost(class, count, bytes)?;
+        Some(read + bytes as f64 / GIGABYTE * TRANSFER_OUT_PRICE)
+    }
+
+    /// Cost of copying keys of the class in the region, None for the
+    /// archived classes
+    pub fn copy_cost(&self, class: &str, count: u64, bytes: i64) -> Option<f64> {
+        let read = self.read_cost(class, count, bytes)?;
+        Some(read + count as f64 / 1000.0 * WRITE_PRICE)
+    }
+
+    /// Cost of restoring keys of an archived class with a retrieval tier
+    /// (Expedited, Standard, Bulk), None when the class has no such tier or
+    /// is not archived. The storage of the restored copies is not included
+    pub fn restore_cost(&self, class: &str, tier: &str, count: u64, bytes: i64) -> Option<f64> {
+        let (_, _, per_gb, per_request) = RESTORE_PRICES
+            .iter()
+            .find(|x| x.0 == class && x.1 == tier)?;
+        Some(bytes as f64 / GIGABYTE * per_gb + count as f64 / 1000.0 * per_request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_table_region() {
+        let table = PriceTable::for_region("us-east-1");
+        assert_eq!(table.storage_price("STANDARD"), 0.023);
+        assert_eq!(table.storage_price("UNKNOWN"), 0.023);
+
+        let table = PriceTable::for_region("eu-central-1");
+        assert_eq!(table.storage_price("STANDARD"), 0.0245);
+        assert_eq!(table.storage_price("GLACIER_IR"), 0.004);
+    }
+
+    #[test]
+    fn price_table_overrides() {
+        let table = PriceTable::for_region("us-east-1").with_overrides(&[StoragePrice {
+            class: "STANDARD".to_owned(),
+            price: 0.01,
+        }]);
+
+        assert_eq!(table.storage_price("STANDARD"), 0.01);
+        assert!((table.monthly_cost("STANDARD", 1024 * 1024 * 1024 * 10) - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn operation_costs() {
+        let table = PriceTable::default();
+        let gigabyte = 1024 * 1024 * 1024;
+        let cost = |x: Option<f64>| x.map(|x| (x * 1e6).round() / 1e6);
+        assert_eq!(
+            cost(table.download_cost("STANDARD", 1000, gigabyte)),
+            Some(0.0904)
+        );
+        assert_eq!(
+            cost(table.copy_cost("STANDARD_IA", 1000, gigabyte)),
+            Some(0.016)
+        );
+        assert_eq!(table.download_cost("GLACIER", 1, gigabyte), None);
+        assert_eq!(
+            cost(table.restore_cost("GLACIER", "Bulk", 1000, gigabyte * 10)),
+            Some(0.05)
+        );
+        assert_eq!(table.restore_cost("DEEP_ARCHIVE", "Expedited", 1, 1), None);
+        assert_eq!(table.restore_cost("STANDARD", "Bulk", 1, 1), None);
+    }
+}
@@ -0,0 +1,195 @@
+use failure::Error;
+use indicatif::{ProgressBar, ProgressStyle};
+use rusoto_s3::Object;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::command::Pages;
+use crate::events::FindEvents;
+
+/// Live status line of a listing: pages, scanned and matched objects,
+/// current prefix and request rate. With the number of objects known in
+/// advance it is a progress bar of the scanned objects with ETA.
+pub struct ScanProgress {
+    bar: ProgressBar,
+    started: Instant,
+    total: Option<AtomicUsize>,
+    pages: AtomicUsize,
+    scanned: AtomicUsize,
+    matched: AtomicUsize,
+    prefix: Mutex<String>,
+}
+
+impl ScanProgress {
+    pub fn new(enabled: bool) -> Self {
+        let bar = if enabled {
+            ProgressBar::new_spinner()
+        } else {
+            ProgressBar::hidden()
+        };
+        bar.set_style(
+            ProgressStyle::default_spinner().template("{spinner:.green} [{elapsed_precise}] {msg}"),
+        );
+
+        ScanProgress {
+            bar,
+            started: Instant::now(),
+            total: None,
+            pages: AtomicUsize::new(0),
+            scanned: AtomicUsize::new(0),
+            matched: AtomicUsize::new(0),
+            prefix: Mutex::new(String::new()),
+        }
+    }
+
+    /// Progress bar over `total` objects
+    pub fn with_total(total: usize) -> Self {
+        let bar = ProgressBar::new(total as u64);
+        bar.set_style(ProgressStyle::default_bar().template(
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}",
+        ));
+
+        ScanProgress {
+            bar,
+            total: Some(AtomicUsize::new(total)),
+            ..ScanProgress::new(false)
+        }
+    }
+
+    pub fn page(&self, list: &[Object]) {
+        self.pages.fetch_add(1, Ordering::Relaxed);
+        self.scanned.fetch_add(list.len(), Ordering::Relaxed);
+
+        if let Some(key) = list.last().and_then(|x| x.key.as_ref()) {
+            let prefix = Path::new(key)
+                .parent()
+                .and_then(Path::to_str)
+                .unwrap_or_default();
+            *self.prefix.lock().unwrap() = prefix.to_owned();
+        }
+
+        self.update();
+    }
+
+    pub fn matched(&self) {
+        self.matched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self) {
+        self.update();
+        self.bar.finish_and_clear();
+    }
+
+    fn update(&self) {
+        if let Some(ref total) = self.total {
+            // objects created after the counting pass
+            let scanned = self.scanned.load(Ordering::Relaxed);
+            if scanned > total.fetch_max(scanned, Ordering::Relaxed) {
+                self.bar.set_length(scanned as u64);
+            }
+            self.bar.set_position(scanned as u64);
+            self.bar.set_message(&format!(
+                "matched: {}, prefix: {}",
+                self.matched.load(Ordering::Relaxed),
+                self.prefix.lock().unwrap(),
+            ));
+            return;
+        }
+
+        let pages = self.pages.load(Ordering::Relaxed);
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            pages as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        self.bar.set_message(&format!(
+            "pages: {}, scanned: {}, matched: {}, {:.1} req/s, prefix: {}",
+            pages,
+            self.scanned.load(Ordering::Relaxed),
+            self.matched.load(Ordering::Relaxed),
+            rate,
+            self.prefix.lock().unwrap(),
+        ));
+    }
+}
+
+impl FindEvents for ScanProgress {
+    fn on_page_listed(&self, page: &[Object]) {
+        self.page(page);
+    }
+
+    fn on_object_matched(&self, _object: &Object) {
+        self.matched();
+    }
+}
+
+/// Counting pass of --progress=full
+pub fn count_objects(pages: Pages) -> Result<usize, Error> {
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(
+        ProgressStyle::default_spinner().template("{spinner:.green} [{elapsed_precise}] {msg}"),
+    );
+
+    let mut count = 0;
+    for page in pages {
+        count += page?.len();
+        bar.set_message(&format!("counting objects: {}", count));
+    }
+    bar.finish_and_clear();
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_progress_counters() {
+        let progress = ScanProgress::new(false);
+        let list = vec![
+            Object {
+                key: Some("path/to/key1".to_owned()),
+                ..Default::default()
+            },
+            Object {
+                key: Some("path/to/key2".to_owned()),
+                ..Default::default()
+            },
+        ];
+
+        progress.page(&list);
+        progress.matched();
+        progress.finish();
+
+        assert_eq!(progress.pages.load(Ordering::Relaxed), 1);
+        assert_eq!(progress.scanned.load(Ordering::Relaxed), 2);
+        assert_eq!(progress.matched.load(Ordering::Relaxed), 1);
+        assert_eq!(*progress.prefix.lock().unwrap(), "path/to");
+    }
+
+    #[test]
+    fn scan_progress_total() -> Result<(), Error> {
+        let page = || {
+            vec![Object {
+                key: Some("path/to/key".to_owned()),
+                ..Default::default()
+            }]
+        };
+        let pages: Pages = Box::new(vec![Ok(page()), Ok(page())].into_iter());
+        let total = count_objects(pages)?;
+        assert_eq!(total, 2);
+
+        let progress = ScanProgress::with_total(total);
+        progress.page(&page());
+        progress.page(&page());
+        progress.page(&page());
+
+        assert_eq!(progress.total.as_ref().unwrap().load(Ordering::Relaxed), 3);
+        progress.finish();
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+
+/// Relative accuracy of the reported quantiles
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Streaming quantile sketch with bounded memory.
+///
+/// Values are counted in logarithmically sized buckets, so every reported
+/// quantile is within `RELATIVE_ACCURACY` of the real value, while the
+/// number of buckets grows only with the logarithm of the value range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantileSketch {
+    gamma_ln: f64,
+    buckets: BTreeMap<i32, u64>,
+    zero_count: u64,
+    count: u64,
+}
+
+impl Default for QuantileSketch {
+    fn default() -> Self {
+        let gamma = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+        QuantileSketch {
+            gamma_ln: gamma.ln(),
+            buckets: BTreeMap::new(),
+            zero_count: 0,
+            count: 0,
+        }
+    }
+}
+
+impl QuantileSketch {
+    pub fn add(&mut self, value: i64) {
+        self.count += 1;
+        if value <= 0 {
+            self.zero_count += 1;
+        } else {
+            let index = ((value as f64).ln() / self.gamma_ln).ceil() as i32;
+            *self.buckets.entry(index).or_insert(0) += 1;
+        }
+    }
+
+    /// Add the values counted by `other`, e.g. of another bucket
+    pub fn merge(&mut self, other: &QuantileSketch) {
+        self.count += other.count;
+        self.zero_count += other.zero_count;
+        for (index, count) in &other.buckets {
+            *self.buckets.entry(*index).or_insert(0) += count;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Approximate value at quantile `q` (0.0..=1.0), None for an empty sketch
+    pub fn quantile(&self, q: f64) -> Option<i64> {
+        if self.count == 0 {
+            return None;
+        }
+
+        // nearest rank: the smallest value with at least q of the values at or below it
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1) - 1;
+        if rank < self.zero_count {
+            return Some(0);
+        }
+
+        let mut seen = self.zero_count;
+        for (index, count) in &self.buckets {
+            seen += count;
+            if seen > rank {
+                let gamma = self.gamma_ln.exp();
+                let value = 2.0 * gamma.powi(*index) / (gamma + 1.0);
+                return Some(value.round() as i64);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantile_merge() {
+        let mut low = QuantileSketch::default();
+        let mut high = QuantileSketch::default();
+        let mut all = QuantileSketch::default();
+        for value in 0..1000 {
+            all.add(value);
+            if value < 500 {
+                low.add(value);
+            } else {
+                high.add(value);
+            }
+        }
+
+        low.merge(&high);
+        assert_eq!(low, all);
+    }
+
+    #[test]
+    fn quantile_empty() {
+        let sketch = QuantileSketch::default();
+        assert_eq!(sketch.quantile(0.5), None);
+    }
+
+    #[test]
+    fn quantile_accuracy() {
+        let mut sketch = QuantileSketch::default();
+        for x in 1..=10_000 {
+            sketch.add(x);
+        }
+
+        assert_eq!(sketch.count(), 10_000);
+        for (q, expected) in &[(0.5, 5_000.0), (0.9, 9_000.0), (0.99, 9_900.0)] {
+            let value = sketch.quantile(*q).unwrap() as f64;
+            assert!((value - expected).abs() / expected <= 2.0 * RELATIVE_ACCURACY);
+        }
+    }
+
+    #[test]
+    fn quantile_small_set() {
+        let mut sketch = QuantileSketch::default();
+        for x in &[3000, 4000, 5000] {
+            sketch.add(*x);
+        }
+
+        for (q, expected) in &[(0.0, 3000.0), (0.5, 4000.0), (0.9, 5000.0), (0.99, 5000.0)] {
+            let value = sketch.quantile(*q).unwrap() as f64;
+            assert!((value - expected).abs() / expected <= RELATIVE_ACCURACY);
+        }
+    }
+
+    #[test]
+    fn quantile_zeros() {
+        let mut sketch = QuantileSketch::default();
+        sketch.add(0);
+        sketch.add(0);
+        sketch.add(100);
+
+        assert_eq!(sketch.quantile(0.5), Some(0));
+        assert_eq!(sketch.quantile(1.0), Some(100));
+    }
+}
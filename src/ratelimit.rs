@@ -0,0 +1,105 @@
+use futures::future::{self, Either};
+use futures::Future;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+static LIMITER: Mutex<Option<TokenBucket>> = Mutex::new(None);
+
+/// Limit the outgoing S3 requests to `rps` per second
+pub fn set_rate_limit(rps: Option<f64>) {
+    *LIMITER.lock().unwrap() = rps.map(|x| TokenBucket::new(x, Instant::now()));
+}
+
+/// Block until the next request is allowed
+pub fn acquire() {
+    let wait = reserve();
+    if wait > Duration::from_secs(0) {
+        thread::sleep(wait);
+    }
+}
+
+/// Resolve when the next request is allowed, waiting on a timer of the
+/// tokio runtime instead of blocking its thread
+pub fn acquire_future() -> impl Future<Item = (), Error = ()> {
+    let wait = reserve();
+    if wait > Duration::from_secs(0) {
+        Either::A(Delay::new(Instant::now() + wait).then(|_| Ok(())))
+    } else {
+        Either::B(future::ok(()))
+    }
+}
+
+fn reserve() -> Duration {
+    match *LIMITER.lock().unwrap() {
+        Some(ref mut bucket) => bucket.reserve(Instant::now()),
+        None => Duration::from_secs(0),
+    }
+}
+
+/// Token bucket refilled at `rate` tokens per second, holding up to one second of requests.
+/// Tokens may go negative: a caller reserves its token and waits for it outside of the lock.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, now: Instant) -> Self {
+        let capacity = rate.max(1.0);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    /// Take a token, returns the time to wait until it is available
+    pub fn reserve(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.tokens -= 1.0;
+
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, start);
+
+        assert_eq!(bucket.reserve(start), Duration::from_secs(0));
+        assert_eq!(bucket.reserve(start), Duration::from_secs(0));
+        assert_eq!(bucket.reserve(start), Duration::from_millis(500));
+        assert_eq!(bucket.reserve(start), Duration::from_secs(1));
+
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.reserve(later), Duration::from_secs(0));
+        assert_eq!(bucket.reserve(later), Duration::from_secs(0));
+        assert_eq!(bucket.reserve(later), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn token_bucket_fractional_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(0.5, start);
+
+        assert_eq!(bucket.reserve(start), Duration::from_secs(0));
+        assert_eq!(bucket.reserve(start), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,388 @@
+use failure::Error;
+use rusoto_core::Region;
+use rusoto_s3::{Object, S3Client};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::arg::{ReconcileReport, S3path};
+use crate::command::{FilterList, FindIter};
+use crate::enrich::EnrichmentCache;
+use crate::function::RunCommand;
+use crate::ops;
+use crate::output::OutputSink;
+use crate::pagesize::MAX_PAGE_SIZE;
+
+/// A key of one side of the reconciliation
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyEntry {
+    pub url: String,
+    pub size: i64,
+    pub e_tag: String,
+}
+
+impl KeyEntry {
+    pub(crate) fn new(bucket: &str, object: &Object) -> Self {
+        KeyEntry {
+            url: format!(
+                "s3://{}/{}",
+                bucket,
+                object.key.as_deref().unwrap_or_default()
+            ),
+            size: object.size.unwrap_or_default(),
+            e_tag: object
+                .e_tag
+                .as_deref()
+                .unwrap_or_default()
+                .trim_matches('"')
+                .to_owned(),
+        }
+    }
+
+    /// Part count of a multipart upload, from the suffix of its ETag
+    fn parts(&self) -> Option<&str> {
+        self.e_tag.split('-').nth(1)
+    }
+
+    /// Why the keys differ, None when they match. The ETag of a multipart
+    /// upload depends on the part size, so the ETags are compared only when
+    /// the keys have the same number of parts, and the sizes otherwise
+    pub fn mismatch(&self, other: &KeyEntry) -> Option<&'static str> {
+        if self.size != other.size {
+            Some("size")
+        } else if self.parts() == other.parts() && self.e_tag != other.e_tag {
+            Some("etag")
+        } else {
+            None
+        }
+    }
+}
+
+/// A difference between the matched keys and the destination
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// A matched key without a destination key
+    Missing { source: String, destination: String },
+    /// A destination key without a matched key
+    Extra { destination: String },
+    /// Keys of the same relative key with another size or ETag
+    Mismatched {
+        source: String,
+        destination: String,
+        reason: &'static str,
+    },
+}
+
+fn quote(url: &str) -> String {
+    format!("'{}'", url.replace('\'', r"'\''"))
+}
+
+impl Difference {
+    pub fn status(&self) -> &'static str {
+        match self {
+            Difference::Missing { .. } => "missing",
+            Difference::Extra { .. } => "extra",
+            Difference::Mismatched { .. } => "mismatched",
+        }
+    }
+
+    /// The aws s3 command fixing the difference
+    pub fn remediation(&self) -> String {
+        match self {
+            Difference::Missing {
+                source,
+                destination,
+            }
+            | Difference::Mismatched {
+                source,
+                destination,
+                ..
+            } => format!("aws s3 cp {} {}", quote(source), quote(destination)),
+            Difference::Extra { destination } => format!("aws s3 rm {}", quote(destination)),
+        }
+    }
+}
+
+/// Differences of the keys by relative key, in key order
+pub fn reconcile(
+    source: BTreeMap<String, KeyEntry>,
+    mut destination: BTreeMap<String, KeyEntry>,
+    target: &S3path,
+) -> Result<(usize, Vec<Difference>), Error> {
+    let mut matched = 0;
+    let mut differences = Vec::new();
+    for (key, entry) in source {
+        match destination.remove(&key) {
+            Some(other) => match entry.mismatch(&other) {
+                Some(reason) => differences.push(Difference::Mismatched {
+                    source: entry.url,
+                    destination: other.url,
+                    reason,
+                }),
+                None => matched += 1,
+            },
+            None => differences.push(Difference::Missing {
+                source: entry.url,
+                destination: format!(
+                    "s3://{}/{}",
+                    target.bucket,
+                    ops::target_key(&key, target, false)?
+                ),
+            }),
+        }
+    }
+    differences.extend(
+        destination
+            .into_values()
+            .map(|x| Difference::Extra { destination: x.url }),
+    );
+    Ok((matched, differences))
+}
+
+/// The keys under a path matching the filters, by relative key
+pub(crate) fn list_keys(
+    client: &S3Client,
+    path: &S3path,
+    filters: &FilterList,
+) -> Result<BTreeMap<String, KeyEntry>, Error> {
+    let listing = FindIter {
+        client: client.clone(),
+        path: path.clone(),
+        token: None,
+        page_size: MAX_PAGE_SIZE,
+        tuner: None,
+        encoding_type: None,
+        fetch_owner: false,
+        delimiter: None,
+        initial: true,
+    };
+
+    let mut keys = BTreeMap::new();
+    for page in listing {
+        for object in page?.iter().filter(|x| filters.test_match(x)) {
+            let key = object.key.as_deref().unwrap_or_default();
+            keys.insert(
+                path.relative_key(key).to_owned(),
+                KeyEntry::new(&path.bucket, object),
+            );
+        }
+    }
+    Ok(keys)
+}
+
+/// Command of report reconcile, the destination is listed and compared at
+/// the end of the run
+pub struct Reconciler {
+    options: ReconcileReport,
+    /// The client of the destination, in the region of its bucket
+    client: S3Client,
+    /// The filters of the search, the destination keys are matched as the
+    /// source keys are
+    filters: FilterList,
+    keys: Mutex<BTreeMap<String, KeyEntry>>,
+}
+
+impl Reconciler {
+    pub fn new(options: ReconcileReport, client: S3Client, filters: FilterList) -> Self {
+        Reconciler {
+            options,
+            client,
+            filters,
+            keys: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The lines of the differences and of the totals, with the commands of
+    /// --remediation
+    fn report(&self, matched: usize, differences: &[Difference]) -> Vec<String> {
+        let mut totals = BTreeMap::new();
+        totals.insert("matched", matched);
+        for status in &["missing", "extra", "mismatched"] {
+            totals.insert(*status, 0);
+        }
+
+        let mut lines = Vec::new();
+        for difference in differences {
+            *totals.entry(difference.status()).or_default() += 1;
+            if self.options.json {
+                let mut document = match difference {
+                    Difference::Missing {
+                        source,
+                        destination,
+                    } => json!({ "source": source, "destination": destination }),
+                    Difference::Extra { destination } => json!({ "destination": destination }),
+                    Difference::Mismatched {
+                        source,
+                        destination,
+                        reason,
+                    } => json!({ "source": source, "destination": destination, "reason": reason }),
+                };
+                document["status"] = json!(difference.status());
+                if self.options.remediation {
+                    document["remediation"] = json!(difference.remediation());
+                }
+                lines.push(document.to_string());
+                continue;
+            }
+
+            lines.push(match difference {
+                Difference::Missing { source, .. } => format!("missing\t{}", source),
+                Difference::Extra { destination } => format!("extra\t{}", destination),
+                Difference::Mismatched {
+                    source,
+                    destination,
+                    reason,
+                } => format!("mismatched\t{}\t{}\t{}", reason, source, destination),
+            });
+        }
+
+        if self.options.json {
+            lines.push(json!({ "totals": totals }).to_string());
+            return lines;
+        }
+        lines.extend(
+            totals
+                .iter()
+                .map(|(status, count)| format!("total\t{}\t{}", status, count)),
+        );
+        if self.options.remediation {
+            lines.extend(differences.iter().map(Difference::remediation));
+        }
+        lines
+    }
+}
+
+impl RunCommand for Reconciler {
+    fn execute(
+        &self,
+        _c: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let mut keys = self.keys.lock().unwrap();
+        for object in list {
+            let key = object.key.as_deref().unwrap_or_default();
+            keys.insert(
+                path.relative_key(key).to_owned(),
+                KeyEntry::new(&path.bucket, object),
+            );
+        }
+        Ok(())
+    }
+
+    fn finish(&self, output: &dyn OutputSink) -> Result<(), Error> {
+        let source = std::mem::take(&mut *self.keys.lock().unwrap());
+        let mut destination = list_keys(&self.client, &self.options.destination, &self.filters)?;
+        if self.options.ignore_extra {
+            destination.retain(|key, _| source.contains_key(key));
+        }
+
+        let (matched, differences) = reconcile(source, destination, &self.options.destination)?;
+        for line in self.report(matched, &differences) {
+            output.line(&line);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconcile_keys() -> Result<(), Error> {
+        let source: S3path = "s3://source/data/".parse()?;
+        let destination: S3path = "s3://backup/copy".parse()?;
+        let object = |key: &str, size: i64, e_tag: &str| Object {
+            key: Some(key.to_owned()),
+            size: Some(size),
+            e_tag: Some(format!("\"{}\"", e_tag)),
+            ..Default::default()
+        };
+        let entries = |path: &S3path, objects: &[Object]| -> BTreeMap<String, KeyEntry> {
+            objects
+                .iter()
+                .map(|x| {
+                    let key = path.relative_key(x.key.as_deref().unwrap_or_default());
+                    (key.to_owned(), KeyEntry::new(&path.bucket, x))
+                })
+                .collect()
+        };
+
+        let (matched, differences) = reconcile(
+            entries(
+                &source,
+                &[
+                    object("data/a", 10, "aa"),
+                    object("data/b", 10, "bb"),
+                    object("data/c", 10, "cc"),
+                    object("data/d", 100, "d1-2"),
+                    object("data/it's", 1, "ee"),
+                ],
+            ),
+            entries(
+                &destination,
+                &[
+                    object("copy/a", 10, "aa"),
+                    object("copy/b", 10, "b2"),
+                    object("copy/c", 20, "cc"),
+                    // uploaded with another part size
+                    object("copy/d", 100, "d2-7"),
+                    object("copy/z", 1, "zz"),
+                ],
+            ),
+            &destination,
+        )?;
+
+        assert_eq!(matched, 2);
+        assert_eq!(
+            differences
+                .iter()
+                .map(|x| (x.status(), x.remediation()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "mismatched",
+                    "aws s3 cp 's3://source/data/b' 's3://backup/copy/b'".to_owned()
+                ),
+                (
+                    "mismatched",
+                    "aws s3 cp 's3://source/data/c' 's3://backup/copy/c'".to_owned()
+                ),
+                (
+                    "missing",
+                    r"aws s3 cp 's3://source/data/it'\''s' 's3://backup/copy/it'\''s'".to_owned()
+                ),
+                ("extra", "aws s3 rm 's3://backup/copy/z'".to_owned()),
+            ]
+        );
+
+        let reconciler = Reconciler::new(
+            ReconcileReport {
+                destination: destination.clone(),
+                ignore_extra: false,
+                remediation: false,
+                json: false,
+            },
+            S3Client::new(Region::UsEast1),
+            FilterList(Vec::new()),
+        );
+        assert_eq!(
+            reconciler.report(matched, &differences),
+            vec![
+                "mismatched\tetag\ts3://source/data/b\ts3://backup/copy/b",
+                "mismatched\tsize\ts3://source/data/c\ts3://backup/copy/c",
+                "missing\ts3://source/data/it's",
+                "extra\ts3://backup/copy/z",
+                "total\textra\t1",
+                "total\tmatched\t2",
+                "total\tmismatched\t2",
+                "total\tmissing\t1",
+            ]
+        );
+        Ok(())
+    }
+}
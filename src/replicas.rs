@@ -0,0 +1,396 @@
+use failure::Error;
+use rusoto_core::Region;
+use rusoto_s3::{Object, S3Client};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use crate::arg::{FindOpt, ReplicasReport, S3path};
+use crate::buckets::{bucket_region, client_region};
+use crate::command::{get_client, FilterList};
+use crate::enrich::EnrichmentCache;
+use crate::function::RunCommand;
+use crate::ops;
+use crate::output::OutputSink;
+use crate::reconcile::{list_keys, KeyEntry};
+use crate::report::ErrorReport;
+
+/// A key of the dataset merged with its copies on the replicas
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergedKey {
+    /// Key relative to the paths
+    pub key: String,
+    /// The matched key, None for a key found only on replicas
+    pub source: Option<KeyEntry>,
+    /// The copy of each replica, None when the replica misses the key
+    pub copies: Vec<Option<KeyEntry>>,
+}
+
+/// State of the copy of a replica
+#[derive(Debug, Clone, PartialEq)]
+pub enum CopyStatus {
+    Current,
+    Missing,
+    /// Another size or ETag than the matched key
+    Stale(&'static str),
+    /// A copy of a key that was not matched
+    Extra,
+}
+
+impl CopyStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CopyStatus::Current => "current",
+            CopyStatus::Missing => "missing",
+            CopyStatus::Stale(_) => "stale",
+            CopyStatus::Extra => "extra",
+        }
+    }
+}
+
+impl MergedKey {
+    pub fn status(&self, replica: usize) -> CopyStatus {
+        match (&self.source, &self.copies[replica]) {
+            (Some(_), None) => CopyStatus::Missing,
+            (Some(source), Some(copy)) => match source.mismatch(copy) {
+                Some(reason) => CopyStatus::Stale(reason),
+                None => CopyStatus::Current,
+            },
+            (None, _) => CopyStatus::Extra,
+        }
+    }
+
+    /// Every replica has the version of the matched key
+    pub fn replicated(&self) -> bool {
+        self.source.is_some()
+            && (0..self.copies.len()).all(|i| self.status(i) == CopyStatus::Current)
+    }
+
+    /// The distinct versions of the key by ETag and size, with their URLs
+    pub fn versions(&self) -> BTreeMap<(String, i64), Vec<String>> {
+        let mut versions: BTreeMap<(String, i64), Vec<String>> = BTreeMap::new();
+        for entry in self.source.iter().chain(self.copies.iter().flatten()) {
+            versions
+                .entry((entry.e_tag.clone(), entry.size))
+                .or_default()
+                .push(entry.url.clone());
+        }
+        versions
+    }
+}
+
+/// Merge the matched keys with the keys of the replicas by relative key, in
+/// key order
+pub fn merge(
+    mut source: BTreeMap<String, KeyEntry>,
+    mut replicas: Vec<BTreeMap<String, KeyEntry>>,
+) -> Vec<MergedKey> {
+    let keys: BTreeSet<String> = source
+        .keys()
+        .chain(replicas.iter().flat_map(|x| x.keys()))
+        .cloned()
+        .collect();
+    keys.into_iter()
+        .map(|key| MergedKey {
+            source: source.remove(&key),
+            copies: replicas.iter_mut().map(|x| x.remove(&key)).collect(),
+            key,
+        })
+        .collect()
+}
+
+/// A client for each path in the region of its bucket, from GetBucketLocation.
+/// The client of the options is kept when the region is unknown
+pub(crate) fn regional_clients(
+    opts: &FindOpt,
+    client: &S3Client,
+    paths: &[S3path],
+) -> Result<Vec<S3Client>, Error> {
+    paths
+        .iter()
+        .map(|path| {
+            match bucket_region(client, &path.bucket).and_then(|x| Region::from_str(&x).ok()) {
+                Some(region) => get_client(opts, client_region(opts, &region)),
+                None => Ok(client.clone()),
+            }
+        })
+        .collect()
+}
+
+fn path_url(path: &S3path) -> String {
+    format!(
+        "s3://{}/{}",
+        path.bucket,
+        path.prefix.as_deref().unwrap_or("")
+    )
+}
+
+/// Command of report replicas, the replicas are listed and merged at the end
+/// of the run
+pub struct ReplicaMerger {
+    options: ReplicasReport,
+    /// The client of each replica, in the region of its bucket
+    clients: Vec<S3Client>,
+    keys: Mutex<BTreeMap<String, KeyEntry>>,
+}
+
+impl ReplicaMerger {
+    pub fn new(options: ReplicasReport, clients: Vec<S3Client>) -> Self {
+        ReplicaMerger {
+            options,
+            clients,
+            keys: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// URL of the copy of a key on a replica
+    fn copy_url(&self, replica: usize, merged: &MergedKey) -> Result<String, Error> {
+        Ok(match merged.copies[replica] {
+            Some(ref copy) => copy.url.clone(),
+            None => {
+                let path = &self.options.also[replica];
+                let key = ops::target_key(&merged.key, path, false)?;
+                format!("s3://{}/{}", path.bucket, key)
+            }
+        })
+    }
+
+    /// The lines of the merged keys and of the totals per replica
+    fn report(&self, merged: &[MergedKey]) -> Result<Vec<String>, Error> {
+        let mut totals = vec![BTreeMap::new(); self.options.also.len()];
+        for counts in totals.iter_mut() {
+            for status in &["current", "missing", "stale", "extra"] {
+                counts.insert(*status, 0);
+            }
+        }
+        let (mut keys, mut replicated) = (0, 0);
+
+        let mut lines = Vec::new();
+        for key in merged {
+            if key.source.is_none() && self.options.ignore_extra {
+                continue;
+            }
+            keys += 1;
+            if key.replicated() {
+                replicated += 1;
+            }
+
+            let mut problems = Vec::new();
+            for (i, counts) in totals.iter_mut().enumerate() {
+                let status = key.status(i);
+                if status == CopyStatus::Extra && key.copies[i].is_none() {
+                    continue;
+                }
+                *counts.entry(status.name()).or_default() += 1;
+                if status != CopyStatus::Current {
+                    problems.push((status, self.copy_url(i, key)?));
+                }
+            }
+
+            if self.options.json {
+                let versions: Vec<Value> = key
+                    .versions()
+                    .into_iter()
+                    .map(|((e_tag, size), urls)| json!({ "e_tag": e_tag, "size": size, "urls": urls }))
+                    .collect();
+                let problems: Vec<Value> = problems
+                    .iter()
+                    .map(|(status, url)| match status {
+                        CopyStatus::Stale(reason) => {
+                            json!({ "status": status.name(), "url": url, "reason": reason })
+                        }
+                        _ => json!({ "status": status.name(), "url": url }),
+                    })
+                    .collect();
+                lines.push(
+                    json!({
+                        "key": key.key,
+                        "replicated": key.replicated(),
+                        "versions": versions,
+                        "replicas": problems,
+                    })
+                    .to_string(),
+                );
+                continue;
+            }
+
+            if let Some(ref source) = key.source {
+                lines.push(format!(
+                    "{}\t{}\t{}\t{}",
+                    if key.replicated() {
+                        "replicated"
+                    } else {
+                        "matched"
+                    },
+                    source.e_tag,
+                    source.size,
+                    source.url
+                ));
+            }
+            for (status, url) in problems {
+                lines.push(match status {
+                    CopyStatus::Stale(reason) => format!("stale\t{}\t{}", reason, url),
+                    _ => format!("{}\t{}", status.name(), url),
+                });
+            }
+        }
+
+        if self.options.json {
+            let replicas: Map<String, Value> = self
+                .options
+                .also
+                .iter()
+                .zip(totals)
+                .map(|(path, counts)| (path_url(path), json!(counts)))
+                .collect();
+            lines.push(
+                json!({
+                    "totals": { "keys": keys, "replicated": replicated, "replicas": replicas }
+                })
+                .to_string(),
+            );
+            return Ok(lines);
+        }
+        lines.push(format!("total\tkeys\t{}", keys));
+        lines.push(format!("total\treplicated\t{}", replicated));
+        for (path, counts) in self.options.also.iter().zip(totals) {
+            lines.extend(counts.iter().map(|(status, count)| {
+                format!("total\t{}\t{}\t{}", status, path_url(path), count)
+            }));
+        }
+        Ok(lines)
+    }
+}
+
+impl RunCommand for ReplicaMerger {
+    fn execute(
+        &self,
+        _c: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let mut keys = self.keys.lock().unwrap();
+        for object in list {
+            let key = object.key.as_deref().unwrap_or_default();
+            keys.insert(
+                path.relative_key(key).to_owned(),
+                KeyEntry::new(&path.bucket, object),
+            );
+        }
+        Ok(())
+    }
+
+    /// A replica that cannot be listed is reported and merged as empty
+    fn finish(&self, output: &dyn OutputSink) -> Result<(), Error> {
+        let source = std::mem::take(&mut *self.keys.lock().unwrap());
+        let replicas = self
+            .options
+            .also
+            .iter()
+            .zip(&self.clients)
+            .map(|(path, client)| {
+                list_keys(client, path, &FilterList(Vec::new())).unwrap_or_else(|e| {
+                    ErrorReport::new("ListObjectsV2", &path.bucket, None, &e).emit();
+                    BTreeMap::new()
+                })
+            })
+            .collect();
+
+        for line in self.report(&merge(source, replicas))? {
+            output.line(&line);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_replicas() -> Result<(), Error> {
+        let source: S3path = "s3://bucket-us/data/".parse()?;
+        let eu: S3path = "s3://bucket-eu/data".parse()?;
+        let ap: S3path = "s3://bucket-ap/copy/".parse()?;
+        let entries = |path: &S3path, objects: &[(&str, i64, &str)]| {
+            objects
+                .iter()
+                .map(|(key, size, e_tag)| {
+                    let object = Object {
+                        key: Some(format!("{}{}", path.prefix.as_deref().unwrap_or(""), key)),
+                        size: Some(*size),
+                        e_tag: Some(format!("\"{}\"", e_tag)),
+                        ..Default::default()
+                    };
+                    (
+                        key.trim_start_matches('/').to_owned(),
+                        KeyEntry::new(&path.bucket, &object),
+                    )
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        let merged = merge(
+            entries(&source, &[("a", 10, "aa"), ("b", 10, "bb")]),
+            vec![
+                entries(&eu, &[("/a", 10, "aa"), ("/b", 10, "b0"), ("/z", 1, "zz")]),
+                entries(&ap, &[("a", 10, "aa")]),
+            ],
+        );
+        assert_eq!(
+            merged.iter().map(|x| x.key.as_str()).collect::<Vec<_>>(),
+            vec!["a", "b", "z"]
+        );
+        assert!(merged[0].replicated());
+        // deduplicated by ETag and size
+        assert_eq!(merged[0].versions().len(), 1);
+        assert_eq!(merged[1].versions().len(), 2);
+        assert_eq!(merged[1].status(0), CopyStatus::Stale("etag"));
+        assert_eq!(merged[1].status(1), CopyStatus::Missing);
+
+        let options = ReplicasReport {
+            also: vec![eu, ap],
+            ignore_extra: false,
+            json: false,
+        };
+        let clients = vec![
+            S3Client::new(Region::EuWest1),
+            S3Client::new(Region::ApSoutheast1),
+        ];
+        let merger = ReplicaMerger::new(options.clone(), clients.clone());
+        assert_eq!(
+            merger.report(&merged)?,
+            vec![
+                "replicated\taa\t10\ts3://bucket-us/data/a",
+                "matched\tbb\t10\ts3://bucket-us/data/b",
+                "stale\tetag\ts3://bucket-eu/data/b",
+                "missing\ts3://bucket-ap/copy/b",
+                "extra\ts3://bucket-eu/data/z",
+                "total\tkeys\t3",
+                "total\treplicated\t1",
+                "total\tcurrent\ts3://bucket-eu/data\t1",
+                "total\textra\ts3://bucket-eu/data\t1",
+                "total\tmissing\ts3://bucket-eu/data\t0",
+                "total\tstale\ts3://bucket-eu/data\t1",
+                "total\tcurrent\ts3://bucket-ap/copy/\t1",
+                "total\textra\ts3://bucket-ap/copy/\t0",
+                "total\tmissing\ts3://bucket-ap/copy/\t1",
+                "total\tstale\ts3://bucket-ap/copy/\t0",
+            ]
+        );
+
+        let merger = ReplicaMerger::new(
+            ReplicasReport {
+                ignore_extra: true,
+                ..options
+            },
+            clients,
+        );
+        assert_eq!(merger.report(&merged)?.len(), 14);
+        Ok(())
+    }
+}
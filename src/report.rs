@@ -0,0 +1,350 @@
+use failure::Error;
+use regex::Regex;
+use rusoto_core::RusotoError;
+use rusoto_s3::Object;
+use serde_json::json;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::arg::{ColorChoice, ErrorFormat};
+use crate::error::ErrorKind;
+use crate::events;
+
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+static COLOR_ERRORS: AtomicBool = AtomicBool::new(false);
+static ERROR_COUNT: AtomicUsize = AtomicUsize::new(0);
+static ERROR_KINDS: AtomicUsize = AtomicUsize::new(0);
+static COLLECTORS: Mutex<Vec<Arc<Mutex<Failures>>>> = Mutex::new(Vec::new());
+
+/// Select the format of the errors reported on stderr
+pub fn set_error_format(format: ErrorFormat) {
+    JSON_ERRORS.store(format == ErrorFormat::Json, Ordering::Relaxed);
+}
+
+/// Color the text errors reported on stderr in red
+pub fn set_error_color(color: ColorChoice) {
+    COLOR_ERRORS.store(color.enabled(io::stderr().is_terminal()), Ordering::Relaxed);
+}
+
+/// Number of errors reported during the run
+pub fn error_count() -> usize {
+    ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+/// Whether an error of this kind has been reported during the run
+pub fn error_reported(kind: ErrorKind) -> bool {
+    ERROR_KINDS.load(Ordering::Relaxed) & (1 << kind as usize) != 0
+}
+
+/// Keys of a bucket whose operation failed while [`collect_failures`] ran
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Failures {
+    bucket: String,
+    keys: HashSet<String>,
+    /// A failure without a key, every key has failed
+    all: bool,
+}
+
+impl Failures {
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.keys.is_empty()
+    }
+
+    pub fn contains(&self, object: &Object) -> bool {
+        self.all || object.key.as_ref().is_some_and(|x| self.keys.contains(x))
+    }
+
+    /// The objects which have not failed
+    pub fn succeeded(&self, objects: &[Object]) -> Vec<Object> {
+        objects
+            .iter()
+            .filter(|x| !self.contains(x))
+            .cloned()
+            .collect()
+    }
+
+    fn add(&mut self, report: &ErrorReport) {
+        if report.bucket != self.bucket {
+            return;
+        }
+        match report.key {
+            Some(ref key) => {
+                self.keys.insert(key.clone());
+            }
+            None => self.all = true,
+        }
+    }
+}
+
+/// Run `f` and return the failures it has reported in `bucket`, the commands
+/// report them per key without stopping
+pub fn collect_failures<T>(bucket: &str, f: impl FnOnce() -> T) -> (T, Failures) {
+    let collector = Arc::new(Mutex::new(Failures {
+        bucket: bucket.to_owned(),
+        ..Default::default()
+    }));
+    COLLECTORS.lock().unwrap().push(collector.clone());
+    let result = f();
+    COLLECTORS
+        .lock()
+        .unwrap()
+        .retain(|x| !Arc::ptr_eq(x, &collector));
+
+    let failures = collector.lock().unwrap().clone();
+    (result, failures)
+}
+
+/// Exit code of the CLI: the kind of the reported errors first, then 1 on a
+/// fatal error and 2 when some operations have failed
+pub fn exit_code(fatal: bool) -> i32 {
+    let kind = [
+        ErrorKind::Credentials,
+        ErrorKind::AccessDenied,
+        ErrorKind::Throttling,
+    ]
+    .iter()
+    .find(|x| error_reported(**x));
+
+    match kind.and_then(|x| x.exit_code()) {
+        Some(code) => code,
+        None if fatal => 1,
+        None if error_count() > 0 => 2,
+        None => 0,
+    }
+}
+
+/// Failure of a single S3 operation, reported without stopping the run
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    pub operation: String,
+    pub bucket: String,
+    pub key: Option<String>,
+    pub code: Option<String>,
+    pub request_id: Option<String>,
+    pub message: String,
+}
+
+impl ErrorReport {
+    pub fn new(operation: &str, bucket: &str, key: Option<&str>, error: &Error) -> Self {
+        ErrorReport {
+            operation: operation.to_owned(),
+            bucket: bucket.to_owned(),
+            key: key.map(str::to_owned),
+            kind: ErrorKind::of(error),
+            message: error.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_rusoto<E>(
+        operation: &str,
+        bucket: &str,
+        key: Option<&str>,
+        error: &RusotoError<E>,
+    ) -> Self
+    where
+        E: std::error::Error + fmt::Debug + 'static,
+    {
+        let (code, request_id, status) = match error {
+            RusotoError::Service(e) => {
+                let debug = format!("{:?}", e);
+                let code = debug.split('(').next().map(str::to_owned);
+                (code, None, None)
+            }
+            RusotoError::Unknown(response) => {
+                let body = response.body_as_str();
+                let request_id = response
+                    .headers
+                    .get("x-amz-request-id")
+                    .cloned()
+                    .or_else(|| xml_field(body, "RequestId"));
+                let status = response.status.as_u16();
+                (xml_field(body, "Code"), request_id, Some(status))
+            }
+            RusotoError::Credentials(_) => (Some("CredentialsError".to_owned()), None, None),
+            _ => (None, None, None),
+        };
+
+        let kind = match (code.as_deref().map(ErrorKind::from_code), status) {
+            (Some(kind), _) if kind != ErrorKind::Other => kind,
+            (_, Some(status)) => ErrorKind::from_status(status),
+            _ => ErrorKind::Other,
+        };
+
+        ErrorReport {
+            kind,
+            operation: operation.to_owned(),
+            bucket: bucket.to_owned(),
+            key: key.map(str::to_owned),
+            code,
+            request_id,
+            message: error.to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        json!({
+            "kind": self.kind.to_string(),
+            "operation": self.operation,
+            "bucket": self.bucket,
+            "key": self.key,
+            "code": self.code,
+            "request_id": self.request_id,
+            "message": self.message,
+        })
+        .to_string()
+    }
+
+    /// Write the report to stderr in the selected format
+    pub fn emit(&self) {
+        ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+        ERROR_KINDS.fetch_or(1 << self.kind as usize, Ordering::Relaxed);
+        for collector in COLLECTORS.lock().unwrap().iter() {
+            collector.lock().unwrap().add(self);
+        }
+        events::error_reported(self);
+        if JSON_ERRORS.load(Ordering::Relaxed) {
+            eprintln!("{}", self.to_json());
+        } else if COLOR_ERRORS.load(Ordering::Relaxed) {
+            eprintln!("\x1b[31m{}\x1b[0m", self);
+        } else {
+            eprintln!("{}", self);
+        }
+    }
+}
+
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed", self.operation)?;
+        if !self.bucket.is_empty() {
+            write!(f, ": s3://{}", self.bucket)?;
+        }
+        if let Some(ref key) = self.key {
+            write!(f, "/{}", key)?;
+        }
+        if let Some(ref code) = self.code {
+            write!(f, ": {}", code)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Returned to library callers in a [`S3FindError`](crate::error::S3FindError),
+/// the kind, AWS code and request id of the failure are in the fields
+impl std::error::Error for ErrorReport {}
+
+pub(crate) fn xml_field(body: &str, field: &str) -> Option<String> {
+    let re = Regex::new(&format!("<{0}>([^<]*)</{0}>", field)).ok()?;
+    re.captures(body)
+        .and_then(|x| x.get(1))
+        .map(|x| x.as_str().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::S3FindError;
+    use rusoto_s3::GetObjectError;
+
+    #[test]
+    fn report_from_service_error() {
+        let error: RusotoError<GetObjectError> =
+            RusotoError::Service(GetObjectError::NoSuchKey("missing".to_owned()));
+        let report = ErrorReport::from_rusoto("GetObject", "bucket", Some("key"), &error);
+
+        assert_eq!(report.code, Some("NoSuchKey".to_owned()));
+        assert_eq!(report.kind, ErrorKind::NotFound);
+        assert_eq!(
+            report.to_string(),
+            "GetObject failed: s3://bucket/key: NoSuchKey: missing"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["kind"], "not-found");
+        assert_eq!(json["operation"], "GetObject");
+        assert_eq!(json["key"], "key");
+        assert_eq!(json["code"], "NoSuchKey");
+        assert_eq!(json["request_id"], serde_json::Value::Null);
+
+        match S3FindError::from(report.clone()) {
+            S3FindError::NotFound(ref x) => assert_eq!(x, &report),
+            error => panic!("unexpected error {:?}", error),
+        }
+        let error = S3FindError::from(report);
+        assert_eq!(error.code(), Some("NoSuchKey"));
+        assert_eq!(error.request_id(), None);
+        assert_eq!(
+            error.to_string(),
+            "GetObject failed: s3://bucket/key: NoSuchKey: missing"
+        );
+    }
+
+    #[test]
+    fn error_kinds() {
+        let error: RusotoError<GetObjectError> =
+            RusotoError::Credentials(rusoto_credential::CredentialsError::new("no credentials"));
+        let report = ErrorReport::from_rusoto("GetObject", "bucket", None, &error);
+        assert_eq!(report.kind, ErrorKind::Credentials);
+
+        assert_eq!(ErrorKind::from_code("SlowDown"), ErrorKind::Throttling);
+        assert_eq!(
+            ErrorKind::from_code("AccessDenied"),
+            ErrorKind::AccessDenied
+        );
+        assert_eq!(ErrorKind::from_code("InternalError"), ErrorKind::Other);
+        assert_eq!(ErrorKind::from_status(403), ErrorKind::AccessDenied);
+        assert_eq!(ErrorKind::from_status(500), ErrorKind::Other);
+
+        let io: Error = std::io::Error::from(std::io::ErrorKind::NotFound).into();
+        assert_eq!(ErrorKind::of(&io), ErrorKind::Io);
+        let wrapped: Error = report.into();
+        assert_eq!(ErrorKind::of(&wrapped), ErrorKind::Credentials);
+        assert_eq!(ErrorKind::AccessDenied.exit_code(), Some(4));
+    }
+
+    #[test]
+    fn failure_collection() {
+        let object = |key: &str| Object {
+            key: Some(key.to_owned()),
+            ..Default::default()
+        };
+        let report = |bucket: &str, key: Option<&str>| ErrorReport {
+            operation: "GetObject".to_owned(),
+            bucket: bucket.to_owned(),
+            key: key.map(str::to_owned),
+            ..Default::default()
+        };
+        let objects = [object("a"), object("b")];
+
+        let ((), failures) = collect_failures("failures-test", || {
+            report("failures-test", Some("a")).emit();
+            report("other-bucket", Some("b")).emit();
+            report("other-bucket", None).emit();
+        });
+        assert!(failures.contains(&objects[0]));
+        assert_eq!(failures.succeeded(&objects), vec![object("b")]);
+
+        let (_, failures) = collect_failures("failures-test", || {
+            report("failures-test", None).emit();
+        });
+        assert!(failures.succeeded(&objects).is_empty());
+        let (_, failures) = collect_failures("failures-test", || ());
+        assert!(failures.is_empty());
+    }
+
+    #[test]
+    fn xml_error_fields() {
+        let body = "<Error><Code>AccessDenied</Code><Message>Access Denied</Message>\
+                    <RequestId>4442587FB7D0A2F9</RequestId></Error>";
+        assert_eq!(xml_field(body, "Code"), Some("AccessDenied".to_owned()));
+        assert_eq!(
+            xml_field(body, "RequestId"),
+            Some("4442587FB7D0A2F9".to_owned())
+        );
+        assert_eq!(xml_field(body, "HostId"), None);
+    }
+}
@@ -0,0 +1,273 @@
+use failure::Error;
+use rusoto_s3::Object;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::arg::S3path;
+use crate::error::FunctionError;
+use crate::metrics::write_atomically;
+
+const RESUME_VERSION: u64 = 1;
+
+/// A prefix of a partitioned listing, not started yet when there is no token
+#[derive(Debug, Clone, PartialEq)]
+pub struct Partition {
+    pub prefix: String,
+    pub token: Option<String>,
+}
+
+/// Where a listing continues from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListingState {
+    Sequential { token: Option<String> },
+    Partitioned { partitions: Vec<Partition> },
+    Complete,
+}
+
+impl ListingState {
+    /// The saved state has to be for the requested path
+    pub fn load(file: &Path, path: &S3path) -> Result<Self, Error> {
+        let value: Value = serde_json::from_slice(&fs::read(file)?)
+            .map_err(|_| FunctionError::ResumeFormatError)?;
+
+        if value["version"] != RESUME_VERSION {
+            return Err(FunctionError::ResumeFormatError.into());
+        }
+        if value["bucket"] != path.bucket.as_str() || value["prefix"] != json!(path.prefix) {
+            return Err(FunctionError::ResumePathError.into());
+        }
+
+        let string = |value: &Value| value.as_str().map(str::to_owned);
+        match value["listing"].as_str() {
+            Some("sequential") => Ok(ListingState::Sequential {
+                token: string(&value["token"]),
+            }),
+            Some("partitioned") => {
+                let partitions = value["partitions"]
+                    .as_array()
+                    .ok_or(FunctionError::ResumeFormatError)?
+                    .iter()
+                    .map(|x| {
+                        Ok(Partition {
+                            prefix: string(&x["prefix"]).ok_or(FunctionError::ResumeFormatError)?,
+                            token: string(&x["token"]),
+                        })
+                    })
+                    .collect::<Result<_, Error>>()?;
+                Ok(ListingState::Partitioned { partitions })
+            }
+            Some("complete") => Ok(ListingState::Complete),
+            _ => Err(FunctionError::ResumeFormatError.into()),
+        }
+    }
+
+    pub fn to_json(&self, path: &S3path) -> Value {
+        let mut value = json!({
+            "version": RESUME_VERSION,
+            "bucket": path.bucket,
+            "prefix": path.prefix,
+        });
+
+        match self {
+            ListingState::Sequential { token } => {
+                value["listing"] = json!("sequential");
+                value["token"] = json!(token);
+            }
+            ListingState::Partitioned { partitions } => {
+                value["listing"] = json!("partitioned");
+                value["partitions"] = partitions
+                    .iter()
+                    .map(|x| json!({ "prefix": x.prefix, "token": x.token }))
+                    .collect();
+            }
+            ListingState::Complete => value["listing"] = json!("complete"),
+        }
+        value
+    }
+}
+
+/// A listing that can report where it would continue from after the last returned page
+pub trait Resumable: Iterator<Item = Result<Vec<Object>, Error>> {
+    fn state(&self) -> ListingState;
+}
+
+/// Saves the listing state once every object listed before it has been
+/// filtered and passed to the command, so a resumed run does not skip keys
+pub struct ResumeTracker {
+    file: PathBuf,
+    path: S3path,
+    progress: Mutex<Progress>,
+}
+
+#[derive(Default)]
+struct Progress {
+    listed: usize,
+    scanned: usize,
+    // positions of the matched objects not passed to the command yet
+    pending: VecDeque<usize>,
+    // listing states with the number of objects listed before them
+    checkpoints: VecDeque<(usize, ListingState)>,
+}
+
+impl ResumeTracker {
+    pub fn new(file: PathBuf, path: S3path) -> Self {
+        ResumeTracker {
+            file,
+            path,
+            progress: Mutex::new(Progress::default()),
+        }
+    }
+
+    /// A page has been listed, `state` continues after it
+    pub fn page(&self, count: usize, state: ListingState) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.listed += count;
+        let listed = progress.listed;
+        progress.checkpoints.push_back((listed, state));
+    }
+
+    /// An object has been filtered
+    pub fn scanned(&self, matched: bool) -> Result<(), Error> {
+        let mut progress = self.progress.lock().unwrap();
+        progress.scanned += 1;
+        if matched {
+            let position = progress.scanned;
+            progress.pending.push_back(position);
+        }
+        self.save(&mut progress)
+    }
+
+    /// The first `count` pending matched objects have been passed to the command
+    pub fn executed(&self, count: usize) -> Result<(), Error> {
+        let mut progress = self.progress.lock().unwrap();
+        let count = count.min(progress.pending.len());
+        progress.pending.drain(..count);
+        self.save(&mut progress)
+    }
+
+    fn save(&self, progress: &mut Progress) -> Result<(), Error> {
+        let done = match progress.pending.front() {
+            Some(position) => position - 1,
+            None => progress.scanned,
+        };
+
+        let mut state = None;
+        while progress.checkpoints.front().map(|x| x.0 <= done) == Some(true) {
+            state = progress.checkpoints.pop_front().map(|x| x.1);
+        }
+
+        match state {
+            Some(state) => write_atomically(&self.file, &state.to_json(&self.path).to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Records the state of the listing after every page
+pub struct ResumeWriter<I> {
+    inner: I,
+    tracker: Arc<ResumeTracker>,
+}
+
+impl<I: Resumable> ResumeWriter<I> {
+    pub fn new(inner: I, tracker: Arc<ResumeTracker>) -> Self {
+        ResumeWriter { inner, tracker }
+    }
+}
+
+impl<I: Resumable> Iterator for ResumeWriter<I> {
+    type Item = Result<Vec<Object>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let page = self.inner.next();
+        match page {
+            Some(Ok(ref list)) => self.tracker.page(list.len(), self.inner.state()),
+            Some(Err(_)) => {}
+            None => self.tracker.page(0, ListingState::Complete),
+        }
+        page
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn path() -> S3path {
+        S3path {
+            bucket: "bucket".to_owned(),
+            prefix: Some("logs/".to_owned()),
+        }
+    }
+
+    fn token(x: &str) -> ListingState {
+        ListingState::Sequential {
+            token: Some(x.to_owned()),
+        }
+    }
+
+    #[test]
+    fn state_roundtrip() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let file = dir.path().join("state.json");
+
+        let states = vec![
+            token("token"),
+            ListingState::Partitioned {
+                partitions: vec![
+                    Partition {
+                        prefix: "logs/a/".to_owned(),
+                        token: None,
+                    },
+                    Partition {
+                        prefix: "logs/b/".to_owned(),
+                        token: Some("token".to_owned()),
+                    },
+                ],
+            },
+            ListingState::Complete,
+        ];
+
+        for state in states {
+            fs::write(&file, state.to_json(&path()).to_string())?;
+            assert_eq!(ListingState::load(&file, &path())?, state);
+        }
+
+        let other = S3path {
+            bucket: "bucket".to_owned(),
+            prefix: None,
+        };
+        assert!(ListingState::load(&file, &other).is_err());
+
+        fs::write(&file, "{}")?;
+        assert!(ListingState::load(&file, &path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn state_saved_after_execution() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let file = dir.path().join("state.json");
+        let tracker = ResumeTracker::new(file.clone(), path());
+
+        tracker.page(2, token("page2"));
+        tracker.page(2, token("page3"));
+
+        // the matched object of the first page is waiting for the command
+        tracker.scanned(true)?;
+        tracker.scanned(false)?;
+        tracker.scanned(false)?;
+        assert!(!file.exists());
+
+        tracker.executed(1)?;
+        assert_eq!(ListingState::load(&file, &path())?, token("page2"));
+
+        tracker.scanned(false)?;
+        assert_eq!(ListingState::load(&file, &path())?, token("page3"));
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use futures::future::{self, Either, Loop};
+use futures::Future;
+use log::warn;
+use rand::Rng;
+use rusoto_core::{RusotoError, RusotoFuture, RusotoResult};
+use std::cell::Cell;
+use std::cmp;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+use crate::benchmark;
+use crate::metrics;
+use crate::ratelimit;
+
+/// Upper bound of a single backoff delay
+const MAX_DELAY_MS: u64 = 20_000;
+
+static RETRIES: AtomicUsize = AtomicUsize::new(3);
+static BASE_DELAY_MS: AtomicU64 = AtomicU64::new(100);
+
+thread_local! {
+    static LAST_LATENCY: Cell<Duration> = const { Cell::new(Duration::from_secs(0)) };
+}
+
+/// Configure the retries of the failed S3 calls
+pub fn set_retry_policy(retries: usize, base_delay_ms: u64) {
+    RETRIES.store(retries, Ordering::Relaxed);
+    BASE_DELAY_MS.store(base_delay_ms, Ordering::Relaxed);
+}
+
+/// Duration of the last S3 call attempt made by the current thread, without
+/// the rate limit and backoff waits
+pub fn last_latency() -> Duration {
+    LAST_LATENCY.with(Cell::get)
+}
+
+/// Throttling, server-side and network errors are worth another attempt
+pub fn is_retryable<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(response) => is_retryable_status(response.status.as_u16()),
+        _ => false,
+    }
+}
+
+/// 503 is returned by S3 with the SlowDown code when throttling
+pub fn is_retryable_status(status: u16) -> bool {
+    matches!(status, 500 | 502 | 503 | 504)
+}
+
+/// Exponential backoff with full jitter: a random delay up to base * 2^attempt
+pub fn backoff(attempt: u32, base_delay_ms: u64) -> Duration {
+    let ceiling = base_delay_ms
+        .saturating_mul(1 << cmp::min(attempt, 16))
+        .min(MAX_DELAY_MS);
+    Duration::from_millis(rand::thread_rng().gen_range(0, ceiling + 1))
+}
+
+/// Run an S3 call, retrying it on transient errors
+// same result as RusotoFuture::sync, the error size is up to rusoto
+#[allow(clippy::result_large_err)]
+pub fn with_retry<T, E, F>(operation: &str, mut call: F) -> RusotoResult<T, E>
+where
+    T: Send + 'static,
+    E: std::error::Error + Send + 'static,
+    F: FnMut() -> RusotoFuture<T, E>,
+{
+    let policy = RetryPolicy::current();
+    let mut attempt = 0;
+
+    loop {
+        ratelimit::acquire();
+        let start = attempt_started();
+        let result = call().sync();
+        attempt_finished(operation, start);
+
+        match result {
+            Err(ref e) if policy.retries(attempt, e) => {
+                thread::sleep(policy.delay(operation, attempt, e));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Run an S3 call as a future on a tokio runtime, retrying it on transient
+/// errors as with_retry does. The rate limit of --rps waits on a timer
+#[allow(clippy::result_large_err)]
+pub fn retry_future<T, E, F>(
+    operation: &str,
+    mut call: F,
+) -> impl Future<Item = T, Error = RusotoError<E>>
+where
+    E: std::error::Error + 'static,
+    F: FnMut() -> RusotoFuture<T, E>,
+{
+    let policy = RetryPolicy::current();
+    let operation = operation.to_owned();
+
+    future::loop_fn(0, move |attempt| {
+        let operation = operation.clone();
+        // the request is only sent when the rusoto future is first polled
+        let request = call();
+        ratelimit::acquire_future()
+            .then(|_| {
+                let start = attempt_started();
+                request.then(move |result| Ok((start, result)))
+            })
+            .and_then(move |(start, result)| {
+                attempt_finished(&operation, start);
+                match result {
+                    Err(ref e) if policy.retries(attempt, e) => {
+                        let delay = policy.delay(&operation, attempt, e);
+                        Either::A(
+                            Delay::new(Instant::now() + delay)
+                                .then(move |_| Ok(Loop::Continue(attempt + 1))),
+                        )
+                    }
+                    result => Either::B(future::result(result.map(Loop::Break))),
+                }
+            })
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+    retries: usize,
+    base_delay_ms: u64,
+}
+
+impl RetryPolicy {
+    fn current() -> Self {
+        RetryPolicy {
+            retries: RETRIES.load(Ordering::Relaxed),
+            base_delay_ms: BASE_DELAY_MS.load(Ordering::Relaxed),
+        }
+    }
+
+    fn retries<E>(&self, attempt: usize, error: &RusotoError<E>) -> bool {
+        attempt < self.retries && is_retryable(error)
+    }
+
+    fn delay<E: std::error::Error + 'static>(
+        &self,
+        operation: &str,
+        attempt: usize,
+        error: &RusotoError<E>,
+    ) -> Duration {
+        let delay = backoff(attempt as u32, self.base_delay_ms);
+        warn!(
+            "{} failed, retrying in {:?} ({}/{}): {}",
+            operation,
+            delay,
+            attempt + 1,
+            self.retries,
+            error
+        );
+        metrics::retry();
+        delay
+    }
+}
+
+fn attempt_started() -> Instant {
+    metrics::api_call();
+    Instant::now()
+}
+
+fn attempt_finished(operation: &str, start: Instant) {
+    let latency = start.elapsed();
+    LAST_LATENCY.with(|x| x.set(latency));
+    benchmark::request(operation, latency);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::HttpDispatchError;
+    use rusoto_s3::GetObjectError;
+    use std::cell::Cell;
+
+    fn dispatch_error() -> RusotoError<GetObjectError> {
+        RusotoError::HttpDispatch(HttpDispatchError::new("connection reset".to_owned()))
+    }
+
+    #[test]
+    fn retryable_errors() {
+        assert!(is_retryable_status(503));
+        assert!(is_retryable_status(500));
+        assert!(!is_retryable_status(403));
+        assert!(!is_retryable_status(404));
+        assert!(is_retryable(&dispatch_error()));
+        assert!(!is_retryable(&RusotoError::Service(
+            GetObjectError::NoSuchKey("key".to_owned())
+        )));
+    }
+
+    #[test]
+    fn backoff_bounds() {
+        for attempt in 0..10 {
+            let delay = backoff(attempt, 100);
+            assert!(delay <= Duration::from_millis(cmp::min(100 << attempt, MAX_DELAY_MS)));
+        }
+        assert!(backoff(40, 1000) <= Duration::from_millis(MAX_DELAY_MS));
+    }
+
+    #[test]
+    fn retry_until_success() {
+        set_retry_policy(3, 0);
+        let attempts = Cell::new(0);
+        let result = with_retry("GetObject", || {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() < 3 {
+                Err(dispatch_error()).into()
+            } else {
+                Ok(attempts.get()).into()
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let attempts = Cell::new(0);
+        let result: Result<(), _> = with_retry("GetObject", || {
+            attempts.set(attempts.get() + 1);
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(
+                "key".to_owned(),
+            )))
+            .into()
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
@@ -1,66 +1,516 @@
 use failure::Error;
-use itertools::Itertools;
-use rusoto_s3::Object;
+use itertools::{process_results, Itertools};
+use rusoto_core::RusotoError;
+use rusoto_s3::{ListObjectsError, ListObjectsV2Error, Object};
+use serde_json::Value;
+use std::cell::Cell;
+use std::fs;
+use std::vec;
 
-use crate::command::{FindIter, FindStat};
+use crate::arg::{ProgressMode, S3path};
+use crate::cancel::is_cancelled;
+use crate::command::{Find, FindStat, Pages};
+use crate::error::S3FindError;
+use crate::events::{set_error_listeners, FindEvents};
+use crate::metrics::MetricsEvents;
+use crate::progress::{count_objects, ScanProgress};
+use crate::report::{collect_failures, ErrorReport};
 
-const CHUNK: usize = 1000;
+/// Outcome of a search over all of its paths
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Walk {
+    /// Summary of all the paths, with --summarize or --summarize-json
+    pub stats: Option<FindStat>,
+    /// Summary of every path when there are several
+    pub path_stats: Vec<(S3path, FindStat)>,
+    /// Failures of the paths that could not be listed, by kind
+    pub errors: Vec<S3FindError>,
+    /// The walk was stopped by a signal or [`crate::cancel::cancel`]
+    pub cancelled: bool,
+}
+
+impl Walk {
+    /// A path could not be listed
+    pub fn failed(&self) -> bool {
+        !self.errors.is_empty()
+    }
+}
+
+/// Walk the paths one after another until --limit keys are matched or the
+/// walk is cancelled. The failure of a path is reported and the next path is
+/// walked
+pub fn walk(status: &mut Find) -> Walk {
+    let mut walk = Walk {
+        stats: status.stats(),
+        ..Default::default()
+    };
+    set_error_listeners(status.events.clone());
+
+    for path in status.paths.clone() {
+        if status.limit == Some(0) || is_cancelled() {
+            break;
+        }
+        status.set_path(path);
+
+        match walk_path(status, status.stats()) {
+            Ok((found, matched)) => {
+                let path_stats = &mut walk.path_stats;
+                walk.stats = walk.stats.take().zip(found).map(|(total, found)| {
+                    if status.paths.len() > 1 {
+                        path_stats.push((status.path.clone(), found.clone()));
+                    }
+                    total + found
+                });
+                status.limit = status.limit.map(|x| x.saturating_sub(matched));
+            }
+            Err(e) => {
+                let bucket = &status.path.bucket;
+                let report = match (
+                    e.downcast_ref::<RusotoError<ListObjectsV2Error>>(),
+                    e.downcast_ref::<RusotoError<ListObjectsError>>(),
+                ) {
+                    (Some(x), _) => ErrorReport::from_rusoto("ListObjectsV2", bucket, None, x),
+                    (_, Some(x)) => ErrorReport::from_rusoto("ListObjects", bucket, None, x),
+                    _ => ErrorReport::new("s3find", bucket, None, &e),
+                };
+                report.emit();
+                walk.errors.push(report.into());
+            }
+        }
+    }
 
-pub fn list_filter_execute<P, F>(
-    iterator: FindIter,
+    set_error_listeners(Vec::new());
+    walk.cancelled = is_cancelled();
+    walk
+}
+
+/// Walk the current path of `status`: list, filter and run the command on the
+/// matched keys. Returns the stats of the path added to `stats` and the number
+/// of matched keys. On cancellation no more pages are listed and the matched
+/// keys not yet passed to the command are left for a resumed run
+pub fn walk_path(
+    status: &Find,
+    stats: Option<FindStat>,
+) -> Result<(Option<FindStat>, usize), Error> {
+    let progress = match status.progress {
+        Some(ProgressMode::Full) => ScanProgress::with_total(count_objects(status.count_pages()?)?),
+        Some(ProgressMode::Line) => ScanProgress::new(true),
+        None => ScanProgress::new(false),
+    };
+    let result = walk_pages(status, status.pages()?, &progress, stats)?;
+    progress.finish();
+    Ok(result)
+}
+
+/// Filter the pages of the current path of `status` and run the command on
+/// the matched keys, as walk_path does with the listed pages
+pub fn walk_pages<I>(
+    status: &Find,
+    pages: I,
+    progress: &ScanProgress,
+    stats: Option<FindStat>,
+) -> Result<(Option<FindStat>, usize), Error>
+where
+    I: Iterator<Item = Result<Vec<Object>, Error>>,
+{
+    let matched_count = Cell::new(0);
+    let mut events: Vec<&dyn FindEvents> = vec![progress, &MetricsEvents];
+    events.extend(status.events.iter().map(|x| &**x as &dyn FindEvents));
+
+    let stats = list_filter_execute(
+        pages.take_while(|_| !is_cancelled()).inspect(|x| {
+            if let Ok(list) = x {
+                events.iter().for_each(|e| e.on_page_listed(list));
+            }
+        }),
+        status.limit,
+        status.buffer_size,
+        stats,
+        |x| {
+            let matched = status.accept(x);
+            if matched {
+                matched_count.set(matched_count.get() + 1);
+                events.iter().for_each(|e| e.on_object_matched(x));
+            }
+            if let Some(ref tuner) = status.page_tuner {
+                tuner.scanned(matched);
+            }
+            if let Some(ref tracker) = status.resume_tracker {
+                if let Err(e) = tracker.scanned(matched) {
+                    ErrorReport::new("SaveResume", &status.path.bucket, None, &e).emit();
+                }
+            }
+            matched
+        },
+        |acc, x| {
+            if is_cancelled() {
+                return Ok(acc);
+            }
+            // the keys whose command failed are left for a re-run
+            let (stats, failures) = collect_failures(&status.path.bucket, || status.exec(acc, x));
+            let stats = stats?;
+            let succeeded = failures.succeeded(x);
+            events
+                .iter()
+                .for_each(|e| e.on_action_completed(&succeeded));
+            if let Some(ref state) = status.job_state {
+                if let Err(e) = state.record(&status.path.bucket, &succeeded) {
+                    ErrorReport::new("SaveState", &status.path.bucket, None, &e).emit();
+                }
+            }
+            if let Some(ref tracker) = status.resume_tracker {
+                if let Err(e) = tracker.executed(x.len()) {
+                    ErrorReport::new("SaveResume", &status.path.bucket, None, &e).emit();
+                }
+            }
+            Ok(stats)
+        },
+    )?;
+
+    Ok((stats, matched_count.get()))
+}
+
+/// Matched objects of all the paths of a search, from [`Find::iter_matches`].
+/// A page is listed when the objects of the previous one are consumed, and
+/// the command is not run. The keys are accepted as by the walk of the
+/// command, the --skip count is restored once the iterator is dropped
+pub struct Matches<'a> {
+    status: &'a mut Find,
+    paths: vec::IntoIter<S3path>,
+    pages: Option<Pages>,
+    page: vec::IntoIter<Object>,
+    remaining: Option<usize>,
+    skip: usize,
+}
+
+impl<'a> Matches<'a> {
+    pub fn new(status: &'a mut Find) -> Self {
+        Matches {
+            paths: status.paths.clone().into_iter(),
+            remaining: status.limit,
+            skip: status.skip.get(),
+            status,
+            pages: None,
+            page: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Iterator for Matches<'_> {
+    type Item = Result<Object, Error>;
+
+    /// The failure of a path is returned and the next path is listed
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.remaining == Some(0) || is_cancelled() {
+                return None;
+            }
+            if let Some(object) = self.page.next() {
+                let matched = self.status.accept(&object);
+                // no command uses the data of the enriched filters
+                self.status.enrichment.clear();
+                if matched {
+                    self.remaining = self.remaining.map(|x| x - 1);
+                    return Some(Ok(object));
+                }
+                continue;
+            }
+
+            match self.pages.as_mut().and_then(Iterator::next) {
+                Some(Ok(page)) => self.page = page.into_iter(),
+                Some(Err(e)) => {
+                    self.pages = None;
+                    return Some(Err(e));
+                }
+                None => {
+                    let path = self.paths.next()?;
+                    self.status.set_path(path);
+                    match self.status.pages() {
+                        Ok(pages) => self.pages = Some(pages),
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Matches<'_> {
+    fn drop(&mut self) {
+        self.status.skip.set(self.skip);
+    }
+}
+
+/// Print the summary with --summarize and write it with --summarize-json.
+/// With several paths the summary of each path comes before the overall one
+pub fn summarize(status: &Find, walk: &Walk) -> Result<(), Error> {
+    let url = |path: &S3path| {
+        format!(
+            "s3://{}/{}",
+            path.bucket,
+            path.prefix.as_deref().unwrap_or_default()
+        )
+    };
+
+    if let Some(ref stats) = walk.stats {
+        if status.summarize {
+            for (path, stats) in &walk.path_stats {
+                print!("\n{}{}", url(path), stats);
+            }
+            if !walk.path_stats.is_empty() {
+                print!("\nAll paths");
+            }
+            println!("{}", stats);
+        }
+
+        let mut json = stats.to_json();
+        if !walk.path_stats.is_empty() {
+            let paths: Vec<Value> = walk
+                .path_stats
+                .iter()
+                .map(|(path, stats)| {
+                    let mut json = stats.to_json();
+                    json["path"] = url(path).into();
+                    json
+                })
+                .collect();
+            json["paths"] = paths.into();
+        }
+        match status.summarize_json {
+            Some(ref file) if file.as_os_str() == "-" => println!("{}", json),
+            Some(ref file) => fs::write(file, json.to_string())?,
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Objects are filtered as the pages stream in and dispatched to the command
+/// in buffers of at most `buffer_size` matches, so memory stays bounded by
+/// one page plus one buffer
+pub fn list_filter_execute<I, P, F>(
+    iterator: I,
     limit: Option<usize>,
+    buffer_size: usize,
     stats: Option<FindStat>,
     p: P,
     f: F,
 ) -> Result<Option<FindStat>, Error>
 where
+    I: Iterator<Item = Result<Vec<Object>, Error>>,
     P: Fn(&Object) -> bool,
     F: Fn(Option<FindStat>, &[Object]) -> Result<Option<FindStat>, Error>,
 {
     match limit {
-        Some(limit) => list_filter_limit_execute(iterator, limit, stats, p, f),
-        None => list_filter_unlimited_execute(iterator, stats, p, f),
+        Some(limit) => list_filter_limit_execute(iterator, limit, buffer_size, stats, p, f),
+        None => list_filter_unlimited_execute(iterator, buffer_size, stats, p, f),
     }
 }
 
 #[inline]
-fn list_filter_limit_execute<P, F>(
-    iterator: FindIter,
+fn list_filter_limit_execute<I, P, F>(
+    iterator: I,
     limit: usize,
+    buffer_size: usize,
     stats: Option<FindStat>,
     p: P,
     f: F,
 ) -> Result<Option<FindStat>, Error>
 where
+    I: Iterator<Item = Result<Vec<Object>, Error>>,
     P: Fn(&Object) -> bool,
     F: Fn(Option<FindStat>, &[Object]) -> Result<Option<FindStat>, Error>,
 {
-    iterator
-        .map(|x| x.unwrap())
-        .flatten()
-        .filter(p)
-        .take(limit)
-        .chunks(CHUNK)
-        .into_iter()
-        .try_fold(stats, |acc, x| f(acc, &x.collect::<Vec<Object>>()))
+    process_results(iterator, |pages| {
+        pages
+            .flatten()
+            .filter(p)
+            .take(limit)
+            .chunks(buffer_size)
+            .into_iter()
+            .try_fold(stats, |acc, x| f(acc, &x.collect::<Vec<Object>>()))
+    })?
 }
 
 #[inline]
-fn list_filter_unlimited_execute<P, F>(
-    iterator: FindIter,
+fn list_filter_unlimited_execute<I, P, F>(
+    iterator: I,
+    buffer_size: usize,
     stats: Option<FindStat>,
     p: P,
     f: F,
 ) -> Result<Option<FindStat>, Error>
 where
+    I: Iterator<Item = Result<Vec<Object>, Error>>,
     P: Fn(&Object) -> bool,
     F: Fn(Option<FindStat>, &[Object]) -> Result<Option<FindStat>, Error>,
 {
-    iterator
-        .map(|x| x.unwrap())
-        .flatten()
-        .filter(p)
-        .chunks(CHUNK)
-        .into_iter()
-        .try_fold(stats, |acc, x| f(acc, &x.collect::<Vec<Object>>()))
+    process_results(iterator, |pages| {
+        pages
+            .flatten()
+            .filter(p)
+            .chunks(buffer_size)
+            .into_iter()
+            .try_fold(stats, |acc, x| f(acc, &x.collect::<Vec<Object>>()))
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn pages(keys: &[&[&str]]) -> Vec<Result<Vec<Object>, Error>> {
+        keys.iter()
+            .map(|page| {
+                Ok(page
+                    .iter()
+                    .map(|x| Object {
+                        key: Some((*x).to_owned()),
+                        ..Default::default()
+                    })
+                    .collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn buffers_are_bounded() -> Result<(), Error> {
+        let buffers = RefCell::new(Vec::new());
+        list_filter_execute(
+            pages(&[&["a1", "b1", "a2"], &["a3", "a4", "b2", "a5"]]).into_iter(),
+            None,
+            2,
+            None,
+            |x| x.key.as_deref().unwrap_or_default().starts_with('a'),
+            |acc, x| {
+                buffers.borrow_mut().push(x.len());
+                Ok(acc)
+            },
+        )?;
+
+        assert_eq!(*buffers.borrow(), vec![2, 2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn limit_stops_listing() -> Result<(), Error> {
+        let fetched = RefCell::new(0);
+        let matched = RefCell::new(0);
+        let listing = pages(&[&["a1", "b1"], &["a2", "a3"], &["a4"], &["a5"]])
+            .into_iter()
+            .inspect(|_| *fetched.borrow_mut() += 1);
+
+        list_filter_execute(
+            listing,
+            Some(2),
+            1000,
+            None,
+            |x| x.key.as_deref().unwrap_or_default().starts_with('a'),
+            |acc, x| {
+                *matched.borrow_mut() += x.len();
+                Ok(acc)
+            },
+        )?;
+
+        assert_eq!(*matched.borrow(), 2);
+        assert_eq!(*fetched.borrow(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn incomplete_cache_runs_nothing() -> Result<(), Error> {
+        use crate::cache::CacheWriter;
+        use crate::enrich::EnrichmentCache;
+        use crate::function::RunCommand;
+        use crate::output::OutputSink;
+        use rusoto_core::Region;
+        use rusoto_s3::S3Client;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use structopt::StructOpt;
+
+        struct Counter(Arc<AtomicUsize>);
+        impl RunCommand for Counter {
+            fn execute(
+                &self,
+                _c: &S3Client,
+                _r: &Region,
+                _p: &S3path,
+                list: &[Object],
+                _e: &EnrichmentCache,
+                _o: &dyn OutputSink,
+            ) -> Result<(), Error> {
+                self.0.fetch_add(list.len(), Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("listing.cache");
+        let path: S3path = "s3://bucket/logs/".parse()?;
+        // a listing stopped by --limit after its first page
+        let listing = pages(&[&["logs/a", "logs/b"], &["logs/c"]]).into_iter();
+        CacheWriter::new(listing, &file, &path)?.next();
+
+        let mut find = Find::new(crate::arg::FindOpt::from_iter(&[
+            "s3find",
+            "s3://bucket/logs/",
+            "--aws-access-key",
+            "access",
+            "--aws-secret-key",
+            "secret",
+            "--cache",
+            file.to_str().unwrap(),
+            "--from-cache",
+        ]))?;
+        let executed = Arc::new(AtomicUsize::new(0));
+        find.command = Box::new(Counter(executed.clone()));
+
+        assert!(walk(&mut find).failed());
+        assert_eq!(executed.load(Ordering::SeqCst), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn matches_are_iterated() -> Result<(), Error> {
+        use structopt::StructOpt;
+
+        let mut file = tempfile::NamedTempFile::new()?;
+        std::io::Write::write_all(&mut file, b"logs/a.gz\nlogs/b.txt\nlogs/c.gz\nlogs/d.gz\n")?;
+        let file = file.path().to_str().unwrap().to_owned();
+        let find = |limit: &str| -> Find {
+            Find::new(crate::arg::FindOpt::from_iter(&[
+                "s3find",
+                "s3://bucket/logs/",
+                "--keys-from",
+                &file,
+                "--name",
+                "*.gz",
+                "--limit",
+                limit,
+                "ls",
+            ]))
+            .unwrap()
+        };
+        let keys = |find: &mut Find| -> Result<Vec<String>, Error> {
+            find.iter_matches()
+                .map(|x| Ok(x?.key.unwrap_or_default()))
+                .collect()
+        };
+
+        assert_eq!(
+            keys(&mut find("10"))?,
+            vec!["logs/a.gz", "logs/c.gz", "logs/d.gz"]
+        );
+        assert_eq!(keys(&mut find("2"))?, vec!["logs/a.gz", "logs/c.gz"]);
+
+        // the --skip keys are not returned, and still skipped by the next walk
+        let mut skipping = find("10");
+        skipping.skip.set(1);
+        assert_eq!(keys(&mut skipping)?, vec!["logs/c.gz", "logs/d.gz"]);
+        assert_eq!(skipping.skip.get(), 1);
+        Ok(())
+    }
 }
@@ -0,0 +1,313 @@
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use failure::Error;
+use rusoto_core::Region;
+use rusoto_s3::{Object, S3Client};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::arg::{S3path, SnapshotDiff, SnapshotSave};
+use crate::enrich::EnrichmentCache;
+use crate::error::FunctionError;
+use crate::function::RunCommand;
+use crate::output::OutputSink;
+
+/// Header of a snapshot file and version of its format
+const MAGIC: &[u8; 8] = b"S3FSNAP1";
+
+/// A matched key of a snapshot. The snapshot file is the header followed by
+/// the entries: the length of the URL (u32) and the URL, the size (i64), the
+/// length of the ETag (u8) and the ETag without quotes, and the modification
+/// time in milliseconds (i64), all little-endian
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SnapshotEntry {
+    /// s3://bucket/key
+    pub url: String,
+    pub size: i64,
+    pub e_tag: String,
+    pub last_modified: i64,
+}
+
+impl SnapshotEntry {
+    pub fn new(path: &S3path, object: &Object) -> Self {
+        SnapshotEntry {
+            url: format!(
+                "s3://{}/{}",
+                path.bucket,
+                object.key.as_deref().unwrap_or_default()
+            ),
+            size: object.size.unwrap_or_default(),
+            e_tag: object
+                .e_tag
+                .as_deref()
+                .unwrap_or_default()
+                .trim_matches('"')
+                .to_owned(),
+            last_modified: object
+                .last_modified
+                .as_deref()
+                .and_then(|x| DateTime::parse_from_rfc3339(x).ok())
+                .map(|x| x.timestamp_millis())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        let e_tag = &self.e_tag.as_bytes()[..self.e_tag.len().min(255)];
+        buffer.extend_from_slice(&(self.url.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(self.url.as_bytes());
+        buffer.extend_from_slice(&self.size.to_le_bytes());
+        buffer.push(e_tag.len() as u8);
+        buffer.extend_from_slice(e_tag);
+        buffer.extend_from_slice(&self.last_modified.to_le_bytes());
+    }
+
+    /// The entry at the start of `data` and the rest of the data
+    fn decode(data: &[u8]) -> Result<(Self, &[u8]), Error> {
+        fn take(data: &[u8], length: usize) -> Result<(&[u8], &[u8]), Error> {
+            if data.len() < length {
+                return Err(FunctionError::SnapshotFormatError.into());
+            }
+            Ok(data.split_at(length))
+        }
+        fn int64(data: &[u8]) -> Result<(i64, &[u8]), Error> {
+            let (bytes, rest) = take(data, 8)?;
+            let mut int = [0; 8];
+            int.copy_from_slice(bytes);
+            Ok((i64::from_le_bytes(int), rest))
+        }
+
+        let (length, rest) = take(data, 4)?;
+        let mut int = [0; 4];
+        int.copy_from_slice(length);
+        let (url, rest) = take(rest, u32::from_le_bytes(int) as usize)?;
+        let (size, rest) = int64(rest)?;
+        let (length, rest) = take(rest, 1)?;
+        let (e_tag, rest) = take(rest, length[0] as usize)?;
+        let (last_modified, rest) = int64(rest)?;
+
+        let text = |x: &[u8]| {
+            String::from_utf8(x.to_vec()).map_err(|_| FunctionError::SnapshotFormatError)
+        };
+        let entry = SnapshotEntry {
+            url: text(url)?,
+            size,
+            e_tag: text(e_tag)?,
+            last_modified,
+        };
+        Ok((entry, rest))
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "key": self.url,
+            "size": self.size,
+            "etag": self.e_tag,
+            "last_modified": Utc
+                .timestamp_millis_opt(self.last_modified)
+                .single()
+                .map(|x| x.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        })
+    }
+}
+
+impl SnapshotSave {
+    /// Replace the file with an empty snapshot, the entries are appended by
+    /// the command
+    pub fn create(&self) -> Result<(), Error> {
+        fs::write(&self.file, MAGIC)?;
+        Ok(())
+    }
+}
+
+impl RunCommand for SnapshotSave {
+    /// The entries of a buffer are appended with a single write
+    fn execute(
+        &self,
+        _c: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let mut buffer = Vec::new();
+        for object in list {
+            SnapshotEntry::new(path, object).encode(&mut buffer);
+        }
+        OpenOptions::new()
+            .append(true)
+            .open(&self.file)?
+            .write_all(&buffer)?;
+        Ok(())
+    }
+}
+
+/// The entries of a snapshot file by URL
+pub fn read_snapshot(file: &Path) -> Result<BTreeMap<String, SnapshotEntry>, Error> {
+    let mut data = Vec::new();
+    fs::File::open(file)?.read_to_end(&mut data)?;
+    if !data.starts_with(MAGIC) {
+        return Err(FunctionError::SnapshotFormatError.into());
+    }
+
+    let mut entries = BTreeMap::new();
+    let mut rest = &data[MAGIC.len()..];
+    while !rest.is_empty() {
+        let (entry, next) = SnapshotEntry::decode(rest)?;
+        entries.insert(entry.url.clone(), entry);
+        rest = next;
+    }
+    Ok(entries)
+}
+
+/// A key added, removed or changed (size, ETag or modification time)
+/// between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(SnapshotEntry),
+    Removed(SnapshotEntry),
+    Changed(SnapshotEntry, SnapshotEntry),
+}
+
+impl Change {
+    pub fn to_text(&self) -> String {
+        match self {
+            Change::Added(x) => format!("added\t{}\t{}", x.url, x.size),
+            Change::Removed(x) => format!("removed\t{}\t{}", x.url, x.size),
+            Change::Changed(old, new) => {
+                format!("changed\t{}\t{}\t{}", new.url, old.size, new.size)
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            Change::Added(x) => json!({ "change": "added", "new": x.to_json() }),
+            Change::Removed(x) => json!({ "change": "removed", "old": x.to_json() }),
+            Change::Changed(old, new) => json!({
+                "change": "changed",
+                "old": old.to_json(),
+                "new": new.to_json(),
+            }),
+        }
+    }
+}
+
+/// The changes from `old` to `new`, by URL
+pub fn diff(
+    old: &BTreeMap<String, SnapshotEntry>,
+    new: &BTreeMap<String, SnapshotEntry>,
+) -> Vec<Change> {
+    let mut changes: Vec<(&String, Change)> = Vec::new();
+    for (url, entry) in old {
+        match new.get(url) {
+            None => changes.push((url, Change::Removed(entry.clone()))),
+            Some(x) if x != entry => changes.push((url, Change::Changed(entry.clone(), x.clone()))),
+            Some(_) => {}
+        }
+    }
+    for (url, entry) in new {
+        if !old.contains_key(url) {
+            changes.push((url, Change::Added(entry.clone())));
+        }
+    }
+
+    changes.sort_by(|a, b| a.0.cmp(b.0));
+    changes.into_iter().map(|(_, change)| change).collect()
+}
+
+/// Print the changes between the snapshots of the diff subcommand
+pub fn diff_snapshots(options: &SnapshotDiff) -> Result<(), Error> {
+    let old = read_snapshot(&options.old)?;
+    let new = read_snapshot(&options.new)?;
+
+    for change in diff(&old, &new) {
+        if options.json {
+            println!("{}", change.to_json());
+        } else {
+            println!("{}", change.to_text());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn object(key: &str, size: i64, etag: &str) -> Object {
+        Object {
+            key: Some(key.to_owned()),
+            size: Some(size),
+            e_tag: Some(format!("\"{}\"", etag)),
+            last_modified: Some("2020-01-01T00:00:00.000Z".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn snapshot_diff() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path: S3path = "s3://bucket/logs".parse()?;
+        let client = S3Client::new(Region::UsEast1);
+        let save = |name: &str, objects: &[Object]| -> Result<_, Error> {
+            let save = SnapshotSave {
+                file: dir.path().join(name),
+            };
+            save.create()?;
+            save.execute(
+                &client,
+                &Region::UsEast1,
+                &path,
+                objects,
+                &EnrichmentCache::default(),
+                &crate::output::StdoutSink,
+            )?;
+            read_snapshot(&save.file)
+        };
+
+        let old = save(
+            "old.bin",
+            &[
+                object("a", 1, "1"),
+                object("b", 2, "2"),
+                object("c", 3, "3"),
+            ],
+        )?;
+        assert_eq!(old["s3://bucket/a"].e_tag, "1");
+        assert_eq!(
+            old["s3://bucket/a"].to_json()["last_modified"],
+            "2020-01-01T00:00:00.000Z"
+        );
+        let new = save(
+            "new.bin",
+            &[
+                object("b", 2, "2"),
+                object("c", 4, "4"),
+                object("d", 5, "5"),
+            ],
+        )?;
+        assert!(save("empty.bin", &[])?.is_empty());
+
+        let changes: Vec<String> = diff(&old, &new).iter().map(Change::to_text).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "removed\ts3://bucket/a\t1",
+                "changed\ts3://bucket/c\t3\t4",
+                "added\ts3://bucket/d\t5",
+            ]
+        );
+
+        fs::write(
+            dir.path().join("cut.bin"),
+            &fs::read(dir.path().join("old.bin"))?[..20],
+        )?;
+        assert!(read_snapshot(&dir.path().join("cut.bin")).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use failure::Error;
+use rusoto_core::{Client, Region};
+use rusoto_s3::{Object, S3Client};
+use rusoto_sns::{PublishInput, Sns, SnsClient};
+use serde_json::{json, Value};
+
+use crate::arg::{PublishSns, S3path};
+use crate::enrich::EnrichmentCache;
+use crate::function::{object_to_json, RunCommand};
+use crate::output::OutputSink;
+use crate::parallel::parallel_map;
+use crate::report::ErrorReport;
+use crate::retry::with_retry;
+
+/// Messages published in parallel, rusoto_sns has no PublishBatch
+const PUBLISH_CONCURRENCY: usize = 10;
+
+/// Keys of a --digest message, far below the 256KB limit of SNS
+const DIGEST_SIZE: usize = 100;
+
+/// Command publishing the matched keys to the SNS topic of publish-sns
+pub struct SnsPublisher {
+    options: PublishSns,
+    client: SnsClient,
+}
+
+impl SnsPublisher {
+    /// The topic is called in its region with the credentials of `client`
+    pub fn new(options: PublishSns, region: &Region, client: Client) -> Self {
+        let region = options.topic.region(region);
+        SnsPublisher {
+            client: SnsClient::new_with_client(client, region),
+            options,
+        }
+    }
+
+    fn publish(&self, message: &str) -> Result<(), Error> {
+        with_retry("Publish", || {
+            self.client.publish(PublishInput {
+                topic_arn: Some(self.options.topic.0.clone()),
+                message: message.to_owned(),
+                ..Default::default()
+            })
+        })
+        .map(|_| ())
+        .map_err(|e| ErrorReport::from_rusoto("Publish", "", None, &e).into())
+    }
+}
+
+/// Message of a matched key: the fields of the object and its bucket
+pub fn key_message(bucket: &str, object: &Object) -> Value {
+    let mut message = object_to_json(object);
+    message.insert("bucket".to_owned(), json!(bucket));
+    message.into()
+}
+
+/// Message of --digest with the fields of the keys
+pub fn digest_message(bucket: &str, objects: &[Object]) -> Value {
+    let keys: Vec<Value> = objects.iter().map(|x| object_to_json(x).into()).collect();
+    json!({
+        "bucket": bucket,
+        "count": keys.len(),
+        "keys": keys,
+    })
+}
+
+impl RunCommand for SnsPublisher {
+    fn execute(
+        &self,
+        _c: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        // the keys of every message, for the output and the errors
+        let (messages, keys): (Vec<String>, Vec<Vec<&Object>>) = if self.options.digest {
+            list.chunks(DIGEST_SIZE)
+                .map(|x| {
+                    (
+                        digest_message(&path.bucket, x).to_string(),
+                        x.iter().collect(),
+                    )
+                })
+                .unzip()
+        } else {
+            list.iter()
+                .map(|x| (key_message(&path.bucket, x).to_string(), vec![x]))
+                .unzip()
+        };
+
+        let results = parallel_map(&messages, PUBLISH_CONCURRENCY, |x| self.publish(x));
+        for (result, objects) in results.into_iter().zip(keys) {
+            let report: Option<ErrorReport> = result.err().map(|e| {
+                e.downcast()
+                    .unwrap_or_else(|e| ErrorReport::new("Publish", &path.bucket, None, &e))
+            });
+            for object in objects {
+                let key = object.key.as_deref().unwrap_or_default();
+                match report {
+                    Some(ref report) => ErrorReport {
+                        bucket: path.bucket.clone(),
+                        key: Some(key.to_owned()),
+                        ..report.clone()
+                    }
+                    .emit(),
+                    None if !self.options.quiet => {
+                        output.line(&format!("published: s3://{}/{}", path.bucket, key))
+                    }
+                    None => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sns_messages() {
+        let object = Object {
+            key: Some("in/a.csv".to_owned()),
+            size: Some(10),
+            ..Default::default()
+        };
+        let message = key_message("bucket", &object);
+        assert_eq!(message["bucket"], "bucket");
+        assert_eq!(message["key"], "in/a.csv");
+        assert_eq!(message["size"], 10);
+        let digest = digest_message("bucket", &[object.clone(), object]);
+        assert_eq!(digest["count"], 2);
+        assert_eq!(digest["keys"][1]["key"], "in/a.csv");
+    }
+}
@@ -0,0 +1,368 @@
+use failure::Error;
+use log::debug;
+use rusoto_s3::Object;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Lines, Seek, SeekFrom, Write};
+use std::vec;
+
+use crate::arg::SortField;
+use crate::cache::{object_from_cache, object_to_cache};
+use crate::command::Pages;
+
+/// Keys sorted in memory by default before they are spilled to a file
+pub const SORT_RUN_SIZE: usize = 500_000;
+
+/// Runs merged at once, each of them an open file
+const MAX_FAN_IN: usize = 64;
+
+/// Order of --sort, the keys with the same size or mtime are ordered by key
+/// so that the order is total
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortOrder {
+    pub field: SortField,
+    pub reverse: bool,
+}
+
+impl SortOrder {
+    pub fn compare(self, a: &Object, b: &Object) -> Ordering {
+        let ordering = match self.field {
+            SortField::Key => Ordering::Equal,
+            SortField::Size => a.size.cmp(&b.size),
+            // the timestamps of a listing have the same ISO 8601 format
+            SortField::Mtime => a.last_modified.cmp(&b.last_modified),
+        }
+        .then_with(|| a.key.cmp(&b.key));
+
+        if self.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+/// Sorted run of keys written to an anonymous temporary file, removed by the
+/// system once closed
+struct Spill {
+    lines: Lines<BufReader<File>>,
+}
+
+impl Spill {
+    fn write<I>(objects: I) -> Result<Spill, Error>
+    where
+        I: Iterator<Item = Result<Object, Error>>,
+    {
+        let mut file = tempfile::tempfile()?;
+        let mut output = BufWriter::new(&mut file);
+        for object in objects {
+            writeln!(output, "{}", object_to_cache(&object?))?;
+        }
+        output.flush()?;
+        drop(output);
+
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Spill {
+            lines: BufReader::new(file).lines(),
+        })
+    }
+
+    fn next_object(&mut self) -> Result<Option<Object>, Error> {
+        match self.lines.next() {
+            Some(line) => Ok(Some(object_from_cache(&serde_json::from_str(&line?)?)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Next key of a run, the heap of the merge pops the first one in the order
+struct Head {
+    object: Object,
+    run: usize,
+    order: SortOrder,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed for the max-heap, the runs of equal keys in their order
+        self.order
+            .compare(&other.object, &self.object)
+            .then_with(|| other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// K-way merge of sorted runs
+struct Merge {
+    runs: Vec<Spill>,
+    heads: BinaryHeap<Head>,
+    order: SortOrder,
+}
+
+impl Merge {
+    fn new(mut runs: Vec<Spill>, order: SortOrder) -> Result<Self, Error> {
+        let mut heads = BinaryHeap::with_capacity(runs.len());
+        for (run, spill) in runs.iter_mut().enumerate() {
+            if let Some(object) = spill.next_object()? {
+                heads.push(Head { object, run, order });
+            }
+        }
+        Ok(Merge { runs, heads, order })
+    }
+
+    fn next_object(&mut self) -> Result<Option<Object>, Error> {
+        let head = match self.heads.pop() {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        if let Some(object) = self.runs[head.run].next_object()? {
+            self.heads.push(Head {
+                object,
+                run: head.run,
+                order: self.order,
+            });
+        }
+        Ok(Some(head.object))
+    }
+
+    /// The merged keys written to a single run
+    fn spill(mut self) -> Result<Spill, Error> {
+        Spill::write(std::iter::from_fn(|| self.next_object().transpose()))
+    }
+}
+
+/// Runs merged by at most MAX_FAN_IN at once, so that the open files stay
+/// bounded: a level full of runs is merged into one run of the next level
+struct Runs {
+    levels: Vec<Vec<Spill>>,
+    order: SortOrder,
+}
+
+impl Runs {
+    fn push(&mut self, mut spill: Spill) -> Result<(), Error> {
+        for level in 0.. {
+            if self.levels.len() == level {
+                self.levels.push(Vec::new());
+            }
+            self.levels[level].push(spill);
+            if self.levels[level].len() < MAX_FAN_IN {
+                break;
+            }
+            let runs = std::mem::take(&mut self.levels[level]);
+            spill = Merge::new(runs, self.order)?.spill()?;
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.levels.iter().all(Vec::is_empty)
+    }
+
+    /// The final merge, the smaller runs of the lower levels are merged first
+    /// while there are more than MAX_FAN_IN of them
+    fn merge(self) -> Result<Merge, Error> {
+        let mut runs: Vec<Spill> = self.levels.into_iter().flatten().collect();
+        while runs.len() > MAX_FAN_IN {
+            let rest = runs.split_off(MAX_FAN_IN);
+            let merged = Merge::new(runs, self.order)?.spill()?;
+            runs = rest;
+            runs.push(merged);
+        }
+        debug!("Merging {} sorted runs", runs.len());
+        Merge::new(runs, self.order)
+    }
+}
+
+enum Sorted {
+    Memory(vec::IntoIter<Object>),
+    Merge(Merge),
+}
+
+impl Sorted {
+    fn next_object(&mut self) -> Result<Option<Object>, Error> {
+        match self {
+            Sorted::Memory(objects) => Ok(objects.next()),
+            Sorted::Merge(merge) => merge.next_object(),
+        }
+    }
+}
+
+/// Pages of the listed keys in --sort order. The listing is read whole on
+/// the first page: runs of `run_size` keys are sorted in memory, and merged
+/// from temporary files when the keys do not fit into a single run
+pub struct SortedPages {
+    listing: Option<Pages>,
+    order: SortOrder,
+    run_size: usize,
+    page_size: usize,
+    sorted: Option<Sorted>,
+}
+
+impl SortedPages {
+    pub fn new(listing: Pages, order: SortOrder, run_size: usize, page_size: usize) -> Self {
+        SortedPages {
+            listing: Some(listing),
+            order,
+            run_size: run_size.max(1),
+            page_size: page_size.max(1),
+            sorted: None,
+        }
+    }
+
+    fn sort(&self, listing: Pages) -> Result<Sorted, Error> {
+        let mut run = Vec::new();
+        let mut runs = Runs {
+            levels: Vec::new(),
+            order: self.order,
+        };
+        for page in listing {
+            run.extend(page?);
+            if run.len() >= self.run_size {
+                run.sort_by(|a, b| self.order.compare(a, b));
+                runs.push(Spill::write(run.drain(..).map(Ok))?)?;
+            }
+        }
+
+        run.sort_by(|a, b| self.order.compare(a, b));
+        if runs.is_empty() {
+            return Ok(Sorted::Memory(run.into_iter()));
+        }
+        if !run.is_empty() {
+            runs.push(Spill::write(run.into_iter().map(Ok))?)?;
+        }
+        Ok(Sorted::Merge(runs.merge()?))
+    }
+
+    fn next_page(&mut self) -> Result<Vec<Object>, Error> {
+        if let Some(listing) = self.listing.take() {
+            self.sorted = Some(self.sort(listing)?);
+        }
+
+        let mut page = Vec::new();
+        if let Some(ref mut sorted) = self.sorted {
+            while page.len() < self.page_size {
+                match sorted.next_object()? {
+                    Some(object) => page.push(object),
+                    None => break,
+                }
+            }
+        }
+        Ok(page)
+    }
+}
+
+impl Iterator for SortedPages {
+    type Item = Result<Vec<Object>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_page() {
+            Ok(page) if page.is_empty() => None,
+            Ok(page) => Some(Ok(page)),
+            Err(e) => {
+                // no more pages after a failure
+                self.sorted = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(key: &str, size: i64) -> Object {
+        Object {
+            key: Some(key.to_owned()),
+            size: Some(size),
+            ..Default::default()
+        }
+    }
+
+    fn keys(pages: SortedPages) -> Result<Vec<Vec<String>>, Error> {
+        pages
+            .map(|page| Ok(page?.into_iter().filter_map(|x| x.key).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn sorted_pages() -> Result<(), Error> {
+        let listing = || -> Pages {
+            Box::new(
+                vec![
+                    Ok(vec![object("d", 1), object("a", 3)]),
+                    Ok(vec![object("c", 2), object("b", 3)]),
+                    Ok(vec![object("e", 1)]),
+                ]
+                .into_iter(),
+            )
+        };
+        let by_key = SortOrder {
+            field: SortField::Key,
+            reverse: false,
+        };
+        let by_size = SortOrder {
+            field: SortField::Size,
+            reverse: true,
+        };
+
+        // in memory
+        assert_eq!(
+            keys(SortedPages::new(listing(), by_key, 100, 2))?,
+            vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]
+        );
+        // merged from 3 runs, the keys of the same size by key
+        assert_eq!(
+            keys(SortedPages::new(listing(), by_size, 2, 10))?,
+            vec![vec!["b", "a", "c", "e", "d"]]
+        );
+
+        let failed: Pages =
+            Box::new(vec![Ok(vec![object("a", 1)]), Err(failure::err_msg("x"))].into_iter());
+        let mut pages = SortedPages::new(failed, by_key, 100, 10);
+        assert!(pages.next().unwrap().is_err());
+        assert!(pages.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_passes() -> Result<(), Error> {
+        // more runs than MAX_FAN_IN, merged in several levels
+        let objects: Vec<Object> = (0..300)
+            .map(|i| object(&format!("key{:03}", i), (i * 7919) % 101))
+            .collect();
+        let by_size = SortOrder {
+            field: SortField::Size,
+            reverse: false,
+        };
+        let mut expected = objects.clone();
+        expected.sort_by(|a, b| by_size.compare(a, b));
+
+        let listing: Pages = Box::new(
+            objects
+                .chunks(7)
+                .map(|x| Ok(x.to_vec()))
+                .collect::<Vec<_>>()
+                .into_iter(),
+        );
+        let sorted: Vec<Object> = SortedPages::new(listing, by_size, 1, 50)
+            .collect::<Result<Vec<_>, _>>()?
+            .concat();
+        assert_eq!(sorted, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use failure::Error;
+use rusoto_core::{Client, Region};
+use rusoto_s3::Object;
+use rusoto_sqs::{DeleteMessageRequest, Message, ReceiveMessageRequest, Sqs, SqsClient};
+use serde_json::Value;
+use std::iter;
+
+use crate::arg::{QueueUrl, S3path};
+use crate::cancel::is_cancelled;
+use crate::command::Find;
+use crate::events::set_error_listeners;
+use crate::function::percent_decode;
+use crate::progress::ScanProgress;
+use crate::report::{collect_failures, ErrorReport};
+use crate::retry::with_retry;
+use crate::run::{walk_pages, Walk};
+
+/// Seconds a ReceiveMessage call waits for messages
+const WAIT_TIME_SECONDS: i64 = 20;
+
+/// SQS queue of the S3 event notifications of --listen-sqs
+pub struct SqsQueue {
+    client: SqsClient,
+    url: String,
+}
+
+/// An ObjectCreated or ObjectRemoved event, the object has the ETag, size and
+/// event time of the notification
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3Event {
+    pub name: String,
+    pub bucket: String,
+    pub object: Object,
+}
+
+impl SqsQueue {
+    /// The queue of `url`, its region is taken from the host when it is an AWS one
+    pub fn new(url: &QueueUrl, region: &Region, client: Client) -> Self {
+        SqsQueue {
+            client: SqsClient::new_with_client(client, url.region(region)),
+            url: format!("{}{}", url.endpoint, url.path),
+        }
+    }
+
+    /// Wait for up to 10 messages with long polling
+    pub fn receive(&self) -> Result<Vec<Message>, Error> {
+        let result = with_retry("ReceiveMessage", || {
+            self.client.receive_message(ReceiveMessageRequest {
+                queue_url: self.url.clone(),
+                max_number_of_messages: Some(10),
+                wait_time_seconds: Some(WAIT_TIME_SECONDS),
+                ..Default::default()
+            })
+        })
+        .map_err(|e| ErrorReport::from_rusoto("ReceiveMessage", "", None, &e))?;
+        Ok(result.messages.unwrap_or_default())
+    }
+
+    pub fn delete(&self, message: &Message) -> Result<(), Error> {
+        let receipt_handle = match message.receipt_handle {
+            Some(ref handle) => handle.clone(),
+            None => return Ok(()),
+        };
+        with_retry("DeleteMessage", || {
+            self.client.delete_message(DeleteMessageRequest {
+                queue_url: self.url.clone(),
+                receipt_handle: receipt_handle.clone(),
+            })
+        })
+        .map_err(|e| ErrorReport::from_rusoto("DeleteMessage", "", None, &e).into())
+    }
+}
+
+/// The S3 events of a message body, sent by S3 or wrapped in an SNS
+/// notification. The test event of a new configuration has no records
+pub fn parse_events(body: &str) -> Result<Vec<S3Event>, Error> {
+    let mut value: Value = serde_json::from_str(body)?;
+    if let Some(message) = value["Message"].as_str() {
+        value = serde_json::from_str(message)?;
+    }
+
+    let records = match value["Records"].as_array() {
+        Some(records) => records,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut events = Vec::new();
+    for record in records {
+        let name = record["eventName"].as_str().unwrap_or_default();
+        if !name.starts_with("ObjectCreated:") && !name.starts_with("ObjectRemoved:") {
+            continue;
+        }
+        let object = &record["s3"]["object"];
+        // the keys are url-encoded with + for the spaces
+        let key = match object["key"].as_str() {
+            Some(key) => percent_decode(key)?,
+            None => continue,
+        };
+
+        events.push(S3Event {
+            name: name.to_owned(),
+            bucket: record["s3"]["bucket"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_owned(),
+            object: Object {
+                key: Some(key),
+                size: object["size"].as_i64(),
+                e_tag: object["eTag"].as_str().map(|x| format!("\"{}\"", x)),
+                last_modified: record["eventTime"].as_str().map(str::to_owned),
+                ..Default::default()
+            },
+        });
+    }
+    Ok(events)
+}
+
+impl S3Event {
+    pub fn is_under(&self, path: &S3path) -> bool {
+        let prefix = path.prefix.as_deref().unwrap_or_default();
+        self.bucket == path.bucket
+            && self
+                .object
+                .key
+                .as_deref()
+                .is_some_and(|x| x.starts_with(prefix))
+    }
+}
+
+/// Run the command on the objects of the events received from the queue
+/// until the walk is cancelled or --limit keys are matched. The events
+/// outside of the paths are skipped. A message is deleted once the command
+/// has succeeded on all of its objects, a message which failed on one of them
+/// is received again later
+pub fn listen(status: &mut Find, queue: &SqsQueue) -> Walk {
+    let mut walk = Walk {
+        stats: status.stats(),
+        ..Default::default()
+    };
+    set_error_listeners(status.events.clone());
+    let progress = ScanProgress::new(status.progress.is_some());
+
+    while !is_cancelled() && status.limit != Some(0) {
+        let messages = match queue.receive() {
+            Ok(messages) => messages,
+            Err(e) => {
+                let report = error_report(e);
+                report.emit();
+                walk.errors.push(report.into());
+                break;
+            }
+        };
+
+        // the messages received after a cancellation are left in the queue
+        for message in messages.iter().take_while(|_| !is_cancelled()) {
+            if process_message(status, &mut walk, &progress, message) {
+                if let Err(e) = queue.delete(message) {
+                    error_report(e).emit();
+                }
+            }
+        }
+    }
+
+    progress.finish();
+    set_error_listeners(Vec::new());
+    walk.cancelled = is_cancelled();
+    walk
+}
+
+fn error_report(error: Error) -> ErrorReport {
+    error
+        .downcast()
+        .unwrap_or_else(|e| ErrorReport::new("SQS", "", None, &e))
+}
+
+fn process_message(
+    status: &mut Find,
+    walk: &mut Walk,
+    progress: &ScanProgress,
+    message: &Message,
+) -> bool {
+    let events = match parse_events(message.body.as_deref().unwrap_or_default()) {
+        Ok(events) => events,
+        Err(e) => {
+            ErrorReport::new("ReceiveMessage", "", None, &e).emit();
+            return false;
+        }
+    };
+
+    let mut processed = true;
+    for path in status.paths.clone() {
+        let objects: Vec<Object> = events
+            .iter()
+            .filter(|x| x.is_under(&path))
+            .map(|x| x.object.clone())
+            .collect();
+        if objects.is_empty() || status.limit == Some(0) {
+            continue;
+        }
+
+        status.set_path(path);
+        // the command reports the failed keys without failing the walk
+        let (result, failures) = collect_failures(&status.path.bucket, || {
+            walk_pages(
+                status,
+                iter::once(Ok(objects)),
+                progress,
+                walk.stats.clone(),
+            )
+        });
+        match result {
+            Ok((stats, matched)) => {
+                walk.stats = stats;
+                status.limit = status.limit.map(|x| x.saturating_sub(matched));
+                processed &= failures.is_empty();
+            }
+            Err(e) => {
+                ErrorReport::new("s3find", &status.path.bucket, None, &e).emit();
+                processed = false;
+            }
+        }
+    }
+    processed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqs_events() -> Result<(), Error> {
+        let body = r#"{"Records":[{"eventName":"ObjectCreated:Put","eventTime":"2020-01-01T00:00:00.000Z","s3":{"bucket":{"name":"logs"},"object":{"key":"in/a+b%2B.csv","size":1024,"eTag":"d41d8cd9"}}}]}"#;
+
+        let events = parse_events(body)?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].name, "ObjectCreated:Put");
+        assert_eq!(events[0].object.key.as_deref(), Some("in/a b+.csv"));
+        assert_eq!(events[0].object.size, Some(1024));
+        assert_eq!(events[0].object.e_tag.as_deref(), Some("\"d41d8cd9\""));
+        assert!(events[0].is_under(&"s3://logs/in/".parse()?));
+        assert!(!events[0].is_under(&"s3://logs/out/".parse()?));
+        assert!(!events[0].is_under(&"s3://other".parse()?));
+
+        // through SNS, and the test event of a new notification configuration
+        let sns = serde_json::json!({
+            "Type": "Notification",
+            "Message": r#"{"Records":[{"eventName":"ObjectRemoved:Delete","s3":{"bucket":{"name":"logs"},"object":{"key":"in/b.csv"}}}]}"#,
+        });
+        let events = parse_events(&sns.to_string())?;
+        assert_eq!(events[0].object.size, None);
+        let test = r#"{"Service":"Amazon S3","Event":"s3:TestEvent","Bucket":"logs"}"#;
+        assert!(parse_events(test)?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use failure::Error;
+use rusoto_s3::Object;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Keys processed by a named job (--state, --job), one JSON document per line.
+/// A key is processed again when its ETag has changed. Keys are recorded with
+/// their bucket, so one state file serves several paths
+pub struct JobState {
+    job: String,
+    processed: HashMap<(String, String), String>,
+    file: Mutex<File>,
+}
+
+impl JobState {
+    pub fn open(file: &Path, job: &str) -> Result<Self, Error> {
+        let mut processed = HashMap::new();
+        let mut complete = true;
+        if file.exists() {
+            let data = fs::read_to_string(file)?;
+            complete = data.is_empty() || data.ends_with('\n');
+            for line in data.lines() {
+                // the last line may be cut by a crash
+                if let Ok(entry) = serde_json::from_str::<Value>(line) {
+                    if entry["job"] != job {
+                        continue;
+                    }
+                    if let (Some(bucket), Some(key), Some(etag)) = (
+                        entry["bucket"].as_str(),
+                        entry["key"].as_str(),
+                        entry["etag"].as_str(),
+                    ) {
+                        processed.insert((bucket.to_owned(), key.to_owned()), etag.to_owned());
+                    }
+                }
+            }
+        }
+
+        let mut output = OpenOptions::new().create(true).append(true).open(file)?;
+        if !complete {
+            output.write_all(b"\n")?;
+        }
+
+        Ok(JobState {
+            job: job.to_owned(),
+            processed,
+            file: Mutex::new(output),
+        })
+    }
+
+    pub fn is_processed(&self, bucket: &str, object: &Object) -> bool {
+        match object.key {
+            Some(ref key) => {
+                self.processed
+                    .get(&(bucket.to_owned(), key.to_owned()))
+                    .map(String::as_str)
+                    == Some(object.e_tag.as_deref().unwrap_or_default())
+            }
+            None => false,
+        }
+    }
+
+    /// The command has run on the objects of the bucket
+    pub fn record(&self, bucket: &str, list: &[Object]) -> Result<(), Error> {
+        let lines: String = list
+            .iter()
+            .filter_map(|x| {
+                x.key.as_ref().map(|key| {
+                    let entry = json!({
+                        "job": self.job,
+                        "bucket": bucket,
+                        "key": key,
+                        "etag": x.e_tag.as_deref().unwrap_or_default(),
+                    });
+                    format!("{}\n", entry)
+                })
+            })
+            .collect();
+
+        let mut file = self.file.lock().unwrap();
+        file.write_all(lines.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn object(key: &str, etag: &str) -> Object {
+        Object {
+            key: Some(key.to_owned()),
+            e_tag: Some(etag.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn job_state_reopen() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let file = dir.path().join("s3find.db");
+
+        let state = JobState::open(&file, "nightly")?;
+        assert!(!state.is_processed("bucket", &object("a", "\"1\"")));
+        state.record("bucket", &[object("a", "\"1\""), object("b", "\"2\"")])?;
+        JobState::open(&file, "other")?.record("bucket", &[object("c", "\"3\"")])?;
+        OpenOptions::new()
+            .append(true)
+            .open(&file)?
+            .write_all(b"{\"job\":\"nightly\",\"bucket\":\"bucket\",\"key\":\"d\"")?;
+
+        let state = JobState::open(&file, "nightly")?;
+        assert!(state.is_processed("bucket", &object("a", "\"1\"")));
+        assert!(!state.is_processed("bucket", &object("b", "\"changed\"")));
+        assert!(!state.is_processed("bucket", &object("c", "\"3\"")));
+        assert!(!state.is_processed("bucket", &object("d", "")));
+
+        state.record("bucket", &[object("e", "\"5\"")])?;
+        let state = JobState::open(&file, "nightly")?;
+        assert!(state.is_processed("bucket", &object("e", "\"5\"")));
+        Ok(())
+    }
+
+    #[test]
+    fn job_state_two_buckets() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let file = dir.path().join("s3find.db");
+
+        let state = JobState::open(&file, "nightly")?;
+        state.record("first", &[object("a", "\"1\"")])?;
+        state.record("second", &[object("b", "\"2\"")])?;
+
+        let state = JobState::open(&file, "nightly")?;
+        assert!(state.is_processed("first", &object("a", "\"1\"")));
+        assert!(!state.is_processed("second", &object("a", "\"1\"")));
+        assert!(state.is_processed("second", &object("b", "\"2\"")));
+        assert!(!state.is_processed("first", &object("b", "\"2\"")));
+        Ok(())
+    }
+}
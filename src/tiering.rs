@@ -0,0 +1,231 @@
+use chrono::{DateTime, Utc};
+use failure::{err_msg, Error};
+use humansize::{file_size_opts as options, FileSize};
+use rusoto_core::Region;
+use rusoto_s3::{Object, S3Client};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::arg::{S3path, TieringReport};
+use crate::enrich::EnrichmentCache;
+use crate::function::RunCommand;
+use crate::output::OutputSink;
+use crate::pricing::{PriceTable, DEFAULT_STORAGE_CLASS};
+
+/// Minimal billable size of STANDARD_IA and GLACIER_IR, smaller keys cost
+/// more after a transition
+pub const MIN_SIZE: i64 = 128 * 1024;
+
+/// Keys of a storage class recommended for a cheaper one
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Recommendation {
+    pub count: u64,
+    pub bytes: i64,
+}
+
+/// Command of report tiering, the recommendations are printed at the end of
+/// the run
+pub struct TieringAdvisor {
+    options: TieringReport,
+    prices: PriceTable,
+    recommendations: Mutex<BTreeMap<(String, &'static str), Recommendation>>,
+}
+
+impl TieringAdvisor {
+    pub fn new(options: TieringReport, prices: PriceTable) -> Self {
+        TieringAdvisor {
+            options,
+            prices,
+            recommendations: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The classes by the age from which they are recommended
+    fn ladder(&self) -> [(&'static str, Duration); 3] {
+        [
+            ("STANDARD_IA", self.options.ia_after.0),
+            ("GLACIER_IR", self.options.glacier_ir_after.0),
+            ("DEEP_ARCHIVE", self.options.deep_archive_after.0),
+        ]
+    }
+
+    /// The cheapest class of the ladder the key is old enough for, when it
+    /// is cheaper than its class. The keys of INTELLIGENT_TIERING are moved
+    /// by S3 already
+    pub fn recommend(&self, object: &Object, now: DateTime<Utc>) -> Option<&'static str> {
+        let class = object
+            .storage_class
+            .as_deref()
+            .unwrap_or(DEFAULT_STORAGE_CLASS);
+        if class == "INTELLIGENT_TIERING" || object.size.unwrap_or_default() < MIN_SIZE {
+            return None;
+        }
+        let modified = DateTime::parse_from_rfc3339(object.last_modified.as_deref()?).ok()?;
+        let age = now.signed_duration_since(modified).to_std().ok()?;
+
+        self.ladder()
+            .iter()
+            .filter(|(_, after)| age >= *after)
+            .map(|(target, _)| *target)
+            .filter(|target| self.prices.storage_price(target) < self.prices.storage_price(class))
+            .min_by(|a, b| {
+                self.prices
+                    .storage_price(a)
+                    .partial_cmp(&self.prices.storage_price(b))
+                    .unwrap()
+            })
+    }
+
+    fn add(&self, object: &Object, target: &'static str) {
+        let class = object
+            .storage_class
+            .clone()
+            .unwrap_or_else(|| DEFAULT_STORAGE_CLASS.to_owned());
+        let mut recommendations = self.recommendations.lock().unwrap();
+        let recommendation = recommendations.entry((class, target)).or_default();
+        recommendation.count += 1;
+        recommendation.bytes += object.size.unwrap_or_default();
+    }
+
+    /// The lines of the recommendations, the highest savings first, and of
+    /// the total. The recommendations start again
+    fn report(&self) -> Result<Vec<String>, Error> {
+        let recommendations = std::mem::take(&mut *self.recommendations.lock().unwrap());
+        let ladder = self.ladder();
+        let mut rows: Vec<_> = recommendations
+            .into_iter()
+            .map(|((class, target), x)| {
+                let savings = self.prices.monthly_cost(&class, x.bytes)
+                    - self.prices.monthly_cost(target, x.bytes);
+                let after = ladder.iter().find(|y| y.0 == target).map(|y| y.1);
+                (class, target, x, savings, after.unwrap_or_default())
+            })
+            .collect();
+        rows.sort_by(|a, b| b.3.partial_cmp(&a.3).unwrap());
+
+        let mut lines = Vec::new();
+        let mut total = 0.0;
+        for (class, target, x, savings, after) in rows {
+            let transition = self.prices.transition_cost(target, x.count);
+            total += savings;
+            if self.options.json {
+                lines.push(
+                    json!({
+                        "storage_class": class,
+                        "recommended_class": target,
+                        "min_age_days": after.as_secs() / 86400,
+                        "count": x.count,
+                        "bytes": x.bytes,
+                        "monthly_savings": savings,
+                        "transition_cost": transition,
+                    })
+                    .to_string(),
+                );
+            } else {
+                lines.push(format!(
+                    "{} of {} objects older than {} would save ~${:.2}/month in {} ({} keys, one-time transition cost ~${:.2})",
+                    x.bytes.file_size(options::CONVENTIONAL).map_err(err_msg)?,
+                    class,
+                    age_text(after),
+                    savings,
+                    target,
+                    x.count,
+                    transition
+                ));
+            }
+        }
+        if self.options.json {
+            lines.push(json!({ "total_monthly_savings": total }).to_string());
+        } else {
+            lines.push(format!("Total savings: ~${:.2}/month", total));
+        }
+        Ok(lines)
+    }
+}
+
+fn age_text(age: Duration) -> String {
+    match age.as_secs() {
+        x if x % 86400 == 0 => format!("{} days", x / 86400),
+        x if x % 3600 == 0 => format!("{} hours", x / 3600),
+        x => format!("{} seconds", x),
+    }
+}
+
+impl RunCommand for TieringAdvisor {
+    fn execute(
+        &self,
+        _c: &S3Client,
+        _r: &Region,
+        _p: &S3path,
+        list: &[Object],
+        _enrichment: &EnrichmentCache,
+        _o: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        let now = Utc::now();
+        for object in list {
+            if let Some(target) = self.recommend(object, now) {
+                self.add(object, target);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self, output: &dyn OutputSink) -> Result<(), Error> {
+        for line in self.report()? {
+            output.line(&line);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg::Interval;
+
+    #[test]
+    fn tiering_recommendations() -> Result<(), Error> {
+        let advisor = TieringAdvisor::new(
+            TieringReport {
+                ia_after: "30d".parse()?,
+                glacier_ir_after: "90d".parse()?,
+                deep_archive_after: Interval(Duration::from_secs(180 * 86400)),
+                json: false,
+            },
+            PriceTable::for_region("us-east-1"),
+        );
+        let now: DateTime<Utc> = "2020-12-31T00:00:00Z".parse()?;
+        let object = |modified: &str, class: Option<&str>, size: i64| Object {
+            last_modified: Some(modified.to_owned()),
+            storage_class: class.map(str::to_owned),
+            size: Some(size),
+            ..Default::default()
+        };
+        let gigabyte = 1024 * 1024 * 1024;
+
+        let old = object("2020-10-01T00:00:00.000Z", Some("STANDARD"), gigabyte);
+        assert_eq!(advisor.recommend(&old, now), Some("GLACIER_IR"));
+        let recent = object("2020-12-20T00:00:00.000Z", None, gigabyte);
+        assert_eq!(advisor.recommend(&recent, now), None);
+        let small = object("2020-01-01T00:00:00.000Z", Some("STANDARD"), 1024);
+        assert_eq!(advisor.recommend(&small, now), None);
+        let archived = object("2019-01-01T00:00:00.000Z", Some("DEEP_ARCHIVE"), gigabyte);
+        assert_eq!(advisor.recommend(&archived, now), None);
+        let ia = object("2019-01-01T00:00:00.000Z", Some("STANDARD_IA"), gigabyte);
+        assert_eq!(advisor.recommend(&ia, now), Some("DEEP_ARCHIVE"));
+
+        advisor.add(&old, "GLACIER_IR");
+        advisor.add(&old, "GLACIER_IR");
+        let report = advisor.report()?;
+        assert_eq!(
+            report,
+            vec![
+                "2 GB of STANDARD objects older than 90 days would save ~$0.04/month in GLACIER_IR (2 keys, one-time transition cost ~$0.00)",
+                "Total savings: ~$0.04/month",
+            ]
+        );
+        Ok(())
+    }
+}
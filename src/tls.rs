@@ -0,0 +1,209 @@
+use failure::{format_err, Error};
+use futures::Future;
+use hyper::client::connect::{Connect, Connected, Destination};
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
+use rustls::{
+    Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError,
+};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::timer::Timeout;
+use webpki::DNSNameRef;
+
+use crate::error::FunctionError;
+
+/// HTTP client of all the AWS requests, with the --connect-timeout connector
+pub type HttpClient = rusoto_core::request::HttpClient<HttpsConnector<TimeoutConnector>>;
+
+/// Client of the plain HTTP requests, with the connector of the AWS requests
+pub type WebClient = hyper::Client<HttpsConnector<TimeoutConnector>>;
+
+/// Settings of the HTTP client
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpSettings {
+    /// PEM bundle of the certificates trusted in addition to the built-in roots
+    pub ca_bundle: Option<PathBuf>,
+    /// Any certificate is trusted
+    pub insecure: bool,
+    /// The TCP connections not established within the timeout fail
+    pub connect_timeout: Option<Duration>,
+    /// Idle connections kept open per endpoint, unlimited by default
+    pub max_connections: Option<usize>,
+    /// Time an idle connection is kept open, zero closes the connections after
+    /// every request
+    pub keep_alive: Duration,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            ca_bundle: None,
+            insecure: false,
+            connect_timeout: None,
+            max_connections: None,
+            keep_alive: Duration::from_secs(90),
+        }
+    }
+}
+
+/// HTTP client of the settings, the connections are reused by all the
+/// requests of the client
+pub fn http_client(settings: &HttpSettings) -> Result<HttpClient, Error> {
+    Ok(HttpClient::from_builder(
+        client_builder(settings),
+        https_connector(settings)?,
+    ))
+}
+
+/// Client of the settings for the requests which are not sent to AWS
+pub fn web_client(settings: &HttpSettings) -> Result<WebClient, Error> {
+    Ok(client_builder(settings).build(https_connector(settings)?))
+}
+
+/// Connector of the trusted certificates and the connect timeout of the settings
+fn https_connector(settings: &HttpSettings) -> Result<HttpsConnector<TimeoutConnector>, Error> {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    if let Some(ref bundle) = settings.ca_bundle {
+        add_certificates(&mut config, bundle)?;
+    }
+    if settings.insecure {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NoVerification));
+    }
+
+    let mut http = HttpConnector::new(4);
+    http.enforce_http(false);
+    let connector = TimeoutConnector {
+        http,
+        timeout: settings.connect_timeout,
+    };
+    Ok(HttpsConnector::from((connector, config)))
+}
+
+fn client_builder(settings: &HttpSettings) -> hyper::client::Builder {
+    let mut builder = hyper::Client::builder();
+    if settings.keep_alive == Duration::from_secs(0) {
+        builder.keep_alive(false);
+    } else {
+        builder.keep_alive_timeout(settings.keep_alive);
+    }
+    if let Some(max) = settings.max_connections {
+        builder.max_idle_per_host(max);
+    }
+    builder
+}
+
+/// Connector of the TCP connections failing the ones not established within
+/// the timeout, hyper waits for the operating system otherwise
+#[derive(Clone)]
+pub struct TimeoutConnector {
+    http: HttpConnector,
+    timeout: Option<Duration>,
+}
+
+type Transport = <HttpConnector as Connect>::Transport;
+
+impl Connect for TimeoutConnector {
+    type Transport = Transport;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = (Transport, Connected), Error = io::Error> + Send>;
+
+    fn connect(&self, destination: Destination) -> Self::Future {
+        let connecting = self.http.connect(destination);
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Box::new(connecting),
+        };
+        Box::new(Timeout::new(connecting, timeout).map_err(move |e| {
+            if e.is_elapsed() {
+                let message = format!("connection timed out after {:?}", timeout);
+                io::Error::new(io::ErrorKind::TimedOut, message)
+            } else {
+                e.into_inner()
+                    .unwrap_or_else(|| io::Error::other("timer error"))
+            }
+        }))
+    }
+}
+
+fn add_certificates(config: &mut ClientConfig, bundle: &Path) -> Result<(), Error> {
+    let file = File::open(bundle)
+        .map_err(|e| format_err!("Failed to read the CA bundle {}: {}", bundle.display(), e))?;
+    let mut reader = BufReader::new(file);
+    match config.root_store.add_pem_file(&mut reader) {
+        Ok((added, _)) if added > 0 => Ok(()),
+        _ => Err(FunctionError::CaBundleError.into()),
+    }
+}
+
+/// Accepts any server certificate (--insecure)
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIBijCCAS+gAwIBAgIUO3J3NL1eg939bvvjPYO0/wcKwBwwCgYIKoZIzj0EAwIw
+GTEXMBUGA1UEAwwOczNmaW5kLXRlc3QtY2EwIBcNMjYxMDE3MTk0OTEwWhgPMjEy
+NjA5MjMxOTQ5MTBaMBkxFzAVBgNVBAMMDnMzZmluZC10ZXN0LWNhMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEYt0NaPZ9suH/IunVzLXU6I+Nwg/2GELuc+/5ODV7
+d40pm5BNd3IP8NzYGWQU1zx+FGWBxx6N5e9DcT+Dc7omkaNTMFEwHQYDVR0OBBYE
+FPfsKu0SD0j4eAMGM4dlXjCYRJHKMB8GA1UdIwQYMBaAFPfsKu0SD0j4eAMGM4dl
+XjCYRJHKMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSQAwRgIhAK9C+HjZ
+JCWZ4KSEnKlgs1PbRuXH5pm1++StPhPRPgS5AiEA9m9a3ntCfSJoz5GdK0KVPLNd
+1IaKH3ONhlWLymqfUDs=
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn ca_bundle() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let bundle = dir.path().join("ca.pem");
+        fs::write(&bundle, CERTIFICATE)?;
+        let empty = dir.path().join("empty.pem");
+        fs::write(&empty, "no certificates")?;
+
+        let settings = |ca_bundle: Option<PathBuf>, insecure: bool| HttpSettings {
+            ca_bundle,
+            insecure,
+            ..Default::default()
+        };
+        assert!(http_client(&settings(None, false)).is_ok());
+        assert!(http_client(&settings(None, true)).is_ok());
+        assert!(http_client(&settings(Some(bundle), false)).is_ok());
+        assert!(http_client(&settings(Some(empty), false)).is_err());
+        assert!(http_client(&settings(Some(dir.path().join("missing.pem")), false)).is_err());
+
+        let pooled = HttpSettings {
+            max_connections: Some(64),
+            keep_alive: Duration::from_secs(0),
+            connect_timeout: Some(Duration::from_secs(3)),
+            ..Default::default()
+        };
+        assert!(http_client(&pooled).is_ok());
+        Ok(())
+    }
+}
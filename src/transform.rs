@@ -0,0 +1,364 @@
+use failure::Error;
+use flate2::read::{GzEncoder, MultiGzDecoder};
+use flate2::Compression;
+use log::{debug, info};
+use rusoto_core::Region;
+use rusoto_s3::{GetObjectOutput, GetObjectRequest, Object, PutObjectRequest, S3Client, Tag, S3};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::arg::{EnrichField, S3path, Transform};
+use crate::enrich::EnrichmentCache;
+use crate::error::FunctionError;
+use crate::function::{percent_encode, RunCommand};
+use crate::ops;
+use crate::output::OutputSink;
+use crate::parallel::parallel_map;
+use crate::report::ErrorReport;
+use crate::retry::with_retry;
+
+/// Headers of a key kept by the upload of its transformed content
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ObjectHeaders {
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_language: Option<String>,
+    pub content_type: Option<String>,
+    pub expires: Option<String>,
+    pub metadata: Option<HashMap<String, String>>,
+    pub server_side_encryption: Option<String>,
+    pub ssekms_key_id: Option<String>,
+    pub storage_class: Option<String>,
+    pub website_redirect_location: Option<String>,
+    /// Tags of the key as the query string of x-amz-tagging
+    pub tagging: Option<String>,
+}
+
+impl From<GetObjectOutput> for ObjectHeaders {
+    fn from(output: GetObjectOutput) -> Self {
+        ObjectHeaders {
+            cache_control: output.cache_control,
+            content_disposition: output.content_disposition,
+            content_encoding: output.content_encoding,
+            content_language: output.content_language,
+            content_type: output.content_type,
+            expires: output.expires,
+            metadata: output.metadata,
+            server_side_encryption: output.server_side_encryption,
+            ssekms_key_id: output.ssekms_key_id,
+            storage_class: output.storage_class,
+            website_redirect_location: output.website_redirect_location,
+            tagging: None,
+        }
+    }
+}
+
+impl ObjectHeaders {
+    fn put_request(&self, bucket: &str, key: &str, data: &[u8]) -> PutObjectRequest {
+        let headers = self.clone();
+        PutObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            body: Some(data.to_vec().into()),
+            content_length: Some(data.len() as i64),
+            cache_control: headers.cache_control,
+            content_disposition: headers.content_disposition,
+            content_encoding: headers.content_encoding,
+            content_language: headers.content_language,
+            content_type: headers.content_type,
+            expires: headers.expires,
+            metadata: headers.metadata,
+            server_side_encryption: headers.server_side_encryption,
+            ssekms_key_id: headers.ssekms_key_id,
+            storage_class: headers.storage_class,
+            website_redirect_location: headers.website_redirect_location,
+            tagging: headers.tagging,
+            ..Default::default()
+        }
+    }
+}
+
+/// Pipe the data through a shell command, its output is the result. The
+/// command fails on a non-zero exit status
+pub fn run_filter(command: &str, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    info!("Running filter: {}", command);
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // written from another thread, the command may fill its stdout first
+    let mut stdin = child.stdin.take().ok_or(FunctionError::CommandlineParse)?;
+    let writer = thread::spawn(move || stdin.write_all(&data));
+    let output = child.wait_with_output()?;
+    // the command may exit without reading all of its input
+    let _ = writer.join();
+
+    debug!("Filter exited with {}", output.status);
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        return Err(FunctionError::FilterCommandError(output.status.to_string(), stderr).into());
+    }
+    Ok(output.stdout)
+}
+
+impl Transform {
+    /// The transformed content and headers of a key
+    pub fn transform(
+        &self,
+        data: Vec<u8>,
+        mut headers: ObjectHeaders,
+    ) -> Result<(Vec<u8>, ObjectHeaders), Error> {
+        if self.gunzip || self.recompress {
+            let mut output = Vec::new();
+            MultiGzDecoder::new(&data[..]).read_to_end(&mut output)?;
+            if self.gunzip {
+                if headers.content_encoding.as_deref() == Some("gzip") {
+                    headers.content_encoding = None;
+                }
+                return Ok((output, headers));
+            }
+            return Ok((self.compress(&output)?, headers));
+        }
+        if self.gzip {
+            headers.content_encoding = Some("gzip".to_owned());
+            return Ok((self.compress(&data)?, headers));
+        }
+        match self.exec_filter {
+            Some(ref command) => Ok((run_filter(command, data)?, headers)),
+            None => Ok((data, headers)),
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        GzEncoder::new(data, Compression::new(self.level)).read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    /// Bucket and key of the result of a key
+    pub fn target(&self, bucket: &str, key: &str) -> Result<(String, String), Error> {
+        match self.destination {
+            Some(ref destination) => Ok((
+                destination.bucket.clone(),
+                ops::target_key(key, destination, self.flat)?,
+            )),
+            None => Ok((bucket.to_owned(), key.to_owned())),
+        }
+    }
+
+    fn transform_object(
+        &self,
+        client: &S3Client,
+        bucket: &str,
+        object: &Object,
+        cache: &EnrichmentCache,
+    ) -> Result<Option<String>, Error> {
+        let key = object.key.as_ref().ok_or(FunctionError::ObjectFieldError)?;
+        let (target_bucket, target_key) = self.target(bucket, key)?;
+
+        let request = GetObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            ..Default::default()
+        };
+        debug!("GetObject s3://{}/{}", bucket, key);
+        let mut output = match with_retry("GetObject", || client.get_object(request.clone())) {
+            Ok(x) => x,
+            Err(e) => {
+                ErrorReport::from_rusoto("GetObject", bucket, Some(key), &e).emit();
+                return Ok(None);
+            }
+        };
+        let mut data = Vec::new();
+        output
+            .body
+            .take()
+            .ok_or(FunctionError::S3FetchBodyError)?
+            .into_blocking_read()
+            .read_to_end(&mut data)?;
+
+        // the tags are sent with the upload, the key is skipped when they are unknown
+        let tag_count = output.tag_count.unwrap_or_default();
+        let mut headers = ObjectHeaders::from(output);
+        if tag_count > 0 && !self.dry_run {
+            match cache.fetch(client, bucket, key, &[EnrichField::Tags]).tags {
+                Some(tags) => headers.tagging = Some(tagging(&tags)),
+                None => return Ok(None),
+            }
+        }
+
+        let size = data.len();
+        let (data, headers) = match self.transform(data, headers) {
+            Ok(x) => x,
+            Err(e) => {
+                ErrorReport::new("Transform", bucket, Some(key), &e).emit();
+                return Ok(None);
+            }
+        };
+
+        let line = format!(
+            "s3://{}/{} => s3://{}/{} ({} -> {} bytes)",
+            bucket,
+            key,
+            target_bucket,
+            target_key,
+            size,
+            data.len()
+        );
+        if self.dry_run {
+            return Ok(Some(format!("would transform: {}", line)));
+        }
+
+        debug!("PutObject s3://{}/{}", target_bucket, target_key);
+        if let Err(e) = with_retry("PutObject", || {
+            client.put_object(headers.put_request(&target_bucket, &target_key, &data))
+        }) {
+            ErrorReport::from_rusoto("PutObject", &target_bucket, Some(&target_key), &e).emit();
+            return Ok(None);
+        }
+        if self.quiet {
+            Ok(None)
+        } else {
+            Ok(Some(format!("transformed: {}", line)))
+        }
+    }
+}
+
+/// The tags as the query string of x-amz-tagging
+fn tagging(tags: &[Tag]) -> String {
+    let pairs: Vec<String> = tags
+        .iter()
+        .map(|x| format!("{}={}", percent_encode(&x.key), percent_encode(&x.value)))
+        .collect();
+    pairs.join("&")
+}
+
+impl RunCommand for Transform {
+    fn execute(
+        &self,
+        client: &S3Client,
+        _r: &Region,
+        path: &S3path,
+        list: &[Object],
+        enrichment: &EnrichmentCache,
+        output: &dyn OutputSink,
+    ) -> Result<(), Error> {
+        for line in parallel_map(list, self.concurrency, |x| {
+            self.transform_object(client, &path.bucket, x, enrichment)
+        }) {
+            if let Some(line) = line? {
+                output.line(&line);
+            }
+        }
+        Ok(())
+    }
+
+    fn modifies_keys(&self) -> bool {
+        self.destination.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GZIP_HELLO: [u8; 32] = [
+        31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 203, 72, 205, 201, 201, 87, 40, 207, 47, 202, 73, 225, 2,
+        0, 45, 59, 8, 175, 12, 0, 0, 0,
+    ];
+
+    fn transform(exec_filter: Option<&str>, gunzip: bool) -> Transform {
+        Transform {
+            exec_filter: exec_filter.map(str::to_owned),
+            gunzip,
+            gzip: false,
+            recompress: false,
+            level: 9,
+            destination: None,
+            flat: false,
+            dry_run: false,
+            concurrency: 1,
+            quiet: false,
+        }
+    }
+
+    #[test]
+    fn transform_content() -> Result<(), Error> {
+        let headers = ObjectHeaders {
+            content_type: Some("text/plain".to_owned()),
+            content_encoding: Some("gzip".to_owned()),
+            ..Default::default()
+        };
+
+        let (data, kept) = transform(Some("sed s/foo/bar/"), false)
+            .transform(b"foo foo\n".to_vec(), headers.clone())?;
+        assert_eq!(data, b"bar foo\n");
+        assert_eq!(kept, headers);
+
+        let (data, kept) = transform(None, true).transform(GZIP_HELLO.to_vec(), headers.clone())?;
+        assert_eq!(data, b"hello world\n");
+        assert_eq!(kept.content_encoding, None);
+        assert_eq!(kept.content_type.as_deref(), Some("text/plain"));
+
+        let gzip = Transform {
+            gzip: true,
+            ..transform(None, false)
+        };
+        let plain = ObjectHeaders::default();
+        let (data, kept) = gzip.transform(b"hello world\n".to_vec(), plain.clone())?;
+        assert_eq!(kept.content_encoding.as_deref(), Some("gzip"));
+        let (data, _) = transform(None, true).transform(data, plain.clone())?;
+        assert_eq!(data, b"hello world\n");
+
+        let recompress = Transform {
+            recompress: true,
+            level: 1,
+            ..transform(None, false)
+        };
+        let (data, kept) = recompress.transform(GZIP_HELLO.to_vec(), headers.clone())?;
+        assert_eq!(kept, headers);
+        let (data, _) = transform(None, true).transform(data, plain)?;
+        assert_eq!(data, b"hello world\n");
+
+        assert_eq!(
+            tagging(&[
+                Tag {
+                    key: "team".to_owned(),
+                    value: "data eng".to_owned(),
+                },
+                Tag {
+                    key: "env".to_owned(),
+                    value: "prod&dev".to_owned(),
+                },
+            ]),
+            "team=data%20eng&env=prod%26dev"
+        );
+
+        assert!(run_filter("echo failed >&2; exit 3", b"data".to_vec())
+            .unwrap_err()
+            .to_string()
+            .ends_with(": failed"));
+
+        let moved = Transform {
+            destination: Some("s3://other/fixed/".parse()?),
+            flat: true,
+            ..transform(Some("cat"), false)
+        };
+        assert_eq!(
+            moved.target("bucket", "logs/a.txt")?,
+            ("other".to_owned(), "fixed/a.txt".to_owned())
+        );
+        assert_eq!(
+            transform(Some("cat"), false).target("bucket", "logs/a.txt")?,
+            ("bucket".to_owned(), "logs/a.txt".to_owned())
+        );
+        Ok(())
+    }
+}
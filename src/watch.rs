@@ -0,0 +1,158 @@
+use rusoto_s3::Object;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cancel::is_cancelled;
+use crate::command::Find;
+use crate::events::FindEvents;
+use crate::filter::Filter;
+use crate::run::{walk, Walk};
+
+/// Version of a key: its ETag and modification time
+type Version = (Option<String>, Option<String>);
+
+/// Matched keys of the previous and of the current pass of --watch
+#[derive(Debug, Default)]
+pub struct WatchState {
+    passes: Mutex<Passes>,
+}
+
+#[derive(Debug, Default)]
+struct Passes {
+    previous: HashMap<String, Version>,
+    current: HashMap<String, Version>,
+}
+
+impl WatchState {
+    /// True when the key is new or changed. An unchanged key is kept in the
+    /// current pass, a new one once the command has succeeded on it
+    pub fn is_new(&self, object: &Object) -> bool {
+        let key = match object.key {
+            Some(ref key) => key,
+            None => return true,
+        };
+        let version = version(object);
+
+        let mut passes = self.passes.lock().unwrap();
+        let new = passes.previous.get(key) != Some(&version);
+        if !new {
+            passes.current.insert(key.clone(), version);
+        }
+        new
+    }
+
+    /// Record the keys the command has succeeded on in the current pass, the
+    /// failed ones are new again on the next pass
+    pub fn completed(&self, objects: &[Object]) {
+        let mut passes = self.passes.lock().unwrap();
+        for object in objects {
+            if let Some(ref key) = object.key {
+                passes.current.insert(key.clone(), version(object));
+            }
+        }
+    }
+
+    /// Start the next pass. After an incomplete pass the keys of the previous
+    /// one are kept, so that the keys which were not listed are not seen as new
+    pub fn next_pass(&self, complete: bool) {
+        let mut passes = self.passes.lock().unwrap();
+        let current = std::mem::take(&mut passes.current);
+        if complete {
+            passes.previous = current;
+        } else {
+            passes.previous.extend(current);
+        }
+    }
+}
+
+fn version(object: &Object) -> Version {
+    (object.e_tag.clone(), object.last_modified.clone())
+}
+
+impl FindEvents for WatchState {
+    fn on_action_completed(&self, objects: &[Object]) {
+        self.completed(objects);
+    }
+}
+
+/// Filter of the keys new or changed since the previous pass, the last filter
+/// of a watched search
+pub struct WatchFilter(pub Arc<WatchState>);
+
+impl Filter for WatchFilter {
+    fn filter(&self, object: &Object) -> bool {
+        self.0.is_new(object)
+    }
+}
+
+/// Walk the paths every `interval` until the walk is cancelled, the command
+/// runs only on the keys new or changed since the previous pass. `pass` gets
+/// the outcome of every walk, e.g. to print its summary
+pub fn watch<F>(status: &mut Find, interval: Duration, mut pass: F)
+where
+    F: FnMut(&Find, &Walk),
+{
+    let state = Arc::new(WatchState::default());
+    status.filters.push(WatchFilter(state.clone()));
+    status.events.push(state.clone());
+    let (limit, skip) = (status.limit, status.skip.get());
+
+    while !is_cancelled() {
+        let start = Instant::now();
+        status.limit = limit;
+        status.skip.set(skip);
+        let result = walk(status);
+        state.next_pass(!result.failed() && !result.cancelled);
+        pass(status, &result);
+
+        while !is_cancelled() && start.elapsed() < interval {
+            thread::sleep(
+                interval
+                    .saturating_sub(start.elapsed())
+                    .min(Duration::from_millis(200)),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(key: &str, etag: &str) -> Object {
+        Object {
+            key: Some(key.to_owned()),
+            e_tag: Some(etag.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn watch_passes() {
+        let state = WatchState::default();
+        assert!(state.is_new(&object("a", "1")));
+        assert!(state.is_new(&object("b", "1")));
+        // the command has failed on b
+        state.completed(&[object("a", "1")]);
+        state.next_pass(true);
+
+        assert!(!state.is_new(&object("a", "1")));
+        assert!(state.is_new(&object("b", "1")));
+        state.completed(&[object("b", "1")]);
+        state.next_pass(true);
+
+        assert!(!state.is_new(&object("a", "1")));
+        assert!(state.is_new(&object("b", "2")));
+        assert!(state.is_new(&object("c", "1")));
+        state.completed(&[object("b", "2"), object("c", "1")]);
+        state.next_pass(false);
+
+        // the incomplete pass keeps the keys it did not list
+        assert!(!state.is_new(&object("a", "1")));
+        assert!(!state.is_new(&object("b", "2")));
+        state.next_pass(true);
+        assert!(state.is_new(&object("c", "1")));
+    }
+}